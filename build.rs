@@ -5,7 +5,9 @@
 //! This build script is *optional*, and exists only to set *default* options under
 //! circumstances under which they are supported. Actually, all this build script does
 //! is detect if we are running nightly Rust, and enable backtrace support for errors
-//! if we are.
+//! if we are, and note how libusb was linked, for `bmputil doctor`.
+
+use std::env;
 
 use rustc_version::{version_meta, Channel};
 
@@ -29,4 +31,15 @@ fn main()
             }
         }
     }
+
+    // libusb1-sys tells the crates that depend on it directly whether it built libusb itself (with the
+    // vendored feature), and whether it linked it statically.
+    let libusb_linkage = if env::var_os("DEP_USB_1.0_VENDORED").is_some() {
+        "built in"
+    } else if env::var_os("DEP_USB_1.0_STATIC").is_some() {
+        "system, statically linked"
+    } else {
+        "system"
+    };
+    println!("cargo:rustc-env=BMPUTIL_LIBUSB_LINKAGE={}", libusb_linkage);
 }
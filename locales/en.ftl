@@ -0,0 +1,336 @@
+# SPDX-License-Identifier: MIT OR Apache-2.0
+# SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+#
+# English messages for bmputil. This is also the fallback for anything other catalogs are missing,
+# so every message must be here. See https://projectfluent.org/fluent/guide/ for the syntax.
+
+## Errors
+
+error-firmware-file-io = failed to read firmware file
+error-firmware-file-io-named = failed to read firmware file { $filename }
+error-firmware-file-write = failed to write firmware file { $filename }
+error-hardware-mismatch = firmware is built for { $firmware } hardware, but this Black Magic Probe is { $probe } hardware
+error-not-confirmed = { $operation } was not confirmed (use --force to skip confirmation)
+error-download = failed to download { $url }
+error-download-too-large = { $url } is larger than the { $limit } bytes bmputil will download, so isn't what it should be
+error-invalid-proxy = invalid proxy { $proxy } (expected e.g. http://proxy.example.com:3128)
+error-release-index = failed to read the list of firmware releases from { $url }
+error-not-cached = { $what } hasn't been downloaded before, so can't be used with --offline (run once without it first)
+error-unknown-release = no firmware release is tagged { $tag } (see bmputil firmware releases)
+error-no-release-for-hardware = firmware release { $release } has no build for { $hardware } hardware (flash one yourself with bmputil firmware flash)
+error-no-release-for-platform = bmputil { $release } has no standalone executable for { $platform } (update bmputil the way you installed it)
+error-checksum-mismatch = { $what } does not match its published checksum (corrupted download?)
+error-bad-signature = { $what } is not correctly signed by any trusted key
+error-unsigned = { $what } has no published signature, but signed firmware is required
+error-unverified = { $what } has no published checksum or signature bmputil can check it against, so won't be installed
+error-invalid-signing-key = { $key } in signing_keys in the configuration file is not a valid minisign public key
+error-some-probes-failed = { $failed } of { $total } Black Magic Probes failed
+error-too-many-devices = current operation only supports one Black Magic Probe device but more than one device was found (select one with --serial, --probe or --index)
+error-device-not-found = Black Magic Probe device not found (check connection?)
+error-device-disconnect-during-operation = Black Magic Probe device found disconnected
+error-device-reboot = Black Magic Probe device did not come back online (invalid firmware?)
+error-reboot-timed-out = Black Magic Probe device did not come back online on port { $port } after waiting { $waited }s; { $seen_count ->
+        [0] no Black Magic Probe devices were seen at all
+       *[other] saw only: { $seen }
+    } (invalid firmware, or try a longer --reenumerate-timeout?)
+error-transfer-size-unsupported = Black Magic Probe device advertises { $advertised }-byte DFU transfers, but { $requested }-byte transfers were asked for (see --transfer-size)
+error-too-many-blocks = { $length } bytes is more than DFU can download in { $transfer_size }-byte blocks (try a larger --transfer-size)
+error-dfu-status = Black Magic Probe device reported { $status }: { $description }, and is now in state { $state }
+error-dfu-status-at-offset = Black Magic Probe device reported { $status }: { $description } at byte offset { $offset } ({ $offset_hex }), and is now in state { $state }
+error-device-fd-reenumerated =
+    The probe re-enumerated (e.g. switching to or from DFU mode), so the file descriptor given with --device-fd no
+    longer refers to it. Run bmputil again with a file descriptor for the probe as it is now (e.g. with termux-usb).
+error-kernel-driver-active =
+    The system's own driver has interface { $interface } of the Black Magic Probe, which bmputil needs.
+    Making it let go means taking the probe over from the system (capturing it), which macOS only lets root do:
+    close any other program using the probe (such as a serial terminal), or run bmputil again with sudo.
+error-device-seems-invalid =
+    Black Magic Probe device returned bad data ({ $thing }) during configuration.
+    This generally shouldn't be possible. Maybe cable is bad, or OS is messing with things?
+error-upload-unsupported = this Black Magic Probe's bootloader does not support reading flash back
+error-outside-flash = { $start }-{ $end } is not within the Black Magic Probe's flash
+error-verification-failed = firmware verification failed: flash contents differ from the firmware image at offset { $offset }
+error-config-file = failed to load configuration file { $path }
+error-config-file-write = failed to save to configuration file { $path }
+error-command-failed = running { $command } failed
+error-driver-install = failed to install the WinUSB driver: { $why }
+error-no-driver =
+    The Black Magic Probe's DFU interface (interface { $interface }) has no driver bound to it, so bmputil can't use it.
+    Run bmputil probe list on a terminal to have bmputil install WinUSB for it.
+error-wrong-driver =
+    The Black Magic Probe's DFU interface (interface { $interface }) is bound to the { $driver } driver rather than WinUSB, so bmputil can't use it.
+    Run bmputil probe list on a terminal to have bmputil install WinUSB for it in place of { $driver }.
+error-driver-cached =
+    The probe's firmware asks Windows to use WinUSB by itself, so Windows has probably remembered the probe from before its firmware did.
+    Uninstalling the probe in Device Manager and plugging it back in has Windows set it up afresh.
+error-pin-file = failed to load firmware pin file { $path }
+error-pin-file-why = failed to load firmware pin file { $path }: { $why }
+error-unknown-probe-alias = no probe alias named { $alias } is defined in the configuration file's [aliases] table
+error-unknown-alt-setting = Black Magic Probe device has no DFU alt-setting { $alt } (see bmputil probe list for the ones it has)
+error-gdb-port-not-found = couldn't find the serial port of the Black Magic Probe's GDB server (give it with --gdb-port)
+error-gdb-server = failed to talk to the Black Magic Probe's GDB server on { $port } (is GDB connected to it?)
+error-monitor-command-failed = the Black Magic Probe refused `monitor { $command }`
+error-monitor-command-failed-output = the Black Magic Probe refused `monitor { $command }`: { $output }
+error-invalid-firmware = specified firmware does not seem valid
+error-invalid-firmware-why = specified firmware does not seem valid: { $why }
+error-external-io = unhandled std::io::Error: { $error }
+error-external-libusb = unhandled libusb error: { $error }
+error-external-goblin = unhandled ELF parsing error: { $error }
+
+error-label = Error:
+error-backtrace-hint = note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace.
+error-backtrace-nightly-hint = note: recompile with nightly toolchain and run with `RUST_BACKTRACE=1` environment variable to display a backtrace.
+
+## Prompts
+
+prompt-pick = { $question } [0-{ $last }, empty to abort]:
+prompt-pick-invalid = { $answer } is not one of the listed options.
+prompt-confirm = { $question } [y/N]:
+# Answers that count as "yes" to a yes/no question, separated by commas. English ones always work too.
+prompt-yes-answers = y, yes
+prompt-confirm-typed = { $question } Type "{ $expected }" to continue:
+
+## Status messages
+
+status-found = Found: { $probe }
+status-using-last-probe = Multiple Black Magic Probe devices found; using the last used one.
+status-detach-to-dfu = Requesting device detach from runtime mode to DFU mode...
+status-in-dfu-mode = Black Magic Probe is currently in DFU (bootloader) mode.
+status-detach-to-runtime = Requesting device detach from DFU mode to runtime mode...
+status-using-pin = Using the firmware pinned in { $path }
+status-latest-release = Latest { $channel } firmware release: { $release }
+status-latest-prerelease = Latest { $channel } firmware release: { $release } (pre-release)
+status-already-running = This probe is already running { $version }; nothing to do (use --force to reinstall).
+status-confirm-update = Update to { $release }?
+status-updating = Updating from { $from } to { $to } ({ $length } bytes)...
+status-unknown-firmware = unknown firmware
+status-check-up-to-date = up to date
+status-check-as-pinned = as pinned
+status-check-update-available = update available
+status-check-differs-from-pin = differs from pin
+status-check-unknown = unknown
+status-check-unknown-version = (can't tell what version it's running)
+status-check-pinned = pinned { $version }
+status-check-outdated = { $outdated } of { $total } Black Magic Probes can be updated (run bmputil firmware update).
+status-flashing-probes = Flashing { $total } Black Magic Probes, { $jobs } at a time...
+status-summary = Summary:
+status-summary-ok = ok
+status-summary-failed = FAILED
+status-device-fd-reenumerating = The probe is re-enumerating, which bmputil can't follow with --device-fd.
+status-device-fd-rebooting = The probe is rebooting, which bmputil can't follow with --device-fd.
+
+## Switching modes, where $mode is DFU or runtime
+
+dfu-already-in-mode = The probe is already in { $mode ->
+        [DFU] DFU
+       *[runtime] runtime
+    } mode; nothing to do.
+dfu-now-in-mode = The probe is now in { $mode ->
+        [DFU] DFU
+       *[runtime] runtime
+    } mode: { $probe }
+
+## Serial numbers
+
+serial-already = The probe's serial number is already { $serial }; nothing to do.
+serial-unknown = (unknown)
+serial-confirm = Change the serial number of { $probe } from { $from } to { $to }?
+serial-programming = Programming serial number { $serial }...
+serial-written = Serial number written and read back.
+serial-switch-back-failed = Failed to switch the probe back to its firmware: { $error }
+serial-now = The probe's serial number is now { $serial }.
+serial-not-reported =
+    The serial number was programmed, but the probe still reports { $reported }; its firmware may not support
+    programmed serial numbers
+serial-read-back-failed = Couldn't read the probe's serial number back after it rebooted: { $error }
+
+## Target power
+
+power-state = { $enabled ->
+        [true] on
+       *[false] off
+    }
+power-switched = Target power switched { $enabled ->
+        [true] on
+       *[false] off
+    }.
+
+## Target voltage
+
+voltage-target = Target voltage: { $voltage }V
+voltage-unsupported = This probe's hardware can't measure the target voltage.
+
+## Option bytes and read protection
+
+rdp-level-0 = level 0 (not protected)
+rdp-level-1 = level 1 (read-protected; removing it mass erases the flash)
+rdp-level-2 = level 2 (permanently locked)
+option-bytes-at = Option bytes at { $address }:
+option-bytes-at-layout = Option bytes at { $address } ({ $family } layout):
+option-bytes-rdp = Read protection: { $level }
+option-bytes-unknown-layout = bmputil doesn't know this MCU's option byte layout, so can't tell its read protection level.
+option-bytes-rdp-depends = Read protection: depends on the MCU, as RDP { $rdp } is { $levels }
+# One of the levels in option-bytes-rdp-depends, which are joined with option-bytes-rdp-separator.
+option-bytes-rdp-on-family = level { $level } on the { $family }
+option-bytes-rdp-separator = {", but "}
+
+## Unlocking
+
+unlock-option-bytes-refused = The bootloader refused to read the option bytes, so the flash is probably read-protected.
+unlock-not-protected =
+    This probe's flash isn't read-protected, so there's nothing to unlock.
+    To rewrite its bootloader and firmware anyway, use bmputil firmware recover.
+unlock-permanently-locked = this probe's MCU is permanently locked (read protection level 2), and can't be unlocked
+unlock-unknown-layout = Unknown option byte layout; can't tell whether the flash is read-protected
+unlock-rdp-depends = Read protection: depends on the MCU (RDP is { $rdp }), so it may be read-protected.
+unlock-confirm =
+    Removing read protection erases ALL of { $probe }'s flash, bootloader and firmware included, and can't be
+    undone; the new bootloader is written in its place. If that's interrupted, the probe won't be able to boot.
+    Keep BOOT0 held until it's done. Unlock it?
+unlock-removing = Removing read protection (this mass erases the flash, and can take a while)...
+unlock-device-fd = The probe is resetting, which bmputil can't follow with --device-fd; recover it once it's back.
+unlock-removed = Read protection removed.
+
+## Developer commands
+
+debug-mass-erasing = Mass erasing all of the flash...
+debug-erasing = Erasing the flash covering { $start }-{ $end }...
+debug-erased = Done. The probe has been left in its bootloader.
+debug-writing = Writing { $length } bytes to { $address }...
+
+## Bootloader changes, where $action is update, erase or overwrite
+
+bootloader-not-confirming = Not asking for confirmation of the bootloader { $action } as --force was given
+bootloader-warning-label = WARNING:
+bootloader-warning =
+    You are about to { $action ->
+        [erase] erase
+        [overwrite] overwrite
+       *[update] replace
+    } this probe's bootloader. If this is interrupted (e.g. by the probe losing power or being unplugged), or the
+    new bootloader is bad, the probe will not be able to boot, and may need a second debugger to recover.
+    Make sure the probe is on a reliable USB connection and power source before continuing.
+bootloader-confirm = { $action ->
+        [erase] Erase
+        [overwrite] Overwrite
+       *[update] Update
+    } the bootloader?
+bootloader-confirm-again = Are you really sure?
+# What the user has to type to go ahead.
+bootloader-confirm-typed = { $action ->
+        [erase] erase
+        [overwrite] overwrite
+       *[update] update
+    } bootloader
+
+## Setup
+
+setup-offer = This looks like the first time you've run bmputil. Walk through setting it up first?
+setup-declined = OK. You can run bmputil setup at any time.
+setup-continuing = Now on to what you asked for.
+setup-needs-terminal = bmputil setup asks questions, so needs to be run on a terminal (and without --non-interactive)
+setup-welcome =
+    This will check bmputil can use your Black Magic Probe, choose which firmware releases to follow,
+    and offer to update your probe's firmware. Press Ctrl+C at any time to stop.
+setup-access-heading = 1. Finding your probe
+setup-try-again = Try again?
+setup-no-probe = No Black Magic Probe is connected.
+setup-wait-for-probe = Wait for one to be plugged in?
+setup-permissions-linux =
+    bmputil isn't allowed to use your probe. On Linux, that takes udev rules, which bmputil install-udev-rules
+    installs (and which bmputil can install now).
+setup-install-udev-rules = Install the udev rules now? This needs sudo.
+setup-udev-failed = Couldn't install the udev rules: { $error }
+setup-permissions-freebsd =
+    bmputil isn't allowed to use your probe. On FreeBSD, that takes devd rules, which bmputil install-devd-rules
+    installs (and which bmputil can install now).
+setup-install-devd-rules = Install the devd rules now? This needs sudo or doas.
+setup-devd-failed = Couldn't install the devd rules: { $error }
+setup-permissions-openbsd =
+    bmputil isn't allowed to use your probe. On OpenBSD, only root can open USB devices' /dev/ugen nodes by default,
+    so run bmputil with doas, or give your user access to the probe's /dev/ugen nodes.
+setup-permissions-windows =
+    bmputil couldn't open your probe, which usually means the WinUSB driver isn't installed for it.
+    If installing it didn't help, unplug the probe and plug it back in.
+setup-permissions-other = bmputil isn't allowed to use your probe. Check nothing else is using it, then unplug it and plug it back in.
+setup-channel-heading = 2. Choosing which firmware releases to follow
+setup-channel-question = Which releases should bmputil update probes to?
+setup-channel-stable = stable: tested releases only (recommended)
+setup-channel-prerelease = pre-release: release candidates as well, to try fixes and features early
+setup-channel-nightly = nightly: development builds, from your nightly build feed
+setup-channel-current = { $option } (current)
+setup-channel-unchanged = Keeping the { $channel } channel.
+setup-channel-saved = Following the { $channel } channel from now on (saved in { $path }).
+setup-update-heading = 3. Updating your probe
+setup-update-no-probes = No probe to update; run bmputil firmware update once one's connected.
+setup-update-lookup-failed = Couldn't look up the latest firmware ({ $error }); run bmputil firmware update later.
+setup-update-up-to-date = { $probe } is already up to date.
+setup-update-unknown-hardware = Can't tell what hardware { $probe } is, so can't pick a firmware build for it.
+setup-update-no-build = Firmware release { $release } has no build for { $probe }.
+setup-update-question = Update { $probe } to { $release }?
+setup-done = All set. Run bmputil firmware check at any time to see if there's newer firmware.
+
+## WinUSB driver installation (Windows)
+
+elevate-offer = Access was denied, which running bmputil as an administrator may get around. Try again as an administrator?
+elevate-running = Running bmputil again as an administrator; Windows will ask for permission...
+winusb-offer = The WinUSB driver isn't installed for { $devices }, so bmputil can't use it. Install it now?
+winusb-needed = The WinUSB driver isn't installed for { $devices }, so bmputil can't use it. Run bmputil on a terminal to have it install the driver.
+winusb-remembered = The probe's firmware asks Windows to use WinUSB by itself, but Windows has remembered the probe from before it did. Installing WinUSB for it fixes that too.
+winusb-elevating = Installing drivers needs administrator access, so Windows will ask for it.
+winusb-installing = Installing WinUSB for { $device }; this can take a few minutes...
+winusb-installed = WinUSB is installed. If the probe still isn't found, unplug it and plug it back in.
+winusb-not-elevated = couldn't run bmputil as an administrator
+winusb-elevated-failed = the administrator bmputil exited with status { $status }
+
+## Device access rules (Linux and FreeBSD)
+
+access-creating-group = Creating the { $group } group...
+access-verify-no-probes = The rules are installed. No probe is plugged in to check them with, but any you plug in should now work.
+access-verified = The rules are installed, and bmputil can now use { $probes ->
+        [one] the probe
+       *[other] all { $probes } probes
+    } plugged in.
+
+## udev rules (Linux)
+
+udev-installing = Installing udev rules for Black Magic Probe to { $path }...
+udev-removing = Removing the old rules in { $path }...
+udev-reloading = Reloading udev rules...
+udev-not-in-group =
+    The rules are installed, but only give access to the { $group } group, which you aren't in. Add yourself with
+    sudo usermod -aG { $group } $USER, then log out and back in.
+udev-verify-failed-uaccess =
+    The rules are installed, but bmputil still can't open the probe. uaccess only gives access to whoever is logged
+    in at the machine itself, so if you're logged in remotely (e.g. over SSH), use --mode plugdev instead.
+    Otherwise, unplug the probe and plug it back in.
+udev-verify-failed-group =
+    The rules are installed, but bmputil still can't open the probe. If you were only just added to the { $group }
+    group, log out and back in; otherwise, unplug the probe and plug it back in.
+
+## devd rules (FreeBSD)
+
+devd-installing = Installing devd rules for Black Magic Probe to { $path }...
+devd-restarting = Restarting devd...
+devd-not-in-group =
+    The rules are installed, but only give access to the { $group } group, which you aren't in. Add yourself with
+    pw groupmod { $group } -m $USER (as root), then log out and back in. To use a probe's serial ports, join the
+    dialer group the same way.
+devd-verify-failed =
+    The rules are installed, but bmputil still can't open the probe. If you were only just added to the { $group }
+    group, log out and back in; otherwise, unplug the probe and plug it back in.
+
+## WSL (Windows Subsystem for Linux)
+
+wsl-v1 = bmputil is running in WSL 1, which can't use USB devices. Run it in WSL 2 (with usbipd-win), or on Windows itself.
+wsl-no-usbipd =
+    No probe is attached to WSL. WSL only sees USB devices attached to it with usbipd-win, which couldn't be run:
+    install it on Windows (see https://learn.microsoft.com/windows/wsl/connect-usb), then try again.
+wsl-no-probes = No probe is attached to WSL, and Windows can't see any either.
+wsl-attached-elsewhere = { $probe } ({ $busid }) is attached to another WSL distribution ({ $distribution }); detach it with usbipd detach --busid { $busid } to use it here.
+wsl-attached = { $probe } ({ $busid }) is attached to WSL, but can't be seen here yet; if it's attached to another WSL distribution, detach it with usbipd detach --busid { $busid } to use it here.
+wsl-not-attached = No probe is attached to WSL, but Windows can see these. To attach them, run these on Windows (usbipd bind needs an administrator):
+wsl-auto-attach-hint = Or run bmputil again with --auto-attach, to have it do that.
+wsl-binding = Sharing { $busid } with WSL (Windows will ask for administrator access)...
+wsl-attaching = Attaching { $busid } to WSL...
+wsl-attach-failed = Couldn't attach { $busid } to WSL: { $error }
@@ -0,0 +1,203 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+//! Module for what installing rules that give users access to their probes has in common across the
+//! Unix-likes that need them: udev rules on Linux (see [`crate::udev`]), and devd rules on FreeBSD
+//! (see [`crate::devd`]).
+//!
+//! Installing rules needs root, so unless we're already root, the commands that need it are run
+//! through `sudo` (or `doas`, where that's what's installed instead, as is common on the BSDs).
+
+use std::env;
+use std::io::Write as _;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use rusb::UsbContext;
+use tracing::debug;
+
+use crate::bmp::{BmpPlatform, CustomUsbIds};
+use crate::error::{Error, ErrorKind, ErrorSource};
+use crate::usb::{self, DfuOperatingMode, Pid, Vid};
+use crate::{status, tr, S};
+
+type UsbDevice = rusb::Device<rusb::Context>;
+
+/// Every USB ID a probe might have, with what it is and the mode it means.
+pub fn known_ids(custom_ids: CustomUsbIds) -> Vec<((Vid, Pid), String, DfuOperatingMode)>
+{
+    use DfuOperatingMode::*;
+
+    let mut ids = vec![
+        (BmpPlatform::BMD_RUNTIME_VID_PID, S!("Black Magic Probe"), Runtime),
+        (BmpPlatform::BMD_DFU_VID_PID, S!("Black Magic Probe bootloader"), FirmwareUpgrade),
+        (BmpPlatform::DRAGON_BOOT_VID_PID, S!("dragonBoot bootloader"), FirmwareUpgrade),
+        (BmpPlatform::STM32_DFU_VID_PID, S!("STM32 built-in DFU bootloader"), FirmwareUpgrade),
+    ];
+    ids.extend(
+        BmpPlatform::LEGACY_VID_PIDS
+            .iter()
+            .map(|&(ids, mode)| (ids, S!("Black Magic Probe with legacy USB IDs"), mode)),
+    );
+    if let Some(runtime) = custom_ids.runtime_ids() {
+        ids.push((runtime, S!("Black Magic Probe with custom USB IDs"), Runtime));
+    }
+    if let Some(dfu) = custom_ids.dfu_ids() {
+        ids.push((dfu, S!("Black Magic Probe bootloader with custom USB IDs"), FirmwareUpgrade));
+    }
+
+    // Custom IDs may well be the usual ones.
+    let mut seen = Vec::new();
+    ids.retain(|(ids, ..)| {
+        let new = !seen.contains(ids);
+        seen.push(*ids);
+        new
+    });
+    ids
+}
+
+/// Wraps an I/O error, e.g. from talking to a command we've run.
+pub fn io_error(e: std::io::Error) -> Error
+{
+    ErrorKind::External(ErrorSource::StdIo(e)).error()
+}
+
+/// Whether `program` can be found on the `PATH`.
+fn on_path(program: &str) -> bool
+{
+    env::var_os("PATH")
+        .map(|path| env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+        .unwrap_or(false)
+}
+
+/// Builds a command to run `program` as root, through `sudo` (or `doas`) unless we already are.
+pub fn as_root(program: &str) -> Command
+{
+    // SAFETY: geteuid() can't fail, and has no preconditions.
+    if unsafe { libc::geteuid() } == 0 {
+        return Command::new(program);
+    }
+
+    let escalate = if !on_path("sudo") && on_path("doas") { "doas" } else { "sudo" };
+    let mut command = Command::new(escalate);
+    command.arg(program);
+    command
+}
+
+/// Runs `command`, erroring if it can't be run or fails.
+pub fn run(command: &mut Command) -> Result<(), Error>
+{
+    let description = format!("{:?}", command);
+    debug!("Running {}", description);
+
+    let status = command.status().map_err(|e| ErrorKind::CommandFailed(description.clone()).error_from(e))?;
+    if !status.success() {
+        return Err(ErrorKind::CommandFailed(description).error());
+    }
+
+    Ok(())
+}
+
+/// Writes `contents` to `path` as root.
+pub fn write_as_root(path: &Path, contents: &str) -> Result<(), Error>
+{
+    let mut command = as_root("tee");
+    command.arg(path).stdin(Stdio::piped()).stdout(Stdio::null());
+    let description = format!("{:?}", command);
+    debug!("Running {}", description);
+
+    let mut child = command.spawn().map_err(|e| ErrorKind::CommandFailed(description.clone()).error_from(e))?;
+    child
+        .stdin
+        .take()
+        .expect("tee's stdin should be piped")
+        .write_all(contents.as_bytes())
+        .map_err(io_error)?;
+    let status = child.wait().map_err(io_error)?;
+    if !status.success() {
+        return Err(ErrorKind::CommandFailed(description).error());
+    }
+
+    Ok(())
+}
+
+/// Whether the user running bmputil (or running it through `sudo` or `doas`) is a member of `group`.
+pub fn in_group(group: &str) -> bool
+{
+    let mut id = Command::new("id");
+    id.arg("-nG");
+    if let Some(user) = env::var_os("SUDO_USER").or_else(|| env::var_os("DOAS_USER")) {
+        id.arg(user);
+    }
+
+    id.output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).split_whitespace().any(|name| name == group))
+        .unwrap_or(false)
+}
+
+/// Makes sure `group` exists, creating it if not.
+pub fn ensure_group(group: &str) -> Result<(), Error>
+{
+    let exists = Command::new("getent")
+        .args(["group", group])
+        .stdout(Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false);
+    if exists {
+        return Ok(());
+    }
+
+    status!("{}", tr!("access-creating-group", group = group));
+    // The BSDs manage users and groups with pw(8) rather than shadow-utils.
+    let mut groupadd = if cfg!(target_os = "linux") {
+        let mut groupadd = as_root("groupadd");
+        groupadd.args(["--system", group]);
+        groupadd
+    } else {
+        let mut pw = as_root("pw");
+        pw.args(["groupadd", "-n", group]);
+        pw
+    };
+    run(&mut groupadd)
+}
+
+/// Finds the probes (recognised with `custom_ids`) plugged in.
+pub fn probes(custom_ids: CustomUsbIds) -> Result<Vec<UsbDevice>, Error>
+{
+    let context = usb::new_context()?;
+    let probes = context
+        .devices()?
+        .iter()
+        .filter(|device| {
+            device
+                .device_descriptor()
+                .is_ok_and(|desc| custom_ids.identify(Vid(desc.vendor_id()), Pid(desc.product_id())).is_some())
+        })
+        .collect();
+
+    Ok(probes)
+}
+
+/// Checks that the probes plugged in can now be opened, saying `explanation` if not.
+pub fn verify(custom_ids: CustomUsbIds, explanation: &str) -> Result<(), Error>
+{
+    let probes = probes(custom_ids)?;
+    if probes.is_empty() {
+        status!("{}", tr!("access-verify-no-probes"));
+        return Ok(());
+    }
+
+    let denied = probes
+        .iter()
+        .filter(|device| matches!(device.open(), Err(rusb::Error::Access)))
+        .count();
+    if denied == 0 {
+        status!("{}", tr!("access-verified", probes = probes.len()));
+        return Ok(());
+    }
+
+    status!("{}", explanation);
+    Err(ErrorKind::External(ErrorSource::Libusb(rusb::Error::Access))
+        .error()
+        .with_ctx("opening probes after installing rules for them"))
+}
@@ -1,31 +1,39 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 // SPDX-FileCopyrightText: 2022-2023 1BitSquared <info@1bitsquared.com>
 // SPDX-FileContributor: Written by Mikaela Szekely <mikaela.szekely@qyriad.me>
+use std::env;
 use std::mem;
 use std::thread;
-use std::io::Read;
-use std::cell::{RefCell, Ref, RefMut};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::cell::{Cell, RefCell, Ref, RefMut};
 use std::time::{Duration, Instant};
-use std::fmt::{self, Display, Formatter};
+use std::fmt::{self, Display, Formatter, Write as _};
 use std::array::TryFromSliceError;
 
 use clap::ArgMatches;
-use dfu_core::DfuIo;
-use dfu_core::DfuProtocol;
-use dfu_core::sync::DfuSync;
-use log::{trace, debug, info, warn, error};
-use rusb::{UsbContext, Direction, RequestType, Recipient};
-use dfu_libusb::{DfuLibusb, Error as DfuLibusbError};
-use dfu_core::{State as DfuState, Error as DfuCoreError};
-
-use crate::{libusb_cannot_fail, S};
+use tracing::{trace, debug, info, warn, error, instrument};
+use rusb::{UsbContext, Hotplug, HotplugBuilder, Registration};
+use serde::{Deserialize, Serialize};
+
+use crate::{libusb_cannot_fail, prompt, status, S};
+use crate::config::Config;
+use crate::dfu::{self, DfuInterface};
+#[cfg(windows)]
+use crate::msos;
+use crate::retry::RetryPolicy;
+use crate::state;
+use crate::version::FirmwareVersion;
 use crate::error::{Error, ErrorKind, ErrorSource, ResErrorKind};
-use crate::usb::{DfuFunctionalDescriptor, InterfaceClass, InterfaceSubClass, GenericDescriptorRef, DfuRequest};
-use crate::usb::{Vid, Pid, DfuOperatingMode};
+use crate::usb::{DfuFunctionalDescriptor, InterfaceClass, InterfaceSubClass, GenericDescriptorRef};
+use crate::usb::{self, Vid, Pid, DfuOperatingMode};
 
 type UsbDevice = rusb::Device<rusb::Context>;
 type UsbHandle = rusb::DeviceHandle<rusb::Context>;
 
+/// The address internal flash is mapped at on the STM32s Black Magic Probes are built around.
+pub const FLASH_BASE: u32 = 0x0800_0000;
+
 
 /// Semantically represents a Black Magic Probe USB device.
 #[derive(Debug, PartialEq, Eq)]
@@ -40,6 +48,24 @@ pub struct BmpDevice
     /// The platform this BMP is running on.
     platform: BmpPlatform,
 
+    /// The hardware this BMP is, if it could be determined.
+    hardware: Option<ProbeHardware>,
+
+    /// Any non-standard USB IDs this BMP was found with, needed to find it again after it re-enumerates.
+    custom_ids: CustomUsbIds,
+
+    /// The DFU transfer size to use instead of the device's own maximum, if one was asked for.
+    transfer_size: Option<u16>,
+
+    /// How to retry USB requests that fail for transient reasons.
+    retry: RetryPolicy,
+
+    /// The DFU alt-setting to use instead of the one for the internal flash, if one was asked for.
+    alt_setting: Option<AltSetting>,
+
+    /// How long to give this BMP to answer requests, and to switch between runtime and DFU mode.
+    timeouts: UsbTimeouts,
+
     /// RefCell for interior-mutability-based caching.
     serial: RefCell<Option<String>>,
 
@@ -51,26 +77,49 @@ impl BmpDevice
 {
     pub fn from_usb_device(device: UsbDevice) -> Result<Self, Error>
     {
+        Self::from_usb_device_with_ids(device, CustomUsbIds::default())
+    }
+
+    /// Like [`from_usb_device()`], but also recognising the given non-standard USB IDs.
+    pub fn from_usb_device_with_ids(device: UsbDevice, custom_ids: CustomUsbIds) -> Result<Self, Error>
+    {
+        let handle = device.open()?;
+        Self::from_usb_handle_with_ids(handle, custom_ids)
+    }
+
+    /// Like [`from_usb_device_with_ids()`], but for a device that's already open, such as one given with
+    /// `--device-fd`.
+    pub fn from_usb_handle_with_ids(handle: UsbHandle, custom_ids: CustomUsbIds) -> Result<Self, Error>
+    {
+        let device = handle.device();
         let desc = device.device_descriptor()
             .expect(libusb_cannot_fail!("libusb_get_device_descriptor()"));
         let (vid, pid) = (Vid(desc.vendor_id()), Pid(desc.product_id()));
-        let (platform, mode) = BmpPlatform::from_vid_pid(vid, pid).ok_or_else(|| {
+        let (platform, mode) = custom_ids.identify(vid, pid).ok_or_else(|| {
             warn!("Device passed to BmpDevice::from_usb_device() does not seem to be a BMP device!");
             warn!("The logic for finding this device is probably incorrect!");
             ErrorKind::DeviceNotFound.error()
         })?;
 
-        let handle = device.open()?;
-
-
-        Ok(Self {
+        let mut bmpdev = Self {
             device: RefCell::new(Some(device)),
             mode,
             platform,
+            hardware: None,
+            custom_ids,
+            transfer_size: None,
+            retry: RetryPolicy::default(),
+            alt_setting: None,
+            timeouts: UsbTimeouts::default(),
             handle: RefCell::new(Some(handle)),
             serial: RefCell::new(None),
             port: RefCell::new(None),
-        })
+        };
+
+        // Not all bootloaders say what hardware they're running on, so this is best-effort.
+        bmpdev.hardware = bmpdev.identity().ok().and_then(|identity| identity.hardware());
+
+        Ok(bmpdev)
     }
 
     /// Get the [`rusb::Device<rusb::Context>`] associated with the connected Black Magic Probe.
@@ -121,6 +170,93 @@ impl BmpDevice
         self.platform
     }
 
+    /// Warns if this probe is using [legacy USB IDs](BmpPlatform::LEGACY_VID_PIDS).
+    ///
+    /// This is done once, where the probe is selected or listed, rather than every time it's reopened.
+    pub(crate) fn warn_legacy_ids(&self)
+    {
+        let desc = self.device()
+            .device_descriptor()
+            .expect(libusb_cannot_fail!("libusb_get_device_descriptor()"));
+        let (vid, pid) = (Vid(desc.vendor_id()), Pid(desc.product_id()));
+        if BmpPlatform::legacy_mode(vid, pid).is_some() {
+            warn!(
+                "This probe is using legacy USB IDs {:04x}:{:04x}; updating its firmware will migrate it to the current IDs",
+                vid.0,
+                pid.0,
+            );
+        }
+    }
+
+    /// The hardware this device is, if it could be determined.
+    pub fn hardware(&self) -> Option<ProbeHardware>
+    {
+        self.hardware
+    }
+
+    /// The non-standard USB IDs this device was found with, if any.
+    pub fn custom_ids(&self) -> CustomUsbIds
+    {
+        self.custom_ids
+    }
+
+    /// Sets the DFU transfer size to use instead of the device's own maximum, e.g. for unreliable
+    /// USB hubs. Asking for more than the device's maximum is an error when it comes to using it.
+    pub fn set_transfer_size(&mut self, transfer_size: Option<u16>)
+    {
+        self.transfer_size = transfer_size;
+    }
+
+    /// Sets how to retry USB requests that fail for transient reasons.
+    pub fn set_retry_policy(&mut self, retry: RetryPolicy)
+    {
+        self.retry = retry;
+    }
+
+    /// Sets the DFU alt-setting to use instead of the one for the internal flash.
+    pub fn set_alt_setting(&mut self, alt_setting: Option<AltSetting>)
+    {
+        self.alt_setting = alt_setting;
+    }
+
+    /// How long to give this BMP to answer requests, and to switch between runtime and DFU mode.
+    pub fn timeouts(&self) -> UsbTimeouts
+    {
+        self.timeouts
+    }
+
+    /// Sets how long to give this BMP to answer requests, and to switch between runtime and DFU mode.
+    pub fn set_timeouts(&mut self, timeouts: UsbTimeouts)
+    {
+        self.timeouts = timeouts;
+    }
+
+    /// The DFU transfer size to use, given the device's functional descriptor.
+    ///
+    /// A requested size over the device's maximum is an error rather than being clamped to it, so that
+    /// what's asked for is what's used, and the user is told exactly why it can't be.
+    fn transfer_size(&self, func_desc: &DfuFunctionalDescriptor) -> Result<u16, Error>
+    {
+        let maximum = func_desc.wTransferSize;
+        if maximum == 0 {
+            return Err(ErrorKind::DeviceSeemsInvalid(S!("DFU functional descriptor wTransferSize of 0")).error());
+        }
+
+        match self.transfer_size {
+            Some(requested) if requested > maximum => {
+                Err(ErrorKind::TransferSizeUnsupported(requested, maximum).error())
+            },
+            Some(requested) => Ok(requested),
+            None => Ok(maximum),
+        }
+    }
+
+    /// Get the load address for firmware of `firm_type` on this device.
+    pub fn load_address(&self, firm_type: FirmwareType) -> u32
+    {
+        self.platform.load_address(firm_type, self.hardware)
+    }
+
     /// Returns a the serial number string for this device.
     ///
     /// This struct caches the serial number in an [`std::cell::RefCell`],
@@ -136,7 +272,7 @@ impl BmpDevice
         // self.serial as mutable later.
         drop(serial);
 
-        let languages = self.handle().read_languages(Duration::from_secs(2))?;
+        let languages = self.handle().read_languages(self.timeouts.request)?;
         if languages.is_empty() {
             return Err(
                 ErrorKind::DeviceSeemsInvalid(String::from("no string descriptor languages"))
@@ -151,7 +287,7 @@ impl BmpDevice
             .read_serial_number_string(
                 *language,
                 &self.device().device_descriptor().unwrap(),
-                Duration::from_secs(2),
+                self.timeouts.request,
             )?;
 
         // Finally, now that we have the serial number, cache it...
@@ -172,54 +308,132 @@ impl BmpDevice
             return port.to_string();
         }
 
-        let bus = self.device().bus_number();
-        let path = self
-            .device()
-            .port_numbers()
-            .expect("unreachable: rusb always provides a properly sized array to libusb_get_port_numbers()")
-            .into_iter()
-            .map(|v| v.to_string())
-            .collect::<Vec<String>>()
-            .as_slice()
-            .join(".");
-
-        let port = format!("{}-{}", bus, path);
+        let port = port_path(&self.device());
         let ret = port.clone();
         self.port.replace(Some(port));
 
         ret
     }
 
-    /// Return a string suitable for display to the user.
+    /// Returns the product string (iProduct) for this device.
     ///
-    /// Note: this performs USB IO to retrieve the necessary string descriptors, if those strings
-    /// have not yet been retrieved previously (and thus not yet cached).
-    pub fn display(&self) -> Result<String, Error>
+    /// Note: this performs USB IO every time it is called.
+    pub fn product_string(&self) -> Result<String, Error>
     {
-        let handle = self.handle();
-        let mut languages = handle
-            .read_languages(Duration::from_secs(2))
-            .map_err(|e| Error::from(e).with_ctx("reading supported string descriptor langauges"))?;
-
-        let first_lang = languages.pop()
-            .ok_or_else(|| ErrorKind::DeviceSeemsInvalid(S!("no supported string descriptor languages")).error())?;
+        let first_lang = self.string_language()?;
 
         let dev_desc = &self
             .device()
             .device_descriptor()
             .expect(libusb_cannot_fail!("libusb_get_device_descriptor()"));
 
-        let product_string = handle
+        self.handle()
             .read_product_string(
                 first_lang,
                 dev_desc,
-                Duration::from_secs(2),
+                self.timeouts.request,
             )
-            .map_err(|e| ErrorKind::DeviceSeemsInvalid(S!("no product string descriptor")).error_from(e))?;
+            .map_err(|e| ErrorKind::DeviceSeemsInvalid(S!("no product string descriptor")).error_from(e))
+    }
+
+    /// Returns the language to request string descriptors in.
+    fn string_language(&self) -> Result<rusb::Language, Error>
+    {
+        let mut languages = self.handle()
+            .read_languages(self.timeouts.request)
+            .map_err(|e| Error::from(e).with_ctx("reading supported string descriptor langauges"))?;
+
+        languages.pop()
+            .ok_or_else(|| ErrorKind::DeviceSeemsInvalid(S!("no supported string descriptor languages")).error())
+    }
+
+    /// Returns the hardware variant and firmware version this device advertises, parsed from its
+    /// product string.
+    ///
+    /// Note: this performs USB IO every time it is called.
+    pub fn identity(&self) -> Result<ProbeIdentity, Error>
+    {
+        Ok(ProbeIdentity::from_product_string(&self.product_string()?))
+    }
+
+    /// Returns the version of this device's bootloader, where it can be told. In DFU mode, that's read
+    /// from the bootloader's descriptors (the version in its product string, or failing that its device
+    /// release number, bcdDevice); in runtime mode, it's the version the probe had when it was last
+    /// switched to or from DFU mode, if it has been (see [`detach_and_enumerate()`](Self::detach_and_enumerate)).
+    ///
+    /// Note: this performs USB IO.
+    pub fn bootloader_version(&self) -> Option<String>
+    {
+        match self.mode {
+            DfuOperatingMode::Runtime => {
+                let serial = self.serial_number().ok()?;
+                state::bootloader_version(&serial)
+            },
+            DfuOperatingMode::FirmwareUpgrade => Some(self.dfu_bootloader_version()),
+        }
+    }
+
+    /// Reads the version of this device's bootloader from its descriptors, for devices in DFU mode.
+    fn dfu_bootloader_version(&self) -> String
+    {
+        self.product_string()
+            .ok()
+            .and_then(|product| version_in_product_string(&product))
+            .unwrap_or_else(|| {
+                let release = self.device()
+                    .device_descriptor()
+                    .expect(libusb_cannot_fail!("libusb_get_device_descriptor()"))
+                    .device_version();
+                format!("v{}.{}.{}", release.major(), release.minor(), release.sub_minor())
+            })
+    }
 
+    /// Return a string suitable for display to the user.
+    ///
+    /// Note: this performs USB IO to retrieve the necessary string descriptors, if those strings
+    /// have not yet been retrieved previously (and thus not yet cached).
+    pub fn display(&self) -> Result<String, Error>
+    {
+        let product_string = self.product_string()?;
         let serial = self.serial_number()?;
 
-        Ok(format!("{}\n  Serial: {}\n  Port:  {}", product_string, serial, self.port()))
+        let mode = match self.mode {
+            DfuOperatingMode::Runtime => S!("runtime"),
+            DfuOperatingMode::FirmwareUpgrade => S!("DFU (bootloader)"),
+        };
+
+        let mut display = format!("{}\n  Serial: {}\n  Port:  {}\n  Mode:  {}", product_string, serial, self.port(), mode);
+        match (self.bootloader_version(), self.mode) {
+            (Some(version), DfuOperatingMode::Runtime) => {
+                let _ = write!(display, "\n  Bootloader: {} (when last in DFU mode)", version);
+            },
+            (Some(version), DfuOperatingMode::FirmwareUpgrade) => {
+                let _ = write!(display, "\n  Bootloader: {}", version);
+            },
+            (None, _) => (),
+        }
+        for port in self.serial_ports() {
+            let _ = write!(display, "\n  COM port: {}", port);
+        }
+
+        Ok(display)
+    }
+
+    /// Returns a one-line summary of this device (serial number, firmware version, and port), for
+    /// when several devices need to be told apart at a glance.
+    ///
+    /// Note: this performs USB IO, and substitutes placeholders for anything that could not be read.
+    pub fn summary(&self) -> String
+    {
+        let serial = self.serial_number()
+            .map(|serial| serial.to_string())
+            .unwrap_or_else(|_| S!("unknown serial"));
+        let version = self.identity()
+            .ok()
+            .and_then(|identity| identity.version)
+            .unwrap_or_else(|| S!("unknown version"));
+
+        format!("{} {} ({} mode, port {})", serial, version, self.mode, self.port())
     }
 
     /// Find and return the DFU functional descriptor and its interface number for the connected Black Magic Probe device.
@@ -232,36 +446,7 @@ impl BmpDevice
     /// available from libusb's device structures.
     pub fn dfu_descriptors(&self) -> Result<(u8, DfuFunctionalDescriptor), Error>
     {
-        let configuration = match self.device().active_config_descriptor() {
-            Ok(d) => d,
-            Err(rusb::Error::NotFound) => {
-                // In the unlikely even that the OS reports the device as unconfigured
-                // (possibly because it was only just connected and is still enumerating?)
-                // try instead to simply get the first configuration, and hope that the
-                // device is configured by the time we try to send requests to it.
-                // I'm not actually sure this case is even possibly on any OS, but might
-                // as well check.
-
-                warn!("OS reports Black Magic Probe device is unconfigured!");
-                warn!("Attempting to continue anyway, in case the device is still in the process of enumerating.");
-
-                // USB configurations are 1-indexed, as 0 is considered
-                // to be "unconfigured".
-                match self.device().config_descriptor(1) {
-                    Ok(d) => d,
-                    Err(e) => {
-                        return Err(
-                            ErrorKind::DeviceSeemsInvalid(
-                                String::from("no configuration descriptor exists")
-                            ).error_from(e)
-                        );
-                    },
-                }
-            },
-            Err(e) => {
-                return Err(e.into());
-            },
-        };
+        let configuration = self.config_descriptor()?;
 
         let dfu_interface_descriptor = configuration
             .interfaces()
@@ -299,149 +484,749 @@ impl BmpDevice
         Ok((dfu_interface_descriptor.interface_number(), dfu_func_desc))
     }
 
-    /// Requests the device to leave DFU mode, using the DefuSe extensions.
-    fn leave_dfu_mode(&mut self) -> Result<(), Error>
+    /// Returns every DFU interface alt-setting the device exposes, along with its name (iInterface).
+    ///
+    /// Note: this performs USB IO to read the interface names.
+    pub fn dfu_interfaces(&self) -> Result<Vec<DfuInterfaceInfo>, Error>
     {
-        debug!("Attempting to leave DFU mode...");
-        let (iface_number, _func_desc) = self.dfu_descriptors()?;
-        self._handle_mut().claim_interface(iface_number)?;
+        let configuration = self.config_descriptor()?;
+        let language = self.string_language().ok();
 
-        let request_type = rusb::request_type(
-            Direction::Out,
-            RequestType::Class,
-            Recipient::Interface,
-        );
+        let interfaces = configuration
+            .interfaces()
+            .flat_map(|interface| interface.descriptors())
+            .filter(|desc| {
+                desc.class_code() == InterfaceClass::APPLICATION_SPECIFIC.0 &&
+                    desc.sub_class_code() == InterfaceSubClass::DFU.0
+            })
+            .map(|desc| DfuInterfaceInfo {
+                number: desc.interface_number(),
+                alt_setting: desc.setting_number(),
+                name: language.and_then(|language| {
+                    self.handle()
+                        .read_interface_string(language, &desc, self.timeouts.request)
+                        .ok()
+                }),
+            })
+            .collect();
 
-        // Perform the zero-length DFU_DNLOAD request.
-        let _response = self.handle().write_control(
-            request_type, // bmRequestType
-            DfuRequest::Dnload as u8, // bRequest
-            0, // wValue
-            0, // wIndex
-            &[], // data
-            Duration::from_secs(2),
-        )?;
-
-        // Then perform a DFU_GETSTATUS request to complete the leave "request".
-        let request_type = rusb::request_type(
-            Direction::In,
-            RequestType::Class,
-            Recipient::Interface,
-        );
+        Ok(interfaces)
+    }
 
-        let mut buf: [u8; 6] = [0; 6];
-        let status = self.handle().read_control(
-            request_type, // bmRequestType
-            DfuRequest::GetStatus as u8, // bRequest
-            0, // wValue
-            iface_number as u16, // wIndex
-            &mut buf,
-            Duration::from_secs(2),
-        )?;
+    /// Returns the serial ports the OS has given the device's serial interfaces (the GDB server's and the
+    /// target UART's), where we know how to find out. Currently, that's only on Windows.
+    ///
+    /// Note: this performs USB IO to read the interface names.
+    pub fn serial_ports(&self) -> Vec<SerialPortInfo>
+    {
+        // Only runtime firmware has serial interfaces.
+        if self.mode != DfuOperatingMode::Runtime {
+            return Vec::new();
+        }
 
-        trace!("Device status after zero-length DNLOAD is 0x{:02x}", status);
-        info!("DFU_GETSTATUS request completed. Device should now re-enumerate into runtime mode.");
+        #[cfg(windows)]
+        {
+            let Ok(serial) = self.serial_number() else {
+                return Vec::new();
+            };
+            let desc = self.device()
+                .device_descriptor()
+                .expect(libusb_cannot_fail!("libusb_get_device_descriptor()"));
+            let ports = match crate::windows::com_ports(desc.vendor_id(), desc.product_id(), &serial) {
+                Ok(ports) => ports,
+                Err(e) => {
+                    warn!("Failed to look up COM ports: {}", e);
+                    return Vec::new();
+                },
+            };
 
-        match self._handle_mut().release_interface(iface_number) {
-            // Ignore if the device has already disconnected.
-            Err(rusb::Error::NoDevice) => Ok(()),
-            other => other,
-        }?;
+            let configuration = self.config_descriptor().ok();
+            let language = self.string_language().ok();
+            ports
+                .into_iter()
+                .map(|(number, name)| SerialPortInfo {
+                    interface: number,
+                    function: configuration
+                        .as_ref()
+                        .zip(language)
+                        .and_then(|(configuration, language)| {
+                            let desc = configuration
+                                .interfaces()
+                                .flat_map(|interface| interface.descriptors())
+                                .find(|desc| desc.interface_number() == number)?;
+                            self.handle().read_interface_string(language, &desc, self.timeouts.request).ok()
+                        }),
+                    name,
+                })
+                .collect()
+        }
 
+        #[cfg(not(windows))]
+        Vec::new()
+    }
 
-        Ok(())
+    /// Returns the alt-setting of DFU interface `iface_number` to use: the one asked for with `--alt`,
+    /// if any, and otherwise the one that addresses the MCU's internal flash.
+    ///
+    /// Most bootloaders only have the one alt-setting, but some (such as ctxLink's) expose more than one
+    /// memory, and don't necessarily list the internal flash first. Those that do identify their memories
+    /// with DfuSe-style names, e.g. `@Internal Flash  /0x08000000/...`, so we look for that, and fall back
+    /// to the first alt-setting otherwise.
+    fn flash_alt_setting(&self, iface_number: u8) -> Result<u8, Error>
+    {
+        let interfaces = match self.dfu_interfaces() {
+            Ok(interfaces) => interfaces,
+            // Without the descriptors we can't check an index, but we can still trust it.
+            Err(e) => return match &self.alt_setting {
+                None => {
+                    warn!("Failed to read DFU interface alt-settings, assuming the first: {}", e);
+                    Ok(0)
+                },
+                Some(AltSetting::Index(index)) => Ok(*index),
+                Some(AltSetting::Name(_)) => Err(e),
+            },
+        };
+        let mut alt_settings = interfaces
+            .iter()
+            .filter(|interface| interface.number == iface_number);
+
+        let requested = match &self.alt_setting {
+            Some(requested) => requested,
+            None => {
+                let alt_setting = alt_settings
+                    .find(|interface| {
+                        interface.name
+                            .as_deref()
+                            .is_some_and(|name| name.to_ascii_lowercase().starts_with("@internal flash"))
+                    })
+                    .map(|interface| interface.alt_setting)
+                    .unwrap_or(0);
+                return Ok(alt_setting);
+            },
+        };
+
+        alt_settings
+            .find(|interface| requested.matches(interface))
+            .map(|interface| interface.alt_setting)
+            .ok_or_else(|| ErrorKind::UnknownAltSetting(requested.to_string()).error())
     }
 
-    /// Performs a DFU_DETACH request to enter DFU mode.
-    fn enter_dfu_mode(&mut self) -> Result<(), Error>
+    /// Returns the device's active configuration descriptor.
+    fn config_descriptor(&self) -> Result<rusb::ConfigDescriptor, Error>
     {
-        let (iface_number, func_desc) = self.dfu_descriptors()?;
-        self._handle_mut().claim_interface(iface_number)?;
+        let configuration = match self.device().active_config_descriptor() {
+            Ok(d) => d,
+            Err(rusb::Error::NotFound) => {
+                // In the unlikely even that the OS reports the device as unconfigured
+                // (possibly because it was only just connected and is still enumerating?)
+                // try instead to simply get the first configuration, and hope that the
+                // device is configured by the time we try to send requests to it.
+                // I'm not actually sure this case is even possibly on any OS, but might
+                // as well check.
 
-        let request_type = rusb::request_type(
-            Direction::Out,
-            RequestType::Class,
-            Recipient::Interface,
-        );
-        let timeout_ms = func_desc.wDetachTimeOut;
-
-        let _response = self.handle().write_control(
-            request_type, // bmpRequestType
-            DfuRequest::Detach as u8, // bRequest
-            timeout_ms, // wValue
-            iface_number as u16, // wIndex
-            &[], // buffer
-            Duration::from_secs(1), // timeout for libusb
-        )
-        .map_err(Error::from)
-        .map_err(|e| e.with_ctx("sending control request"))?;
+                warn!("OS reports Black Magic Probe device is unconfigured!");
+                warn!("Attempting to continue anyway, in case the device is still in the process of enumerating.");
 
-        info!("DFU_DETACH request completed. Device should now re-enumerate into DFU mode.");
+                // USB configurations are 1-indexed, as 0 is considered
+                // to be "unconfigured".
+                match self.device().config_descriptor(1) {
+                    Ok(d) => d,
+                    Err(e) => {
+                        return Err(
+                            ErrorKind::DeviceSeemsInvalid(
+                                String::from("no configuration descriptor exists")
+                            ).error_from(e)
+                        );
+                    },
+                }
+            },
+            Err(e) => {
+                return Err(e.into());
+            },
+        };
 
-        match self._handle_mut().release_interface(iface_number) {
-            // Ignore if the device has already disconnected.
-            Err(rusb::Error::NoDevice) => Ok(()),
-            other => other,
-        }?;
+        Ok(configuration)
+    }
 
-        Ok(())
+    /// Describes this device, for `bmputil probe list`.
+    ///
+    /// Note: this performs USB IO, and leaves out anything that could not be read.
+    pub fn describe(&self) -> ProbeDescription
+    {
+        let product = self.product_string()
+            .map_err(|e| warn!("Failed to read product string: {}", e))
+            .ok();
+        let identity = product
+            .as_deref()
+            .map(ProbeIdentity::from_product_string)
+            .unwrap_or_default();
+        let desc = self.device()
+            .device_descriptor()
+            .expect(libusb_cannot_fail!("libusb_get_device_descriptor()"));
+        // Only Windows binds drivers by Microsoft OS 2.0 descriptors, so elsewhere they're not worth the
+        // requests (which firmware from before them may not answer at all).
+        #[cfg(windows)]
+        let driverless_dfu = self.driverless_dfu()
+            .map_err(|e| debug!("Failed to read Microsoft OS 2.0 descriptors: {}", e))
+            .ok();
+        #[cfg(not(windows))]
+        let driverless_dfu = None;
+
+        ProbeDescription {
+            serial: self.serial_number()
+                .map(|serial| serial.to_string())
+                .map_err(|e| warn!("Failed to read serial number: {}", e))
+                .ok(),
+            alias: None,
+            hardware: self.hardware.map(|hardware| hardware.to_string()),
+            variant: identity.variant,
+            version: identity.version,
+            product,
+            mode: self.mode,
+            port: self.port(),
+            vid: format!("{:04x}", desc.vendor_id()),
+            pid: format!("{:04x}", desc.product_id()),
+            dfu_interfaces: self.dfu_interfaces()
+                .map_err(|e| warn!("Failed to read DFU interfaces: {}", e))
+                .unwrap_or_default(),
+            serial_ports: self.serial_ports(),
+            driverless_dfu,
+            target_voltage: None,
+            bootloader_version: self.bootloader_version(),
+        }
     }
 
-    /// Requests the Black Magic Probe device to detach, switching from DFU mode to runtime mode or vice versa. You probably want [`detach_and_enumerate`].
+    /// Whether the device's Microsoft OS 2.0 descriptors have Windows bind WinUSB to its DFU interface
+    /// by itself, so that it can be used on Windows without installing a driver (see [`crate::msos`]).
     ///
-    /// This function does not re-enumerate the device and re-initialize this structure, and thus after
-    /// calling this function, the this [`BmpDevice`] instance will not be in a correct state
-    /// if the device successfully detached. Further requests will fail, and functions like
-    /// `dfu_descriptors()` may return now-incorrect data.
-    pub unsafe fn request_detach(&mut self) -> Result<(), Error>
+    /// Note: this performs USB IO.
+    #[cfg(windows)]
+    pub fn driverless_dfu(&self) -> Result<bool, Error>
     {
-        use DfuOperatingMode::*;
-        let res = match self.mode {
-            Runtime => self.enter_dfu_mode(),
-            FirmwareUpgrade => self.leave_dfu_mode(),
-        };
-        match res {
-            Ok(()) => (),
-            Err(e) => return Err(e),
-        };
+        let (iface_number, _) = self.dfu_descriptors()?;
+        let driverless = msos::advertises_winusb(&self.handle(), iface_number, self.timeouts.request)?;
 
-        Ok(())
+        Ok(driverless)
     }
 
-    /// Requests the Black Magic Probe to detach, and re-initializes this struct with the new
-    /// device.
-    pub fn detach_and_enumerate(&mut self) -> Result<(), Error>
+    /// Claims the device's DFU interface (selecting the alt-setting for the internal flash, in DFU mode),
+    /// runs `operation` with it, and releases it again.
+    fn with_dfu_interface<T, F>(&mut self, operation: F) -> Result<T, Error>
+    where
+        F: FnOnce(&DfuInterface) -> Result<T, Error>,
     {
-        // Save the port for finding the device again after.
-        let port = self.port();
+        let (iface_number, func_desc) = self.dfu_descriptors()?;
+        let transfer_size = self.transfer_size(&func_desc)?;
 
-        if cfg!(not(windows)) {
-            unsafe { self.request_detach()? };
-        } else {
-            // HACK: WinUSB seems to have a race condition where it can spuriously give ERROR_GEN_FAILURE
-            // (which becomes LIBUSB_ERROR_PIPE) when a control request results in a device disconnect.
-            use crate::ErrorSource::Libusb;
-            let res = unsafe { self.request_detach() };
-            if let Err(e @ Error { kind: ErrorKind::External(Libusb(rusb::Error::Pipe)), .. }) = res {
-                warn!("Possibly spurious error from Windows when attempting to detach: {}", e);
-            } else {
-                res?;
-            }
+        self.claim_dfu_interface(iface_number)?;
+        if self.mode == DfuOperatingMode::FirmwareUpgrade {
+            let alt_setting = self.flash_alt_setting(iface_number)?;
+            debug!("Using DFU interface {} alt-setting {}", iface_number, alt_setting);
+            self._handle_mut().set_alternate_setting(iface_number, alt_setting)?;
         }
 
-        // Now drop the device so libusb doesn't re-grab the same thing.
-        drop(self.device.take());
-        drop(self.handle.take());
-
-        // TODO: make this sleep() timeout configurable?
-        thread::sleep(Duration::from_millis(500));
+        let res = {
+            let handle = self.handle();
+            let dfu = DfuInterface::new(
+                &handle,
+                iface_number,
+                func_desc,
+                transfer_size,
+                self.retry,
+                self.timeouts.request,
+            );
+            operation(&dfu)
+        };
+        // Requests to the interface can fail for want of a driver even once it's been claimed.
+        let res = res.map_err(|e| self.explain_driver_error(iface_number, e));
 
-        // Now try to find the device again on that same port.
-        let dev = wait_for_probe_reboot(&port, Duration::from_secs(5), "flash")?;
+        match self._handle_mut().release_interface(iface_number) {
+            // Ignore if the device has already disconnected, e.g. after being told to reboot.
+            Ok(()) | Err(rusb::Error::NoDevice) => (),
+            Err(e) if res.is_ok() => return Err(e.into()),
+            Err(e) => warn!("Failed to release the DFU interface: {}", e),
+        };
 
-        // If we've made it here, then we have successfully re-found the device.
-        // Re-initialize this structure from the new data.
-        *self = dev;
+        res
+    }
+
+    /// Claims DFU interface `iface_number`, first getting the OS's own driver to let go of it if it has
+    /// it and we can.
+    ///
+    /// On macOS, the system's drivers can hold on to a probe's interfaces. Detaching them means libusb
+    /// capturing the device, which re-enumerates it (and can leave our handle stale, in which case we
+    /// open it again), and which macOS only allows root to do. Capture ends when the device is closed,
+    /// which gives the system's drivers the device back, so there's nothing to reattach.
+    fn claim_dfu_interface(&mut self, iface_number: u8) -> Result<(), Error>
+    {
+        let claim_error = match self._handle_mut().claim_interface(iface_number) {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        if cfg!(windows) {
+            return Err(self.explain_driver_error(iface_number, claim_error.into()));
+        }
+        if !cfg!(target_os = "macos") || !matches!(claim_error, rusb::Error::Access | rusb::Error::Busy) {
+            return Err(claim_error.into());
+        }
+
+        debug!("Failed to claim interface {} ({}); detaching the system's driver from it", iface_number, claim_error);
+        let res = self._handle_mut().detach_kernel_driver(iface_number);
+        match res {
+            // NotFound means there was no driver to detach after all.
+            Ok(()) | Err(rusb::Error::NotFound) => (),
+            Err(e @ rusb::Error::Access) => return Err(ErrorKind::KernelDriverActive(iface_number).error_from(e)),
+            Err(e) => {
+                debug!("Failed to detach the system's driver: {}", e);
+                return Err(claim_error.into());
+            },
+        };
+
+        let res = self._handle_mut().claim_interface(iface_number);
+        match res {
+            Ok(()) => Ok(()),
+            Err(rusb::Error::NoDevice | rusb::Error::NotFound | rusb::Error::Io) => {
+                debug!("Device re-enumerated when captured; opening it again");
+                self.reopen()?;
+                self._handle_mut().claim_interface(iface_number)?;
+                Ok(())
+            },
+            Err(rusb::Error::Access | rusb::Error::Busy) => {
+                Err(ErrorKind::KernelDriverActive(iface_number).error_from(claim_error))
+            },
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Works out whether `error`, from using DFU interface `iface_number`, is down to Windows not having
+    /// WinUSB bound to the interface, and if so, replaces it with an error saying which driver it does
+    /// have, so users aren't left with libusb's `NotSupported`.
+    #[cfg(windows)]
+    fn explain_driver_error(&self, iface_number: u8, error: Error) -> Error
+    {
+        use rusb::Error::{Access, NotFound, NotSupported};
+
+        if !matches!(error.kind, ErrorKind::External(ErrorSource::Libusb(NotSupported | NotFound | Access))) {
+            return error;
+        }
+
+        let desc = self.device()
+            .device_descriptor()
+            .expect(libusb_cannot_fail!("libusb_get_device_descriptor()"));
+        // Only composite devices have a devnode per interface.
+        let interface = match self.config_descriptor() {
+            Ok(configuration) if configuration.num_interfaces() > 1 => Some(iface_number),
+            Ok(_) => None,
+            Err(_) => return error,
+        };
+        let serial = match self.serial_number() {
+            Ok(serial) => serial.to_string(),
+            Err(_) => return error,
+        };
+
+        match crate::windows::bound_driver(desc.vendor_id(), desc.product_id(), &serial, interface) {
+            Ok(Some(driver)) if driver.eq_ignore_ascii_case("WinUSB") => error,
+            Ok(driver) => {
+                debug!("DFU interface {} is bound to {:?}", iface_number, driver);
+                // Firmware that asks for WinUSB itself shouldn't need it installing, unless Windows
+                // has kept what it learnt of the probe from before it did.
+                let driverless = self.driverless_dfu().unwrap_or(false);
+                ErrorKind::WrongDriver(iface_number, driver, driverless).error_from(error)
+            },
+            Err(e) => {
+                debug!("Couldn't find out which driver DFU interface {} is bound to: {}", iface_number, e);
+                error
+            },
+        }
+    }
+
+    #[cfg(not(windows))]
+    fn explain_driver_error(&self, _iface_number: u8, error: Error) -> Error
+    {
+        error
+    }
+
+    /// Opens the device on this one's port again, for after it re-enumerates without changing mode.
+    fn reopen(&mut self) -> Result<(), Error>
+    {
+        let port = self.port();
+        let context = self.device().context().clone();
+        let start = Instant::now();
+
+        loop {
+            let device = context.devices()?.iter().find(|device| port_path(device) == port);
+            let res = match device {
+                Some(device) => device.open().map(|handle| (device, handle)).map_err(Error::from),
+                None => Err(ErrorKind::DeviceReboot.error()),
+            };
+            match res {
+                Ok((device, handle)) => {
+                    *self.device.borrow_mut() = Some(device);
+                    *self.handle.borrow_mut() = Some(handle);
+                    return Ok(());
+                },
+                Err(e) if start.elapsed() > self.timeouts.reenumerate => return Err(e),
+                Err(_) => thread::sleep(Duration::from_millis(100)),
+            }
+        }
+    }
+
+    /// Requests the device to leave DFU mode, by telling it the (empty) download is complete.
+    fn leave_dfu_mode(&mut self) -> Result<(), Error>
+    {
+        debug!("Attempting to leave DFU mode...");
+        self.with_dfu_interface(|dfu| dfu.leave())?;
+        info!("Device should now re-enumerate into runtime mode.");
+
+        Ok(())
+    }
+
+    /// Reads `length` bytes of flash back from the device, starting at `address`, with DFU_UPLOAD
+    /// requests. The device must already be in DFU mode.
+    ///
+    /// DfuSe bootloaders are told the address to read from first; plain DFU ones have no notion of
+    /// addresses, and always read from the start of the region they would flash.
+    ///
+    /// Returns [`ErrorKind::UploadUnsupported`] if the device's bootloader can't read flash back.
+    pub fn upload(&mut self, address: u32, length: u32) -> Result<Vec<u8>, Error>
+    {
+        self.with_dfu_interface(|dfu| {
+            if !dfu.can_upload() {
+                return Err(ErrorKind::UploadUnsupported.error());
+            }
+            dfu.upload(address, length)
+        })
+    }
+
+    /// Reads back `length` bytes of the firmware currently installed where firmware of `firmware_type`
+    /// goes, switching into DFU mode automatically if necessary. The device is left in DFU mode.
+    fn read_back(&mut self, firmware_type: FirmwareType, length: u32) -> Result<Vec<u8>, Error>
+    {
+        if self.mode == DfuOperatingMode::Runtime {
+            self.detach_and_enumerate()
+                .map_err(|e| e.with_ctx("detaching device to read flash"))?;
+        }
+
+        self.upload(self.load_address(firmware_type), length)
+    }
+
+    /// The size of the whole flash region firmware of `firmware_type` goes in, if we know what
+    /// hardware this is.
+    pub fn region_length(&self, firmware_type: FirmwareType) -> Option<u32>
+    {
+        let region_end = match firmware_type {
+            FirmwareType::Bootloader => self.load_address(FirmwareType::Application),
+            FirmwareType::Application => FLASH_BASE + self.hardware?.flash_size(),
+        };
+
+        Some(region_end.saturating_sub(self.load_address(firmware_type)))
+    }
+
+    /// Erases the whole of the flash with the DfuSe mass erase command, switching into DFU mode automatically
+    /// if necessary. The device is left in DFU mode.
+    ///
+    /// This takes the bootloader with it, so only the STM32s' built-in bootloader can do it.
+    pub fn mass_erase(&mut self) -> Result<(), Error>
+    {
+        if self.platform != BmpPlatform::STM32DeviceDFU {
+            return Err(ErrorKind::Unsupported(S!(
+                "flash can only be mass erased through the STM32's built-in DFU bootloader; \
+                hold the probe's BOOT0 button (or jumper) while plugging it in, then try again"
+            )).error());
+        }
+
+        if self.mode == DfuOperatingMode::Runtime {
+            self.detach_and_enumerate()
+                .map_err(|e| e.with_ctx("detaching device to erase flash"))?;
+        }
+
+        self.with_dfu_interface(|dfu| {
+            let commands = dfu.dfuse_supported_commands()?;
+            debug!("Supported DfuSe commands: {:02x?}", commands);
+            if !commands.contains(&dfu::DFUSE_ERASE) {
+                return Err(ErrorKind::Unsupported(S!("this bootloader does not support erasing flash")).error());
+            }
+
+            dfu.ensure_idle()?;
+            dfu.dfuse_mass_erase()?;
+            dfu.abort()
+        })
+    }
+
+    /// Removes the MCU's read protection (RDP) through the STM32's built-in DFU bootloader, which mass erases
+    /// the whole of the flash, bootloader included, and then resets the MCU. The device has to be found
+    /// again afterwards.
+    pub fn read_unprotect(&mut self) -> Result<(), Error>
+    {
+        if self.platform != BmpPlatform::STM32DeviceDFU {
+            return Err(ErrorKind::Unsupported(S!(
+                "read protection can only be removed through the STM32's built-in DFU bootloader; \
+                hold the probe's BOOT0 button (or jumper) while plugging it in, then try again"
+            )).error());
+        }
+
+        self.with_dfu_interface(|dfu| {
+            let commands = dfu.dfuse_supported_commands()?;
+            debug!("Supported DfuSe commands: {:02x?}", commands);
+            if !commands.contains(&dfu::DFUSE_READ_UNPROTECT) {
+                return Err(ErrorKind::Unsupported(S!("this bootloader does not support removing read protection")).error());
+            }
+
+            dfu.ensure_idle()?;
+            dfu.dfuse_read_unprotect()
+        })
+    }
+
+    /// Rewrites the bootloader, and optionally the application firmware at the given address, through
+    /// the STM32's built-in DFU bootloader, for probes whose own bootloader has been damaged, and then
+    /// has the probe start the new bootloader.
+    ///
+    /// Everything is written before the device is asked to manifest, as the built-in bootloader starts
+    /// what's in flash as soon as it's done.
+    pub fn recover<P>(&mut self, bootloader: &[u8], application: Option<(u32, &[u8])>, progress: P) -> Result<(), Error>
+    where
+        P: Fn(usize),
+    {
+        if self.platform != BmpPlatform::STM32DeviceDFU {
+            return Err(ErrorKind::Unsupported(S!(
+                "a probe can only be recovered through the STM32's built-in DFU bootloader; \
+                hold the probe's BOOT0 button (or jumper) while plugging it in, then try again"
+            )).error());
+        }
+
+        // A bootloader running into where the application goes would be overwritten by it, or worse, the
+        // application by it. Nor can the application go anywhere before the flash.
+        if let Some((address, _firmware)) = application {
+            let offset = address.checked_sub(FLASH_BASE);
+            if offset.is_none_or(|offset| bootloader.len() > offset as usize) {
+                return Err(ErrorKind::InvalidFirmware(Some(format!(
+                    "bootloader image is {} bytes, but the application starts at 0x{:08x}, before 0x{:08x}",
+                    bootloader.len(),
+                    address,
+                    FLASH_BASE as usize + bootloader.len(),
+                ))).error());
+            }
+        }
+
+        let too_big = || ErrorKind::InvalidFirmware(Some(S!("image runs past the end of the address space"))).error();
+        let end = match application {
+            Some((address, firmware)) => u32::try_from(firmware.len())
+                .ok()
+                .and_then(|length| address.checked_add(length))
+                .ok_or_else(too_big)?,
+            None => u32::try_from(bootloader.len())
+                .ok()
+                .and_then(|length| FLASH_BASE.checked_add(length))
+                .ok_or_else(too_big)?,
+        };
+        let length = end.checked_sub(FLASH_BASE).ok_or_else(too_big)?;
+        let pages = self.flash_pages(FLASH_BASE, length);
+
+        self.with_dfu_interface(|dfu| {
+            status!("Erasing flash...");
+            dfu.download(FLASH_BASE, bootloader, 0, &pages, &progress)?;
+            if let Some((address, firmware)) = application {
+                dfu.download(address, firmware, 0, &pages, &progress)?;
+            }
+
+            // The built-in bootloader starts whatever the address pointer points at when it leaves DFU mode.
+            dfu.dfuse_set_address(FLASH_BASE)?;
+            dfu.manifest()
+                .map_err(|e| ErrorKind::DeviceReboot.error_from(e))
+        })
+    }
+
+    /// Erases the whole flash region firmware of `firmware_type` goes in, a page or sector at a time,
+    /// switching into DFU mode automatically if necessary. The device is left in DFU mode.
+    ///
+    /// This needs a DfuSe bootloader, as plain DFU has no notion of erasing, and knowing what
+    /// hardware this is, to know how big the region is.
+    pub fn erase_region(&mut self, firmware_type: FirmwareType) -> Result<(), Error>
+    {
+        let length = self.region_length(firmware_type)
+            .ok_or_else(|| ErrorKind::Unsupported(S!("cannot erase the flash of unknown probe hardware")).error())?;
+
+        if self.mode == DfuOperatingMode::Runtime {
+            self.detach_and_enumerate()
+                .map_err(|e| e.with_ctx("detaching device to erase flash"))?;
+        }
+
+        self.erase_flash(self.load_address(firmware_type), length)
+    }
+
+    /// Reads back the whole flash region firmware of `firmware_type` goes in, switching into DFU mode
+    /// automatically if necessary. The device is left in DFU mode.
+    pub fn read_region(&mut self, firmware_type: FirmwareType) -> Result<Vec<u8>, Error>
+    {
+        let length = match self.region_length(firmware_type) {
+            Some(length) => length,
+            None => {
+                warn!("Unknown probe hardware; assuming it has 128 KiB of flash");
+                (FLASH_BASE + 128 * 1024).saturating_sub(self.load_address(firmware_type))
+            },
+        };
+
+        self.read_back(firmware_type, length)
+    }
+
+    /// Reads back the whole of the firmware currently installed where firmware of `firmware_type` goes,
+    /// for backing it up, switching into DFU mode automatically if necessary. The device is left in
+    /// DFU mode.
+    ///
+    /// Trailing erased (`0xff`) flash is trimmed off.
+    pub fn read_firmware(&mut self, firmware_type: FirmwareType) -> Result<Vec<u8>, Error>
+    {
+        let mut firmware = self.read_region(firmware_type)?;
+        let used = firmware.iter().rposition(|&byte| byte != 0xff).map_or(0, |last| last + 1);
+        firmware.truncate(used);
+
+        Ok(firmware)
+    }
+
+    /// Reads the flash back from the device and checks it matches `firmware`, switching into DFU mode
+    /// automatically if necessary. The device is left in DFU mode either way.
+    ///
+    /// If the device's bootloader can't read flash back, this warns and skips the check.
+    pub fn verify(&mut self, firmware: &[u8], firmware_type: FirmwareType) -> Result<(), Error>
+    {
+        let length = u32::try_from(firmware.len())
+            .expect("firmware filesize exceeded 32 bits! Firmware binary must be invalid");
+        let flashed = match self.read_back(firmware_type, length) {
+            Err(e) if matches!(e.kind, ErrorKind::UploadUnsupported) => {
+                warn!("This probe's bootloader does not support reading flash back; skipping verification");
+                return Ok(());
+            },
+            other => other?,
+        };
+
+        let mismatch = firmware
+            .iter()
+            .zip(flashed.iter())
+            .position(|(expected, actual)| expected != actual)
+            .or_else(|| (flashed.len() < firmware.len()).then_some(flashed.len()));
+
+        match mismatch {
+            Some(offset) => Err(ErrorKind::VerificationFailed(offset).error()),
+            None => Ok(()),
+        }
+    }
+
+    /// Performs a DFU_DETACH request to enter DFU mode.
+    fn enter_dfu_mode(&mut self) -> Result<(), Error>
+    {
+        let hardware = self.hardware;
+        let detach_timeout = self.timeouts.detach;
+        let will_detach = self.with_dfu_interface(|dfu| {
+            let timeout = detach_timeout
+                .map_or(dfu.detach_timeout(), |timeout| u16::try_from(timeout.as_millis()).unwrap_or(u16::MAX));
+            dfu.detach(timeout)?;
+            Ok(dfu.will_detach())
+        })
+            .map_err(|e| e.with_ctx("sending control request"))?;
+
+        info!("DFU_DETACH request completed. Device should now re-enumerate into DFU mode.");
+
+        // Devices that don't say they'll detach by themselves wait for a USB reset, as do some
+        // hardware that can't disconnect itself from the bus no matter what it says.
+        let hardware_needs_reset = hardware.is_some_and(|hardware| hardware.needs_reset_after_detach());
+        if !will_detach || hardware_needs_reset {
+            debug!("Resetting device so it re-enumerates into DFU mode");
+            match self._handle_mut().reset() {
+                // The device may well have gone away on its own already.
+                Ok(()) | Err(rusb::Error::NoDevice) | Err(rusb::Error::NotFound) => (),
+                Err(e) => warn!("Failed to reset device after DFU_DETACH: {}", e),
+            };
+        }
+
+        Ok(())
+    }
+
+    /// Requests the Black Magic Probe device to detach, switching from DFU mode to runtime mode or vice versa. You probably want [`detach_and_enumerate`].
+    ///
+    /// This function does not re-enumerate the device and re-initialize this structure, and thus after
+    /// calling this function, the this [`BmpDevice`] instance will not be in a correct state
+    /// if the device successfully detached. Further requests will fail, and functions like
+    /// `dfu_descriptors()` may return now-incorrect data.
+    pub unsafe fn request_detach(&mut self) -> Result<(), Error>
+    {
+        use DfuOperatingMode::*;
+        let res = match self.mode {
+            Runtime => self.enter_dfu_mode(),
+            FirmwareUpgrade => self.leave_dfu_mode(),
+        };
+        match res {
+            Ok(()) => (),
+            Err(e) => return Err(e),
+        };
+
+        Ok(())
+    }
+
+    /// Requests the Black Magic Probe to detach, and re-initializes this struct with the new
+    /// device.
+    #[instrument(level = "debug", name = "dfu_switch", skip_all, fields(port = %self.port()))]
+    pub fn detach_and_enumerate(&mut self) -> Result<(), Error>
+    {
+        // Save the port for finding the device again after, and the hardware in case the bootloader
+        // doesn't tell us what it is.
+        let port = self.port();
+        let hardware = self.hardware;
+        let transfer_size = self.transfer_size;
+        let retry = self.retry;
+        let alt_setting = self.alt_setting.clone();
+        let timeouts = self.timeouts;
+        // The bootloader's version is remembered by the probe's serial number in runtime mode, which is
+        // what it's looked up by, and may not be the same as in DFU mode.
+        let was_runtime = self.mode == DfuOperatingMode::Runtime;
+        let serial = self.serial_number().ok().map(|serial| serial.to_string());
+        let bootloader_version = (!was_runtime).then(|| self.dfu_bootloader_version());
+
+        if cfg!(not(windows)) {
+            unsafe { self.request_detach()? };
+        } else {
+            // HACK: WinUSB seems to have a race condition where it can spuriously give ERROR_GEN_FAILURE
+            // (which becomes LIBUSB_ERROR_PIPE) when a control request results in a device disconnect.
+            use crate::ErrorSource::Libusb;
+            let res = unsafe { self.request_detach() };
+            if let Err(e @ Error { kind: ErrorKind::External(Libusb(rusb::Error::Pipe)), .. }) = res {
+                warn!("Possibly spurious error from Windows when attempting to detach: {}", e);
+            } else {
+                res?;
+            }
+        }
+
+        // Now drop the device so libusb doesn't re-grab the same thing.
+        drop(self.device.take());
+        drop(self.handle.take());
+
+        thread::sleep(timeouts.detach_wait());
+
+        // Now try to find the device again on that same port.
+        let dev = wait_for_probe_reboot(&port, self.custom_ids, timeouts.reenumerate, "flash")?;
+
+        // If we've made it here, then we have successfully re-found the device.
+        // Re-initialize this structure from the new data.
+        *self = dev;
+        if self.hardware.is_none() {
+            self.hardware = hardware;
+        }
+        self.transfer_size = transfer_size;
+        self.retry = retry;
+        self.alt_setting = alt_setting;
+        self.timeouts = timeouts;
+
+        // Remember the bootloader's version while we can see it, for when the probe is running its firmware.
+        let remembered = match self.mode {
+            DfuOperatingMode::FirmwareUpgrade if was_runtime => serial.map(|serial| (serial, self.dfu_bootloader_version())),
+            DfuOperatingMode::Runtime if !was_runtime => self.serial_number()
+                .ok()
+                .map(|serial| serial.to_string())
+                .zip(bootloader_version),
+            _ => None,
+        };
+        if let Some((serial, version)) = remembered {
+            state::set_bootloader_version(&serial, &version);
+        }
 
         Ok(())
     }
@@ -469,139 +1254,784 @@ impl BmpDevice
         Ok(())
     }
 
-    fn try_download<'r, R, C>(&mut self, firmware: &'r R, length: u32, dfu_dev: &mut dfu_libusb::Dfu<C>) ->
-        Result<(), Error>
-    where
-        &'r R: Read,
-        R: ?Sized,
-        C: UsbContext,
-    {
-        match dfu_dev.download(firmware, length) {
-            Ok(_) => if dfu_dev.will_detach() {
-            match dfu_dev.detach() {
-                    Err(source) => Err(ErrorKind::DeviceReboot.error_from(source)),
-                    _ => Ok(()),
-                }
-            } else {
-                Ok(())
-            },
-            Err(source) => Err(match source {
-                dfu_libusb::Error::LibUsb(rusb::Error::NoDevice) => {
-                    error!("Black Magic Probe device disconnected during the flash process!");
-                    warn!(
-                        "If the device now fails to enumerate, try holding down the button while plugging the device in order to enter the bootloader."
-                    );
-                    ErrorKind::DeviceDisconnectDuringOperation.error_from(source)
-                }
-                _ => source.into(),
-            })
+    /// Checks firmware of `length` bytes will fit in the flash available for firmware of `firmware_type`,
+    /// if we know what hardware this is.
+    pub fn check_fits(&self, length: u32, firmware_type: FirmwareType) -> Result<(), Error>
+    {
+        if let Some(hardware) = self.hardware {
+            let available = hardware.flash_size() - (self.load_address(firmware_type) - FLASH_BASE);
+            if length > available {
+                return Err(ErrorKind::InvalidFirmware(Some(format!(
+                    "firmware is {} bytes but {} hardware only has {} bytes of flash available for it",
+                    length,
+                    hardware,
+                    available,
+                ))).error());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Downloads firmware onto the device, switching into DFU mode automatically if necessary, and then
+    /// has it reboot into the new firmware.
+    ///
+    /// `progress` is a callback of the form `fn(just_written: usize)`, for callers to keep track of
+    /// the flashing process.
+    ///
+    /// If `resume_from` isn't 0, an earlier download of the same firmware was interrupted after that
+    /// many bytes, and this carries on from there if it can (see [`Self::write_flash()`]).
+    ///
+    /// Returns whether the device is still in DFU mode, as manifestation-tolerant devices are, rather
+    /// than rebooting.
+    #[instrument(
+        level = "debug",
+        name = "flash",
+        skip_all,
+        fields(%firmware_type, bytes = firmware.len(), resume_from),
+    )]
+    pub fn download<P>(
+        &mut self,
+        firmware: &[u8],
+        firmware_type: FirmwareType,
+        resume_from: usize,
+        progress: P,
+    ) -> Result<bool, Error>
+    where
+        P: Fn(usize),
+    {
+        let length = u32::try_from(firmware.len())
+            .expect("firmware filesize exceeded 32 bits! Firmware binary must be invalid");
+
+        // Check the firmware will actually fit before we touch the device at all.
+        self.check_fits(length, firmware_type)?;
+
+        if self.mode == DfuOperatingMode::Runtime {
+            self.detach_and_enumerate()
+                .map_err(|e| e.with_ctx("detaching device for download"))?;
+        }
+
+        // The load address can depend on which bootloader we're now talking to.
+        self.write_flash(self.load_address(firmware_type), firmware, resume_from, progress)
+    }
+
+    /// Writes `data` to the flash at `address`, switching into DFU mode automatically if necessary,
+    /// and then has the device manifest it.
+    ///
+    /// If `resume_from` isn't 0, an earlier write of `data` was interrupted after that many bytes.
+    /// If the device can be told where to carry on from, and what was written reads back correctly,
+    /// this starts again from the beginning of the page that was being written, calling `progress`
+    /// for what's skipped; otherwise it starts over.
+    ///
+    /// Manifestation-tolerant devices stay in DFU mode afterwards, ready for more requests, which is
+    /// what's returned; others reboot.
+    ///
+    /// `progress` is only called for bytes past the furthest the device has acknowledged, so if the
+    /// write is retried from the start, what it's given still adds up to how much has been written.
+    ///
+    /// Writing anywhere other than where the bootloader would put application firmware needs a DfuSe
+    /// bootloader; plain DFU ones ignore the address.
+    pub fn write_flash<P>(&mut self, address: u32, data: &[u8], resume_from: usize, progress: P) -> Result<bool, Error>
+    where
+        P: Fn(usize),
+    {
+        let length = u32::try_from(data.len())
+            .expect("data length exceeded 32 bits!");
+
+        if self.mode == DfuOperatingMode::Runtime {
+            self.detach_and_enumerate()
+                .map_err(|e| e.with_ctx("detaching device for download"))?;
+        }
+
+        let pages = self.flash_pages(address, length);
+        debug!("Load address: 0x{:08x}", address);
+
+        let resume_from = if resume_from > 0 {
+            self.resume_point(address, data, resume_from, &pages)
+        } else {
+            0
+        };
+        if resume_from > 0 {
+            status!("Resuming from byte offset {} (0x{:x})", resume_from, resume_from);
+            progress(resume_from);
+        }
+
+        // Retrying sends blocks the device has already acknowledged again, so only pass on progress
+        // past the furthest it's got.
+        let acknowledged = Cell::new(resume_from);
+        let sent = Cell::new(resume_from);
+        let progress = |just_written: usize| {
+            sent.set(sent.get() + just_written);
+            if sent.get() > acknowledged.get() {
+                progress(sent.get() - acknowledged.get());
+                acknowledged.set(sent.get());
+            }
+        };
+        let download = |dfu: &DfuInterface| {
+            sent.set(resume_from);
+            dfu.download(address, data, resume_from, &pages, progress)
+        };
+
+        let retry = self.retry;
+        let settle = self.timeouts.settle();
+        let res = self.with_dfu_interface(|dfu| {
+            debug!("Using a transfer size of {} bytes", dfu.transfer_size());
+            if dfu.is_dfuse() {
+                status!("Erasing flash...");
+            }
+            info!("Performing flash...");
+
+            // The whole download can be started over from scratch, so glitches part way through are
+            // just retried along with everything else.
+            let res = retry.run("flashing", || download(dfu));
+            if let Err(ErrorKind::DfuStatus(..)) = res.err_kind() {
+                warn!("Device reported an error when trying to flash; going to clear status and try one more time...");
+                thread::sleep(settle);
+                dfu.clear_status()?;
+                download(dfu)?;
+            } else {
+                res?;
+            }
+
+            dfu.manifest()
+                .map_err(|e| ErrorKind::DeviceReboot.error_from(e))?;
+            Ok(dfu.is_manifestation_tolerant())
+        });
+
+        let in_dfu_mode = match res {
+            Err(e) if dfu::is_disconnect(&e) => {
+                error!("Black Magic Probe device disconnected during the flash process!");
+                warn!(
+                    "If the device now fails to enumerate, try holding down the button while plugging the device in order to enter the bootloader."
+                );
+                return Err(ErrorKind::DeviceDisconnectDuringOperation.error_from(e));
+            },
+            other => other?,
+        };
+
+        info!("Flash complete!");
+
+        Ok(in_dfu_mode)
+    }
+
+    /// Works out where a write of `data` to `address` that was interrupted after `written` bytes can
+    /// safely carry on from: the start of the page it was interrupted in, so long as the device can
+    /// be told to carry on from there (DfuSe), and everything before it reads back correctly.
+    /// Otherwise, it has to start over from 0.
+    fn resume_point(&mut self, address: u32, data: &[u8], written: usize, pages: &[(u32, u32)]) -> usize
+    {
+        let is_dfuse = self.dfu_descriptors()
+            .is_ok_and(|(_iface_number, func_desc)| func_desc.bcdDFUVersion == dfu::DFUSE_VERSION);
+        if !is_dfuse {
+            warn!("This probe's bootloader can't carry on from part way through; starting over");
+            return 0;
+        }
+
+        // Everything in the page being written when we were interrupted needs writing again, as it
+        // gets erased again.
+        let interrupted_at = address + written.min(data.len()) as u32;
+        let resume_from = pages
+            .iter()
+            .find(|&&(page_address, page_size)| page_address <= interrupted_at && interrupted_at < page_address + page_size)
+            .map_or(0, |&(page_address, _page_size)| page_address.saturating_sub(address) as usize);
+        if resume_from == 0 {
+            return 0;
+        }
+
+        status!("Checking the {} bytes written last time...", resume_from);
+        match self.upload(address, resume_from as u32) {
+            Ok(written) if written[..] == data[..resume_from] => resume_from,
+            Ok(_) => {
+                warn!("What was written last time doesn't match the firmware; starting over");
+                0
+            },
+            Err(e) => {
+                warn!("Couldn't check what was written last time ({}); starting over", e);
+                0
+            },
+        }
+    }
+
+    /// Erases the pages (or sectors) of flash covering `length` bytes from `address`, switching into
+    /// DFU mode automatically if necessary. The device is left in DFU mode.
+    ///
+    /// This needs a DfuSe bootloader, as plain DFU has no notion of erasing.
+    pub fn erase_flash(&mut self, address: u32, length: u32) -> Result<(), Error>
+    {
+        if self.mode == DfuOperatingMode::Runtime {
+            self.detach_and_enumerate()
+                .map_err(|e| e.with_ctx("detaching device to erase flash"))?;
+        }
+
+        let end = address.saturating_add(length);
+        let pages: Vec<u32> = self.flash_pages(address, length)
+            .into_iter()
+            .filter(|&(page_address, page_size)| page_address < end && page_address + page_size > address)
+            .map(|(page_address, _page_size)| page_address)
+            .collect();
+
+        self.with_dfu_interface(|dfu| {
+            dfu.ensure_idle()?;
+            for page_address in pages {
+                dfu.dfuse_erase(page_address)?;
+            }
+            dfu.abort()
+        })
+    }
+
+    /// Finds where the device's bootloader keeps the probe's serial number, for bootloaders that have
+    /// somewhere: an alt-setting with a DfuSe-style name starting [`SERIAL_AREA_NAME`], e.g.
+    /// `@Serial Number /0x0801fc00/01*032 g`. Returns the alt-setting, and the area's address and length.
+    ///
+    /// The device must be in DFU mode, as runtime firmware only has the one alt-setting, to detach with.
+    ///
+    /// Note: this performs USB IO to read the alt-settings' names.
+    pub fn serial_area(&self) -> Result<Option<(u8, u32, u32)>, Error>
+    {
+        self.memory_area(SERIAL_AREA_NAME)
+    }
+
+    /// Finds the area of memory the device's bootloader exposes with an alt-setting whose DfuSe-style name
+    /// starts `prefix` (lower-cased). Returns the alt-setting, and the area's address and length.
+    fn memory_area(&self, prefix: &str) -> Result<Option<(u8, u32, u32)>, Error>
+    {
+        let (iface_number, _func_desc) = self.dfu_descriptors()?;
+        let area = self.dfu_interfaces()?
+            .into_iter()
+            .filter(|interface| interface.number == iface_number)
+            .find_map(|interface| {
+                let name = interface.name?;
+                if !name.to_ascii_lowercase().starts_with(prefix) {
+                    return None;
+                }
+                let pages = dfu::parse_memory_layout(&name)?;
+                let length = pages.iter().map(|&(_page_address, page_size)| page_size).sum();
+                Some((interface.alt_setting, pages[0].0, length))
+            });
+
+        Ok(area)
+    }
+
+    /// Reads the MCU's option bytes, through the alt-setting the STM32 built-in bootloader exposes them with
+    /// (see [`OPTION_BYTES_AREA_NAME`]). Returns the option bytes' address, and the bytes.
+    ///
+    /// Returns [`ErrorKind::Unsupported`] unless the device is in the STM32's own bootloader, as the probe's
+    /// firmware and bootloader don't expose them.
+    pub fn read_option_bytes(&mut self) -> Result<(u32, Vec<u8>), Error>
+    {
+        if self.platform != BmpPlatform::STM32DeviceDFU {
+            return Err(ErrorKind::Unsupported(S!(
+                "only the STM32's built-in DFU bootloader gives access to the option bytes; \
+                hold the probe's BOOT0 button (or jumper) while plugging it in, then try again"
+            )).error());
+        }
+
+        let (alt_setting, address, length) = self.memory_area(OPTION_BYTES_AREA_NAME)?
+            .ok_or_else(|| ErrorKind::Unsupported(S!("this bootloader does not give access to the option bytes")).error())?;
+
+        // Everything that addresses flash goes by the alt-setting we're set to use.
+        let previous = self.alt_setting.replace(AltSetting::Index(alt_setting));
+        let res = self.upload(address, length);
+        self.alt_setting = previous;
+
+        res.map(|bytes| (address, bytes))
+    }
+
+    /// Programs `serial` into the area the device's bootloader keeps for the probe's serial number (see
+    /// [`serial_area()`](Self::serial_area)), switching into DFU mode automatically if necessary, and
+    /// reads it back to check it was written. It's stored as ASCII, followed by a NUL.
+    ///
+    /// Returns [`ErrorKind::Unsupported`] if the bootloader has nowhere to keep a serial number, as is
+    /// the case for probes whose serial number is their MCU's unique ID, leaving the device in DFU mode.
+    /// Otherwise, returns whether the device is still in DFU mode, as for [`write_flash()`](Self::write_flash).
+    pub fn program_serial(&mut self, serial: &str) -> Result<bool, Error>
+    {
+        if self.mode == DfuOperatingMode::Runtime {
+            self.detach_and_enumerate()
+                .map_err(|e| e.with_ctx("detaching device to program its serial number"))?;
+        }
+
+        let (alt_setting, address, length) = self.serial_area()?
+            .ok_or_else(|| ErrorKind::Unsupported(S!(
+                "this probe's bootloader has nowhere to program a serial number; \
+                its serial number comes from its MCU's unique ID"
+            )).error())?;
+        let mut data = serial.as_bytes().to_vec();
+        data.push(0);
+        if data.len() > length as usize {
+            return Err(ErrorKind::Unsupported(format!(
+                "this probe's serial number can be at most {} characters long",
+                length.saturating_sub(1),
+            )).error());
+        }
+
+        // Everything that addresses flash goes by the alt-setting we're set to use.
+        let previous = self.alt_setting.replace(AltSetting::Index(alt_setting));
+        let pages = self.flash_pages(address, data.len() as u32);
+        let res = self.with_dfu_interface(|dfu| {
+            dfu.download(address, &data, 0, &pages, |_written| ())?;
+
+            if dfu.can_upload() {
+                let written = dfu.upload(address, data.len() as u32)?;
+                if let Some(offset) = data.iter().zip(&written).position(|(expected, actual)| expected != actual) {
+                    return Err(ErrorKind::VerificationFailed(offset).error());
+                }
+                if written.len() < data.len() {
+                    return Err(ErrorKind::VerificationFailed(written.len()).error());
+                }
+            } else {
+                warn!("This probe's bootloader does not support reading flash back; skipping verification");
+            }
+
+            dfu.manifest()
+                .map_err(|e| ErrorKind::DeviceReboot.error_from(e))?;
+            Ok(dfu.is_manifestation_tolerant())
+        });
+        self.alt_setting = previous;
+
+        res
+    }
+
+    /// The pages (or sectors) of flash a DfuSe bootloader should erase to write `length` bytes at
+    /// `address`, each given as its address and size.
+    ///
+    /// DfuSe bootloaders describe their flash's layout in the flash alt-setting's name, which is the
+    /// best source; failing that, we go by what we know of the hardware.
+    fn flash_pages(&self, address: u32, length: u32) -> Vec<(u32, u32)>
+    {
+        let layout = self.dfu_descriptors()
+            .ok()
+            .and_then(|(iface_number, _func_desc)| {
+                let alt_setting = self.flash_alt_setting(iface_number).ok()?;
+                self.dfu_interfaces()
+                    .ok()?
+                    .into_iter()
+                    .find(|interface| interface.number == iface_number && interface.alt_setting == alt_setting)?
+                    .name
+            })
+            .and_then(|name| dfu::parse_memory_layout(&name));
+
+        match (layout, self.hardware) {
+            (Some(layout), _) => layout,
+            (None, Some(hardware)) => hardware.erase_blocks(address, length),
+            // Assume the smallest pages of any probe, so we never erase anything we then don't write.
+            (None, None) => {
+                warn!("Unknown probe flash layout; assuming 1 KiB pages");
+                let first_page = address & !0x3ff;
+                (first_page..address + length).step_by(1024).map(|page| (page, 1024)).collect()
+            },
+        }
+    }
+
+    /// Consume the structure and retrieve its parts.
+    #[allow(dead_code)]
+    pub fn into_inner_parts(self) -> (UsbDevice, UsbHandle, DfuOperatingMode)
+    {
+        (
+            self.device.into_inner().expect("Unreachable: self.device is None"),
+            self.handle.into_inner().expect("Unreachable: self.handle is None"),
+            self.mode
+        )
+    }
+}
+
+impl Display for BmpDevice
+{
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error>
+    {
+        let display_str = match self.display() {
+            Ok(s) => s,
+            Err(e) => {
+                // Display impls are only supposed to propagate formatter IO errors, e.g.
+                // from the write!() call below, not internal errors.
+                // https://doc.rust-lang.org/stable/std/fmt/index.html#formatting-traits.
+                error!("Error formatting BlackMagicProbeDevice: {}", e);
+                S!("Unknown Black Magic Probe (error occurred fetching device details)")
+            }
+        };
+
+        write!(f, "{}", display_str)?;
+
+        Ok(())
+    }
+}
+
+/// A DFU interface alt-setting a device exposes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DfuInterfaceInfo
+{
+    pub number: u8,
+    pub alt_setting: u8,
+
+    /// The interface's name (iInterface), if it has one.
+    pub name: Option<String>,
+}
+
+/// A serial port the OS has given one of a probe's serial interfaces.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SerialPortInfo
+{
+    pub interface: u8,
+
+    /// What the port is for, from the interface's name (iInterface), e.g. `Black Magic GDB Server`.
+    pub function: Option<String>,
+
+    /// The port's name, e.g. `COM5`.
+    pub name: String,
+}
+
+impl Display for SerialPortInfo
+{
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result
+    {
+        match &self.function {
+            Some(function) => write!(f, "{} ({})", self.name, function),
+            None => write!(f, "{} (interface {})", self.name, self.interface),
+        }
+    }
+}
+
+/// A DFU alt-setting to use, as given with `--alt`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AltSetting
+{
+    Index(u8),
+
+    /// The alt-setting's name, or the start of it, e.g. `Bootloader` for `@Bootloader /0x08000000/...`.
+    Name(String),
+}
+
+impl AltSetting
+{
+    /// Whether `interface` is the alt-setting this refers to. Names are compared ignoring case, and
+    /// ignoring the `@` and memory layout of DfuSe-style names.
+    fn matches(&self, interface: &DfuInterfaceInfo) -> bool
+    {
+        let requested = match self {
+            AltSetting::Index(index) => return interface.alt_setting == *index,
+            AltSetting::Name(name) => name.trim().to_ascii_lowercase(),
+        };
+        let name = match &interface.name {
+            Some(name) => name.to_ascii_lowercase(),
+            None => return false,
+        };
+        let short_name = name
+            .trim_start_matches('@')
+            .split('/')
+            .next()
+            .unwrap_or_default()
+            .trim();
+
+        name == requested || short_name.starts_with(&requested)
+    }
+}
+
+impl Display for AltSetting
+{
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error>
+    {
+        match self {
+            AltSetting::Index(index) => write!(f, "{}", index),
+            AltSetting::Name(name) => write!(f, "{:?}", name),
         }
     }
+}
 
-    /// Downloads firmware onto the device, switching into DFU mode automatically if necessary.
-    ///
-    /// `progress` is a callback of the form `fn(just_written: usize)`, for callers to keep track of
-    /// the flashing process.
-    pub fn download<'r, R, P>(&mut self, firmware: &'r R, length: u32, firmware_type: FirmwareType, progress: P) -> Result<(), Error>
-    where
-        &'r R: Read,
-        R: ?Sized,
-        P: Fn(usize) + 'static,
-    {
-        if self.mode == DfuOperatingMode::Runtime {
-            self.detach_and_enumerate()
-                .map_err(|e| e.with_ctx("detaching device for download"))?;
-        }
 
-        let load_address = self.platform.load_address(firmware_type);
+/// Everything we know about a Black Magic Probe device, as shown by `bmputil probe list`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProbeDescription
+{
+    pub serial: Option<String>,
 
-        let io = DfuLibusb::from_usb_device(
-            self.device().clone(),
-            self.handle.take().expect("Must have a valid device handle"),
-            0,
-            0,
-        )?.into_inner();
+    /// The alias the configuration file gives this probe, if any.
+    pub alias: Option<String>,
 
-        match io.protocol() {
-            DfuProtocol::Dfuse {
-                address: _,
-                memory_layout: _
-            } => println!("Erasing flash..."),
-            _ => {},
-        }
+    /// The full product string (iProduct).
+    pub product: Option<String>,
 
-        let mut dfu_dev = DfuSync::new(io);
-        dfu_dev
-            .with_progress(progress)
-            .override_address(load_address);
+    /// The hardware, if it is one we know of.
+    pub hardware: Option<String>,
 
-        debug!("Load address: 0x{:08x}", load_address);
-        info!("Performing flash...");
+    /// The hardware variant name advertised in the product string.
+    pub variant: Option<String>,
 
-        let res = self.try_download(firmware, length, &mut dfu_dev);
+    /// The firmware version advertised in the product string.
+    pub version: Option<String>,
 
-        if let Err(ErrorKind::External(ErrorSource::DfuLibusb(DfuLibusbError::Dfu(DfuCoreError::StateError(DfuState::DfuError))))) = res.err_kind() {
+    pub mode: DfuOperatingMode,
+    pub port: String,
 
-            warn!("Device reported an error when trying to flash; going to clear status and try one more time...");
+    /// The USB vendor ID, in hex.
+    pub vid: String,
 
-            thread::sleep(Duration::from_millis(250));
+    /// The USB product ID, in hex.
+    pub pid: String,
 
-            let request_type = rusb::request_type(
-                Direction::Out,
-                RequestType::Class,
-                Recipient::Interface,
-            );
+    pub dfu_interfaces: Vec<DfuInterfaceInfo>,
 
-            self.handle().write_control(
-                request_type,
-                DfuRequest::ClrStatus as u8,
-                0,
-                0, // iface number
-                &[],
-                Duration::from_secs(2),
-            )?;
+    /// The serial ports the OS has given the probe's serial interfaces, where we can tell.
+    pub serial_ports: Vec<SerialPortInfo>,
 
-            self.try_download(firmware, length, &mut dfu_dev)?;
-        } else {
-            res?;
+    /// Whether Windows binds WinUSB to the DFU interface by itself, where we could tell (only on Windows).
+    pub driverless_dfu: Option<bool>,
+
+    /// The target voltage the probe measures, in volts, where it can (only read by `bmputil probe info`).
+    pub target_voltage: Option<f32>,
+
+    /// The bootloader's version, where it can be told (in runtime mode, as it was when last in DFU mode).
+    pub bootloader_version: Option<String>,
+}
+
+
+/// The hardware variant and firmware version a Black Magic Probe advertises in its product string.
+///
+/// Runtime firmware reports itself as e.g. `Black Magic Probe (native) v1.10.0`, while older firmware
+/// omits the variant (`Black Magic Probe v1.8.2`). The bootloaders report things like
+/// `Black Magic Probe DFU` or `Black Magic (Upgrade) for STLink/Discovery`, which carry no version.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ProbeIdentity
+{
+    /// The hardware variant the firmware was built for, if advertised.
+    pub variant: Option<String>,
+
+    /// The firmware version string, if advertised.
+    pub version: Option<String>,
+}
+
+impl ProbeIdentity
+{
+    pub fn from_product_string(product: &str) -> Self
+    {
+        let product = product.trim();
+
+        // The bootloader for the non-native variants names the variant but not any version.
+        if let Some(variant) = product.strip_prefix("Black Magic (Upgrade) for ") {
+            return Self {
+                variant: Some(variant.trim().to_string()),
+                version: None,
+            };
         }
 
-        info!("Flash complete!");
+        let rest = match product.strip_prefix("Black Magic Probe") {
+            Some(rest) => rest.trim_start(),
+            // Not something we know how to parse, so don't try to guess.
+            None => return Default::default(),
+        };
 
-        Ok(())
+        let (variant, rest) = match rest.strip_prefix('(').and_then(|r| r.split_once(')')) {
+            Some((variant, rest)) => (Some(variant.trim().to_string()), rest.trim()),
+            None => (None, rest),
+        };
+
+        let version = match rest {
+            "" | "DFU" => None,
+            version => Some(version.to_string()),
+        };
+
+        Self {
+            variant,
+            version,
+        }
     }
 
+    /// Finds the identity a firmware image will advertise once flashed, from the product string
+    /// embedded in it, if there is one.
+    pub fn from_firmware_image(image: &[u8]) -> Option<Self>
+    {
+        const PREFIX: &[u8] = b"Black Magic Probe";
+
+        image
+            .windows(PREFIX.len())
+            .enumerate()
+            .filter(|(_offset, window)| *window == PREFIX)
+            .filter_map(|(offset, _window)| {
+                let string = &image[offset..];
+                let end = string
+                    .iter()
+                    .position(|byte| !byte.is_ascii_graphic() && *byte != b' ')
+                    .unwrap_or(string.len());
+                std::str::from_utf8(&string[..end]).ok()
+            })
+            .map(Self::from_product_string)
+            .find(|identity| identity.variant.is_some())
+    }
 
-    /// Consume the structure and retrieve its parts.
-    #[allow(dead_code)]
-    pub fn into_inner_parts(self) -> (UsbDevice, UsbHandle, DfuOperatingMode)
+    /// Returns the parsed firmware version, if it was advertised and is in a form we understand.
+    pub fn firmware_version(&self) -> Option<FirmwareVersion>
     {
-        (
-            self.device.into_inner().expect("Unreachable: self.device is None"),
-            self.handle.into_inner().expect("Unreachable: self.handle is None"),
-            self.mode
-        )
+        self.version.as_deref().and_then(|version| version.parse().ok())
+    }
+
+    /// Returns the kind of hardware the firmware was built for, if it was advertised and is one we know of.
+    pub fn hardware(&self) -> Option<ProbeHardware>
+    {
+        self.variant.as_deref().and_then(ProbeHardware::from_variant_string)
     }
 }
 
-impl Display for BmpDevice
+
+/// The kinds of hardware that run the Black Magic Debug firmware.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ProbeHardware
 {
-    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error>
+    /// The official Black Magic Probe hardware.
+    Native,
+    /// ST-Link/v2 programmers (and the ST-Link on Discovery and Nucleo boards) reflashed with the firmware.
+    Stlink,
+    /// The SWLink programmer on STM8S Discovery boards reflashed with the firmware.
+    Swlink,
+    /// WeAct BlackPill boards with an STM32F401CC.
+    BlackpillF401CC,
+    /// WeAct BlackPill boards with an STM32F401CE.
+    BlackpillF401CE,
+    /// WeAct BlackPill boards with an STM32F411CE.
+    BlackpillF411CE,
+    /// The STM32F4-Discovery board (STM32F407VG).
+    F4Discovery,
+    /// The ctxLink wireless probe (STM32F401VE).
+    CtxLink,
+}
+
+impl ProbeHardware
+{
+    /// Identifies the hardware from the variant name a probe advertises, whether in its runtime product
+    /// string (e.g. `ST-Link/v2`) or its bootloader's (e.g. `STLink/Discovery`).
+    pub fn from_variant_string(variant: &str) -> Option<Self>
     {
-        let display_str = match self.display() {
-            Ok(s) => s,
-            Err(e) => {
-                // Display impls are only supposed to propagate formatter IO errors, e.g.
-                // from the write!() call below, not internal errors.
-                // https://doc.rust-lang.org/stable/std/fmt/index.html#formatting-traits.
-                error!("Error formatting BlackMagicProbeDevice: {}", e);
-                S!("Unknown Black Magic Probe (error occurred fetching device details)")
+        let variant = variant.to_ascii_lowercase();
+
+        if variant == "native" {
+            Some(Self::Native)
+        } else if variant.starts_with("st-link") || variant.starts_with("stlink") {
+            // The ST-Link/v3 is entirely different hardware to the v2s, with its own firmware builds.
+            if variant.contains("v3") {
+                None
+            } else {
+                Some(Self::Stlink)
             }
+        } else if variant.starts_with("swlink") {
+            Some(Self::Swlink)
+        } else if variant == "blackpill-f401cc" {
+            Some(Self::BlackpillF401CC)
+        } else if variant == "blackpill-f401ce" {
+            Some(Self::BlackpillF401CE)
+        } else if variant == "blackpill-f411ce" {
+            Some(Self::BlackpillF411CE)
+        } else if variant == "f4discovery" || variant == "f4-discovery" {
+            Some(Self::F4Discovery)
+        } else if variant == "ctxlink" {
+            Some(Self::CtxLink)
+        } else {
+            None
+        }
+    }
+
+    /// Whether this hardware is built around an STM32F4 rather than an STM32F1.
+    pub const fn is_stm32f4(self) -> bool
+    {
+        matches!(
+            self,
+            Self::BlackpillF401CC | Self::BlackpillF401CE | Self::BlackpillF411CE | Self::F4Discovery | Self::CtxLink
+        )
+    }
+
+    /// The total size of the MCU's internal flash, in bytes.
+    pub const fn flash_size(self) -> u32
+    {
+        match self {
+            // The F103s these use nominally have 64 KiB, but in practice all have 128 KiB.
+            Self::Native | Self::Stlink | Self::Swlink => 128 * 1024,
+            Self::BlackpillF401CC => 256 * 1024,
+            Self::BlackpillF401CE | Self::BlackpillF411CE | Self::CtxLink => 512 * 1024,
+            Self::F4Discovery => 1024 * 1024,
+        }
+    }
+
+    /// The pages (F1) or sectors (F4) of flash that writing `length` bytes at `address` will erase,
+    /// as flash is erased a whole page or sector at a time. Each is given as its address and size.
+    pub fn erase_blocks(self, address: u32, length: u32) -> Vec<(u32, u32)>
+    {
+        if length == 0 {
+            return Vec::new();
+        }
+
+        let offset = address - FLASH_BASE;
+        let end = offset + length;
+
+        let block_sizes: Box<dyn Iterator<Item = u32>> = if self.is_stm32f4() {
+            // F4 flash is four 16 KiB sectors, one 64 KiB sector, then 128 KiB sectors.
+            Box::new([16 * 1024; 4]
+                .into_iter()
+                .chain([64 * 1024])
+                .chain(std::iter::repeat(128 * 1024)))
+        } else {
+            // The F103s have 1 KiB pages.
+            Box::new(std::iter::repeat(1024))
         };
 
-        write!(f, "{}", display_str)?;
+        let mut blocks = Vec::new();
+        let mut block_start = 0;
+        for block_size in block_sizes {
+            let block_end = block_start + block_size;
+            if block_start >= end {
+                break;
+            }
+            if block_end > offset {
+                blocks.push((FLASH_BASE + block_start, block_size));
+            }
+            block_start = block_end;
+        }
 
-        Ok(())
+        blocks
+    }
+
+    /// The number of bytes of flash that writing `length` bytes at `address` will erase, as flash is
+    /// erased a whole page (F1) or sector (F4) at a time.
+    pub fn erase_length(self, address: u32, length: u32) -> u32
+    {
+        self.erase_blocks(address, length)
+            .iter()
+            .map(|&(_address, size)| size)
+            .sum()
+    }
+
+    /// The size of the flash region reserved for the Black Magic Debug bootloader.
+    ///
+    /// The bootloader always fits in 8 KiB, but the F4s' smallest flash sectors are 16 KiB.
+    pub const fn bootloader_size(self) -> u32
+    {
+        if self.is_stm32f4() {
+            0x4000
+        } else {
+            0x2000
+        }
+    }
+
+    /// Whether the hardware is unable to disconnect itself from the USB bus on DFU_DETACH, and so needs
+    /// the host to reset the port before it will re-enumerate into its bootloader.
+    ///
+    /// Most ST-Link boards have their USB D+ pull-up hard-wired, rather than under the MCU's control.
+    pub const fn needs_reset_after_detach(self) -> bool
+    {
+        matches!(self, Self::Stlink)
+    }
+}
+
+impl Display for ProbeHardware
+{
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result
+    {
+        match self {
+            Self::Native => write!(f, "native"),
+            Self::Stlink => write!(f, "ST-Link"),
+            Self::Swlink => write!(f, "SWLink"),
+            Self::BlackpillF401CC => write!(f, "BlackPill-F401CC"),
+            Self::BlackpillF401CE => write!(f, "BlackPill-F401CE"),
+            Self::BlackpillF411CE => write!(f, "BlackPill-F411CE"),
+            Self::F4Discovery => write!(f, "F4Discovery"),
+            Self::CtxLink => write!(f, "ctxLink"),
+        }
     }
 }
 
@@ -666,10 +2096,10 @@ pub enum FirmwareType
 
 impl FirmwareType
 {
-    /// Detect the kind of firmware from the given binary by examining its reset vector address.
+    /// Get the reset vector address from the given binary's vector table, checking it points into flash.
     ///
     /// This function panics if `firmware.len() < 8`.
-    pub fn detect_from_firmware(platform: BmpPlatform, firmware: &[u8]) -> Result<Self, Error>
+    pub fn reset_vector(firmware: &[u8]) -> Result<u32, Error>
     {
         let buffer = &firmware[0..(4 * 2)];
 
@@ -687,7 +2117,20 @@ impl FirmwareType
             ))).error());
         }
 
-        let app_start = platform.load_address(Self::Application);
+        Ok(reset_vector)
+    }
+
+    /// Detect the kind of firmware from the given binary by examining its reset vector address.
+    ///
+    /// This function panics if `firmware.len() < 8`.
+    pub fn detect_from_firmware(
+        platform: BmpPlatform,
+        hardware: Option<ProbeHardware>,
+        firmware: &[u8],
+    ) -> Result<Self, Error>
+    {
+        let reset_vector = Self::reset_vector(firmware)?;
+        let app_start = platform.load_address(Self::Application, hardware);
 
         if reset_vector > app_start {
             Ok(Self::Application)
@@ -722,6 +2165,7 @@ impl Default for FirmwareType
 
 
 /// File formats that Black Magic Probe firmware can be in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum FirmwareFormat
 {
     /// Raw binary format. Made with `objcopy -O binary`. Typical file extension: `.bin`.
@@ -732,10 +2176,15 @@ pub enum FirmwareFormat
 
     /// Intel HEX. Typical file extensions: `.hex`, `.ihex`.
     IntelHex,
+
+    /// STMicroelectronics' DfuSe container format. Typical file extension: `.dfu`.
+    DfuSe,
 }
 
 impl FirmwareFormat
 {
+    /// The names each format can be selected by with `--format`.
+    pub const CLI_NAMES: [&'static str; 4] = ["binary", "elf", "ihex", "dfuse"];
 
     /// Detect the kind of firmware from its data.
     ///
@@ -744,12 +2193,58 @@ impl FirmwareFormat
     {
         if &firmware[0..4] == b"\x7fELF" {
             FirmwareFormat::Elf
-        } else if &firmware[0..1] == b":" {
+        } else if firmware.starts_with(crate::dfuse::SIGNATURE) {
+            FirmwareFormat::DfuSe
+        } else if Self::looks_like_intel_hex(firmware) {
             FirmwareFormat::IntelHex
         } else {
             FirmwareFormat::Binary
         }
     }
+
+    /// Intel HEX files are text, starting with a `:` and a record's worth of hex digits.
+    ///
+    /// Checking more than just the `:` keeps a binary that happens to start with 0x3a from being
+    /// mistaken for one.
+    fn looks_like_intel_hex(firmware: &[u8]) -> bool
+    {
+        let first_line = firmware
+            .split(|&byte| byte == b'\n')
+            .next()
+            .unwrap_or_default();
+        let first_line = first_line.strip_suffix(b"\r").unwrap_or(first_line);
+
+        match first_line.strip_prefix(b":") {
+            // The shortest record is 5 bytes (10 hex digits): length, address, type and checksum.
+            Some(record) => record.len() >= 10 && record.iter().all(u8::is_ascii_hexdigit),
+            None => false,
+        }
+    }
+
+    /// Returns the format with the given `--format` name, one of [`FirmwareFormat::CLI_NAMES`].
+    pub fn from_cli_name(name: &str) -> Option<Self>
+    {
+        match name {
+            "binary" => Some(FirmwareFormat::Binary),
+            "elf" => Some(FirmwareFormat::Elf),
+            "ihex" => Some(FirmwareFormat::IntelHex),
+            "dfuse" => Some(FirmwareFormat::DfuSe),
+            _ => None,
+        }
+    }
+}
+
+impl Display for FirmwareFormat
+{
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error>
+    {
+        match self {
+            FirmwareFormat::Binary => write!(f, "raw binary"),
+            FirmwareFormat::Elf => write!(f, "ELF"),
+            FirmwareFormat::IntelHex => write!(f, "Intel HEX"),
+            FirmwareFormat::DfuSe => write!(f, "DfuSe"),
+        }
+    }
 }
 
 
@@ -760,6 +2255,14 @@ pub struct BmpMatcher
     index: Option<usize>,
     serial: Option<String>,
     port: Option<String>,
+    older_than: Option<FirmwareVersion>,
+    custom_ids: CustomUsbIds,
+    transfer_size: Option<u16>,
+    retry: RetryPolicy,
+    alt_setting: Option<AltSetting>,
+    timeouts: UsbTimeouts,
+    wait: bool,
+    auto_attach: bool,
 }
 impl BmpMatcher
 {
@@ -768,12 +2271,40 @@ impl BmpMatcher
         Default::default()
     }
 
-    pub(crate) fn from_cli_args(matches: &ArgMatches) -> Self
+    pub(crate) fn from_cli_args(matches: &ArgMatches, config: &Config) -> Result<Self, Error>
     {
-        Self::new()
+        // A probe alias is just another way of giving the serial number.
+        let serial = match matches.get_one::<String>("probe") {
+            Some(alias) => Some(config.resolve_alias(alias)?.to_string()),
+            None => matches.get_one::<String>("serial_number").cloned(),
+        };
+        // Fall back on the environment, and then the configured default probe, unless a probe was picked
+        // some other way. The environment variables aren't clap's `env()`s, as clap would count them as
+        // given, and so as conflicting with picking the probe some other way.
+        let env_var = |name: &str| env::var(name).ok().filter(|value| !value.is_empty());
+        let serial = match serial {
+            None if !matches.contains_id("index") && !matches.contains_id("port") => match env_var("BMPUTIL_PROBE") {
+                Some(alias) => Some(config.resolve_alias(&alias)?.to_string()),
+                None => env_var("BMPUTIL_SERIAL").or_else(|| config.default_serial().map(str::to_string)),
+            },
+            serial => serial,
+        };
+
+        Ok(Self::new()
             .index(matches.get_one::<usize>("index").map(|&value| value))
-            .serial(matches.get_one::<String>("serial_number").map(|s| s.as_str()))
+            .serial(serial.as_deref())
             .port(matches.get_one::<String>("port").map(|s| s.as_str()))
+            // Only some subcommands filter by firmware version.
+            .older_than(matches.try_get_one::<FirmwareVersion>("only-older-than").ok().flatten().cloned())
+            .custom_ids(CustomUsbIds::from_cli_args(matches).or(config.usb_ids))
+            // Only the subcommands that write firmware take a transfer size.
+            .transfer_size(matches.try_get_one::<u16>("transfer-size").ok().flatten().copied().or(config.transfer_size))
+            .retry(matches.get_one::<u32>("retries").copied().or(config.usb_retries).map(RetryPolicy::new).unwrap_or_default())
+            .alt_setting(matches.get_one::<AltSetting>("alt").cloned())
+            .timeouts(UsbTimeouts::from_cli_args(matches, config))
+            .wait(matches.get_flag("wait"))
+            // Only defined on Linux, where we might be running in WSL.
+            .auto_attach(matches.try_get_one::<bool>("auto-attach").ok().flatten().copied().unwrap_or(false)))
     }
 
     /// Set the index to match against.
@@ -802,47 +2333,229 @@ impl BmpMatcher
         self
     }
 
-    /// Get any index previously set with `.index()`.
-    #[allow(dead_code)]
-    pub fn get_index(&self) -> Option<usize>
+    /// Set the firmware version that matching devices must be running something older than.
+    ///
+    /// Devices whose firmware version cannot be determined (such as those in DFU mode) are
+    /// considered to match.
+    #[must_use]
+    pub fn older_than(mut self, version: Option<FirmwareVersion>) -> Self
+    {
+        self.older_than = version;
+        self
+    }
+
+    /// Set non-standard USB IDs to also consider to be Black Magic Probe devices.
+    #[must_use]
+    pub fn custom_ids(mut self, custom_ids: CustomUsbIds) -> Self
+    {
+        self.custom_ids = custom_ids;
+        self
+    }
+
+    /// Set the DFU transfer size matching devices should use, instead of their own maximum.
+    #[must_use]
+    pub fn transfer_size(mut self, transfer_size: Option<u16>) -> Self
+    {
+        self.transfer_size = transfer_size;
+        self
+    }
+
+    /// Set how matching devices should retry USB requests that fail for transient reasons.
+    #[must_use]
+    pub fn retry(mut self, retry: RetryPolicy) -> Self
+    {
+        self.retry = retry;
+        self
+    }
+
+    /// Set the DFU alt-setting matching devices should use, instead of the one for the internal flash.
+    #[must_use]
+    pub fn alt_setting(mut self, alt_setting: Option<AltSetting>) -> Self
+    {
+        self.alt_setting = alt_setting;
+        self
+    }
+
+    /// Set how long matching devices are given to switch between runtime and DFU mode.
+    #[must_use]
+    pub fn timeouts(mut self, timeouts: UsbTimeouts) -> Self
+    {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Set whether to wait for a matching device to be connected, if none are found.
+    #[must_use]
+    pub fn wait(mut self, wait: bool) -> Self
+    {
+        self.wait = wait;
+        self
+    }
+
+    /// Set whether to have Windows attach probes it can see to WSL, if we're running in WSL and none
+    /// are found.
+    #[must_use]
+    pub fn auto_attach(mut self, auto_attach: bool) -> Self
+    {
+        self.auto_attach = auto_attach;
+        self
+    }
+
+    /// Get any index previously set with `.index()`.
+    #[allow(dead_code)]
+    pub fn get_index(&self) -> Option<usize>
+    {
+        self.index
+    }
+
+    /// Get any serial number previously set with `.serial()`.
+    #[allow(dead_code)]
+    pub fn get_serial(&self) -> Option<&str>
+    {
+        self.serial.as_deref()
+    }
+
+    /// Get any port path previously set with `.port()`.
+    #[allow(dead_code)]
+    pub fn get_port(&self) -> Option<&str>
+    {
+        self.port.as_deref()
+    }
+
+    /// Find all connected Black Magic Probe devices that match from the command-line criteria.
+    ///
+    /// This uses the `serial_number`, `index`, and `port` values from `matches`, treating any that
+    /// were not provided as always matching.
+    ///
+    /// This function returns all found devices and all errors that occurred during the search.
+    /// This is so errors are not hidden, but also do not prevent matching devices from being found.
+    /// However, if the length of the error `Vec` is not 0, you should consider the results
+    /// potentially incomplete.
+    ///
+    /// The `index` matcher *includes* devices that errored when attempting to match them.
+    ///
+    /// If waiting was requested with `.wait()` and no matching devices are found, this blocks until
+    /// a matching device is connected, unless one was only skipped for being up to date (see
+    /// `.older_than()`), as there's then nothing left to wait for.
+    #[instrument(
+        level = "debug",
+        name = "discover",
+        skip_all,
+        fields(serial = ?self.serial, port = ?self.port, index = ?self.index, wait = self.wait),
+    )]
+    pub fn find_matching_probes(&self) -> BmpMatchResults
+    {
+        if !self.wait {
+            return self.scan();
+        }
+
+        let context = match usb::new_context() {
+            Ok(c) => c,
+            Err(e) => {
+                let mut results = BmpMatchResults::default();
+                results.errors.push(e.into());
+                return results;
+            },
+        };
+
+        let mut announced = false;
+        loop {
+            // Register for arrivals *before* scanning, so a device plugged in mid-scan isn't missed.
+            let arrivals = ArrivalWatcher::new(&context, self.custom_ids);
+
+            let results = self.scan();
+            if !results.found.is_empty() || results.up_to_date > 0 {
+                return results;
+            }
+
+            if !announced {
+                status!("Waiting for a matching Black Magic Probe to be connected...");
+                announced = true;
+            }
+
+            arrivals.wait(&context, self.timeouts.settle());
+        }
+    }
+
+    /// Looks for probes, helping with what's commonly in the way of finding them if there are none.
+    fn scan(&self) -> BmpMatchResults
+    {
+        let results = self.offer_drivers(self.scan_for_probes());
+        self.attach_from_windows(results)
+    }
+
+    /// When running in WSL, if no probe was seen at all, helps get probes Windows can see attached, looking
+    /// for probes again (for a while) if they were. Probes that were seen, but filtered out or not
+    /// usable, are already attached, so there's nothing to help with.
+    fn attach_from_windows(&self, results: BmpMatchResults) -> BmpMatchResults
     {
-        self.index
+        #[cfg(target_os = "linux")]
+        let seen_none = results.found.is_empty() && results.filtered_out.is_empty() && results.errors.is_empty();
+        #[cfg(target_os = "linux")]
+        if seen_none && crate::wsl::help_attach(self.custom_ids, self.auto_attach) {
+            let start = Instant::now();
+            loop {
+                let results = self.scan_for_probes();
+                if !results.found.is_empty() || start.elapsed() > self.timeouts.reenumerate {
+                    return results;
+                }
+                thread::sleep(self.timeouts.settle());
+            }
+        }
+
+        results
     }
 
-    /// Get any serial number previously set with `.serial()`.
-    #[allow(dead_code)]
-    pub fn get_serial(&self) -> Option<&str>
+    /// On Windows, if no probe could be used and that may be for want of a driver, offers to install
+    /// WinUSB, looking for probes again if it was.
+    fn offer_drivers(&self, results: BmpMatchResults) -> BmpMatchResults
     {
-        self.serial.as_deref()
+        #[cfg(windows)]
+        if results.found.is_empty() && !results.errors.is_empty() {
+            match crate::winusb::offer_install(self.custom_ids) {
+                Ok(true) => return self.scan_for_probes(),
+                Ok(false) => (),
+                Err(e) => warn!("Couldn't install WinUSB: {}", e),
+            }
+        }
+
+        results
     }
 
-    /// Get any port path previously set with `.port()`.
-    #[allow(dead_code)]
-    pub fn get_port(&self) -> Option<&str>
+    /// Checks `dev` against the firmware version set with `.older_than()`, if any.
+    fn version_matches(&self, dev: &BmpDevice) -> bool
     {
-        self.port.as_deref()
+        let limit = match &self.older_than {
+            Some(limit) => limit,
+            None => return true,
+        };
+
+        match dev.identity().ok().and_then(|identity| identity.firmware_version()) {
+            Some(version) => {
+                if version >= *limit {
+                    debug!("Skipping device with firmware {}, which is not older than {}", version, limit);
+                }
+                version < *limit
+            },
+            None => true,
+        }
     }
 
-    /// Find all connected Black Magic Probe devices that match from the command-line criteria.
-    ///
-    /// This uses the `serial_number`, `index`, and `port` values from `matches`, treating any that
-    /// were not provided as always matching.
-    ///
-    /// This function returns all found devices and all errors that occurred during the search.
-    /// This is so errors are not hidden, but also do not prevent matching devices from being found.
-    /// However, if the length of the error `Vec` is not 0, you should consider the results
-    /// potentially incomplete.
-    ///
-    /// The `index` matcher *includes* devices that errored when attempting to match them.
-    pub fn find_matching_probes(&self) -> BmpMatchResults
+    /// Performs a single pass over the connected USB devices for [`find_matching_probes()`].
+    fn scan_for_probes(&self) -> BmpMatchResults
     {
         let mut results = BmpMatchResults {
             found: Vec::new(),
             filtered_out: Vec::new(),
+            up_to_date: 0,
             errors: Vec::new(),
         };
 
-        let context = match rusb::Context::new() {
+        if let Some(fd) = usb::device_fd() {
+            return self.scan_device_fd(fd);
+        }
+
+        let context = match usb::new_context() {
             Ok(c) => c,
             Err(e) => {
                 results.errors.push(e.into());
@@ -858,18 +2571,19 @@ impl BmpMatcher
             },
         };
 
-        // Filter out devices that don't match the Black Magic Probe's vid/pid in the first place.
+        // Filter out devices that don't match the Black Magic Probe's vid/pid in the first place,
+        // noting which mode each remaining device is in.
         let devices = devices
             .iter()
-            .filter(|dev| {
+            .filter_map(|dev| {
                 let desc = dev.device_descriptor()
                     .expect(libusb_cannot_fail!("libusb_get_device_descriptor()"));
 
                 let (vid, pid) = (desc.vendor_id(), desc.product_id());
-                BmpPlatform::from_vid_pid(Vid(vid), Pid(pid)).is_some()
+                self.custom_ids.identify(Vid(vid), Pid(pid)).map(|(_platform, mode)| (dev, mode))
             });
 
-        for (index, dev) in devices.enumerate() {
+        for (index, (dev, mode)) in devices.enumerate() {
 
             // Note: the control flow in this function is kind of weird, due to the lack of early returns
             // (since we're returning all successes and errors).
@@ -890,8 +2604,14 @@ impl BmpMatcher
             // If we opened the device and now have that handle, try to get the device's first language, which we need
             // to request the string descriptor that contains the serial number.
             let lang = if let Some(handle) = handle.as_ref() {
-                match handle.read_languages(Duration::from_secs(2)) {
-                    Ok(mut l) => Some(l.remove(0)),
+                match handle.read_languages(self.timeouts.request) {
+                    Ok(languages) if !languages.is_empty() => Some(languages[0]),
+                    Ok(_) => {
+                        results.errors.push(
+                            ErrorKind::DeviceSeemsInvalid(S!("no string descriptor languages")).error()
+                        );
+                        continue;
+                    },
                     Err(e) => {
                         results.errors.push(e.into());
                         continue;
@@ -906,8 +2626,9 @@ impl BmpMatcher
                 let handle = handle.unwrap();
                 let desc = dev.device_descriptor()
                     .expect(libusb_cannot_fail!("libusb_get_device_descriptor"));
-                match handle.read_serial_number_string(lang, &desc, Duration::from_secs(2)) {
-                    Ok(s) => Some(s) == self.serial,
+                match handle.read_serial_number_string(lang, &desc, self.timeouts.request) {
+                    // Serial numbers are hex strings, so don't make the user match the case exactly.
+                    Ok(s) => self.serial.as_deref().is_some_and(|needle| needle.eq_ignore_ascii_case(&s)),
                     Err(e) => {
                         results.errors.push(e.into());
                         continue;
@@ -925,28 +2646,22 @@ impl BmpMatcher
             let index_matches = self.index.map_or(true, |needle| needle == index);
 
             // Consider the port to match if it equals that of the device or if one was not specified at all.
-            let port_matches = self.port.as_ref().map_or(true, |p| {
-                let port_chain = dev
-                    .port_numbers()
-                    // Unwrap should be safe as the only possible error from libusb_get_port_numbers()
-                    // is LIBUSB_ERROR_OVERFLOW, and only if the buffer given to it is too small,
-                    // but rusb g ives it a buffer big enough for the maximum hub chain allowed by the spec.
-                    .expect("Could not get port numbers! Hub depth > 7 shouldn't be possible!")
-                    .into_iter()
-                    .map(|p| p.to_string())
-                    .collect::<Vec<String>>()
-                    .as_slice()
-                    .join(".");
-
-                let port_path = format!("{}-{}", dev.bus_number(), port_chain);
-
-                p == &port_path
-            });
+            let port_matches = self.port.as_ref().is_none_or(|p| p == &port_path(&dev));
 
             // Finally, check the provided matchers.
             if index_matches && port_matches && serial_matches {
-                match BmpDevice::from_usb_device(dev) {
-                    Ok(bmpdev) => results.found.push(bmpdev),
+                match BmpDevice::from_usb_device_with_ids(dev, self.custom_ids) {
+                    Ok(bmpdev) if !self.version_matches(&bmpdev) => {
+                        results.filtered_out.push(bmpdev.device().clone());
+                        results.up_to_date += 1;
+                    },
+                    Ok(bmpdev) => results.found.push(self.configure(bmpdev)),
+                    // Bootloaders often need their own permissions or drivers, so make it clear that
+                    // we *did* see the probe, just in DFU mode.
+                    Err(e) if mode == DfuOperatingMode::FirmwareUpgrade => {
+                        results.errors.push(e.with_ctx("opening Black Magic Probe found in DFU (bootloader) mode"));
+                        continue;
+                    },
                     Err(e) => {
                         results.errors.push(e);
                         continue;
@@ -962,6 +2677,142 @@ impl BmpMatcher
         // occured along the way.
         results
     }
+
+    /// Like [`scan_for_probes()`](Self::scan_for_probes), but for the already-open device given with
+    /// `--device-fd`, which is the only device we can see.
+    fn scan_device_fd(&self, fd: i32) -> BmpMatchResults
+    {
+        let mut results = BmpMatchResults {
+            found: Vec::new(),
+            filtered_out: Vec::new(),
+            up_to_date: 0,
+            errors: Vec::new(),
+        };
+
+        let handle = match usb::new_context().and_then(|context| usb::open_device_fd(&context, fd)) {
+            Ok(handle) => handle,
+            Err(e) => {
+                results.errors.push(Error::from(e).with_ctx("opening the device given with --device-fd"));
+                return results;
+            },
+        };
+
+        let device = handle.device();
+        let desc = device.device_descriptor()
+            .expect(libusb_cannot_fail!("libusb_get_device_descriptor()"));
+        if self.custom_ids.identify(Vid(desc.vendor_id()), Pid(desc.product_id())).is_none() {
+            debug!("The device given with --device-fd ({:04x}:{:04x}) isn't a Black Magic Probe", desc.vendor_id(), desc.product_id());
+            results.filtered_out.push(device);
+            return results;
+        }
+
+        let bmpdev = match BmpDevice::from_usb_handle_with_ids(handle, self.custom_ids) {
+            Ok(bmpdev) => bmpdev,
+            Err(e) => {
+                results.errors.push(e);
+                return results;
+            },
+        };
+
+        let serial_matches = self.serial.as_deref().is_none_or(|needle| {
+            bmpdev.serial_number().is_ok_and(|serial| needle.eq_ignore_ascii_case(&serial))
+        });
+        let index_matches = self.index.is_none_or(|needle| needle == 0);
+        let port_matches = self.port.as_ref().is_none_or(|port| *port == bmpdev.port());
+        if !(index_matches && port_matches && serial_matches) {
+            results.filtered_out.push(bmpdev.device().clone());
+        } else if !self.version_matches(&bmpdev) {
+            results.filtered_out.push(bmpdev.device().clone());
+            results.up_to_date += 1;
+        } else {
+            results.found.push(self.configure(bmpdev));
+        }
+
+        results
+    }
+
+    /// Applies the settings this matcher was given for the probes it finds to `bmpdev`.
+    fn configure(&self, mut bmpdev: BmpDevice) -> BmpDevice
+    {
+        bmpdev.set_transfer_size(self.transfer_size);
+        bmpdev.set_retry_policy(self.retry);
+        bmpdev.set_alt_setting(self.alt_setting.clone());
+        bmpdev.set_timeouts(self.timeouts);
+        bmpdev
+    }
+}
+
+
+/// Watches for Black Magic Probe devices being connected, for [`BmpMatcher::find_matching_probes()`].
+///
+/// Uses libusb's hotplug notifications where the platform supports them, and falls back to polling
+/// otherwise.
+struct ArrivalWatcher
+{
+    arrived: Arc<AtomicBool>,
+    registration: Option<Registration<rusb::Context>>,
+}
+
+impl ArrivalWatcher
+{
+    fn new(context: &rusb::Context, custom_ids: CustomUsbIds) -> Self
+    {
+        let arrived = Arc::new(AtomicBool::new(false));
+
+        let registration = if rusb::has_hotplug() {
+            HotplugBuilder::new()
+                .enumerate(false)
+                .register(context, Box::new(ArrivalNotifier(Arc::clone(&arrived), custom_ids)))
+                .map_err(|e| warn!("Failed to register for USB hotplug events, falling back to polling: {}", e))
+                .ok()
+        } else {
+            None
+        };
+
+        Self {
+            arrived,
+            registration,
+        }
+    }
+
+    /// Blocks until a Black Magic Probe device (probably) has been connected, and then for `settle`.
+    fn wait(self, context: &rusb::Context, settle: Duration)
+    {
+        if self.registration.is_none() {
+            thread::sleep(Duration::from_millis(500));
+            return;
+        }
+
+        while !self.arrived.load(Ordering::SeqCst) {
+            if let Err(e) = context.handle_events(Some(Duration::from_secs(1))) {
+                warn!("Error handling USB hotplug events: {}", e);
+                thread::sleep(Duration::from_millis(500));
+                return;
+            }
+        }
+
+        // Give the OS a moment to finish setting the new device up (e.g. udev applying permissions)
+        // before we try to open it.
+        thread::sleep(settle);
+    }
+}
+
+/// Hotplug callback that notes when a device with Black Magic Probe IDs arrives.
+struct ArrivalNotifier(Arc<AtomicBool>, CustomUsbIds);
+
+impl Hotplug<rusb::Context> for ArrivalNotifier
+{
+    fn device_arrived(&mut self, device: UsbDevice)
+    {
+        let desc = device.device_descriptor()
+            .expect(libusb_cannot_fail!("libusb_get_device_descriptor()"));
+        if self.1.identify(Vid(desc.vendor_id()), Pid(desc.product_id())).is_some() {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn device_left(&mut self, _device: UsbDevice)
+    { }
 }
 
 
@@ -970,6 +2821,9 @@ pub struct BmpMatchResults
 {
     pub found: Vec<BmpDevice>,
     pub filtered_out: Vec<UsbDevice>,
+    /// How many of the probes filtered out otherwise matched, but had firmware that's not older than
+    /// the version given with [`BmpMatcher::older_than()`].
+    pub up_to_date: usize,
     pub errors: Vec<Error>,
 }
 
@@ -1013,6 +2867,10 @@ impl BmpMatchResults
             warn!("Other device errors: {:?}", self.errors.as_slice());
         }
 
+        for dev in &self.found {
+            dev.warn_legacy_ids();
+        }
+
         Ok(mem::take(&mut self.found))
     }
 
@@ -1044,7 +2902,7 @@ impl BmpMatchResults
                 operation,
                 self.found.len()
             );
-            error!("Hint: try bmputil info and revise your filter arguments (--serial, --index, --port).");
+            error!("Hint: try bmputil probe info and revise your filter arguments (--serial, --index, --port).");
             return Err(ErrorKind::TooManyDevices.error());
         }
 
@@ -1054,7 +2912,56 @@ impl BmpMatchResults
             warn!("Other device errors: {:?}", self.errors.as_slice());
         }
 
-        Ok(self.found.remove(0))
+        let dev = self.found.remove(0);
+        dev.warn_legacy_ids();
+
+        Ok(dev)
+    }
+
+    /// Like `pop_single()`, but if more than one device was found and we're able to, asks the user which
+    /// one to use instead of erroring.
+    pub(crate) fn pop_single_interactive(&mut self, operation: &str) -> Result<BmpDevice, Error>
+    {
+        if self.found.len() <= 1 || !prompt::can_prompt() {
+            return self.pop_single(operation);
+        }
+
+        if !self.errors.is_empty() {
+            warn!("Errors occurred when searching for devices, so not every device may be listed.");
+            warn!("Other device errors: {:?}", self.errors.as_slice());
+        }
+
+        status!(
+            "{} Black Magic Probe devices found, but the {} operation only accepts one.",
+            self.found.len(),
+            operation,
+        );
+        let options: Vec<String> = self.found
+            .iter()
+            .map(|dev| dev.summary())
+            .collect();
+
+        let choice = prompt::pick("Which device should be used?", &options)
+            .map_err(|e| ErrorKind::External(ErrorSource::StdIo(e)).error())?;
+
+        match choice {
+            Some(index) => {
+                let dev = self.found.remove(index);
+                dev.warn_legacy_ids();
+                Ok(dev)
+            },
+            None => Err(ErrorKind::TooManyDevices.error()),
+        }
+    }
+
+    /// Pops the found device with the serial number `serial`, if there is one.
+    pub(crate) fn pop_by_serial(&mut self, serial: &str) -> Option<BmpDevice>
+    {
+        let index = self.found
+            .iter()
+            .position(|dev| dev.serial_number().is_ok_and(|s| s.eq_ignore_ascii_case(serial)))?;
+
+        Some(self.found.remove(index))
     }
 
     /// Like `pop_single()`, but does not print helpful diagnostics for edge cases.
@@ -1071,28 +2978,225 @@ impl BmpMatchResults
 }
 
 
+/// Returns the full port path of a USB device, in the format of `<bus>-<port>.<subport>.<subport...>`.
+///
+/// Not every libusb backend knows which ports a device is on (OpenBSD's doesn't), in which case this
+/// is just `<bus>-`, which still tells probes on different buses apart across re-enumeration.
+pub fn port_path(dev: &UsbDevice) -> String
+{
+    let port_chain = dev
+        .port_numbers()
+        // Unwrap should be safe as the only possible error from libusb_get_port_numbers()
+        // is LIBUSB_ERROR_OVERFLOW, and only if the buffer given to it is too small,
+        // but rusb gives it a buffer big enough for the maximum hub chain allowed by the spec.
+        .expect("Could not get port numbers! Hub depth > 7 shouldn't be possible!")
+        .into_iter()
+        .map(|p| p.to_string())
+        .collect::<Vec<String>>()
+        .as_slice()
+        .join(".");
+
+    format!("{}-{}", dev.bus_number(), port_chain)
+}
+
+/// Finds the version in a bootloader's product string, e.g. `v1.2.0` in `Black Magic Probe DFU v1.2.0`.
+fn version_in_product_string(product: &str) -> Option<String>
+{
+    product
+        .split(|c: char| c.is_whitespace() || matches!(c, '(' | ')' | ','))
+        .find(|word| word.strip_prefix('v').is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_digit())))
+        .map(String::from)
+}
+
+/// Parses a user-provided port path in the format of `<bus>-<port>.<subport>.<subport...>`, returning it
+/// in the same canonical form [`port_path()`] produces (so e.g. `001-04.2` becomes `1-4.2`).
+pub fn parse_port_path(path: &str) -> Result<String, String>
+{
+    let (bus, ports) = path
+        .split_once('-')
+        .ok_or_else(|| format!("{:?} is not in the form <bus>-<port>[.<port>...]", path))?;
+
+    let bus: u8 = bus
+        .parse()
+        .map_err(|_| format!("{:?} is not a valid USB bus number", bus))?;
+
+    let ports = ports
+        .split('.')
+        .map(|port| port.parse::<u8>().map_err(|_| format!("{:?} is not a valid USB port number", port)))
+        .collect::<Result<Vec<u8>, String>>()?;
+
+    // The USB spec limits hub chains to a depth of 7.
+    if ports.len() > 7 {
+        return Err(format!("{:?} has more than the 7 levels of hub nesting USB allows", path));
+    }
+
+    let ports: Vec<String> = ports
+        .into_iter()
+        .map(|port| port.to_string())
+        .collect();
+
+    Ok(format!("{}-{}", bus, ports.join(".")))
+}
+
+
+/// How long to give a Black Magic Probe to answer requests, and to switch between runtime and DFU mode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct UsbTimeouts
+{
+    /// How long to give the probe to answer each USB control request.
+    pub request: Duration,
+
+    /// How long to give the probe to drop off the bus after DFU_DETACH before looking for it again,
+    /// which is also how long it's asked to wait for a USB reset. If not set, the probe's own
+    /// wDetachTimeOut is asked for, and we wait [`Self::DEFAULT_DETACH`].
+    pub detach: Option<Duration>,
+
+    /// How long to wait for the probe to re-enumerate after switching modes or rebooting.
+    pub reenumerate: Duration,
+}
+
+impl UsbTimeouts
+{
+    pub const DEFAULT_REQUEST: Duration = Duration::from_secs(2);
+    pub const DEFAULT_DETACH: Duration = Duration::from_millis(500);
+    pub const DEFAULT_REENUMERATE: Duration = Duration::from_secs(5);
+
+    /// How long to give the probe to drop off the bus after detaching, or being told to reboot, before
+    /// looking for it again.
+    pub fn detach_wait(&self) -> Duration
+    {
+        self.detach.unwrap_or(Self::DEFAULT_DETACH)
+    }
+
+    /// How long to pause before trying the probe again, after an error or after it's appeared, for it (or
+    /// the OS) to settle: an eighth of the request timeout, so 250ms by default.
+    pub fn settle(&self) -> Duration
+    {
+        self.request / 8
+    }
+
+    /// How long to give the probe to come back after a mass erase, which takes much longer than it
+    /// normally takes to re-enumerate: six times as long, so 30s by default.
+    pub fn mass_erase(&self) -> Duration
+    {
+        self.reenumerate * 6
+    }
+
+    pub(crate) fn from_cli_args(matches: &ArgMatches, config: &Config) -> Self
+    {
+        let request = matches.get_one::<u64>("timeout")
+            .copied()
+            .or(config.usb_timeout_ms);
+        let detach = matches.get_one::<u64>("detach-timeout")
+            .copied()
+            .or(config.detach_timeout_ms);
+        let reenumerate = matches.get_one::<u64>("reenumerate-timeout")
+            .copied()
+            .or(config.reenumerate_timeout_ms);
+
+        Self {
+            request: request.map_or(Self::DEFAULT_REQUEST, Duration::from_millis),
+            detach: detach.map(Duration::from_millis),
+            reenumerate: reenumerate.map_or(Self::DEFAULT_REENUMERATE, Duration::from_millis),
+        }
+    }
+}
+
+impl Default for UsbTimeouts
+{
+    fn default() -> Self
+    {
+        Self {
+            request: Self::DEFAULT_REQUEST,
+            detach: None,
+            reenumerate: Self::DEFAULT_REENUMERATE,
+        }
+    }
+}
+
+
+/// The start of the DfuSe-style name of the alt-setting bootloaders that can keep a serial number for
+/// the probe expose the area it's kept in with, lower-cased.
+pub const SERIAL_AREA_NAME: &str = "@serial number";
+
+/// The start of the DfuSe-style name of the alt-setting the STM32 built-in bootloader exposes the MCU's
+/// option bytes with, e.g. `@Option Bytes  /0x1FFFF800/01*016 e`, lower-cased.
+pub const OPTION_BYTES_AREA_NAME: &str = "@option bytes";
+
+/// The least time to give a probe to re-enumerate on macOS, which can be slow to, and which can hand
+/// the probe to its own drivers for a moment before letting go of it again.
+const MACOS_REENUMERATE_GRACE: Duration = Duration::from_secs(10);
+
+/// On macOS, checks that a re-enumerated probe's DFU interface can be claimed yet, handing the probe
+/// back if so. If macOS's drivers still have it, or the device has already gone again, this returns
+/// [`ErrorKind::DeviceNotFound`] so that [`wait_for_probe_reboot()`] keeps looking, unless `settling`
+/// is over, in which case they're probably there to stay, which claiming the interface for real deals
+/// with (see [`BmpDevice::claim_dfu_interface()`]).
+fn settle(mut dev: BmpDevice, settling: bool) -> Result<BmpDevice, Error>
+{
+    if !cfg!(target_os = "macos") || !settling {
+        return Ok(dev);
+    }
+
+    let (iface_number, _) = dev.dfu_descriptors()?;
+    let res = dev._handle_mut().claim_interface(iface_number);
+    match res {
+        Ok(()) => {
+            let _ = dev._handle_mut().release_interface(iface_number);
+            Ok(dev)
+        },
+        Err(
+            e @ (rusb::Error::Access | rusb::Error::Busy | rusb::Error::NoDevice | rusb::Error::NotFound |
+                rusb::Error::Io)
+        ) => {
+            debug!("Re-enumerated probe isn't ready to use yet ({})", e);
+            Err(ErrorKind::DeviceNotFound.error_from(e))
+        },
+        Err(e) => Err(e.into()),
+    }
+}
+
 /// Waits for a Black Magic Probe to reboot, erroring after a timeout.
 ///
 /// This function takes a port string to attempt to keep track of a single physical device
-/// across USB resets.
+/// across USB resets. On macOS, the port path comes from the device's IOKit location ID, which
+/// stays the same across re-enumeration, but macOS is slower to re-enumerate probes than the other
+/// OSes, so it's given at least [`MACOS_REENUMERATE_GRACE`], and we wait for its drivers to let go
+/// of the probe before handing it back.
 ///
 /// This would take a serial number, but serial numbers can actually change between firmware
 /// versions, and thus also between application and bootloader mode, so serial number is not a
 /// reliable way to keep track of a single device across USB resets.
 // TODO: test how reliable the port path is on multiple platforms.
-pub fn wait_for_probe_reboot(port: &str, timeout: Duration, operation: &str) -> Result<BmpDevice, Error>
+#[instrument(level = "debug", skip(custom_ids))]
+pub fn wait_for_probe_reboot(
+    port: &str,
+    custom_ids: CustomUsbIds,
+    timeout: Duration,
+    operation: &str,
+) -> Result<BmpDevice, Error>
 {
+    // A device we were handed the file descriptor of can't be found again once it's re-enumerated.
+    if usb::device_fd().is_some() {
+        return Err(ErrorKind::DeviceFdReenumerated.error());
+    }
+
+    let timeout = if cfg!(target_os = "macos") { timeout.max(MACOS_REENUMERATE_GRACE) } else { timeout };
     let silence_timeout = timeout / 2;
 
-    let matcher = BmpMatcher {
-        index: None,
-        serial: None,
-        port: Some(port.to_string()),
-    };
+    let matcher = BmpMatcher::new()
+        .port(port)
+        .custom_ids(custom_ids);
 
     let start = Instant::now();
+    // Give up on waiting for the probe to be free of the OS's drivers at the same time as we start
+    // complaining about not finding it.
+    let settling = || Instant::now().duration_since(start) <= silence_timeout;
 
-    let mut dev = matcher.find_matching_probes().pop_single_silent();
+    let mut dev = matcher
+        .find_matching_probes()
+        .pop_single_silent()
+        .and_then(|dev| settle(dev, settling()));
 
     while let Err(ErrorKind::DeviceNotFound) = dev.err_kind() {
 
@@ -1103,7 +3207,16 @@ pub fn wait_for_probe_reboot(port: &str, timeout: Duration, operation: &str) ->
             error!(
                 "Timed-out waiting for Black Magic Probe to re-enumerate!"
             );
-            return Err(ErrorKind::DeviceReboot.error_from(dev.unwrap_err()));
+            // Say what we could see instead, in case it came back somewhere unexpected.
+            let seen = BmpMatcher::new()
+                .custom_ids(custom_ids)
+                .find_matching_probes()
+                .found
+                .iter()
+                .map(|dev| format!("{} in {} mode", dev.port(), dev.operating_mode()))
+                .collect();
+            let kind = ErrorKind::RebootTimedOut(Instant::now().duration_since(start), port.to_string(), seen);
+            return Err(kind.error_from(dev.unwrap_err()));
         }
 
         // Wait 200 milliseconds between checks. Hardware is a bottleneck and we
@@ -1117,6 +3230,7 @@ pub fn wait_for_probe_reboot(port: &str, timeout: Duration, operation: &str) ->
         } else {
             dev = matcher.find_matching_probes().pop_single_silent();
         }
+        dev = dev.and_then(|dev| settle(dev, settling()));
     }
 
     let dev = dev?;
@@ -1144,6 +3258,13 @@ impl BmpPlatform
     pub const DRAGON_BOOT_VID_PID: (Vid, Pid) = (Vid(0x1209), Pid(0xbadb));
     pub const STM32_DFU_VID_PID:   (Vid, Pid) = (Vid(0x0483), Pid(0xdf11));
 
+    /// USB IDs that old firmware revisions and some clones enumerate with instead of the current ones,
+    /// along with the mode they imply. Flashing current firmware onto these migrates them to the
+    /// current IDs.
+    ///
+    /// Add entries here, with a note of where they come from, as they're reported.
+    pub const LEGACY_VID_PIDS: &[((Vid, Pid), DfuOperatingMode)] = &[];
+
     pub const fn from_vid_pid(vid: Vid, pid: Pid) -> Option<(Self, DfuOperatingMode)>
     {
         // TODO: in the case that we need to do IO to figure out the platform, this function will need
@@ -1158,8 +3279,26 @@ impl BmpPlatform
             Self::BMD_DFU_VID_PID => Some((BlackMagicDebug, FirmwareUpgrade)),
             Self::DRAGON_BOOT_VID_PID => Some((DragonBoot, FirmwareUpgrade)),
             Self::STM32_DFU_VID_PID => Some((STM32DeviceDFU, FirmwareUpgrade)),
-            _ => None,
+            _ => match Self::legacy_mode(vid, pid) {
+                Some(mode) => Some((BlackMagicDebug, mode)),
+                None => None,
+            },
+        }
+    }
+
+    /// If `vid` and `pid` are one of the [legacy IDs](Self::LEGACY_VID_PIDS), returns the mode they imply.
+    pub const fn legacy_mode(vid: Vid, pid: Pid) -> Option<DfuOperatingMode>
+    {
+        let mut index = 0;
+        while index < Self::LEGACY_VID_PIDS.len() {
+            let ((legacy_vid, legacy_pid), mode) = Self::LEGACY_VID_PIDS[index];
+            if legacy_vid.0 == vid.0 && legacy_pid.0 == pid.0 {
+                return Some(mode);
+            }
+            index += 1;
         }
+
+        None
     }
 
     #[allow(dead_code)]
@@ -1191,23 +3330,145 @@ impl BmpPlatform
         }
     }
 
-    /// Get the load address for firmware of `firm_type` on this platform.
-    pub const fn load_address(self, firm_type: FirmwareType) -> u32
+    /// Get the load address for firmware of `firm_type` on this platform, running on `hardware`
+    /// (if known).
+    pub const fn load_address(self, firm_type: FirmwareType, hardware: Option<ProbeHardware>) -> u32
     {
         use BmpPlatform::*;
         use FirmwareType::*;
 
+        let bootloader_size = match hardware {
+            Some(hardware) => hardware.bootloader_size(),
+            None => 0x2000,
+        };
+
         match self {
             BlackMagicDebug => match firm_type {
-                Bootloader => 0x0800_0000,
-                Application => 0x0800_2000,
+                Bootloader => FLASH_BASE,
+                Application => FLASH_BASE + bootloader_size,
             },
             DragonBoot => 0x0800_2000,
-            STM32DeviceDFU => 0x0800_0000,
+            STM32DeviceDFU => FLASH_BASE,
+        }
+    }
+}
+
+/// Non-standard USB IDs to also consider to be Black Magic Probe devices, for custom firmware builds.
+///
+/// Where only a PID is given, the VID defaults to the standard Black Magic Probe one, and the DFU mode
+/// VID defaults to the runtime mode one.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CustomUsbIds
+{
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    pub dfu_vid: Option<u16>,
+    pub dfu_pid: Option<u16>,
+}
+
+impl CustomUsbIds
+{
+    pub(crate) fn from_cli_args(matches: &ArgMatches) -> Self
+    {
+        Self {
+            vid: matches.get_one::<u16>("vid").copied(),
+            pid: matches.get_one::<u16>("pid").copied(),
+            dfu_vid: matches.get_one::<u16>("dfu-vid").copied(),
+            dfu_pid: matches.get_one::<u16>("dfu-pid").copied(),
+        }
+    }
+
+    /// Fills in any IDs not set in `self` from `other`.
+    #[must_use]
+    pub fn or(self, other: Self) -> Self
+    {
+        Self {
+            vid: self.vid.or(other.vid),
+            pid: self.pid.or(other.pid),
+            dfu_vid: self.dfu_vid.or(other.dfu_vid),
+            dfu_pid: self.dfu_pid.or(other.dfu_pid),
+        }
+    }
+
+    /// The custom runtime mode IDs, if any.
+    pub fn runtime_ids(&self) -> Option<(Vid, Pid)>
+    {
+        let vid = self.vid.unwrap_or(BmpPlatform::BMD_RUNTIME_VID_PID.0.0);
+        self.pid.map(|pid| (Vid(vid), Pid(pid)))
+    }
+
+    /// The custom DFU mode IDs, if any.
+    pub fn dfu_ids(&self) -> Option<(Vid, Pid)>
+    {
+        let vid = self.dfu_vid
+            .or(self.vid)
+            .unwrap_or(BmpPlatform::BMD_DFU_VID_PID.0.0);
+        self.dfu_pid.map(|pid| (Vid(vid), Pid(pid)))
+    }
+
+    /// Like [`BmpPlatform::from_vid_pid()`], but also recognising these custom IDs.
+    pub fn identify(&self, vid: Vid, pid: Pid) -> Option<(BmpPlatform, DfuOperatingMode)>
+    {
+        if self.runtime_ids() == Some((vid, pid)) {
+            Some((BmpPlatform::BlackMagicDebug, DfuOperatingMode::Runtime))
+        } else if self.dfu_ids() == Some((vid, pid)) {
+            Some((BmpPlatform::BlackMagicDebug, DfuOperatingMode::FirmwareUpgrade))
+        } else {
+            BmpPlatform::from_vid_pid(vid, pid)
         }
     }
 }
 
+/// Parses a USB VID or PID, which is always given in hex (as `lsusb` and friends show it), with or
+/// without a leading `0x`.
+pub fn parse_usb_id(id: &str) -> Result<u16, String>
+{
+    let digits = id.strip_prefix("0x").or_else(|| id.strip_prefix("0X")).unwrap_or(id);
+    u16::from_str_radix(digits, 16)
+        .map_err(|_| format!("{:?} is not a valid hexadecimal USB ID (e.g. 1d50)", id))
+}
+
+/// Parses a memory address or length, given in hex with a `0x` prefix, or otherwise in decimal.
+pub fn parse_address(value: &str) -> Result<u32, String>
+{
+    let res = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(digits) => u32::from_str_radix(digits, 16),
+        None => value.parse(),
+    };
+    res.map_err(|_| format!("{:?} is not a valid address or length (e.g. 0x08002000)", value))
+}
+
+/// Parses a serial number to program into a probe, which has to be printable ASCII without spaces, as
+/// the serial numbers probes derive from their MCU's unique ID are.
+pub fn parse_serial(value: &str) -> Result<String, String>
+{
+    if value.is_empty() {
+        return Err(S!("a serial number is required"));
+    }
+    if !value.chars().all(|c| c.is_ascii_graphic()) {
+        return Err(format!("{:?} is not a valid serial number (use letters, digits and punctuation only)", value));
+    }
+    Ok(value.to_string())
+}
+
+/// Parses a hardware variant name, as probes advertise them (e.g. `native` or `blackpill-f411ce`).
+pub fn parse_hardware(value: &str) -> Result<ProbeHardware, String>
+{
+    ProbeHardware::from_variant_string(value)
+        .ok_or_else(|| format!("{:?} is not a hardware variant we know of (e.g. native, stlink, swlink)", value))
+}
+
+/// Parses a DFU alt-setting, given as its index or its name.
+pub fn parse_alt_setting(value: &str) -> Result<AltSetting, String>
+{
+    if value.trim().is_empty() {
+        return Err(S!("an alt-setting index or name is required"));
+    }
+    Ok(value.parse().map_or_else(|_| AltSetting::Name(value.to_string()), AltSetting::Index))
+}
+
+
 /// Defaults to [`BmpPlatform::BlackMagicDebug`].
 impl Default for BmpPlatform
 {
@@ -0,0 +1,164 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+//! Module for bmputil's cache of downloaded firmware releases.
+//!
+//! This lives in the platform's cache directory, e.g. `~/.cache/bmputil` on Linux (or wherever the
+//! `BMPUTIL_CACHE_DIR` environment variable says), with each release
+//! file kept under the release's tag and the hardware it's for, e.g. `firmware/v1.10.2/native/`, and
+//! the release information last looked up kept in `releases/`, so `--offline` can use both.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use tracing::{debug, warn};
+
+use crate::error::{Error, ErrorKind, ErrorSource};
+
+/// The directory bmputil's cache is in, if the platform has a cache directory.
+fn cache_dir() -> Option<PathBuf>
+{
+    env::var_os("BMPUTIL_CACHE_DIR")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| dirs::cache_dir().map(|dir| dir.join("bmputil")))
+}
+
+/// The directory downloaded firmware is cached in, if the platform has a cache directory.
+fn firmware_dir() -> Option<PathBuf>
+{
+    cache_dir().map(|dir| dir.join("firmware"))
+}
+
+/// The directory release information is cached in, if the platform has a cache directory.
+fn index_dir() -> Option<PathBuf>
+{
+    cache_dir().map(|dir| dir.join("releases"))
+}
+
+/// Whether `component` is safe to use in a path in the cache; these mostly come from the internet, so
+/// make sure none of them can take us out of the cache.
+fn is_safe(component: &str) -> bool
+{
+    !component.is_empty() && !component.contains(['/', '\\']) && component != ".."
+}
+
+/// Where the release file `asset` of release `tag`, for hardware `variant`, is cached.
+fn asset_path(tag: &str, variant: &str, asset: &str) -> Option<PathBuf>
+{
+    if !(is_safe(tag) && is_safe(variant) && is_safe(asset)) {
+        return None;
+    }
+
+    firmware_dir().map(|dir| dir.join(tag).join(variant).join(asset))
+}
+
+/// Returns the cached copy of the release file `asset` of release `tag`, for hardware `variant`, if
+/// there is one.
+pub fn load(tag: &str, variant: &str, asset: &str) -> Option<Vec<u8>>
+{
+    let path = asset_path(tag, variant, asset)?;
+    let data = fs::read(&path).ok()?;
+    debug!("Using cached {}", path.display());
+    Some(data)
+}
+
+/// Caches `data` as the release file `asset` of release `tag`, for hardware `variant`.
+pub fn store(tag: &str, variant: &str, asset: &str, data: &[u8])
+{
+    if let Some(path) = asset_path(tag, variant, asset) {
+        write_atomically(&path, data);
+    }
+}
+
+/// Removes the cached copy of the release file `asset` of release `tag`, for hardware `variant`, e.g.
+/// because it turned out to be corrupt.
+pub fn remove(tag: &str, variant: &str, asset: &str)
+{
+    if let Some(path) = asset_path(tag, variant, asset) {
+        if let Err(e) = fs::remove_file(&path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                warn!("Failed to delete cached {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// Returns the cached copy of the release information `name` (e.g. `latest`), if there is one.
+pub fn load_index(name: &str) -> Option<Vec<u8>>
+{
+    if !is_safe(name) {
+        return None;
+    }
+    let path = index_dir()?.join(format!("{}.json", name));
+    let data = fs::read(&path).ok()?;
+    debug!("Using cached {}", path.display());
+    Some(data)
+}
+
+/// Caches `data` as the release information `name`, replacing what was cached before.
+pub fn store_index(name: &str, data: &[u8])
+{
+    if !is_safe(name) {
+        return;
+    }
+    if let Some(dir) = index_dir() {
+        write_atomically(&dir.join(format!("{}.json", name)), data);
+    }
+}
+
+/// Writes `data` to the cache file `path`.
+///
+/// Failing to do so is not worth failing the whole operation over, so this only warns.
+fn write_atomically(path: &Path, data: &[u8])
+{
+    // Write to a temporary file first, so an interrupted write can't leave a truncated file to be used later.
+    // Its name keeps the whole file name, so files differing only by extension don't share one, and the
+    // process ID, so two bmputils caching the same file at once don't write over each other's.
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(format!(".partial.{}", process::id()));
+    let temp_path = path.with_file_name(temp_name);
+    let res = path.parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| fs::write(&temp_path, data))
+        .and_then(|()| fs::rename(&temp_path, path));
+
+    if let Err(e) = res {
+        warn!("Failed to cache {}: {}", path.display(), e);
+    }
+}
+
+/// Deletes all cached firmware and release information, returning how many bytes that freed.
+pub fn clean() -> Result<u64, Error>
+{
+    let mut freed = 0;
+    for dir in [firmware_dir(), index_dir()].into_iter().flatten() {
+        let size = dir_size(&dir);
+        match fs::remove_dir_all(&dir) {
+            Ok(()) => freed += size,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(ErrorKind::External(ErrorSource::StdIo(e)).error().with_ctx("deleting cached firmware")),
+        }
+    }
+
+    Ok(freed)
+}
+
+/// The total size of the files in `dir` and its subdirectories.
+fn dir_size(dir: &Path) -> u64
+{
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+
+    entries
+        .flatten()
+        .map(|entry| match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => dir_size(&entry.path()),
+            _ => entry.metadata().map_or(0, |metadata| metadata.len()),
+        })
+        .sum()
+}
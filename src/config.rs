@@ -0,0 +1,254 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+//! Module for bmputil's configuration file.
+//!
+//! The configuration file lives at `bmputil/config.toml` in the platform's configuration directory,
+//! e.g. `~/.config/bmputil/config.toml` on Linux, unless the `BMPUTIL_CONFIG` environment variable
+//! gives another path, and is entirely optional.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::collections::BTreeMap;
+
+use tracing::debug;
+use serde::Deserialize;
+
+use crate::bmp::CustomUsbIds;
+use crate::release::Channel;
+use crate::error::{Error, ErrorKind};
+use crate::S;
+
+/// bmputil's user configuration.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config
+{
+    /// Friendly names for probes, mapping each name to the probe's serial number.
+    ///
+    /// ```toml
+    /// [aliases]
+    /// bench-left = "7BB180B4"
+    /// ```
+    pub aliases: BTreeMap<String, String>,
+
+    /// The probe to use when none is selected with `--serial`, `--probe`, `--index` or `--port`, by its
+    /// serial number or alias.
+    ///
+    /// ```toml
+    /// default_probe = "bench-left"
+    /// ```
+    pub default_probe: Option<String>,
+
+    /// Whether to remember the last probe used, and use it by default when more than one probe
+    /// matches and none was explicitly selected.
+    pub remember_last_probe: bool,
+
+    /// Non-standard USB IDs to also consider to be Black Magic Probe devices, for custom firmware builds.
+    ///
+    /// ```toml
+    /// [usb_ids]
+    /// pid = 0x6030
+    /// dfu_pid = 0x6031
+    /// ```
+    pub usb_ids: CustomUsbIds,
+
+    /// The DFU transfer size to flash with, in bytes, instead of the probe's own maximum.
+    /// `--transfer-size` takes precedence over this. A size larger than the probe's maximum is an
+    /// error, not clamped.
+    ///
+    /// ```toml
+    /// transfer_size = 256
+    /// ```
+    pub transfer_size: Option<u16>,
+
+    /// How many times to try USB requests that fail for transient reasons (such as a flaky hub)
+    /// before giving up, including the first attempt. `--retries` takes precedence over this.
+    ///
+    /// ```toml
+    /// usb_retries = 5
+    /// ```
+    pub usb_retries: Option<u32>,
+
+    /// How long to give probes to answer each USB request, in milliseconds, for slow or virtualised
+    /// USB stacks. `--timeout` takes precedence over this.
+    ///
+    /// ```toml
+    /// usb_timeout_ms = 10000
+    /// ```
+    pub usb_timeout_ms: Option<u64>,
+
+    /// How long to give probes to drop off the bus after being told to switch to DFU mode, in
+    /// milliseconds. `--detach-timeout` takes precedence over this.
+    ///
+    /// ```toml
+    /// detach_timeout_ms = 1000
+    /// ```
+    pub detach_timeout_ms: Option<u64>,
+
+    /// How long to wait for probes to re-enumerate after switching modes or rebooting, in
+    /// milliseconds. `--reenumerate-timeout` takes precedence over this.
+    ///
+    /// ```toml
+    /// reenumerate_timeout_ms = 15000
+    /// ```
+    pub reenumerate_timeout_ms: Option<u64>,
+
+    /// Which firmware builds `bmputil firmware update` updates to: `stable`, `prerelease`, or `nightly`.
+    /// `--channel` takes precedence over this.
+    ///
+    /// ```toml
+    /// channel = "prerelease"
+    /// ```
+    pub channel: Option<Channel>,
+
+    /// Where to find the latest nightly build, for the `nightly` channel. This should give the build
+    /// in the same form as the GitHub API gives a release, with the firmware files as its assets.
+    ///
+    /// ```toml
+    /// nightly_feed = "https://ci.example.com/blackmagic/nightly/latest.json"
+    /// ```
+    pub nightly_feed: Option<String>,
+
+    /// A mirror of the firmware releases to use instead of GitHub's. It should serve the same
+    /// information GitHub's API does at `/releases`, `/releases/latest` and `/releases/tags/<tag>`,
+    /// relative to this URL. `--mirror` takes precedence over this.
+    ///
+    /// ```toml
+    /// mirror = "https://artifacts.example.com/blackmagic/releases"
+    /// ```
+    pub mirror: Option<String>,
+
+    /// The proxy to download firmware through, rather than the one set by `HTTPS_PROXY` in the
+    /// environment. `--proxy` takes precedence over this.
+    ///
+    /// ```toml
+    /// proxy = "http://proxy.example.com:3128"
+    /// ```
+    pub proxy: Option<String>,
+
+    /// Whether `bmputil firmware update` should refuse firmware releases that aren't signed by one of the
+    /// `signing_keys`. bmputil doesn't pin keys of its own yet, so this needs those.
+    ///
+    /// ```toml
+    /// require_signed = true
+    /// ```
+    pub require_signed: bool,
+
+    /// The minisign public keys to trust firmware releases to be signed with, e.g. for in-house builds.
+    ///
+    /// ```toml
+    /// signing_keys = ["RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3"]
+    /// ```
+    pub signing_keys: Vec<String>,
+}
+
+impl Config
+{
+    /// The path the configuration file is loaded from, if the platform has a configuration directory.
+    pub fn path() -> Option<PathBuf>
+    {
+        env::var_os("BMPUTIL_CONFIG")
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| dirs::config_dir().map(|dir| dir.join("bmputil").join("config.toml")))
+    }
+
+    /// Loads the configuration file, falling back to the defaults if there isn't one.
+    pub fn load() -> Result<Self, Error>
+    {
+        let path = match Self::path() {
+            Some(path) => path,
+            None => return Ok(Default::default()),
+        };
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                debug!("No configuration file at {}; using defaults", path.display());
+                return Ok(Default::default());
+            },
+            Err(e) => return Err(ErrorKind::ConfigFile(path.display().to_string()).error_from(e)),
+        };
+
+        debug!("Loading configuration from {}", path.display());
+        toml::from_str(&contents)
+            .map_err(|e| ErrorKind::ConfigFile(path.display().to_string()).error_from(e))
+    }
+
+    /// Sets `key` to `value` (in TOML, e.g. `"stable"`, quotes included) in the configuration file,
+    /// creating the file if there isn't one yet. Everything else in it, comments included, is left alone.
+    ///
+    /// Returns the path of the configuration file.
+    pub fn save_setting(key: &str, value: &str) -> Result<PathBuf, Error>
+    {
+        let path = Self::path()
+            .ok_or_else(|| ErrorKind::Unsupported(S!("there is no configuration directory to save settings in")).error())?;
+        let path_string = path.display().to_string();
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(ErrorKind::ConfigFile(path_string).error_from(e)),
+        };
+
+        // Settings like this one come before the first [table].
+        let mut lines = contents.lines().map(String::from).collect::<Vec<_>>();
+        let mut top_level_end = lines
+            .iter()
+            .position(|line| line.trim_start().starts_with('['))
+            .unwrap_or(lines.len());
+        while top_level_end > 0 && lines[top_level_end - 1].trim().is_empty() {
+            top_level_end -= 1;
+        }
+        let existing = lines[..top_level_end].iter().position(|line| {
+            line.split_once('=')
+                .is_some_and(|(name, _value)| name.trim() == key && !line.trim_start().starts_with('#'))
+        });
+        let setting = format!("{} = {}", key, value);
+        match existing {
+            Some(index) => lines[index] = setting,
+            None => lines.insert(top_level_end, setting),
+        };
+
+        let mut contents = lines.join("\n");
+        contents.push('\n');
+
+        // Don't leave a file behind that bmputil itself can't load.
+        toml::from_str::<Config>(&contents)
+            .map_err(|e| ErrorKind::ConfigFileWrite(path_string.clone()).error_from(e))?;
+
+        path.parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| fs::write(&path, contents))
+            .map_err(|e| ErrorKind::ConfigFileWrite(path_string).error_from(e))?;
+
+        Ok(path)
+    }
+
+    /// Returns the serial number of the default probe, if one is configured.
+    pub fn default_serial(&self) -> Option<&str>
+    {
+        let probe = self.default_probe.as_deref()?;
+        Some(self.resolve_alias(probe).unwrap_or(probe))
+    }
+
+    /// Returns the serial number the probe alias `name` refers to.
+    pub fn resolve_alias(&self, name: &str) -> Result<&str, Error>
+    {
+        self.aliases
+            .get(name)
+            .map(|serial| serial.as_str())
+            .ok_or_else(|| ErrorKind::UnknownProbeAlias(name.to_string()).error())
+    }
+
+    /// Returns the alias given to the probe with serial number `serial`, if there is one.
+    pub fn alias_for_serial(&self, serial: &str) -> Option<&str>
+    {
+        self.aliases
+            .iter()
+            .find(|(_name, aliased)| aliased.eq_ignore_ascii_case(serial))
+            .map(|(name, _aliased)| name.as_str())
+    }
+}
@@ -0,0 +1,132 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+//! Module for `bmputil install-devd-rules`, FreeBSD's equivalent of [`crate::udev`]. Without them,
+//! only root can open a probe's `/dev/ugen` device node.
+//!
+//! The rules have devd give a group (`usb`, by default, as in the FreeBSD Handbook) access to the
+//! device node of any probe that's plugged in, by every USB ID bmputil knows a probe by in both
+//! runtime and DFU mode, along with any custom IDs in use. devd only acts on devices as they attach,
+//! so probes that are already plugged in are given access directly. A probe's serial ports (`cuaU`)
+//! belong to the `dialer` group already.
+
+use std::fmt::Write as _;
+use std::path::Path;
+
+use clap::ArgMatches;
+use tracing::debug;
+
+use crate::access::{as_root, ensure_group, in_group, known_ids, probes, run, verify, write_as_root};
+use crate::bmp::CustomUsbIds;
+use crate::config::Config;
+use crate::error::Error;
+use crate::{status, tr};
+
+/// Where devd configuration local to this machine goes, which the stock devd.conf includes.
+pub const RULES_DIR: &str = "/usr/local/etc/devd";
+
+/// The name of the rules file in [`RULES_DIR`].
+const RULES_FILE: &str = "bmputil.conf";
+
+/// The group given access to probes unless another is asked for.
+pub const DEFAULT_GROUP: &str = "usb";
+
+/// Writes the devd rules giving `group` access to probes, recognising `custom_ids` as well as the usual IDs.
+pub fn rules(group: &str, custom_ids: CustomUsbIds) -> String
+{
+    let mut rules = format!(
+        "# devd rules giving access to Black Magic Probes, written by bmputil {} (bmputil install-devd-rules).\n",
+        env!("CARGO_PKG_VERSION"),
+    );
+    let _ = writeln!(rules, "# Access is given to members of the {} group.", group);
+
+    for ((vid, pid), description, _mode) in known_ids(custom_ids) {
+        let _ = write!(
+            rules,
+            concat!(
+                "\n# {}\n",
+                "notify 100 {{\n",
+                "\tmatch \"system\"\t\t\"USB\";\n",
+                "\tmatch \"subsystem\"\t\"DEVICE\";\n",
+                "\tmatch \"type\"\t\t\"ATTACH\";\n",
+                "\tmatch \"vendor\"\t\t\"0x{:04x}\";\n",
+                "\tmatch \"product\"\t\t\"0x{:04x}\";\n",
+                "\taction \"chgrp {} /dev/$cdev && chmod 0660 /dev/$cdev\";\n",
+                "}};\n",
+            ),
+            description,
+            vid.0,
+            pid.0,
+            group,
+        );
+    }
+
+    rules
+}
+
+/// Gives `group` access to the device nodes of the probes (recognised with `custom_ids`) already
+/// plugged in, which devd won't see attach until they're next plugged in.
+fn apply_now(group: &str, custom_ids: CustomUsbIds) -> Result<(), Error>
+{
+    for device in probes(custom_ids)? {
+        // ugen device nodes are named for the bus and address, e.g. /dev/ugen0.5, and are links to
+        // the real node, which chgrp and chmod follow.
+        let node = format!("/dev/ugen{}.{}", device.bus_number(), device.address());
+        if !Path::new(&node).exists() {
+            debug!("No device node {} to give access to", node);
+            continue;
+        }
+
+        let mut chgrp = as_root("chgrp");
+        chgrp.args([group, &node]);
+        run(&mut chgrp)?;
+        let mut chmod = as_root("chmod");
+        chmod.args(["0660", &node]);
+        run(&mut chmod)?;
+    }
+
+    Ok(())
+}
+
+/// Installs devd rules giving `group` access to probes (including any with `custom_ids`), restarts
+/// devd, and checks the rules work.
+pub fn install(group: &str, custom_ids: CustomUsbIds) -> Result<(), Error>
+{
+    ensure_group(group)?;
+
+    let path = Path::new(RULES_DIR).join(RULES_FILE);
+    status!("{}", tr!("devd-installing", path = path.display().to_string()));
+    let mut mkdir = as_root("mkdir");
+    mkdir.args(["-p", RULES_DIR]);
+    run(&mut mkdir)?;
+    write_as_root(&path, &rules(group, custom_ids))?;
+
+    status!("{}", tr!("devd-restarting"));
+    let mut restart = as_root("service");
+    restart.args(["devd", "restart"]);
+    run(&mut restart)?;
+    apply_now(group, custom_ids)?;
+
+    if !in_group(group) {
+        status!("{}", tr!("devd-not-in-group", group = group));
+        return Ok(());
+    }
+
+    verify(custom_ids, &tr!("devd-verify-failed", group = group))
+}
+
+/// Runs `bmputil install-devd-rules`.
+pub fn install_command(matches: &ArgMatches, config: &Config) -> Result<(), Error>
+{
+    let group = matches
+        .get_one::<String>("group")
+        .map(String::as_str)
+        .unwrap_or(DEFAULT_GROUP);
+    let custom_ids = CustomUsbIds::from_cli_args(matches).or(config.usb_ids);
+
+    if matches.get_flag("print") {
+        print!("{}", rules(group, custom_ids));
+        return Ok(());
+    }
+
+    install(group, custom_ids)
+}
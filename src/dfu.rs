@@ -0,0 +1,825 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+//! Module implementing the host side of the USB DFU 1.1 protocol, and ST's DfuSe extensions to it.
+//!
+//! This sits directly on top of libusb control transfers, so that we're in charge of the polling
+//! timing, how the device's status is handled, and how errors are reported.
+
+use std::thread;
+use std::cell::Cell;
+use std::fmt::{self, Display, Formatter};
+use std::time::Duration;
+
+use tracing::{debug, trace, warn};
+use rusb::{Direction, Recipient, RequestType};
+
+use crate::S;
+use crate::error::{Error, ErrorKind, ErrorSource};
+use crate::retry::RetryPolicy;
+use crate::usb::{DfuFunctionalDescriptor, DfuRequest};
+
+/// The DFU version DfuSe bootloaders report in their functional descriptor.
+pub const DFUSE_VERSION: u16 = 0x011a;
+
+/// The DfuSe command to set the address pointer.
+const DFUSE_SET_ADDRESS: u8 = 0x21;
+/// The DfuSe command to erase the page or sector containing an address.
+pub const DFUSE_ERASE: u8 = 0x41;
+/// The DfuSe command to remove read protection, which the STM32 built-in bootloader has.
+pub const DFUSE_READ_UNPROTECT: u8 = 0x92;
+
+/// The log target every DFU request and status poll is logged under (at trace level), for debugging
+/// bootloaders. `--trace-dfu` turns it on.
+pub const TRACE_TARGET: &str = "bmputil::dfu::trace";
+
+/// The smallest transfer size we'll fall back to when a device stalls on a download block, which is
+/// the smallest control endpoint packet size full-speed devices can have.
+const MIN_TRANSFER_SIZE: u16 = 8;
+
+/// The functional descriptor's bmAttributes bits.
+const BIT_CAN_UPLOAD: u8 = 1 << 1;
+const BIT_MANIFESTATION_TOLERANT: u8 = 1 << 2;
+const BIT_WILL_DETACH: u8 = 1 << 3;
+
+
+/// The states of the DFU state machine, as reported by DFU_GETSTATUS (DFU 1.1 §6.1.2).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DfuState
+{
+    AppIdle,
+    AppDetach,
+    DfuIdle,
+    DnloadSync,
+    DnBusy,
+    DnloadIdle,
+    ManifestSync,
+    Manifest,
+    ManifestWaitReset,
+    UploadIdle,
+    Error,
+    /// A state not defined by the specification.
+    Unknown(u8),
+}
+
+impl From<u8> for DfuState
+{
+    fn from(value: u8) -> Self
+    {
+        use DfuState::*;
+        match value {
+            0 => AppIdle,
+            1 => AppDetach,
+            2 => DfuIdle,
+            3 => DnloadSync,
+            4 => DnBusy,
+            5 => DnloadIdle,
+            6 => ManifestSync,
+            7 => Manifest,
+            8 => ManifestWaitReset,
+            9 => UploadIdle,
+            10 => Error,
+            other => Unknown(other),
+        }
+    }
+}
+
+impl Display for DfuState
+{
+    /// Displays the state by the name the specification gives it, e.g. `dfuDNBUSY`.
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result
+    {
+        use DfuState::*;
+        match self {
+            AppIdle => write!(f, "appIDLE"),
+            AppDetach => write!(f, "appDETACH"),
+            DfuIdle => write!(f, "dfuIDLE"),
+            DnloadSync => write!(f, "dfuDNLOAD-SYNC"),
+            DnBusy => write!(f, "dfuDNBUSY"),
+            DnloadIdle => write!(f, "dfuDNLOAD-IDLE"),
+            ManifestSync => write!(f, "dfuMANIFEST-SYNC"),
+            Manifest => write!(f, "dfuMANIFEST"),
+            ManifestWaitReset => write!(f, "dfuMANIFEST-WAIT-RESET"),
+            UploadIdle => write!(f, "dfuUPLOAD-IDLE"),
+            Error => write!(f, "dfuERROR"),
+            Unknown(state) => write!(f, "unknown state {}", state),
+        }
+    }
+}
+
+
+/// The status codes reported by DFU_GETSTATUS, saying whether the last request succeeded, and if
+/// not why (DFU 1.1 §6.1.2).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DfuStatusCode
+{
+    Ok,
+    ErrTarget,
+    ErrFile,
+    ErrWrite,
+    ErrErase,
+    ErrCheckErased,
+    ErrProg,
+    ErrVerify,
+    ErrAddress,
+    ErrNotDone,
+    ErrFirmware,
+    ErrVendor,
+    ErrUsbReset,
+    ErrPowerOnReset,
+    ErrUnknown,
+    ErrStalledPacket,
+    /// A status not defined by the specification.
+    Unknown(u8),
+}
+
+impl DfuStatusCode
+{
+    /// What the status means, as the specification describes it.
+    pub fn description(self) -> &'static str
+    {
+        use DfuStatusCode::*;
+        match self {
+            Ok => "no error",
+            ErrTarget => "file is not targeted for use by this device",
+            ErrFile => "file is for this device but fails some vendor-specific verification test",
+            ErrWrite => "device is unable to write memory",
+            ErrErase => "memory erase function failed",
+            ErrCheckErased => "memory erase check failed",
+            ErrProg => "program memory function failed",
+            ErrVerify => "programmed memory failed verification",
+            ErrAddress => "cannot program memory due to received address that is out of range",
+            ErrNotDone => "received a zero-length download, but the device does not think it has all the data yet",
+            ErrFirmware => "device's firmware is corrupt; it cannot return to run-time operations",
+            ErrVendor => "vendor-specific error",
+            ErrUsbReset => "device detected unexpected USB reset signaling",
+            ErrPowerOnReset => "device detected unexpected power on reset",
+            ErrUnknown => "something went wrong, but the device does not know what it was",
+            ErrStalledPacket => "device stalled an unexpected request",
+            Unknown(_) => "unknown status",
+        }
+    }
+}
+
+impl From<u8> for DfuStatusCode
+{
+    fn from(value: u8) -> Self
+    {
+        use DfuStatusCode::*;
+        match value {
+            0x00 => Ok,
+            0x01 => ErrTarget,
+            0x02 => ErrFile,
+            0x03 => ErrWrite,
+            0x04 => ErrErase,
+            0x05 => ErrCheckErased,
+            0x06 => ErrProg,
+            0x07 => ErrVerify,
+            0x08 => ErrAddress,
+            0x09 => ErrNotDone,
+            0x0a => ErrFirmware,
+            0x0b => ErrVendor,
+            0x0c => ErrUsbReset,
+            0x0d => ErrPowerOnReset,
+            0x0e => ErrUnknown,
+            0x0f => ErrStalledPacket,
+            other => Unknown(other),
+        }
+    }
+}
+
+impl Display for DfuStatusCode
+{
+    /// Displays the status by the name the specification gives it, e.g. `errVERIFY`.
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result
+    {
+        use DfuStatusCode::*;
+        match self {
+            Ok => write!(f, "OK"),
+            ErrTarget => write!(f, "errTARGET"),
+            ErrFile => write!(f, "errFILE"),
+            ErrWrite => write!(f, "errWRITE"),
+            ErrErase => write!(f, "errERASE"),
+            ErrCheckErased => write!(f, "errCHECK_ERASED"),
+            ErrProg => write!(f, "errPROG"),
+            ErrVerify => write!(f, "errVERIFY"),
+            ErrAddress => write!(f, "errADDRESS"),
+            ErrNotDone => write!(f, "errNOTDONE"),
+            ErrFirmware => write!(f, "errFIRMWARE"),
+            ErrVendor => write!(f, "errVENDOR"),
+            ErrUsbReset => write!(f, "errUSBR"),
+            ErrPowerOnReset => write!(f, "errPOR"),
+            ErrUnknown => write!(f, "errUNKNOWN"),
+            ErrStalledPacket => write!(f, "errSTALLEDPKT"),
+            Unknown(status) => write!(f, "status 0x{:02x}", status),
+        }
+    }
+}
+
+
+/// The response to a DFU_GETSTATUS request.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DfuStatus
+{
+    /// Whether the last request succeeded.
+    pub status: DfuStatusCode,
+
+    /// How long the host should wait before asking for the status again.
+    pub poll_timeout: Duration,
+
+    /// The state the device is now in.
+    pub state: DfuState,
+}
+
+impl DfuStatus
+{
+    /// Returns an error if the device reported that the last request failed, saying how far into
+    /// the data being downloaded we were, if that's known.
+    fn check(self, offset: Option<usize>) -> Result<Self, Error>
+    {
+        if self.status == DfuStatusCode::Ok {
+            Ok(self)
+        } else {
+            Err(ErrorKind::DfuStatus(self.status, self.state, offset).error())
+        }
+    }
+}
+
+
+/// A claimed DFU interface of a device, on which DFU requests can be made.
+///
+/// Claiming the interface (and selecting the alt-setting) is left to the caller, as is releasing it.
+pub struct DfuInterface<'h>
+{
+    handle: &'h rusb::DeviceHandle<rusb::Context>,
+    number: u8,
+    func_desc: DfuFunctionalDescriptor,
+    /// This can shrink part way through a download, if the device can't keep up.
+    transfer_size: Cell<u16>,
+    retry: RetryPolicy,
+    /// The timeout for individual control requests.
+    request_timeout: Duration,
+}
+
+impl<'h> DfuInterface<'h>
+{
+    /// `transfer_size` is the most to send or receive in one request, which should be at most the
+    /// functional descriptor's wTransferSize.
+    pub fn new(
+        handle: &'h rusb::DeviceHandle<rusb::Context>,
+        number: u8,
+        func_desc: DfuFunctionalDescriptor,
+        transfer_size: u16,
+        retry: RetryPolicy,
+        request_timeout: Duration,
+    ) -> Self
+    {
+        Self {
+            handle,
+            number,
+            func_desc,
+            transfer_size: Cell::new(transfer_size),
+            retry,
+            request_timeout,
+        }
+    }
+
+    /// The most that's sent or received in one request.
+    pub fn transfer_size(&self) -> u16
+    {
+        self.transfer_size.get()
+    }
+
+    /// Whether the device speaks ST's DfuSe extensions to DFU.
+    pub fn is_dfuse(&self) -> bool
+    {
+        self.func_desc.bcdDFUVersion == DFUSE_VERSION
+    }
+
+    /// Whether the device supports reading its memory back (DFU_UPLOAD).
+    pub fn can_upload(&self) -> bool
+    {
+        self.func_desc.bmAttributes & BIT_CAN_UPLOAD != 0
+    }
+
+    /// Whether the device stays in DFU mode after manifesting new firmware.
+    pub fn is_manifestation_tolerant(&self) -> bool
+    {
+        self.func_desc.bmAttributes & BIT_MANIFESTATION_TOLERANT != 0
+    }
+
+    /// Whether the device detaches from the bus by itself on DFU_DETACH, rather than waiting to be reset.
+    pub fn will_detach(&self) -> bool
+    {
+        self.func_desc.bmAttributes & BIT_WILL_DETACH != 0
+    }
+
+    fn request_out(&self, request: DfuRequest, value: u16, data: &[u8]) -> Result<usize, rusb::Error>
+    {
+        let request_type = rusb::request_type(Direction::Out, RequestType::Class, Recipient::Interface);
+        let res = self.handle.write_control(
+            request_type, // bmRequestType
+            request as u8, // bRequest
+            value, // wValue
+            self.number as u16, // wIndex
+            data,
+            self.request_timeout,
+        );
+        trace!(target: TRACE_TARGET, "{:?} (wValue {}, {} bytes out) -> {:?}", request, value, data.len(), res);
+        res
+    }
+
+    fn request_in(&self, request: DfuRequest, value: u16, buf: &mut [u8]) -> Result<usize, rusb::Error>
+    {
+        let request_type = rusb::request_type(Direction::In, RequestType::Class, Recipient::Interface);
+        let res = self.handle.read_control(
+            request_type, // bmRequestType
+            request as u8, // bRequest
+            value, // wValue
+            self.number as u16, // wIndex
+            buf,
+            self.request_timeout,
+        );
+        trace!(target: TRACE_TARGET, "{:?} (wValue {}, {} bytes in) -> {:?}", request, value, buf.len(), res);
+        res
+    }
+
+    /// How long, in milliseconds, the device says it'll wait for a USB reset after DFU_DETACH.
+    pub fn detach_timeout(&self) -> u16
+    {
+        self.func_desc.wDetachTimeOut
+    }
+
+    /// Performs a DFU_DETACH request, asking a device in runtime mode to switch to DFU mode, and to
+    /// wait up to `timeout` milliseconds for the USB reset if it won't detach by itself.
+    pub fn detach(&self, timeout: u16) -> Result<(), Error>
+    {
+        self.request_out(DfuRequest::Detach, timeout, &[])?;
+        Ok(())
+    }
+
+    /// Performs a DFU_GETSTATUS request.
+    pub fn get_status(&self) -> Result<DfuStatus, Error>
+    {
+        let mut buf = [0u8; 6];
+        self.retry.run("DFU_GETSTATUS request", || {
+            self.request_in(DfuRequest::GetStatus, 0, &mut buf).map_err(Error::from)
+        })?;
+
+        let status = DfuStatus {
+            status: DfuStatusCode::from(buf[0]),
+            poll_timeout: Duration::from_millis(u32::from_le_bytes([buf[1], buf[2], buf[3], 0]) as u64),
+            state: DfuState::from(buf[4]),
+        };
+        trace!(
+            target: TRACE_TARGET,
+            "DFU_GETSTATUS: state {}, status {}, poll timeout {} ms",
+            status.state,
+            status.status,
+            status.poll_timeout.as_millis(),
+        );
+
+        Ok(status)
+    }
+
+    /// Performs a DFU_GETSTATE request, which (unlike DFU_GETSTATUS) doesn't move the device's state
+    /// machine along.
+    pub fn get_state(&self) -> Result<DfuState, Error>
+    {
+        let mut buf = [0u8; 1];
+        self.retry.run("DFU_GETSTATE request", || {
+            self.request_in(DfuRequest::GetState, 0, &mut buf).map_err(Error::from)
+        })?;
+
+        let state = DfuState::from(buf[0]);
+        trace!(target: TRACE_TARGET, "DFU_GETSTATE: state {}", state);
+
+        Ok(state)
+    }
+
+    /// Performs a DFU_CLRSTATUS request, taking the device out of the dfuERROR state.
+    pub fn clear_status(&self) -> Result<(), Error>
+    {
+        self.retry.run("DFU_CLRSTATUS request", || {
+            self.request_out(DfuRequest::ClrStatus, 0, &[]).map_err(Error::from)
+        })?;
+        Ok(())
+    }
+
+    /// Performs a DFU_ABORT request, returning the device to the dfuIDLE state.
+    pub fn abort(&self) -> Result<(), Error>
+    {
+        self.retry.run("DFU_ABORT request", || {
+            self.request_out(DfuRequest::Abort, 0, &[]).map_err(Error::from)
+        })?;
+        Ok(())
+    }
+
+    /// Gets the device into the dfuIDLE state, from whatever state a previous operation left it in.
+    pub fn ensure_idle(&self) -> Result<(), Error>
+    {
+        match self.get_status()?.state {
+            DfuState::DfuIdle => return Ok(()),
+            DfuState::Error => {
+                debug!("Device is in dfuERROR; clearing its status");
+                self.clear_status()?;
+            },
+            state => {
+                debug!("Device is in {}; aborting back to dfuIDLE", state);
+                self.abort()?;
+            },
+        };
+
+        match self.get_state()? {
+            DfuState::DfuIdle => Ok(()),
+            state => Err(ErrorKind::DeviceSeemsInvalid(format!("DFU state {} instead of dfuIDLE", state)).error()),
+        }
+    }
+
+    /// Performs a DFU_DNLOAD request with block number `block`, then waits for the device to finish
+    /// with it. `offset` is how far into the whole download the block is, if it's part of one.
+    fn download_block(&self, block: u16, data: &[u8], offset: Option<usize>) -> Result<DfuStatus, Error>
+    {
+        trace!(target: TRACE_TARGET, "DFU_DNLOAD block {} ({} bytes)", block, data.len());
+        self.request_out(DfuRequest::Dnload, block, data)?;
+
+        // The device only gets on with the block once we ask for the status, and then we need to
+        // keep asking until it's done, waiting as long as it tells us to each time.
+        loop {
+            let status = self.get_status()?.check(offset)?;
+            match status.state {
+                DfuState::DnBusy | DfuState::DnloadSync => thread::sleep(status.poll_timeout),
+                _ => return Ok(status),
+            }
+        }
+    }
+
+    /// Sends a DfuSe command (the command byte, then its arguments) to the device, and waits for it to be
+    /// carried out. DfuSe commands are sent as download block 0.
+    fn dfuse_command(&self, command: &[u8]) -> Result<(), Error>
+    {
+        if !self.is_dfuse() {
+            return Err(ErrorKind::Unsupported(
+                S!("this probe's bootloader does not support the DfuSe extensions to DFU")
+            ).error());
+        }
+
+        self.download_block(0, command, None)?;
+        Ok(())
+    }
+
+    /// Sets the address that subsequent DfuSe downloads and uploads start at.
+    pub fn dfuse_set_address(&self, address: u32) -> Result<(), Error>
+    {
+        let mut command = vec![DFUSE_SET_ADDRESS];
+        command.extend_from_slice(&address.to_le_bytes());
+        self.dfuse_command(&command)
+    }
+
+    /// Erases the page or sector of flash containing `address`, with the DfuSe erase command.
+    pub fn dfuse_erase(&self, address: u32) -> Result<(), Error>
+    {
+        debug!("Erasing flash at 0x{:08x}", address);
+        let mut command = vec![DFUSE_ERASE];
+        command.extend_from_slice(&address.to_le_bytes());
+        self.dfuse_command(&command)
+    }
+
+    /// Erases the whole of the flash, with the DfuSe erase command given no address.
+    ///
+    /// Only the STM32s' built-in bootloader supports this, as it's not running from that flash.
+    pub fn dfuse_mass_erase(&self) -> Result<(), Error>
+    {
+        debug!("Mass erasing flash");
+        self.dfuse_command(&[DFUSE_ERASE])
+    }
+
+    /// Removes read protection, with the DfuSe read unprotect command. The device mass erases the whole
+    /// of the flash, and then resets, so it disconnects from the bus.
+    pub fn dfuse_read_unprotect(&self) -> Result<(), Error>
+    {
+        debug!("Removing read protection");
+        match self.dfuse_command(&[DFUSE_READ_UNPROTECT]) {
+            Err(e) if is_disconnect(&e) => {
+                debug!("Device disconnected after removing read protection, as expected");
+                Ok(())
+            },
+            other => other,
+        }
+    }
+
+    /// Asks the device which DfuSe commands it supports, with the DfuSe "get" command (an upload of
+    /// block 0). The first is always the "get" command itself (0x00).
+    pub fn dfuse_supported_commands(&self) -> Result<Vec<u8>, Error>
+    {
+        if !self.is_dfuse() {
+            return Ok(Vec::new());
+        }
+
+        self.ensure_idle()?;
+        let mut buf = [0u8; 16];
+        let read = self.retry.run("DfuSe get command", || {
+            self.request_in(DfuRequest::Upload, 0, &mut buf).map_err(Error::from)
+        })?;
+        self.abort()?;
+
+        Ok(buf[..read].to_vec())
+    }
+
+    /// Downloads `firmware` to the device, to be written at `address`, calling `progress` with the
+    /// number of bytes just written after each block.
+    ///
+    /// DfuSe devices are told to erase `pages` (each given as its address and size) first, and where
+    /// to write; plain DFU devices have no notion of addresses, and take care of erasing themselves.
+    ///
+    /// If `resume_from` isn't 0, the first that many bytes of `firmware` have already been written by
+    /// an earlier, interrupted, download, and are skipped. Only DfuSe devices can be told where to
+    /// carry on from, so plain DFU ones always start over.
+    ///
+    /// This doesn't ask the device to manifest the firmware; see [`DfuInterface::manifest()`].
+    pub fn download<P>(
+        &self,
+        address: u32,
+        firmware: &[u8],
+        resume_from: usize,
+        pages: &[(u32, u32)],
+        progress: P,
+    ) -> Result<(), Error>
+    where
+        P: Fn(usize),
+    {
+        let dfuse = self.is_dfuse();
+        if !dfuse {
+            self.check_block_count(firmware.len())?;
+        }
+        self.ensure_idle()?;
+
+        let resume_from = if dfuse {
+            let start = address + resume_from as u32;
+            let end = address + firmware.len() as u32;
+            for &(page_address, page_size) in pages {
+                if page_address < end && page_address + page_size > start {
+                    self.dfuse_erase(page_address)?;
+                }
+            }
+            resume_from
+        } else {
+            0
+        };
+
+        let mut offset = resume_from;
+        let mut block: u16 = 0;
+        while offset < firmware.len() {
+            let transfer_size = self.transfer_size();
+            let chunk = &firmware[offset..(offset + transfer_size as usize).min(firmware.len())];
+
+            // DfuSe devices work out where a block goes from its number (counting from 2) times their own
+            // wTransferSize, which our transfer size needn't match, so each block is sent as block 2 with
+            // the address pointer set to where it goes.
+            let res = if dfuse {
+                self.dfuse_set_address(address + offset as u32)
+                    .and_then(|()| self.download_block(2, chunk, Some(offset)))
+            } else {
+                self.download_block(block, chunk, Some(offset))
+            };
+
+            match res {
+                // Some bootloader builds advertise a transfer size they can't actually sustain on some
+                // hosts, and stall. DfuSe devices can be told to carry on from where they were, so we
+                // can try again with smaller blocks.
+                Err(e) if is_stall(&e) && dfuse && transfer_size / 2 >= MIN_TRANSFER_SIZE => {
+                    warn!(
+                        "Device stalled on a {}-byte block at offset {}; retrying with {}-byte blocks",
+                        transfer_size,
+                        offset,
+                        transfer_size / 2,
+                    );
+                    self.clear_status()?;
+                    self.transfer_size.set(transfer_size / 2);
+                    continue;
+                },
+                res => res?,
+            };
+
+            progress(chunk.len());
+            offset += chunk.len();
+            block = block.wrapping_add(1);
+        }
+
+        Ok(())
+    }
+
+    /// Checks `length` bytes can be downloaded in blocks of our transfer size without running out of
+    /// block numbers, before we start, rather than finding out part way through. Only plain DFU devices
+    /// can run out, as DfuSe ones are sent every block as block 2.
+    fn check_block_count(&self, length: usize) -> Result<(), Error>
+    {
+        let blocks = length.div_ceil(self.transfer_size() as usize);
+        if blocks > 0x1_0000 {
+            return Err(ErrorKind::TooManyBlocks(length, self.transfer_size()).error());
+        }
+
+        Ok(())
+    }
+
+    /// Tells the device the download is complete with a zero-length DFU_DNLOAD, so it manifests
+    /// the new firmware, and then gets it to reboot into it.
+    ///
+    /// The device may well disconnect in the middle of this, which isn't an error.
+    pub fn manifest(&self) -> Result<(), Error>
+    {
+        let res = self.request_out(DfuRequest::Dnload, 0, &[])
+            .map_err(Error::from)
+            .and_then(|_| self.wait_for_manifestation());
+
+        match res {
+            Err(e) if is_disconnect(&e) => {
+                debug!("Device disconnected while manifesting, as expected");
+                Ok(())
+            },
+            other => other,
+        }
+    }
+
+    fn wait_for_manifestation(&self) -> Result<(), Error>
+    {
+        loop {
+            let status = self.get_status()?.check(None)?;
+            match status.state {
+                DfuState::ManifestSync | DfuState::Manifest => thread::sleep(status.poll_timeout),
+                // Manifestation-tolerant devices go back to dfuIDLE, and otherwise the device resets
+                // itself once it's done.
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Has the device leave DFU mode, by manifesting (an empty download, if nothing was downloaded)
+    /// and then, for manifestation-tolerant devices which would otherwise stay in DFU mode, telling
+    /// it to reboot.
+    pub fn leave(&self) -> Result<(), Error>
+    {
+        self.manifest()?;
+        if !self.is_manifestation_tolerant() {
+            return Ok(());
+        }
+
+        let res = if self.will_detach() {
+            self.detach(self.detach_timeout())
+        } else {
+            debug!("Resetting device to leave DFU mode");
+            match self.handle.reset() {
+                Ok(()) | Err(rusb::Error::NotFound) => Ok(()),
+                Err(e) => Err(e.into()),
+            }
+        };
+
+        match res {
+            Err(e) if is_disconnect(&e) => Ok(()),
+            other => other,
+        }
+    }
+
+    /// Reads `length` bytes of memory back from the device with DFU_UPLOAD requests, starting at
+    /// `address`, or fewer if the device runs out.
+    ///
+    /// DfuSe devices are told the address of each block to read; plain DFU ones have no notion of
+    /// addresses, and always read from the start of the memory they'd write.
+    pub fn upload(&self, address: u32, length: u32) -> Result<Vec<u8>, Error>
+    {
+        self.ensure_idle()?;
+
+        let dfuse = self.is_dfuse();
+        let length = length as usize;
+        let mut data = Vec::with_capacity(length);
+        let mut next_block: u16 = 0;
+        while data.len() < length {
+            // As with downloads, DfuSe devices work out where to read a block from with their own
+            // wTransferSize, so each block is read as block 2 from the address pointer, set to where it is.
+            let block = if dfuse {
+                self.dfuse_set_address(address + data.len() as u32)?;
+                self.abort()?;
+                2
+            } else {
+                next_block
+            };
+
+            let mut buf = vec![0; (self.transfer_size() as usize).min(length - data.len())];
+            let read = self.retry.run("DFU_UPLOAD request", || {
+                self.request_in(DfuRequest::Upload, block, &mut buf).map_err(Error::from)
+            })?;
+
+            // A short read means the device has nothing more to give us.
+            data.extend_from_slice(&buf[..read]);
+            if read < buf.len() {
+                break;
+            }
+            next_block = next_block.wrapping_add(1);
+        }
+
+        self.abort()?;
+
+        Ok(data)
+    }
+}
+
+
+/// Whether `e` is the device stalling a request, i.e. refusing it.
+fn is_stall(e: &Error) -> bool
+{
+    matches!(e.kind, ErrorKind::External(ErrorSource::Libusb(rusb::Error::Pipe)))
+}
+
+/// Whether `e` is the device having disconnected from the bus.
+///
+/// Stalls and IO errors aren't counted, even though some platforms report a request the device never
+/// answered because it reset that way, as they're just as likely to be the device failing the request.
+pub fn is_disconnect(e: &Error) -> bool
+{
+    use crate::error::ErrorSource::Libusb;
+
+    matches!(
+        e.kind,
+        ErrorKind::DeviceNotFound |
+        ErrorKind::External(Libusb(rusb::Error::NoDevice | rusb::Error::NotFound))
+    )
+}
+
+
+/// Parses the pages of memory from a DfuSe alt-setting name, returning each page's address and size.
+///
+/// These look like `@Internal Flash  /0x08000000/04*016Kg,01*064Kg,07*128Kg`: a name, then the address
+/// of each region, followed by the number of pages in each run, their size, a unit, and a letter for
+/// whether they can be read, erased and written.
+pub fn parse_memory_layout(name: &str) -> Option<Vec<(u32, u32)>>
+{
+    let mut parts = name.trim().split('/').skip(1).map(str::trim);
+    let mut pages = Vec::new();
+
+    while let Some(address) = parts.next() {
+        let mut address = u32::from_str_radix(address.strip_prefix("0x")?, 16).ok()?;
+        for run in parts.next()?.split(',') {
+            let (count, size) = run.trim().split_once('*')?;
+            let count: u32 = count.trim().parse().ok()?;
+
+            let digits = size.find(|c: char| !c.is_ascii_digit())?;
+            let (size, unit) = size.split_at(digits);
+            let size: u32 = size.parse().ok()?;
+            let size = match unit.chars().next()? {
+                'K' => size.checked_mul(1024)?,
+                'M' => size.checked_mul(1024 * 1024)?,
+                _ => size,
+            };
+
+            for _ in 0..count {
+                pages.push((address, size));
+                address = address.checked_add(size)?;
+            }
+        }
+    }
+
+    if pages.is_empty() {
+        None
+    } else {
+        Some(pages)
+    }
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn parses_f1_layout()
+    {
+        let pages = parse_memory_layout("@Internal Flash   /0x08000000/8*001Ka,120*001Kg").unwrap();
+        assert_eq!(pages.len(), 128);
+        assert_eq!(pages[0], (0x0800_0000, 1024));
+        assert_eq!(pages[8], (0x0800_2000, 1024));
+        assert_eq!(pages[127], (0x0801_fc00, 1024));
+    }
+
+    #[test]
+    fn parses_f4_layout()
+    {
+        let pages = parse_memory_layout("@Internal Flash  /0x08000000/04*016Kg,01*064Kg,07*128Kg").unwrap();
+        assert_eq!(pages.len(), 12);
+        assert_eq!(pages[0], (0x0800_0000, 16 * 1024));
+        assert_eq!(pages[3], (0x0800_c000, 16 * 1024));
+        assert_eq!(pages[4], (0x0801_0000, 64 * 1024));
+        assert_eq!(pages[5], (0x0802_0000, 128 * 1024));
+        assert_eq!(pages[11], (0x080e_0000, 128 * 1024));
+    }
+
+    #[test]
+    fn rejects_invalid_layouts()
+    {
+        assert_eq!(parse_memory_layout("@Internal Flash"), None);
+        assert_eq!(parse_memory_layout("@Internal Flash  /08000000/04*016Kg"), None);
+        assert_eq!(parse_memory_layout("@Internal Flash  /0x08000000/04*016"), None);
+        // Sizes that overflow, whether from the unit or the pages running past the end of memory.
+        assert_eq!(parse_memory_layout("@Internal Flash  /0x08000000/01*8192Mg"), None);
+        assert_eq!(parse_memory_layout("@Internal Flash  /0xffff0000/02*064Kg"), None);
+    }
+}
@@ -0,0 +1,149 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+//! Module for the DFU file suffix, which `dfu-suffix` and friends append to firmware images.
+//!
+//! See section B.1 of the USB DFU 1.1 specification for the format.
+
+use crate::usb::{Vid, Pid};
+
+/// The DFU suffix at the end of a firmware image.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DfuSuffix
+{
+    pub bcd_device: u16,
+    pub id_product: u16,
+    pub id_vendor: u16,
+    pub bcd_dfu: u16,
+
+    /// The length of the suffix, which the specification allows to be more than the standard 16 bytes.
+    pub length: u8,
+
+    /// The CRC of the whole file, suffix included, up to (but not including) this field.
+    pub crc: u32,
+}
+
+impl DfuSuffix
+{
+    pub const MIN_LENGTH: usize = 16;
+    const SIGNATURE: &'static [u8; 3] = b"UFD";
+    /// Any of the ID fields may be set to this to mean "any".
+    const WILDCARD: u16 = 0xffff;
+
+    /// Parses the DFU suffix off the end of `image`, if it has one.
+    pub fn parse(image: &[u8]) -> Option<Self>
+    {
+        let suffix = image.get(image.len().checked_sub(Self::MIN_LENGTH)?..)?;
+        if &suffix[8..11] != Self::SIGNATURE {
+            return None;
+        }
+
+        let length = suffix[11];
+        if (length as usize) < Self::MIN_LENGTH || (length as usize) > image.len() {
+            return None;
+        }
+
+        let u16_at = |offset: usize| u16::from_le_bytes([suffix[offset], suffix[offset + 1]]);
+
+        Some(Self {
+            bcd_device: u16_at(0),
+            id_product: u16_at(2),
+            id_vendor: u16_at(4),
+            bcd_dfu: u16_at(6),
+            length,
+            crc: u32::from_le_bytes(suffix[12..16].try_into().unwrap()),
+        })
+    }
+
+    /// Whether the CRC in the suffix matches the contents of `image` (which this suffix was parsed from).
+    pub fn crc_matches(&self, image: &[u8]) -> bool
+    {
+        dfu_crc(&image[..image.len() - 4]) == self.crc
+    }
+
+    /// Whether the suffix says the image is for a device with the given IDs.
+    pub fn matches_ids(&self, vid: Vid, pid: Pid) -> bool
+    {
+        (self.id_vendor == Self::WILDCARD || self.id_vendor == vid.0) &&
+            (self.id_product == Self::WILDCARD || self.id_product == pid.0)
+    }
+
+    /// Returns `image` (which this suffix was parsed from) without the suffix.
+    pub fn strip<'i>(&self, image: &'i [u8]) -> &'i [u8]
+    {
+        &image[..image.len() - self.length as usize]
+    }
+}
+
+/// The CRC the DFU suffix uses: CRC-32 as in Ethernet, but without the final inversion.
+fn dfu_crc(data: &[u8]) -> u32
+{
+    data.iter().fold(0xffff_ffff, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _bit| {
+            if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// `firmware`, followed by a suffix for 1d50:6017 with a correct CRC.
+    const IMAGE: &[u8] = &[
+        b'f', b'i', b'r', b'm', b'w', b'a', b'r', b'e',
+        0xff, 0xff, 0x17, 0x60, 0x50, 0x1d, 0x00, 0x01,
+        b'U', b'F', b'D', 0x10, 0x4b, 0x84, 0xb8, 0x58,
+    ];
+
+    #[test]
+    fn computes_crc()
+    {
+        // The standard CRC-32 check value is 0xcbf43926; the DFU CRC is that without the final inversion.
+        assert_eq!(dfu_crc(b"123456789"), !0xcbf4_3926);
+    }
+
+    #[test]
+    fn parses_suffix()
+    {
+        let suffix = DfuSuffix::parse(IMAGE).unwrap();
+        assert_eq!(suffix, DfuSuffix {
+            bcd_device: 0xffff,
+            id_product: 0x6017,
+            id_vendor: 0x1d50,
+            bcd_dfu: 0x0100,
+            length: 16,
+            crc: 0x58b8_844b,
+        });
+        assert!(suffix.crc_matches(IMAGE));
+        assert!(suffix.matches_ids(Vid(0x1d50), Pid(0x6017)));
+        assert!(!suffix.matches_ids(Vid(0x1d50), Pid(0x6018)));
+        assert_eq!(suffix.strip(IMAGE), b"firmware");
+    }
+
+    #[test]
+    fn detects_corruption()
+    {
+        let mut image = IMAGE.to_vec();
+        image[0] ^= 1;
+        let suffix = DfuSuffix::parse(&image).unwrap();
+        assert!(!suffix.crc_matches(&image));
+    }
+
+    #[test]
+    fn rejects_missing_suffixes()
+    {
+        assert_eq!(DfuSuffix::parse(b"firmware"), None);
+        assert_eq!(DfuSuffix::parse(&IMAGE[..IMAGE.len() - 1]), None);
+
+        // A suffix claiming to be longer than the whole image.
+        let mut image = IMAGE.to_vec();
+        image[19] = 0x20;
+        assert_eq!(DfuSuffix::parse(&image), None);
+    }
+}
@@ -0,0 +1,223 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+//! Module for STMicroelectronics' DfuSe firmware container files (`.dfu`).
+//!
+//! These hold one or more targets (one per DFU alt-setting, i.e. memory), each made of one or more
+//! elements to be written at given addresses. See ST's UM0391 for the format.
+
+use crate::S;
+use crate::error::{Error, ErrorKind};
+use crate::segments::{self, Segment};
+
+/// An element of a DfuSe file: some data, and where it goes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DfuseElement
+{
+    /// The alt-setting (memory) of the target this element belongs to.
+    pub alt_setting: u8,
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+/// A little cursor over the file, so the parser reads like the format description.
+struct Reader<'d>
+{
+    data: &'d [u8],
+    offset: usize,
+}
+
+impl<'d> Reader<'d>
+{
+    fn bytes(&mut self, count: usize) -> Result<&'d [u8], Error>
+    {
+        let bytes = self.data
+            .get(self.offset..self.offset + count)
+            .ok_or_else(|| invalid(format!("file ends early, at offset {}", self.offset)))?;
+        self.offset += count;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error>
+    {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, Error>
+    {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+}
+
+fn invalid(why: String) -> Error
+{
+    ErrorKind::InvalidFirmware(Some(format!("invalid DfuSe file: {}", why))).error()
+}
+
+/// The signature DfuSe files start with.
+pub const SIGNATURE: &[u8] = b"DfuSe";
+
+/// Parses a DfuSe file (with its DFU suffix already removed) into its elements.
+pub fn parse(file: &[u8]) -> Result<Vec<DfuseElement>, Error>
+{
+    let mut reader = Reader {
+        data: file,
+        offset: 0,
+    };
+
+    // The prefix.
+    if reader.bytes(SIGNATURE.len())? != SIGNATURE {
+        return Err(invalid(S!("missing DfuSe signature")));
+    }
+    let version = reader.u8()?;
+    if version != 0x01 {
+        return Err(invalid(format!("unsupported version {}", version)));
+    }
+    let _image_size = reader.u32()?;
+    let target_count = reader.u8()?;
+
+    let mut elements = Vec::new();
+    for _ in 0..target_count {
+        // The target prefix.
+        if reader.bytes(6)? != b"Target" {
+            return Err(invalid(format!("missing target signature at offset {}", reader.offset - 6)));
+        }
+        let alt_setting = reader.u8()?;
+        let _is_named = reader.u32()?;
+        let _name = reader.bytes(255)?;
+        let _target_size = reader.u32()?;
+        let element_count = reader.u32()?;
+
+        for _ in 0..element_count {
+            let address = reader.u32()?;
+            let size = reader.u32()?;
+            let data = reader.bytes(size as usize)?;
+            elements.push(DfuseElement {
+                alt_setting,
+                address,
+                data: data.to_vec(),
+            });
+        }
+    }
+
+    Ok(elements)
+}
+
+/// Assembles the internal flash (alt-setting 0) elements of a DfuSe file into one contiguous image,
+/// as [`segments::assemble()`] does.
+pub fn flash_image(elements: Vec<DfuseElement>) -> Result<(u32, Vec<u8>), Error>
+{
+    if let Some(other) = elements.iter().find(|element| element.alt_setting != 0) {
+        return Err(ErrorKind::Unsupported(format!(
+            "DfuSe file has data for alt-setting {} (at 0x{:08x}), but only internal flash (alt-setting 0) \
+            can be written",
+            other.alt_setting,
+            other.address,
+        )).error());
+    }
+
+    let segments = elements
+        .into_iter()
+        .map(|element| Segment {
+            address: element.address,
+            data: element.data,
+        })
+        .collect();
+
+    segments::assemble(segments)
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// Builds a DfuSe file with a single target for `alt_setting`, holding `elements`.
+    fn dfuse_file(alt_setting: u8, elements: &[(u32, &[u8])]) -> Vec<u8>
+    {
+        let mut target = Vec::new();
+        for (address, data) in elements {
+            target.extend_from_slice(&address.to_le_bytes());
+            target.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            target.extend_from_slice(data);
+        }
+
+        let mut file = Vec::new();
+        file.extend_from_slice(SIGNATURE);
+        file.push(0x01);
+        file.extend_from_slice(&0u32.to_le_bytes());
+        file.push(1);
+        file.extend_from_slice(b"Target");
+        file.push(alt_setting);
+        file.extend_from_slice(&0u32.to_le_bytes());
+        file.extend_from_slice(&[0; 255]);
+        file.extend_from_slice(&(target.len() as u32).to_le_bytes());
+        file.extend_from_slice(&(elements.len() as u32).to_le_bytes());
+        file.extend_from_slice(&target);
+        file
+    }
+
+    fn invalid_reason(e: Error) -> String
+    {
+        match e.kind {
+            ErrorKind::InvalidFirmware(Some(reason)) => reason,
+            kind => panic!("unexpected error {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn parses_elements()
+    {
+        let file = dfuse_file(0, &[(0x0800_0000, b"abcd"), (0x0800_0008, b"efgh")]);
+        assert_eq!(parse(&file).unwrap(), vec![
+            DfuseElement {
+                alt_setting: 0,
+                address: 0x0800_0000,
+                data: b"abcd".to_vec(),
+            },
+            DfuseElement {
+                alt_setting: 0,
+                address: 0x0800_0008,
+                data: b"efgh".to_vec(),
+            },
+        ]);
+    }
+
+    #[test]
+    fn rejects_truncated_files()
+    {
+        let file = dfuse_file(0, &[(0x0800_0000, b"abcd"), (0x0800_0008, b"efgh")]);
+        let truncated = &file[..file.len() - 2];
+
+        // The last element's data starts 4 bytes from the end of the untruncated file.
+        let reason = invalid_reason(parse(truncated).unwrap_err());
+        assert!(reason.ends_with(&format!("file ends early, at offset {}", file.len() - 4)), "{}", reason);
+    }
+
+    #[test]
+    fn rejects_other_files()
+    {
+        assert!(parse(b"DfuSf\x01").is_err());
+
+        let mut file = dfuse_file(0, &[(0x0800_0000, b"abcd")]);
+        file[5] = 0x02;
+        assert!(invalid_reason(parse(&file).unwrap_err()).ends_with("unsupported version 2"));
+    }
+
+    #[test]
+    fn assembles_flash_image()
+    {
+        let file = dfuse_file(0, &[(0x0800_0000, b"abcd"), (0x0800_0008, b"efgh")]);
+        let (address, image) = flash_image(parse(&file).unwrap()).unwrap();
+        assert_eq!(address, 0x0800_0000);
+        assert_eq!(image, b"abcd\xff\xff\xff\xffefgh");
+    }
+
+    #[test]
+    fn rejects_other_memories()
+    {
+        let file = dfuse_file(1, &[(0x1fff_7800, b"abcd")]);
+        let e = flash_image(parse(&file).unwrap()).unwrap_err();
+        assert!(matches!(e.kind, ErrorKind::Unsupported(_)));
+    }
+}
@@ -1,100 +1,56 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 // SPDX-FileCopyrightText: 2022-2023 1BitSquared <info@1bitsquared.com>
 // SPDX-FileContributor: Written by Mikaela Szekely <mikaela.szekely@qyriad.me>
-use goblin::elf::{Elf, SectionHeader};
+use goblin::elf::Elf;
+use goblin::elf::program_header::PT_LOAD;
 use goblin::error::Error as GoblinError;
+use tracing::debug;
 
-use crate::S;
+use crate::bmp::FLASH_BASE;
+use crate::error::{Error, ErrorKind};
+use crate::segments::Segment;
 
-/// Convenience extensions to [Elf].
-trait ElfExt
-{
-    /// Get a reference to a section header with the given name. Returns None if
-    /// a section by that name does not exist.
-    fn get_section_by_name(&self, name: &str) -> Option<&goblin::elf::SectionHeader>;
-}
-
-impl<'a> ElfExt for Elf<'a>
-{
-    fn get_section_by_name(&self, name: &str) -> Option<&goblin::elf::SectionHeader>
-    {
-        for section in &self.section_headers {
-            let parsed_name = self.shdr_strtab.get_at(section.sh_name)?;
-
-            if parsed_name == name {
-                return Some(section);
-            }
-        }
-
-        None
-    }
-}
-
-/// Convenience extensions to [SectionHeader].
-trait SectionHeaderExt
-{
-    /// Get the raw data of this section, given the full ELF data.
-    fn get_data<'s>(&'s self, parent_data: &'s [u8]) -> Result<&'s [u8], GoblinError>;
-}
-
-impl SectionHeaderExt for SectionHeader
-{
-    fn get_data<'s>(&'s self, parent_data: &'s [u8]) -> Result<&'s [u8], GoblinError>
-    {
-        let start_idx = self.sh_offset as usize;
-        let size = self.sh_size;
-        let end_idx = start_idx + size as usize;
-        let data: &[u8] = parent_data.get(start_idx..end_idx)
-            .ok_or_else(|| GoblinError::Malformed(format!(
-                "ELF section header does not point to a valid section (offset [{}..{}])",
-                start_idx,
-                end_idx,
-            )))?;
-
-        Ok(data)
-    }
-}
-
-
-/// Extracts binary data from raw ELF data.
+/// Extracts the loadable segments from raw ELF data, at the addresses they are to be loaded at.
 ///
-/// This should be equivalent to `$ arm-none-eabi-objcopy -Obinary`, but is not yet robust
-/// enough to automatically detect what sections should be copied.
-/// Currently, `.text`, `.ARM.exidx`, and `.data` are copied.
-pub fn extract_binary(elf_data: &[u8]) -> Result<Vec<u8>, goblin::error::Error>
+/// This is equivalent to what `$ arm-none-eabi-objcopy -Obinary` uses: the contents of each `PT_LOAD`
+/// program header that has any, at its physical address (so `.data`'s initial values land in flash,
+/// where the startup code copies them to RAM from). Segments with no file contents (e.g. `.bss`) are
+/// skipped.
+pub fn extract_segments(elf_data: &[u8]) -> Result<Vec<Segment>, Error>
 {
     let elf = Elf::parse(elf_data)?;
 
-    // FIXME: Dynamically detect what sections should be copied.
-    // arm-none-eabi-objcopy seems to only copy these three, but I'm not yet certain why only these three
-    // (as these aren't the only three that have PROGBITS set).
-
-    let text = elf
-        .get_section_by_name(".text")
-        .ok_or_else(|| GoblinError::Malformed(S!("ELF .text section not found")))?
-        .get_data(elf_data)?;
-
-    // Allow .ARM.exidx to not exist.
-    let arm_exidx = elf
-        .get_section_by_name(".ARM.exidx")
-        .map(|v| v.get_data(elf_data).ok())
-        .flatten();
-    let arm_exidx_len = arm_exidx.map(|sect| sect.len()).unwrap_or(0);
-
-    let data = elf
-        .get_section_by_name(".data")
-        .ok_or_else(|| GoblinError::Malformed(S!("ELF .data section not found")))?
-        .get_data(elf_data)?;
-
+    let mut segments = Vec::new();
+    for header in elf.program_headers.iter().filter(|header| header.p_type == PT_LOAD && header.p_filesz > 0) {
+        let range = header.file_range();
+        let data = elf_data.get(range.clone())
+            .ok_or_else(|| GoblinError::Malformed(format!(
+                "ELF program header does not point to a valid segment (offset [{}..{}])",
+                range.start,
+                range.end,
+            )))?;
 
-    let mut extracted = Vec::with_capacity(text.len() + arm_exidx_len + data.len());
+        let address = u32::try_from(header.p_paddr)
+            .map_err(|_| ErrorKind::InvalidFirmware(Some(format!(
+                "ELF segment load address 0x{:x} is not a 32-bit address",
+                header.p_paddr,
+            ))).error())?;
+
+        // The flash is the only thing we can write, so anything meant to be loaded elsewhere means
+        // this isn't firmware for a probe.
+        if address & 0xff00_0000 != FLASH_BASE {
+            return Err(ErrorKind::InvalidFirmware(Some(format!(
+                "ELF segment at 0x{:08x} is not in flash",
+                address,
+            ))).error());
+        }
 
-    extracted.extend_from_slice(text);
-    if let Some(arm_exidx) = arm_exidx {
-        extracted.extend_from_slice(arm_exidx);
+        debug!("ELF segment: {} bytes at 0x{:08x}", data.len(), address);
+        segments.push(Segment {
+            address,
+            data: data.to_vec(),
+        });
     }
 
-    extracted.extend_from_slice(data);
-
-    Ok(extracted)
+    Ok(segments)
 }
@@ -4,18 +4,61 @@
 //! Module for error handling code.
 
 use std::fmt::{Display, Formatter};
+use std::time::Duration;
 #[cfg(feature = "backtrace")]
 use std::backtrace::{Backtrace, BacktraceStatus};
 use std::error::Error as StdError;
 
 use thiserror::Error;
 
-use crate::S;
+use crate::dfu::{DfuState, DfuStatusCode};
+use crate::tr;
 
 /// More convenient alias for `Box<dyn StdError + Send + Sync>`,
 /// which shows up in a few signatures and structs.
 type BoxedError = Box<dyn StdError + Send + Sync>;
 
+/// The statuses bmputil exits with, so scripts can tell kinds of failure apart without parsing messages.
+///
+/// These are part of bmputil's interface (and documented in the README), so never renumber them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExitStatus
+{
+    /// Any failure not covered by a more specific status.
+    Failure = 1,
+    /// No matching probe was found, or more than one was and we couldn't ask which to use.
+    NoDevice = 2,
+    /// The operating system denied access to a probe or file.
+    PermissionDenied = 3,
+    /// Reading the firmware back after flashing did not match.
+    VerificationFailed = 4,
+    /// The firmware given was unreadable, invalid, or for different hardware.
+    InvalidFirmware = 5,
+    /// A firmware release (or information about one) could not be found or downloaded.
+    Download = 6,
+    /// A download did not match its published checksum or signature.
+    Untrusted = 7,
+    /// Something went wrong talking to a probe over USB.
+    Usb = 8,
+    /// The user didn't confirm an operation that needs it.
+    NotConfirmed = 9,
+    /// Some of several probes failed (e.g. with `flash --all`).
+    SomeProbesFailed = 10,
+    /// Not a failure: `bmputil firmware check` found a probe whose firmware can be updated.
+    UpdateAvailable = 20,
+    /// The command line was invalid.
+    Usage = 64,
+}
+
+impl ExitStatus
+{
+    /// Exits the process with this status.
+    pub fn exit(self) -> !
+    {
+        std::process::exit(self as i32)
+    }
+}
+
 /// Kinds of errors for [Error]. Use [ErrorKind::error] and [ErrorKind::error_from] to generate the
 /// [Error] value for this ErrorKind.
 #[derive(Debug)]
@@ -24,9 +67,63 @@ pub enum ErrorKind
     /// Failed to read firmware file.
     FirmwareFileIo(/** filename **/ Option<String>),
 
+    /// Failed to write a firmware file (e.g. a backup).
+    FirmwareFileWrite(/** filename **/ String),
+
+    /// Failed to download a file.
+    Download(/** url **/ String),
+
+    /// A file to download was larger than we're willing to download.
+    DownloadTooLarge(/** url **/ String, /** limit **/ u64),
+
+    /// A proxy to download through was not valid.
+    InvalidProxy(/** proxy **/ String),
+
+    /// The list of firmware releases could not be read.
+    ReleaseIndex(/** url **/ String),
+
+    /// Something needed isn't in the cache, and can't be downloaded because we're offline.
+    NotCached(/** what **/ String),
+
+    /// There is no firmware release with the given tag.
+    UnknownRelease(/** tag **/ String),
+
+    /// A firmware release has no build for a probe's hardware.
+    NoReleaseForHardware(/** release **/ String, /** hardware **/ String),
+
+    /// A bmputil release has no standalone executable for the platform we're running on.
+    NoReleaseForPlatform(/** release **/ String, /** platform **/ String),
+
+    /// A file did not match its published checksum.
+    ChecksumMismatch(/** what **/ String),
+
+    /// A file's signature is invalid, or not by any trusted key.
+    BadSignature(/** what **/ String),
+
+    /// A file isn't signed, but signatures are required.
+    Unsigned(/** what **/ String),
+
+    /// A file has neither a published checksum nor a signature we could check, but must be checked.
+    Unverified(/** what **/ String),
+
+    /// A public key in the configuration file's `signing_keys` isn't a valid minisign public key.
+    InvalidSigningKey(/** key **/ String),
+
     /// Specified firmware seems invalid.
     InvalidFirmware(/** why **/ Option<String>),
 
+    /// The firmware was built for different hardware than the probe it would be flashed onto.
+    HardwareMismatch(/** firmware's hardware **/ String, /** probe's hardware **/ String),
+
+    /// The requested operation is not possible with this device as it is.
+    Unsupported(/** why **/ String),
+
+    /// A dangerous operation was not confirmed by the user.
+    NotConfirmed(/** operation **/ String),
+
+    /// Some of the probes an operation was applied to failed.
+    SomeProbesFailed(/** failed **/ usize, /** total **/ usize),
+
     /// Current operation only supports one Black Magic Probe but more tha none device was found.
     TooManyDevices,
 
@@ -40,12 +137,76 @@ pub enum ErrorKind
     /// or flashing firmware).
     DeviceReboot,
 
+    /// Black Magic Probe device did not re-enumerate on its port in time, having waited for the
+    /// given duration and seen only the given other probes (by port and mode).
+    RebootTimedOut(/** waited **/ Duration, /** port **/ String, /** probes seen **/ Vec<String>),
+
+    /// The device given with `--device-fd` re-enumerated, so the descriptor no longer refers to it.
+    DeviceFdReenumerated,
+
+    /// The OS's own driver has an interface of the device we need, and we aren't allowed to make it let go.
+    KernelDriverActive(/** interface **/ u8),
+
     /// Black Magic Probe device returned bad data during configuration.
     ///
     /// This generally shouldn't be possible, but could happen if the cable is bad, the OS is
     /// messing with things, or the firmware on the device is corrupted.
     DeviceSeemsInvalid(/** invalid thing **/ String),
 
+    /// A DFU transfer size was asked for that's more than the device advertises it can take.
+    TransferSizeUnsupported(/** requested **/ u16, /** advertised **/ u16),
+
+    /// Something is too big to download in DFU blocks of the transfer size.
+    TooManyBlocks(/** length **/ usize, /** transfer size **/ u16),
+
+    /// The device reported that a DFU request failed.
+    DfuStatus(/** status **/ DfuStatusCode, /** state **/ DfuState, /** offset into the download **/ Option<usize>),
+
+    /// The device's bootloader does not support reading flash back (DFU_UPLOAD).
+    UploadUnsupported,
+
+    /// Reading the firmware back after flashing did not match the firmware image.
+    VerificationFailed(/** offset of first mismatch **/ usize),
+
+    /// A range of addresses given on the command line isn't within the probe's flash.
+    OutsideFlash(/** address **/ u32, /** length **/ u32),
+
+    /// Failed to read or parse the configuration file.
+    ConfigFile(/** path **/ String),
+
+    /// Failed to save a setting to the configuration file.
+    ConfigFileWrite(/** path **/ String),
+
+    /// A command we ran to do something for the user couldn't be run, or failed.
+    CommandFailed(/** command **/ String),
+
+    /// Failed to install a driver for a probe.
+    #[cfg(windows)]
+    DriverInstall(/** why **/ String),
+
+    /// The probe's DFU interface has some other driver than WinUSB bound to it, or none at all, even
+    /// though its Microsoft OS 2.0 descriptors might ask for WinUSB.
+    #[cfg(windows)]
+    WrongDriver(/** interface **/ u8, /** driver **/ Option<String>, /** driverless **/ bool),
+
+    /// Failed to read or parse a firmware pin file.
+    PinFile(/** path **/ String, /** why **/ Option<String>),
+
+    /// A probe alias was used that is not defined in the configuration file.
+    UnknownProbeAlias(/** alias **/ String),
+
+    /// The DFU alt-setting asked for with `--alt` does not exist on the device.
+    UnknownAltSetting(/** index or name **/ String),
+
+    /// The serial port of the probe's GDB server couldn't be found.
+    GdbPortNotFound,
+
+    /// Talking to the probe's GDB server (on the given serial port) failed.
+    GdbServer(/** port **/ String),
+
+    /// The probe's GDB server refused a monitor command, saying the given output.
+    MonitorCommandFailed(/** command **/ String, /** output **/ String),
+
     /// Unhandled external error.
     External(ErrorSource),
 }
@@ -93,46 +254,129 @@ impl Display for ErrorKind
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result
     {
         use ErrorKind::*;
-        match self {
-            FirmwareFileIo(None) => write!(f, "failed to read firmware file")?,
-            FirmwareFileIo(Some(filename)) => write!(f, "failed to read firmware file {}", filename)?,
-            TooManyDevices => write!(f, "current operation only supports one Black Magic Probe device but more than one device was found")?,
-            DeviceNotFound => write!(f, "Black Magic Probe device not found (check connection?)")?,
-            DeviceDisconnectDuringOperation => write!(f, "Black Magic Probe device found disconnected")?,
-            DeviceReboot => write!(f, "Black Magic Probe device did not come back online (invalid firmware?)")?,
-            DeviceSeemsInvalid(thing) => {
-                write!(
-                    f,
-                    "\nBlack Magic Probe device returned bad data ({}) during configuration.\n\
-                    This generally shouldn't be possible. Maybe cable is bad, or OS is messing with things?",
-                    thing,
-                )?;
+        let message = match self {
+            FirmwareFileIo(None) => tr!("error-firmware-file-io"),
+            FirmwareFileIo(Some(filename)) => tr!("error-firmware-file-io-named", filename = filename.as_str()),
+            FirmwareFileWrite(filename) => tr!("error-firmware-file-write", filename = filename.as_str()),
+            HardwareMismatch(firmware, probe) => tr!(
+                "error-hardware-mismatch",
+                firmware = firmware.as_str(),
+                probe = probe.as_str(),
+            ),
+            Unsupported(why) => why.clone(),
+            NotConfirmed(operation) => tr!("error-not-confirmed", operation = operation.as_str()),
+            Download(url) => tr!("error-download", url = url.as_str()),
+            DownloadTooLarge(url, limit) => tr!("error-download-too-large", url = url.as_str(), limit = *limit),
+            InvalidProxy(proxy) => tr!("error-invalid-proxy", proxy = proxy.as_str()),
+            ReleaseIndex(url) => tr!("error-release-index", url = url.as_str()),
+            NotCached(what) => tr!("error-not-cached", what = what.as_str()),
+            UnknownRelease(tag) => tr!("error-unknown-release", tag = tag.as_str()),
+            NoReleaseForHardware(release, hardware) => tr!(
+                "error-no-release-for-hardware",
+                release = release.as_str(),
+                hardware = hardware.as_str(),
+            ),
+            NoReleaseForPlatform(release, platform) => tr!(
+                "error-no-release-for-platform",
+                release = release.as_str(),
+                platform = platform.as_str(),
+            ),
+            ChecksumMismatch(what) => tr!("error-checksum-mismatch", what = what.as_str()),
+            BadSignature(what) => tr!("error-bad-signature", what = what.as_str()),
+            Unsigned(what) => tr!("error-unsigned", what = what.as_str()),
+            Unverified(what) => tr!("error-unverified", what = what.as_str()),
+            InvalidSigningKey(key) => tr!("error-invalid-signing-key", key = key.as_str()),
+            SomeProbesFailed(failed, total) => tr!("error-some-probes-failed", failed = *failed, total = *total),
+            TooManyDevices => tr!("error-too-many-devices"),
+            DeviceNotFound => tr!("error-device-not-found"),
+            DeviceDisconnectDuringOperation => tr!("error-device-disconnect-during-operation"),
+            DeviceReboot => tr!("error-device-reboot"),
+            RebootTimedOut(waited, port, seen) => tr!(
+                "error-reboot-timed-out",
+                port = port.as_str(),
+                waited = format!("{:.1}", waited.as_secs_f64()),
+                seen_count = seen.len(),
+                seen = seen.join(", "),
+            ),
+            TransferSizeUnsupported(requested, advertised) => tr!(
+                "error-transfer-size-unsupported",
+                advertised = *advertised,
+                requested = *requested,
+            ),
+            TooManyBlocks(length, transfer_size) => tr!(
+                "error-too-many-blocks",
+                length = *length,
+                transfer_size = *transfer_size,
+            ),
+            OutsideFlash(address, length) => tr!(
+                "error-outside-flash",
+                start = format!("0x{:08x}", address),
+                end = format!("0x{:08x}", *address as u64 + *length as u64),
+            ),
+            DfuStatus(status, state, None) => tr!(
+                "error-dfu-status",
+                status = status.to_string(),
+                description = status.description(),
+                state = state.to_string(),
+            ),
+            DfuStatus(status, state, Some(offset)) => tr!(
+                "error-dfu-status-at-offset",
+                status = status.to_string(),
+                description = status.description(),
+                offset = *offset,
+                offset_hex = format!("0x{:x}", offset),
+                state = state.to_string(),
+            ),
+            DeviceFdReenumerated => format!("\n{}", tr!("error-device-fd-reenumerated")),
+            KernelDriverActive(interface) => format!("\n{}", tr!("error-kernel-driver-active", interface = *interface)),
+            // This one is long enough to be worth starting on a line of its own.
+            DeviceSeemsInvalid(thing) => format!("\n{}", tr!("error-device-seems-invalid", thing = thing.as_str())),
+            UploadUnsupported => tr!("error-upload-unsupported"),
+            VerificationFailed(offset) => tr!("error-verification-failed", offset = format!("0x{:x}", offset)),
+            ConfigFile(path) => tr!("error-config-file", path = path.as_str()),
+            ConfigFileWrite(path) => tr!("error-config-file-write", path = path.as_str()),
+            CommandFailed(command) => tr!("error-command-failed", command = command.as_str()),
+            #[cfg(windows)]
+            DriverInstall(why) => tr!("error-driver-install", why = why.as_str()),
+            #[cfg(windows)]
+            WrongDriver(interface, driver, driverless) => {
+                let message = match driver {
+                    None => tr!("error-no-driver", interface = *interface),
+                    Some(driver) => tr!("error-wrong-driver", interface = *interface, driver = driver.as_str()),
+                };
+                if *driverless {
+                    format!("\n{}\n{}", message, tr!("error-driver-cached"))
+                } else {
+                    format!("\n{}", message)
+                }
             },
-            InvalidFirmware(None) => write!(f, "specified firmware does not seem valid")?,
-            InvalidFirmware(Some(why)) => write!(f, "specified firmware does not seem valid: {}", why)?,
+            PinFile(path, None) => tr!("error-pin-file", path = path.as_str()),
+            PinFile(path, Some(why)) => tr!("error-pin-file-why", path = path.as_str(), why = why.as_str()),
+            UnknownProbeAlias(alias) => tr!("error-unknown-probe-alias", alias = format!("{:?}", alias)),
+            UnknownAltSetting(alt) => tr!("error-unknown-alt-setting", alt = alt.as_str()),
+            GdbPortNotFound => tr!("error-gdb-port-not-found"),
+            GdbServer(port) => tr!("error-gdb-server", port = port.as_str()),
+            MonitorCommandFailed(command, output) if output.is_empty() => {
+                tr!("error-monitor-command-failed", command = command.as_str())
+            },
+            MonitorCommandFailed(command, output) => tr!(
+                "error-monitor-command-failed-output",
+                command = command.as_str(),
+                output = output.as_str(),
+            ),
+            InvalidFirmware(None) => tr!("error-invalid-firmware"),
+            InvalidFirmware(Some(why)) => tr!("error-invalid-firmware-why", why = why.as_str()),
             External(source) => {
                 use ErrorSource::*;
                 match source {
-                    StdIo(e) => {
-                        write!(f, "unhandled std::io::Error: {}", e)?;
-                    },
-                    Libusb(e) => {
-                        write!(f, "unhandled libusb error: {}", e)?;
-                    },
-                    DfuLibusb(e) => {
-                        write!(f, "unhandled dfu_libusb error: {}", e)?;
-                    },
-                    DfuCore(e) => {
-                        write!(f, "unhandled dfu_core error: {}", e)?;
-                    },
-                    Goblin(e) => {
-                        write!(f, "unhandled ELF parsing error: {}", e)?;
-                    },
-                };
+                    StdIo(e) => tr!("error-external-io", error = e.to_string()),
+                    Libusb(e) => tr!("error-external-libusb", error = e.to_string()),
+                    Goblin(e) => tr!("error-external-goblin", error = e.to_string()),
+                }
             },
         };
 
-        Ok(())
+        f.write_str(&message)
     }
 }
 
@@ -157,6 +401,49 @@ pub struct Error
 
 impl Error
 {
+    /// The status bmputil should exit with because of this error.
+    pub fn exit_status(&self) -> ExitStatus
+    {
+        use ErrorKind::*;
+
+        // Access being denied matters more than whatever it stopped us from doing.
+        let is_permission_error = |error: &(dyn StdError + 'static)| {
+            matches!(error.downcast_ref::<rusb::Error>(), Some(rusb::Error::Access)) ||
+                matches!(
+                    error.downcast_ref::<std::io::Error>(),
+                    Some(e) if e.kind() == std::io::ErrorKind::PermissionDenied
+                )
+        };
+        let permission_denied = match &self.kind {
+            External(ErrorSource::Libusb(rusb::Error::Access)) => true,
+            External(ErrorSource::StdIo(e)) => e.kind() == std::io::ErrorKind::PermissionDenied,
+            _ => self.source.as_deref().is_some_and(|source| is_permission_error(source)),
+        };
+        if permission_denied {
+            return ExitStatus::PermissionDenied;
+        }
+
+        match &self.kind {
+            DeviceNotFound | TooManyDevices | UnknownProbeAlias(_) => ExitStatus::NoDevice,
+            VerificationFailed(_) => ExitStatus::VerificationFailed,
+            FirmwareFileIo(_) | InvalidFirmware(_) | HardwareMismatch(..) | TooManyBlocks(..) => {
+                ExitStatus::InvalidFirmware
+            },
+            Download(_) | DownloadTooLarge(..) | InvalidProxy(_) | ReleaseIndex(_) | NotCached(_) | UnknownRelease(_) |
+                NoReleaseForHardware(..) | NoReleaseForPlatform(..) => ExitStatus::Download,
+            ChecksumMismatch(_) | BadSignature(_) | Unsigned(_) | Unverified(_) => ExitStatus::Untrusted,
+            DeviceDisconnectDuringOperation | DeviceReboot | RebootTimedOut(..) | DeviceSeemsInvalid(_) |
+                DeviceFdReenumerated | KernelDriverActive(_) | GdbServer(_) |
+                TransferSizeUnsupported(..) | DfuStatus(..) | External(ErrorSource::Libusb(_)) => ExitStatus::Usb,
+            #[cfg(windows)]
+            WrongDriver(..) => ExitStatus::Usb,
+            NotConfirmed(_) => ExitStatus::NotConfirmed,
+            SomeProbesFailed(..) => ExitStatus::SomeProbesFailed,
+            OutsideFlash(..) => ExitStatus::Usage,
+            _ => ExitStatus::Failure,
+        }
+    }
+
     #[inline(always)]
     pub fn new(kind: ErrorKind, source: Option<BoxedError>) -> Self
     {
@@ -187,6 +474,15 @@ impl Error
         self
     }
 
+    /// Whether this is a USB error that may well not happen again if the operation is retried, such
+    /// as a stall or timeout caused by an unreliable hub or cable.
+    pub fn is_transient_usb(&self) -> bool
+    {
+        use rusb::Error::{Io, Pipe, Timeout};
+
+        matches!(self.kind, ErrorKind::External(ErrorSource::Libusb(Io | Pipe | Timeout)))
+    }
+
     #[cfg(feature = "backtrace")]
     #[allow(dead_code)]
     fn backtrace(&self) -> Option<&Backtrace>
@@ -240,67 +536,6 @@ impl From<rusb::Error> for Error
     }
 }
 
-impl From<dfu_libusb::Error> for Error
-{
-    fn from(other: dfu_libusb::Error) -> Self
-    {
-        use ErrorKind::*;
-        use dfu_libusb::Error as Source;
-        match other {
-            Source::LibUsb(source) => {
-                External(ErrorSource::Libusb(source)).error_from(other)
-            },
-            Source::MissingLanguage => {
-                DeviceSeemsInvalid(S!("no string descriptor languages"))
-                    .error_from(other)
-            },
-            Source::InvalidAlt => {
-                DeviceSeemsInvalid(S!("DFU interface (alt mode) not found"))
-                    .error_from(other)
-            },
-            Source::InvalidInterface => {
-                DeviceSeemsInvalid(S!("DFU interface not found"))
-                    .error_from(other)
-            },
-            Source::FunctionalDescriptor(source) => {
-                DeviceSeemsInvalid(S!("DFU functional interface descriptor"))
-                    .error_from(source)
-            },
-            anything_else => {
-                External(ErrorSource::DfuLibusb(anything_else))
-                    .error()
-            },
-        }
-    }
-}
-
-impl From<dfu_core::Error> for Error
-{
-    fn from(other: dfu_core::Error) -> Self
-    {
-        use ErrorKind::*;
-        use dfu_core::Error as Source;
-        match other {
-            Source::MemoryLayout(source) => {
-                DeviceSeemsInvalid(String::from("DFU interface memory layout string"))
-                    .error_from(source)
-            },
-            Source::InvalidAddress => {
-                DeviceSeemsInvalid(S!("DFU interface memory layout string"))
-                    .error_from(other)
-            },
-            Source::InvalidInterfaceString => {
-                DeviceSeemsInvalid(S!("DFU interface memory layout string"))
-                    .error_from(other)
-            },
-            anything_else => {
-                External(ErrorSource::DfuCore(anything_else))
-                    .error()
-            },
-        }
-    }
-}
-
 impl From<goblin::error::Error> for Error
 {
     fn from(other: goblin::error::Error) -> Self
@@ -323,11 +558,6 @@ pub enum ErrorSource
     #[error(transparent)]
     Libusb(#[from] rusb::Error),
 
-    #[error(transparent)]
-    DfuLibusb(#[from] dfu_libusb::Error),
-
-    #[error(transparent)]
-    DfuCore(#[from] dfu_core::Error),
 
     #[error(transparent)]
     Goblin(#[from] goblin::error::Error),
@@ -350,14 +580,3 @@ impl<T> ResErrorKind<T> for Result<T, Error>
         self.as_ref().map_err(|e| &e.kind)
     }
 }
-
-
-#[macro_export]
-macro_rules! log_and_return
-{
-    ($err:expr) => {
-        let err = $err;
-        log::error!("{}", err);
-        return Err(err);
-    }
-}
@@ -0,0 +1,216 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+//! Module for fetching firmware over HTTPS.
+//!
+//! Downloads go through the proxy given with `--proxy` or in the configuration file if there is one, or
+//! otherwise the one set by the `HTTPS_PROXY` (or `HTTP_PROXY`) environment variable, except for hosts
+//! listed in `NO_PROXY`.
+
+use std::env;
+use std::io::Read;
+use std::sync::OnceLock;
+
+use tracing::{debug, info, warn, instrument};
+use sha2::{Digest, Sha256};
+
+use crate::error::{Error, ErrorKind};
+
+/// The largest file we're willing to download; far bigger than any probe's flash.
+const MAX_DOWNLOAD_SIZE: u64 = 16 * 1024 * 1024;
+
+/// The largest bmputil executable we're willing to download, as release builds include debug info.
+const MAX_EXECUTABLE_SIZE: u64 = 256 * 1024 * 1024;
+
+/// The proxy given with `--proxy` or in the configuration file, which takes precedence over the environment.
+static EXPLICIT_PROXY: OnceLock<ureq::Proxy> = OnceLock::new();
+
+/// Sets the proxy to download through, rather than the one from the environment, e.g. `http://proxy:3128`.
+pub fn set_proxy(proxy: &str) -> Result<(), Error>
+{
+    let proxy = ureq::Proxy::new(proxy)
+        .map_err(|e| ErrorKind::InvalidProxy(proxy.to_string()).error_from(e))?;
+    let _ = EXPLICIT_PROXY.set(proxy);
+    Ok(())
+}
+
+/// The value of the first of the environment variables `names` that is set and not empty.
+fn env_var(names: &[&str]) -> Option<String>
+{
+    names
+        .iter()
+        .find_map(|name| env::var(name).ok().filter(|value| !value.is_empty()))
+}
+
+/// The host part of `url`.
+fn host_of(url: &str) -> &str
+{
+    let authority = url
+        .split_once("://")
+        .map_or(url, |(_scheme, rest)| rest)
+        .split(['/', '?', '#'])
+        .next()
+        .unwrap_or_default();
+    let host_port = authority.rsplit_once('@').map_or(authority, |(_userinfo, host_port)| host_port);
+    match host_port.strip_prefix('[') {
+        // IPv6 addresses are in brackets, as they contain colons themselves.
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => host_port.split(':').next().unwrap_or_default(),
+    }
+}
+
+/// Whether `NO_PROXY` says not to use a proxy for `host`. It's a comma separated list of hosts, each
+/// also matching its subdomains, or `*` for all hosts.
+fn bypasses_proxy(host: &str) -> bool
+{
+    let no_proxy = match env_var(&["NO_PROXY", "no_proxy"]) {
+        Some(no_proxy) => no_proxy,
+        None => return false,
+    };
+
+    let host = host.to_ascii_lowercase();
+    no_proxy
+        .split(',')
+        .map(|entry| entry.trim().trim_start_matches("*.").trim_start_matches('.').to_ascii_lowercase())
+        .filter(|entry| !entry.is_empty())
+        .any(|entry| entry == "*" || host == entry || host.ends_with(&format!(".{}", entry)))
+}
+
+/// The proxy to fetch `url` through, if any.
+fn proxy_for(url: &str) -> Result<Option<ureq::Proxy>, Error>
+{
+    if bypasses_proxy(host_of(url)) {
+        return Ok(None);
+    }
+    if let Some(proxy) = EXPLICIT_PROXY.get() {
+        return Ok(Some(proxy.clone()));
+    }
+
+    match env_var(&["HTTPS_PROXY", "https_proxy", "HTTP_PROXY", "http_proxy"]) {
+        Some(proxy) => ureq::Proxy::new(&proxy)
+            .map(Some)
+            .map_err(|e| ErrorKind::InvalidProxy(proxy).error_from(e)),
+        None => Ok(None),
+    }
+}
+
+/// Whether `location` is a URL we should fetch, rather than a path to a local file.
+pub fn is_url(location: &str) -> bool
+{
+    location.starts_with("https://") || location.starts_with("http://")
+}
+
+/// Fetches the file at `url`, returning `None` if the server says there isn't one.
+pub fn fetch_optional(url: &str) -> Result<Option<Vec<u8>>, Error>
+{
+    fetch_at_most(url, MAX_DOWNLOAD_SIZE)
+}
+
+/// Fetches the file at `url`, returning `None` if the server says there isn't one, and erroring if it's
+/// larger than `max_size` bytes.
+#[instrument(level = "debug", name = "fetch", skip(max_size))]
+fn fetch_at_most(url: &str, max_size: u64) -> Result<Option<Vec<u8>>, Error>
+{
+    if !url.starts_with("https://") {
+        return Err(ErrorKind::Unsupported(format!("refusing to download {} over plain HTTP; use https://", url)).error());
+    }
+
+    let mut agent = ureq::AgentBuilder::new();
+    if let Some(proxy) = proxy_for(url)? {
+        debug!("Fetching {} through proxy {:?}", url, proxy);
+        agent = agent.proxy(proxy);
+    } else {
+        debug!("Fetching {}", url);
+    }
+
+    let response = match agent.build().get(url).call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(404, _)) => return Ok(None),
+        Err(e) => return Err(ErrorKind::Download(url.to_string()).error_from(e)),
+    };
+
+    // Read one byte more than we'll take, to tell a file of exactly the limit from one cut short by it.
+    let mut data = Vec::new();
+    response
+        .into_reader()
+        .take(max_size + 1)
+        .read_to_end(&mut data)
+        .map_err(|e| ErrorKind::Download(url.to_string()).error_from(e))?;
+    if data.len() as u64 > max_size {
+        return Err(ErrorKind::DownloadTooLarge(url.to_string(), max_size).error());
+    }
+    debug!(bytes = data.len(), "Fetched {}", url);
+
+    Ok(Some(data))
+}
+
+/// Fetches the file at `url`.
+pub fn fetch(url: &str) -> Result<Vec<u8>, Error>
+{
+    fetch_optional(url)?
+        .ok_or_else(|| ErrorKind::Download(format!("{} (not found)", url)).error())
+}
+
+/// Fetches the bmputil executable at `url`, which can be rather larger than firmware.
+pub fn fetch_executable(url: &str) -> Result<Vec<u8>, Error>
+{
+    info!("Downloading {}", url);
+    fetch_at_most(url, MAX_EXECUTABLE_SIZE)?
+        .ok_or_else(|| ErrorKind::Download(format!("{} (not found)", url)).error())
+}
+
+/// Fetches the firmware at `url`, checking it against the SHA-256 checksum published alongside it
+/// (at `<url>.sha256`) if there is one.
+pub fn fetch_firmware(url: &str) -> Result<Vec<u8>, Error>
+{
+    info!("Downloading {}", url);
+    let firmware = fetch(url)?;
+
+    let checksum_url = format!("{}.sha256", url);
+    match fetch_optional(&checksum_url)? {
+        Some(checksum) => {
+            // Checksum files are in the format `sha256sum` outputs, `<hash>  <filename>`, or are just the hash.
+            let checksum = String::from_utf8_lossy(&checksum);
+            let expected = checksum.split_whitespace().next().unwrap_or_default();
+            verify_sha256(&firmware, expected, url)?;
+            info!("Firmware matches the published SHA-256 checksum");
+        },
+        None => warn!("No checksum published for {}; not verifying the download", url),
+    };
+
+    Ok(firmware)
+}
+
+/// Returns the SHA-256 hash of `data`, in hex.
+pub fn sha256_hex(data: &[u8]) -> String
+{
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Finds the checksum of the file `filename` in the checksum `manifest`, in the format `sha256sum`
+/// outputs: lines of `<hash>  <filename>`, with a `*` before the filename for binary files.
+pub fn checksum_for<'m>(manifest: &'m str, filename: &str) -> Option<&'m str>
+{
+    manifest
+        .lines()
+        .filter_map(|line| line.trim().split_once(char::is_whitespace))
+        .find(|(_hash, name)| {
+            let name = name.trim_start();
+            name.strip_prefix('*').unwrap_or(name) == filename
+        })
+        .map(|(hash, _name)| hash)
+}
+
+/// Checks `data` has the SHA-256 hash `expected` (in hex), naming it `what` in the error if not.
+pub fn verify_sha256(data: &[u8], expected: &str, what: &str) -> Result<(), Error>
+{
+    let actual = sha256_hex(data);
+
+    if actual.eq_ignore_ascii_case(expected.trim()) {
+        Ok(())
+    } else {
+        Err(ErrorKind::ChecksumMismatch(what.to_string()).error())
+    }
+}
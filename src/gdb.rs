@@ -0,0 +1,336 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+//! Module for talking to a probe's GDB server over its serial port, to run `monitor` commands on it
+//! without a GDB session, e.g. for `bmputil probe power`.
+//!
+//! Probes only have a GDB server in runtime mode, where it's the first of their two CDC-ACM serial
+//! ports (the other being the target UART). [`find_port()`] works out which serial port the OS has
+//! given it, where we know how; otherwise it has to be given with `--gdb-port`. Monitor commands are
+//! sent as `qRcmd` packets of the [GDB remote serial protocol](https://sourceware.org/gdb/current/onlinedocs/gdb.html/Remote-Protocol.html),
+//! and print their output with `O` packets before finishing with `OK` (or an error).
+//!
+//! The same port also takes the packets of the remote protocol the probe offers BMDA (Black Magic Debug
+//! App), which are `!`, the packet, and `#`, and are answered with `&`, `K` (or `E` or `N` for errors and
+//! unsupported packets), any data, and `#`. That has no monitor command for some things, such as reading
+//! the target voltage.
+
+use std::io::{Read, Write};
+use std::time::Duration;
+
+use serialport::{ClearBuffer, SerialPort};
+use tracing::{debug, trace};
+
+use crate::bmp::BmpDevice;
+use crate::error::{Error, ErrorKind};
+use crate::usb::DfuOperatingMode;
+use crate::S;
+
+/// The interface class of CDC communications interfaces, which each of a probe's serial ports has.
+const CDC_COMMUNICATIONS_CLASS: u8 = 0x02;
+
+/// The baud rate to open the GDB server's port at, which (being USB) it ignores.
+const BAUD_RATE: u32 = 115_200;
+
+/// How many times to send a packet the GDB server doesn't acknowledge.
+const RETRIES: usize = 3;
+
+/// The remote protocol packet that reads the target voltage.
+const REMOTE_TARGET_VOLTAGE: &str = "GV";
+
+/// Finds the interface number of `dev`'s GDB server's CDC communications interface, which is the first.
+fn gdb_interface(dev: &BmpDevice) -> Result<u8, Error>
+{
+    let configuration = dev.device().active_config_descriptor()?;
+    configuration
+        .interfaces()
+        .flat_map(|interface| interface.descriptors())
+        .find(|desc| desc.class_code() == CDC_COMMUNICATIONS_CLASS)
+        .map(|desc| desc.interface_number())
+        .ok_or_else(|| ErrorKind::GdbPortNotFound.error())
+}
+
+/// Finds the serial port the OS has given interface `interface` of `dev`, from the `tty` directory of the
+/// interface in sysfs.
+#[cfg(target_os = "linux")]
+fn os_port(dev: &BmpDevice, interface: u8) -> Option<String>
+{
+    let configuration = dev.device().active_config_descriptor().ok()?.number();
+    let tty = std::path::Path::new("/sys/bus/usb/devices")
+        .join(format!("{}:{}.{}", dev.port(), configuration, interface))
+        .join("tty");
+    let name = std::fs::read_dir(&tty).ok()?.flatten().next()?.file_name();
+
+    Some(format!("/dev/{}", name.to_string_lossy()))
+}
+
+/// Finds the serial port the OS has given interface `interface` of `dev`. macOS names the ports of CDC-ACM
+/// devices for their serial number and the interface number, counting from 1.
+#[cfg(target_os = "macos")]
+fn os_port(dev: &BmpDevice, interface: u8) -> Option<String>
+{
+    let serial = dev.serial_number().ok()?;
+    let path = format!("/dev/cu.usbmodem{}{}", &*serial, interface + 1);
+
+    std::path::Path::new(&path).exists().then_some(path)
+}
+
+/// Finds the COM port the OS has given interface `interface` of `dev`.
+#[cfg(windows)]
+fn os_port(dev: &BmpDevice, interface: u8) -> Option<String>
+{
+    dev.serial_ports()
+        .into_iter()
+        .find(|port| port.interface == interface)
+        .map(|port| port.name)
+}
+
+/// We don't know how to find the serial ports of USB devices on other OSes.
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn os_port(_dev: &BmpDevice, _interface: u8) -> Option<String>
+{
+    None
+}
+
+/// Finds the serial port of `dev`'s GDB server.
+pub fn find_port(dev: &BmpDevice) -> Result<String, Error>
+{
+    if dev.operating_mode() != DfuOperatingMode::Runtime {
+        return Err(ErrorKind::Unsupported(
+            S!("The probe is in its bootloader, which has no GDB server. Switch it back to its firmware first."),
+        ).error());
+    }
+
+    let interface = gdb_interface(dev)?;
+    let port = os_port(dev, interface).ok_or_else(|| ErrorKind::GdbPortNotFound.error())?;
+    debug!("Found the GDB server of {} on {} (interface {})", dev.port(), port, interface);
+
+    Ok(port)
+}
+
+/// Hex-encodes `bytes`, as the GDB remote protocol does monitor commands and their output.
+fn hex_encode(bytes: &[u8]) -> String
+{
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Decodes hex-encoded `hex`, ignoring anything that isn't hex.
+fn hex_decode(hex: &str) -> String
+{
+    let bytes = hex
+        .as_bytes()
+        .chunks_exact(2)
+        .filter_map(|pair| std::str::from_utf8(pair).ok().and_then(|pair| u8::from_str_radix(pair, 16).ok()))
+        .collect::<Vec<u8>>();
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// A connection to a probe's GDB server.
+pub struct GdbServer
+{
+    port: Box<dyn SerialPort>,
+    /// The serial port's name, for errors.
+    name: String,
+}
+
+impl GdbServer
+{
+    /// Opens the GDB server on serial port `name` (e.g. `/dev/ttyACM0` or `COM5`), giving it `timeout`
+    /// to send each byte of its replies (as for USB requests; see [`UsbTimeouts`](crate::bmp::UsbTimeouts)).
+    pub fn open(name: &str, timeout: Duration) -> Result<Self, Error>
+    {
+        debug!("Opening GDB server on {}", name);
+        let port = serialport::new(name, BAUD_RATE)
+            .timeout(timeout)
+            .open()
+            .map_err(|e| ErrorKind::GdbServer(S!(name)).error_from(e))?;
+        // Anything left over from an earlier session would be mistaken for replies.
+        if let Err(e) = port.clear(ClearBuffer::Input) {
+            debug!("Couldn't clear {}'s input buffer: {}", name, e);
+        }
+
+        Ok(Self { port, name: S!(name) })
+    }
+
+    fn io_error(&self, e: std::io::Error) -> Error
+    {
+        ErrorKind::GdbServer(self.name.clone()).error_from(e)
+    }
+
+    fn read_byte(&mut self) -> Result<u8, Error>
+    {
+        let mut byte = [0; 1];
+        self.port.read_exact(&mut byte).map_err(|e| self.io_error(e))?;
+        Ok(byte[0])
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error>
+    {
+        self.port
+            .write_all(bytes)
+            .and_then(|()| self.port.flush())
+            .map_err(|e| self.io_error(e))
+    }
+
+    /// Sends `packet`, waiting for the GDB server to acknowledge it.
+    fn send(&mut self, packet: &str) -> Result<(), Error>
+    {
+        let checksum = packet.bytes().fold(0u8, u8::wrapping_add);
+        let frame = format!("${}#{:02x}", packet, checksum);
+
+        for _ in 0..RETRIES {
+            trace!("GDB server <- {}", frame);
+            self.write(frame.as_bytes())?;
+            match self.read_byte()? {
+                b'+' => return Ok(()),
+                other => debug!("GDB server didn't acknowledge a packet ({:?}), sending it again", other as char),
+            }
+        }
+
+        Err(ErrorKind::GdbServer(self.name.clone())
+            .error()
+            .with_ctx("sending a packet the GDB server kept rejecting"))
+    }
+
+    /// Receives the GDB server's next packet, acknowledging it.
+    fn receive(&mut self) -> Result<String, Error>
+    {
+        loop {
+            // Skip anything before the start of the packet.
+            while self.read_byte()? != b'$' {}
+
+            let mut data = Vec::new();
+            let mut checksum = 0u8;
+            loop {
+                match self.read_byte()? {
+                    b'#' => break,
+                    byte => {
+                        checksum = checksum.wrapping_add(byte);
+                        data.push(byte);
+                    },
+                }
+            }
+            let expected = [self.read_byte()?, self.read_byte()?];
+            let expected = std::str::from_utf8(&expected)
+                .ok()
+                .and_then(|expected| u8::from_str_radix(expected, 16).ok());
+            if expected != Some(checksum) {
+                debug!("Packet from the GDB server has a bad checksum, asking for it again");
+                self.write(b"-")?;
+                continue;
+            }
+            self.write(b"+")?;
+
+            // `}` escapes the byte after it, XORed with 0x20.
+            let mut unescaped = Vec::with_capacity(data.len());
+            let mut bytes = data.into_iter();
+            while let Some(byte) = bytes.next() {
+                match byte {
+                    b'}' => unescaped.extend(bytes.next().map(|byte| byte ^ 0x20)),
+                    byte => unescaped.push(byte),
+                }
+            }
+            let packet = String::from_utf8_lossy(&unescaped).into_owned();
+            trace!("GDB server -> ${}", packet);
+
+            return Ok(packet);
+        }
+    }
+
+    /// Runs `monitor <command>`, returning what it printed.
+    pub fn monitor(&mut self, command: &str) -> Result<String, Error>
+    {
+        debug!("Running monitor {}", command);
+        self.send(&format!("qRcmd,{}", hex_encode(command.as_bytes())))?;
+
+        let mut output = String::new();
+        loop {
+            let reply = self.receive()?;
+            match reply.as_str() {
+                "OK" => return Ok(output),
+                // Firmware without the command doesn't know the packet either.
+                "" => {
+                    return Err(ErrorKind::Unsupported(
+                        format!("This probe's firmware doesn't support `monitor {}`.", command),
+                    ).error());
+                },
+                // Errors are `E` and two hex digits, so can't be mistaken for hex-encoded output.
+                error if error.len() == 3 && error.starts_with('E') => {
+                    return Err(ErrorKind::MonitorCommandFailed(S!(command), S!(output.trim())).error());
+                },
+                // Output, as it's printed.
+                output_packet if output_packet.starts_with('O') => output.push_str(&hex_decode(&output_packet[1..])),
+                // The reply may also be the output in one go.
+                rest => {
+                    output.push_str(&hex_decode(rest));
+                    return Ok(output);
+                },
+            }
+        }
+    }
+
+    /// Sends remote protocol packet `packet`, returning the data of the probe's response.
+    fn remote(&mut self, packet: &str) -> Result<String, Error>
+    {
+        trace!("Remote protocol <- !{}#", packet);
+        self.write(format!("!{}#", packet).as_bytes())?;
+
+        // Skip anything before the start of the response.
+        while self.read_byte()? != b'&' {}
+        let result = self.read_byte()?;
+        let mut data = Vec::new();
+        loop {
+            match self.read_byte()? {
+                b'#' => break,
+                byte => data.push(byte),
+            }
+        }
+        let data = String::from_utf8_lossy(&data).into_owned();
+        trace!("Remote protocol -> &{}{}#", result as char, data);
+
+        match result {
+            b'K' => Ok(data),
+            b'N' => Err(ErrorKind::Unsupported(
+                format!("This probe's firmware doesn't support remote protocol packet {}.", packet),
+            ).error()),
+            _ => Err(ErrorKind::DeviceSeemsInvalid(format!("remote protocol response {:?} to {}", data, packet)).error()),
+        }
+    }
+
+    /// Reads the target voltage the probe measures, in volts. That's `None` for hardware that can't
+    /// measure it, which says so with something like `Unknown` instead.
+    pub fn target_voltage(&mut self) -> Result<Option<f32>, Error>
+    {
+        let voltage = self.remote(REMOTE_TARGET_VOLTAGE)?;
+        let volts = voltage.trim().strip_suffix('V').and_then(|volts| volts.parse::<f32>().ok());
+        if volts.is_none() {
+            debug!("Probe can't measure the target voltage: {:?}", voltage);
+        }
+
+        Ok(volts)
+    }
+
+    /// Whether the probe is supplying power to the target, going by what `monitor tpwr` prints, e.g.
+    /// `Target Power: enabled`.
+    pub fn target_power(&mut self) -> Result<bool, Error>
+    {
+        let output = self.monitor("tpwr")?;
+        let state = output
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("Target Power:"))
+            .map(str::trim);
+
+        match state {
+            Some("enabled") => Ok(true),
+            Some("disabled") => Ok(false),
+            _ => Err(ErrorKind::DeviceSeemsInvalid(format!("target power state {:?}", output.trim())).error()),
+        }
+    }
+
+    /// Switches the power the probe supplies to the target on or off.
+    pub fn set_target_power(&mut self, enabled: bool) -> Result<(), Error>
+    {
+        self.monitor(if enabled { "tpwr enable" } else { "tpwr disable" })?;
+        Ok(())
+    }
+}
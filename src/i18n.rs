@@ -0,0 +1,167 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+//! Module for looking up user-facing messages in the message catalogs, so they can be translated.
+//!
+//! The catalogs are [Fluent](https://projectfluent.org) files under `locales/`, one per language, and
+//! are built into the executable. The language comes from `BMPUTIL_LANG` if that's set, and otherwise
+//! from the system's locale (`LC_ALL`, `LC_MESSAGES` and `LANG` on Unix-likes). Anything a catalog
+//! doesn't have a translation for is shown in English.
+//!
+//! Errors, prompts and the main status messages come from the catalogs; messages that haven't moved
+//! into them yet should be moved when they are next touched. Use the [`tr!`](crate::tr) macro to look
+//! messages up.
+
+use std::env;
+use std::sync::OnceLock;
+
+use fluent_bundle::concurrent::FluentBundle;
+use fluent_bundle::{FluentArgs, FluentResource};
+use tracing::{debug, warn};
+use unic_langid::LanguageIdentifier;
+
+use crate::S;
+
+/// The catalog that's used for anything the chosen language doesn't have.
+const FALLBACK_LANGUAGE: &str = "en";
+
+/// Every message catalog we ship, by language.
+const CATALOGS: &[(&str, &str)] = &[
+    ("en", include_str!("../locales/en.ftl")),
+];
+
+/// The catalogs in use, loaded on first use.
+static CATALOG: OnceLock<Catalog> = OnceLock::new();
+
+struct Catalog
+{
+    /// The catalog for the user's language, if we have one and it isn't the fallback.
+    chosen: Option<FluentBundle<FluentResource>>,
+    fallback: FluentBundle<FluentResource>,
+}
+
+/// Parses the catalog for `language`, which must be one of [`CATALOGS`].
+fn load_bundle(language: &str, source: &'static str) -> FluentBundle<FluentResource>
+{
+    let resource = FluentResource::try_new(S!(source)).unwrap_or_else(|(resource, errors)| {
+        warn!("Message catalog for {} has errors: {:?}", language, errors);
+        resource
+    });
+
+    let id: LanguageIdentifier = language
+        .parse()
+        .expect("message catalog languages should be valid language identifiers");
+    let mut bundle = FluentBundle::new_concurrent(vec![id]);
+    // Fluent would otherwise wrap every argument in Unicode isolation marks, which terminals show
+    // as junk more often than not.
+    bundle.set_use_isolating(false);
+    if let Err(errors) = bundle.add_resource(resource) {
+        warn!("Message catalog for {} has duplicate messages: {:?}", language, errors);
+    }
+
+    bundle
+}
+
+/// Works out which language the user wants messages in.
+fn requested_language() -> Option<LanguageIdentifier>
+{
+    let locale = env::var("BMPUTIL_LANG")
+        .ok()
+        .filter(|lang| !lang.is_empty())
+        .or_else(sys_locale::get_locale)?;
+
+    // POSIX locales look like `de_DE.UTF-8@euro`; only the language and region are of interest.
+    let locale = locale.split(['.', '@']).next().unwrap_or_default().replace('_', "-");
+    locale.parse().ok()
+}
+
+/// Picks the catalog to use for `requested`: one for exactly that language and region if we have
+/// it, and otherwise one for the same language.
+fn choose_catalog(requested: &LanguageIdentifier) -> Option<(&'static str, &'static str)>
+{
+    let parsed = CATALOGS
+        .iter()
+        .filter_map(|&(language, source)| {
+            let id: LanguageIdentifier = language.parse().ok()?;
+            Some((id, language, source))
+        })
+        .collect::<Vec<_>>();
+
+    parsed
+        .iter()
+        .find(|(id, ..)| id == requested)
+        .or_else(|| parsed.iter().find(|(id, ..)| id.language == requested.language && id.region.is_none()))
+        .or_else(|| parsed.iter().find(|(id, ..)| id.language == requested.language))
+        .map(|&(_, language, source)| (language, source))
+}
+
+fn catalog() -> &'static Catalog
+{
+    CATALOG.get_or_init(|| {
+        let fallback_source = CATALOGS
+            .iter()
+            .find(|(language, _)| *language == FALLBACK_LANGUAGE)
+            .map(|(_, source)| *source)
+            .expect("the fallback message catalog should always be built in");
+
+        let chosen = requested_language()
+            .and_then(|requested| {
+                let chosen = choose_catalog(&requested);
+                debug!("Requested language {}, using catalog {:?}", requested, chosen.map(|(language, _)| language));
+                chosen
+            })
+            .filter(|(language, _)| *language != FALLBACK_LANGUAGE)
+            .map(|(language, source)| load_bundle(language, source));
+
+        Catalog {
+            chosen,
+            fallback: load_bundle(FALLBACK_LANGUAGE, fallback_source),
+        }
+    })
+}
+
+/// Formats message `id` from `bundle`, if it has it.
+fn format(bundle: &FluentBundle<FluentResource>, id: &str, args: Option<&FluentArgs>) -> Option<String>
+{
+    let pattern = bundle.get_message(id)?.value()?;
+
+    let mut errors = Vec::new();
+    let message = bundle.format_pattern(pattern, args, &mut errors);
+    if !errors.is_empty() {
+        warn!("Errors formatting message {}: {:?}", id, errors);
+    }
+
+    Some(message.into_owned())
+}
+
+/// Looks up and formats message `id`, in the user's language if we can. Prefer [`tr!`](crate::tr).
+pub fn message(id: &str, args: Option<&FluentArgs>) -> String
+{
+    let catalog = catalog();
+    catalog
+        .chosen
+        .as_ref()
+        .and_then(|bundle| format(bundle, id, args))
+        .or_else(|| format(&catalog.fallback, id, args))
+        .unwrap_or_else(|| {
+            // Showing the ID at least says something, and makes the missing message easy to find.
+            warn!("No message {} in any message catalog", id);
+            S!(id)
+        })
+}
+
+/// Looks up a user-facing message in the message catalogs, in the user's language if we can.
+///
+/// Arguments are given as `name = value` and can be strings or numbers; format anything else into
+/// a string first.
+#[macro_export]
+macro_rules! tr
+{
+    ($id:literal) => {
+        $crate::i18n::message($id, None)
+    };
+    ($id:literal, $($name:ident = $value:expr),+ $(,)?) => {{
+        let mut args = fluent_bundle::FluentArgs::new();
+        $(args.set(stringify!($name), $value);)+
+        $crate::i18n::message($id, Some(&args))
+    }};
+}
@@ -0,0 +1,108 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+//! Module for reading Intel HEX firmware files.
+
+use crate::S;
+use crate::error::{Error, ErrorKind};
+use crate::segments::Segment;
+
+fn invalid(line: usize, why: &str) -> Error
+{
+    ErrorKind::InvalidFirmware(Some(format!("invalid Intel HEX file: line {}: {}", line, why))).error()
+}
+
+/// Parses an Intel HEX file into the segments of data it describes, merging consecutive records.
+pub fn parse(file: &[u8]) -> Result<Vec<Segment>, Error>
+{
+    let file = std::str::from_utf8(file)
+        .map_err(|_| ErrorKind::InvalidFirmware(Some(S!("Intel HEX file is not text"))).error())?;
+
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut base_address: u32 = 0;
+
+    for (index, line) in file.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        // Work on the bytes, as records are ASCII, so anything else is as wrong as any other non-hex digit.
+        let hex = line.strip_prefix(':').ok_or_else(|| invalid(line_number, "missing ':'"))?.as_bytes();
+        if !hex.iter().all(u8::is_ascii_hexdigit) {
+            return Err(invalid(line_number, "not hexadecimal"));
+        }
+        if hex.len() % 2 != 0 {
+            return Err(invalid(line_number, "odd number of hex digits"));
+        }
+        let digit = |digit: u8| (digit as char).to_digit(16).expect("checked to be a hex digit") as u8;
+        let bytes = hex
+            .chunks(2)
+            .map(|pair| digit(pair[0]) << 4 | digit(pair[1]))
+            .collect::<Vec<u8>>();
+
+        // Length, 2 address bytes, record type, data, checksum.
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(invalid(line_number, "wrong record length"));
+        }
+        if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+            return Err(invalid(line_number, "bad checksum"));
+        }
+
+        let offset = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+        let data = &bytes[4..bytes.len() - 1];
+
+        match bytes[3] {
+            // Data.
+            0x00 => {
+                let address = base_address.wrapping_add(offset);
+                match segments.last_mut() {
+                    Some(last) if last.address as u64 + last.data.len() as u64 == address as u64 => {
+                        last.data.extend_from_slice(data);
+                    },
+                    _ => segments.push(Segment {
+                        address,
+                        data: data.to_vec(),
+                    }),
+                };
+            },
+            // End of file.
+            0x01 => break,
+            // Extended segment address.
+            0x02 if data.len() == 2 => base_address = (u16::from_be_bytes([data[0], data[1]]) as u32) << 4,
+            // Extended linear address.
+            0x04 if data.len() == 2 => base_address = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16,
+            // Start segment and start linear addresses don't matter to us.
+            0x03 | 0x05 => (),
+            _ => return Err(invalid(line_number, "unknown or malformed record")),
+        };
+    }
+
+    Ok(segments)
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn parses_records()
+    {
+        let file = b":020000040800F2\n:0400000001020304F2\n:0400040005060708DE\n:00000001FF\n";
+        let segments = parse(file).unwrap();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].address, 0x0800_0000);
+        assert_eq!(segments[0].data, [1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn rejects_non_hex_digits()
+    {
+        // Non-ASCII characters mustn't be sliced into, whatever the line's length in bytes.
+        for line in [":0\u{e9}0", ":00000001F\u{e9}", ":+1000001FF", ":00000001FG"] {
+            assert!(parse(line.as_bytes()).is_err(), "{:?} was accepted", line);
+        }
+    }
+}
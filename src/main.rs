@@ -6,27 +6,81 @@
 use std::backtrace::BacktraceStatus;
 
 use std::thread;
-use std::rc::Rc;
+use std::cell::Cell;
+use std::sync::Mutex;
+use std::collections::{BTreeMap, VecDeque};
 use std::io::Write;
 use std::io::Read;
+use std::path::PathBuf;
 use std::str::FromStr;
-use std::time::Duration;
 
 use anstyle;
 use clap::{ArgAction, Command, Arg, ArgMatches, crate_version, crate_description, crate_name};
 use clap::builder::styling::Styles;
-use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
-use indicatif::{ProgressBar, ProgressStyle};
-use log::{debug, warn, error};
+use clap_complete::Shell;
+use termcolor::{Color, ColorSpec, StandardStream, WriteColor};
+use indicatif::{HumanBytes, MultiProgress};
+use tracing::{debug, warn, error};
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::fmt::format::FmtSpan;
+use serde::Serialize;
 
 mod usb;
 mod error;
 mod bmp;
+mod dfu;
 mod elf;
+mod dfu_suffix;
+mod dfuse;
+mod ihex;
+mod segments;
+mod config;
+mod fetch;
+mod version;
+mod state;
+mod prompt;
+mod output;
+mod retry;
+mod release;
+mod cache;
+mod signature;
+mod markdown;
+mod i18n;
+mod self_update;
+mod pin;
+mod tui;
+mod doctor;
+mod setup;
+mod msos;
+mod gdb;
+mod option_bytes;
+mod targets;
 #[cfg(windows)]
 mod windows;
+#[cfg(windows)]
+mod winusb;
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+mod access;
+#[cfg(target_os = "linux")]
+mod udev;
+#[cfg(target_os = "freebsd")]
+mod devd;
+#[cfg(target_os = "linux")]
+mod wsl;
 use crate::bmp::{BmpDevice, BmpMatcher, FirmwareType, FirmwareFormat};
-use crate::error::{Error, ErrorKind, ErrorSource};
+use crate::bmp::{BmpPlatform, ProbeDescription, ProbeHardware, ProbeIdentity, FLASH_BASE};
+use crate::config::Config;
+use crate::release::{Asset, Channel, Release, ReleaseIndex};
+use crate::pin::FirmwarePin;
+use crate::output::{style, Annotation, Outcome, Reporter};
+use crate::state::InterruptedFlash;
+use crate::dfu_suffix::DfuSuffix;
+use crate::usb::{Vid, Pid, DfuOperatingMode};
+use crate::version::FirmwareVersion;
+use crate::error::{Error, ErrorKind, ErrorSource, ExitStatus};
+use crate::gdb::GdbServer;
+use crate::option_bytes::{OptionBytes, RdpLevel};
 
 #[macro_export]
 #[doc(hidden)]
@@ -38,38 +92,55 @@ macro_rules! S
 }
 
 
-fn intel_hex_error() -> !
+/// Finds the single device an `operation` should act on, out of those `matcher` matches.
+///
+/// If more than one device matches, this may use the last used device (if configured to remember it)
+/// or ask the user which to use.
+fn select_single_probe(matcher: &BmpMatcher, config: &Config, operation: &str) -> Result<BmpDevice, Error>
 {
-    // We're ignoring errors for setting the color because the most important thing
-    // is getting the message itself out.
-    // If the messages themselves don't write, though, then we might as well just panic.
-    let mut stderr = StandardStream::stderr(ColorChoice::Auto);
-    let _res = stderr.set_color(ColorSpec::new().set_fg(Some(Color::Red)));
-    write!(&mut stderr, "Error: ")
-        .expect("failed to write to stderr");
-    let _res = stderr.reset();
-    writeln!(
-        &mut stderr,
-        "The specified firmware file appears to be an Intel HEX file, but Intel HEX files are not \
-        currently supported. Please use a binary file (e.g. blackmagic.bin), \
-        or an ELF (e.g. blackmagic.elf) to flash.",
-    )
-    .expect("failed to write to stderr");
+    let mut results = matcher.find_matching_probes();
+
+    let explicitly_selected = matcher.get_serial().is_some() ||
+        matcher.get_port().is_some() ||
+        matcher.get_index().is_some();
+
+    let remembered = if config.remember_last_probe && !explicitly_selected && results.found.len() > 1 {
+        state::last_probe().and_then(|serial| results.pop_by_serial(&serial))
+    } else {
+        None
+    };
+
+    let dev = match remembered {
+        Some(dev) => {
+            status!("{}", tr!("status-using-last-probe"));
+            dev.warn_legacy_ids();
+            dev
+        },
+        None => results.pop_single_interactive(operation)?,
+    };
 
-    std::process::exit(1);
+    if config.remember_last_probe {
+        if let Ok(serial) = dev.serial_number() {
+            state::set_last_probe(&serial);
+        }
+    }
+
+    Ok(dev)
 }
 
 
-fn detach_command(matches: &ArgMatches) -> Result<(), Error>
+fn detach_command(matches: &ArgMatches, config: &Config) -> Result<(), Error>
 {
-    let matcher = BmpMatcher::from_cli_args(matches);
-    let mut results = matcher.find_matching_probes();
-    let dev = results.pop_single("detach")?;
+    let matcher = BmpMatcher::from_cli_args(matches, config)?;
+    let dev = select_single_probe(&matcher, config, "detach")?;
 
     use crate::usb::DfuOperatingMode::*;
     match dev.operating_mode() {
-        Runtime => println!("Requesting device detach from runtime mode to DFU mode..."),
-        FirmwareUpgrade => println!("Requesting device detach from DFU mode to runtime mode..."),
+        Runtime => status!("{}", tr!("status-detach-to-dfu")),
+        FirmwareUpgrade => {
+            status!("{}", tr!("status-in-dfu-mode"));
+            status!("{}", tr!("status-detach-to-runtime"));
+        },
     };
 
     dev.detach_and_destroy()
@@ -78,220 +149,2632 @@ fn detach_command(matches: &ArgMatches) -> Result<(), Error>
     Ok(())
 }
 
-
-fn flash(matches: &ArgMatches) -> Result<(), Error>
+/// Switches a probe into its bootloader (`mode` being DFU mode) or back to its firmware (runtime mode),
+/// and waits for it to come back in that mode, e.g. to leave it in its bootloader for another tool.
+fn dfu_command(matches: &ArgMatches, config: &Config, mode: DfuOperatingMode) -> Result<(), Error>
 {
-    let filename = matches.get_one::<String>("firmware_binary").map(|s| s.as_str())
-        .expect("No firmware file was specified!"); // Should be impossible, thanks to clap.
-    let firmware_file = std::fs::File::open(filename)
-        .map_err(|source| ErrorKind::FirmwareFileIo(Some(filename.to_string())).error_from(source))
-        .map_err(|e| e.with_ctx("reading firmware file to flash"))?;
+    let matcher = BmpMatcher::from_cli_args(matches, config)?;
+    let mut dev = select_single_probe(&matcher, config, "dfu")?;
 
-    let mut firmware_file = std::io::BufReader::new(firmware_file);
+    if dev.operating_mode() == mode {
+        status!("{}", tr!("dfu-already-in-mode", mode = mode.to_string()));
+        return Ok(());
+    }
+    match mode {
+        DfuOperatingMode::FirmwareUpgrade => status!("{}", tr!("status-detach-to-dfu")),
+        DfuOperatingMode::Runtime => status!("{}", tr!("status-detach-to-runtime")),
+    }
 
-    let mut firmware_data = Vec::new();
-    firmware_file.read_to_end(&mut firmware_data).unwrap();
+    if usb::device_fd().is_some() {
+        dev.detach_and_destroy()
+            .map_err(|e| e.with_ctx("detaching device"))?;
+        status!("{}", tr!("status-device-fd-reenumerating"));
+        return Ok(());
+    }
+    dev.detach_and_enumerate()
+        .map_err(|e| e.with_ctx("detaching device"))?;
 
-    // FirmwareFormat::detect_from_firmware() needs at least 4 bytes, and
-    // FirmwareType::detect_from_firmware() needs at least 8 bytes,
-    // but also if we don't even have 8 bytes there's _no way_ this is valid firmware.
-    if firmware_data.len() < 8 {
-        return Err(
-            ErrorKind::InvalidFirmware(Some(S!("less than 8 bytes long"))).error()
+    // A bootloader with no valid firmware to start stays in DFU mode.
+    if dev.operating_mode() != mode {
+        return Err(ErrorKind::DeviceReboot.error());
+    }
+    status!("{}", tr!("dfu-now-in-mode", mode = mode.to_string(), probe = dev.summary()));
+
+    Ok(())
+}
+
+/// Erases part (or all) of a probe's flash, for developers.
+fn erase_command(matches: &ArgMatches, config: &Config) -> Result<(), Error>
+{
+    let matcher = BmpMatcher::from_cli_args(matches, config)?;
+    let mut dev = select_single_probe(&matcher, config, "erase")?;
+
+    if matches.get_flag("mass") {
+        confirm_bootloader_change(matches, "erase")?;
+        status!("{}", tr!("debug-mass-erasing"));
+        dev.mass_erase()?;
+    } else {
+        let address = *matches.get_one::<u32>("address")
+            .expect("No address was specified!"); // Should be impossible, thanks to clap.
+        let length = *matches.get_one::<u32>("length")
+            .expect("No length was specified!"); // Should be impossible, thanks to clap.
+        check_flash_range(&dev, address, length)?;
+        if overlaps_bootloader(&dev, address, length) {
+            confirm_bootloader_change(matches, "erase")?;
+        }
+        status!(
+            "{}",
+            tr!(
+                "debug-erasing",
+                start = format!("0x{:08x}", address),
+                end = format!("0x{:08x}", address.saturating_add(length)),
+            ),
         );
+        dev.erase_flash(address, length)?;
     }
 
-    // Extract the actual firmware data from the file, based on the format we're using.
-    let format = FirmwareFormat::detect_from_firmware(&firmware_data);
-    let firmware_data = match format {
-        FirmwareFormat::Binary => firmware_data,
-        FirmwareFormat::Elf => elf::extract_binary(&firmware_data)?,
-        FirmwareFormat::IntelHex => intel_hex_error(), // FIXME: implement this.
-    };
+    // With (some of) its firmware gone, the probe is best left in its bootloader.
+    status!("{}", tr!("debug-erased"));
 
+    Ok(())
+}
 
-    // Try to find the Black Magic Probe device based on the filter arguments.
-    let matcher = BmpMatcher::from_cli_args(matches);
-    let mut results = matcher.find_matching_probes();
-    // TODO: flashing to multiple BMPs at once should be supported, but maybe we should require some kind of flag?
-    let mut dev: BmpDevice = results.pop_single("flash")?;
+/// Writes a raw binary file to an arbitrary address in a probe's flash, for developers.
+fn write_command(matches: &ArgMatches, config: &Config) -> Result<(), Error>
+{
+    let address = *matches.get_one::<u32>("address")
+        .expect("No address was specified!"); // Should be impossible, thanks to clap.
+    let filename = matches.get_one::<String>("file")
+        .expect("No file was specified!"); // Should be impossible, thanks to clap.
+    let data = read_firmware_file(filename)?;
+
+    let matcher = BmpMatcher::from_cli_args(matches, config)?;
+    let mut dev = select_single_probe(&matcher, config, "write")?;
+
+    let length = u32::try_from(data.len()).unwrap_or(u32::MAX);
+    check_flash_range(&dev, address, length)?;
+    if overlaps_bootloader(&dev, address, length) {
+        confirm_bootloader_change(matches, "overwrite")?;
+    }
 
-    // Grab the platform, which we need for firmware type detection, and the port, which we need
-    // to find the probe after rebooting.
-    let platform = dev.platform();
-    let port = dev.port();
+    status!("{}", tr!("debug-writing", length = data.len(), address = format!("0x{:08x}", address)));
+    let in_dfu_mode = dev.write_flash(address, &data, 0, |_written| ())?;
 
-    // Detect what kind of firmware this is, using the platform to determine the link address.
-    let firmware_type = FirmwareType::detect_from_firmware(platform, &firmware_data)
-        .map_err(|e| e.with_ctx("detecting firmware type"))?;
+    // Manifestation-tolerant bootloaders don't reboot by themselves.
+    if in_dfu_mode {
+        dev.detach_and_destroy()?;
+    }
 
-    debug!("Firmware file was detected as {}", firmware_type);
+    Ok(())
+}
 
-    // But allow the user to override that type, if they *really* know what they are doing.
-    let firmware_type = if let Some(location) = matches
-        .get_one::<String>("override-firmware-type").map(|s| s.as_str()) {
-        if let Some("really") = matches.get_one::<String>("allow-dangerous-options").map(|s| s.as_str()) {
-            warn!("Overriding firmware-type detection and flashing to user-specified location ({}) instead!", location);
-        } else {
-            // We're ignoring errors for setting the color because the most important thing is
-            // getting the message itself out.
-            // If the messages themselves don't write, though, then we might as well just panic.
-            let mut stderr = StandardStream::stderr(ColorChoice::Auto);
-            let _res = stderr.set_color(ColorSpec::new().set_fg(Some(Color::Red)));
-            write!(&mut stderr, "WARNING: ").expect("failed to write to stderr");
-            let _res = stderr.reset();
-            writeln!(
-                &mut stderr,
-                "--override-firmware-type is used to override the firmware type detection and flash \
-                a firmware binary to a location other than the one that it seems to be designed for.\n\
-                This is a potentially destructive operation and can result in an unbootable device! \
-                (can require a second, external JTAG debugger and manual wiring to fix!)\n\
-                \nDo not use this option unless you are a firmware developer and really know what you are doing!\n\
-                \nIf you are sure this is really what you want to do, run again with --allow-dangerous-options=really"
-            ).expect("failed to write to stderr");
-            std::process::exit(1);
-        };
-        if location == "bootloader" {
-            FirmwareType::Bootloader
-        } else if location == "application" {
-            FirmwareType::Application
-        } else {
-            unreachable!("Clap ensures invalid option cannot be passed to --override-firmware-type");
-        }
+/// A probe's serial number, for `bmputil probe serial get`'s JSON output.
+#[derive(Debug, Clone, Serialize)]
+struct SerialReport
+{
+    probe: String,
+    serial: String,
+}
+
+/// Prints a probe's serial number.
+fn serial_get_command(matches: &ArgMatches, config: &Config) -> Result<(), Error>
+{
+    let matcher = BmpMatcher::from_cli_args(matches, config)?;
+    let dev = select_single_probe(&matcher, config, "serial get")?;
+    let serial = dev.serial_number()
+        .map_err(|e| e.with_ctx("reading the probe's serial number"))?
+        .to_string();
+
+    if output::json_output() {
+        output::print_json(&SerialReport { probe: dev.summary(), serial });
     } else {
-        firmware_type
-    };
+        println!("{}", serial);
+    }
 
-    let file_size = firmware_data.len();
-    let file_size = u32::try_from(file_size)
-        .expect("firmware filesize exceeded 32 bits! Firmware binary must be invalid");
+    Ok(())
+}
 
-    // If we can't get the string descriptors, try to go ahead with flashing anyway.
-    // It's unlikely that other control requests will succeed, but the OS might be messing with
-    // the string descriptor stuff.
-    let _ = writeln!(std::io::stdout(), "Found: {}", dev)
-        .map_err(|e| {
-            error!("Failed to read string data from Black Magic Probe: {}\nTrying to continue anyway...", e);
-        });
+/// Programs a new serial number into a probe whose bootloader has somewhere to keep one, and checks the
+/// probe reports it once it's rebooted.
+fn serial_set_command(matches: &ArgMatches, config: &Config) -> Result<(), Error>
+{
+    let serial = matches.get_one::<String>("serial")
+        .expect("No serial number was specified!"); // Should be impossible, thanks to clap.
 
-    // We need an Rc<T> as [`dfu_core::sync::DfuSync`] requires `progress` to be 'static,
-    // so it must be moved into the closure. However, since we need to call .finish() here,
-    // it must be owned by both. Hence: Rc<T>.
-    // Default template: `{wide_bar} {pos}/{len}`.
-    let progress_bar = ProgressBar::new(file_size as u64)
-        .with_style(ProgressStyle::default_bar()
-            .template(" {percent:>3}% |{bar:50}| {bytes}/{total_bytes} [{binary_bytes_per_sec} {elapsed}]").unwrap()
+    let matcher = BmpMatcher::from_cli_args(matches, config)?;
+    let mut dev = select_single_probe(&matcher, config, "serial set")?;
+    let port = dev.port();
+    let custom_ids = dev.custom_ids();
+    let timeouts = dev.timeouts();
+    let was_running = dev.operating_mode() == DfuOperatingMode::Runtime;
+
+    let current = dev.serial_number().map(|serial| serial.to_string()).ok();
+    if current.as_deref() == Some(serial.as_str()) {
+        status!("{}", tr!("serial-already", serial = serial.as_str()));
+        return Ok(());
+    }
+    if !matches.get_flag("force") {
+        if !prompt::can_prompt() {
+            return Err(ErrorKind::NotConfirmed(S!("changing the serial number")).error());
+        }
+        let question = tr!(
+            "serial-confirm",
+            probe = dev.summary(),
+            from = current.clone().unwrap_or_else(|| tr!("serial-unknown")),
+            to = serial.as_str(),
         );
-    let progress_bar = Rc::new(progress_bar);
-    let enclosed = Rc::clone(&progress_bar);
+        let confirmed = prompt::confirm(&question)
+            .map_err(|e| ErrorKind::External(ErrorSource::StdIo(e)).error())?;
+        if !confirmed {
+            return Err(ErrorKind::NotConfirmed(S!("changing the serial number")).error());
+        }
+    }
 
-    match dev.download(&*firmware_data, file_size, firmware_type, move |flash_pos_delta| {
-        // Don't actually print flashing until the erasing has finished.
-        if enclosed.position() == 0 {
-            if firmware_type == FirmwareType::Application {
-                enclosed.println("Flashing...");
-            } else {
-                enclosed.println("Flashing bootloader...");
+    status!("{}", tr!("serial-programming", serial = serial.as_str()));
+    let in_dfu_mode = match dev.program_serial(serial) {
+        Ok(in_dfu_mode) => in_dfu_mode,
+        // Don't leave a probe we had to switch into its bootloader there for nothing.
+        Err(e) if was_running && matches!(e.kind, ErrorKind::Unsupported(_)) => {
+            if let Err(detach_error) = dev.detach_and_destroy() {
+                warn!("{}", tr!("serial-switch-back-failed", error = detach_error.to_string()));
             }
-        }
-        enclosed.inc(flash_pos_delta as u64);
-    }) {
-        Ok(()) => {
-            progress_bar.finish();
-            Ok(())
+            return Err(e);
         },
-        Err(e) => {
-            progress_bar.finish();
-            if progress_bar.position() == (file_size as u64) {
-                warn!("Possibly spurious error from OS at the very end of flashing: {}", e);
-                Ok(())
-            } else {
-                Err(e)
-            }
+        Err(e) => return Err(e),
+    };
+    status!("{}", tr!("serial-written"));
+
+    // Manifestation-tolerant bootloaders don't reboot by themselves.
+    if in_dfu_mode {
+        dev.detach_and_destroy()?;
+    } else {
+        drop(dev);
+    }
+    if usb::device_fd().is_some() {
+        status!("{}", tr!("status-device-fd-rebooting"));
+        return Ok(());
+    }
+    thread::sleep(timeouts.detach_wait());
+
+    // The firmware is what reports the serial number over USB, so check it picks the new one up.
+    let dev = bmp::wait_for_probe_reboot(&port, custom_ids, timeouts.reenumerate, "serial set")?;
+    match dev.serial_number() {
+        Ok(reported) if *reported == **serial => status!("{}", tr!("serial-now", serial = serial.as_str())),
+        Ok(reported) => warn!("{}", tr!("serial-not-reported", reported = reported.to_string())),
+        Err(e) => warn!("{}", tr!("serial-read-back-failed", error = e.to_string())),
+    }
+
+    Ok(())
+}
+
+/// Whether a probe is supplying power to its target, for `bmputil probe power status`'s JSON output.
+#[derive(Debug, Clone, Serialize)]
+struct PowerReport
+{
+    probe: String,
+    enabled: bool,
+}
+
+/// Switches the power a probe supplies to its target on or off (if `enabled` is given), or says whether
+/// it's on, through the probe's GDB server.
+fn power_command(matches: &ArgMatches, config: &Config, enabled: Option<bool>) -> Result<(), Error>
+{
+    let matcher = BmpMatcher::from_cli_args(matches, config)?;
+    let dev = select_single_probe(&matcher, config, "power")?;
+    let port = match matches.get_one::<String>("gdb_port") {
+        Some(port) => port.clone(),
+        None => gdb::find_port(&dev)?,
+    };
+    let probe = dev.summary();
+    let timeouts = dev.timeouts();
+    // The GDB server is its own serial port, so we're done with the probe's USB device.
+    drop(dev);
+
+    let mut server = GdbServer::open(&port, timeouts.request)?;
+    if let Some(enabled) = enabled {
+        server.set_target_power(enabled)
+            .map_err(|e| e.with_ctx("switching target power"))?;
+        status!("{}", tr!("power-switched", enabled = enabled.to_string()));
+        return Ok(());
+    }
+
+    let enabled = server.target_power()
+        .map_err(|e| e.with_ctx("reading target power"))?;
+    if output::json_output() {
+        output::print_json(&PowerReport { probe, enabled });
+    } else {
+        println!("{}", tr!("power-state", enabled = enabled.to_string()));
+    }
+
+    Ok(())
+}
+
+/// The target voltage a probe measures, for `bmputil probe voltage`'s JSON output.
+#[derive(Debug, Clone, Serialize)]
+struct VoltageReport
+{
+    probe: String,
+    /// In volts, or `None` for hardware that can't measure it.
+    voltage: Option<f32>,
+}
+
+/// Reads the target voltage `dev` measures (in volts, where its hardware can), through its GDB server on
+/// `gdb_port`, or the port we find it on if that's not given.
+fn read_target_voltage(dev: &BmpDevice, gdb_port: Option<&String>) -> Result<Option<f32>, Error>
+{
+    let port = match gdb_port {
+        Some(port) => port.clone(),
+        None => gdb::find_port(dev)?,
+    };
+
+    GdbServer::open(&port, dev.timeouts().request)?.target_voltage()
+}
+
+/// Prints the target voltage a probe measures, e.g. to check the target is powered.
+fn voltage_command(matches: &ArgMatches, config: &Config) -> Result<(), Error>
+{
+    let matcher = BmpMatcher::from_cli_args(matches, config)?;
+    let dev = select_single_probe(&matcher, config, "voltage")?;
+    let voltage = read_target_voltage(&dev, matches.get_one::<String>("gdb_port"))
+        .map_err(|e| e.with_ctx("reading the target voltage"))?;
+
+    if output::json_output() {
+        output::print_json(&VoltageReport { probe: dev.summary(), voltage });
+        return Ok(());
+    }
+    match voltage {
+        Some(voltage) => println!("{:.2}", voltage),
+        None => return Err(ErrorKind::Unsupported(tr!("voltage-unsupported")).error()),
+    }
+
+    Ok(())
+}
+
+/// A probe MCU's option bytes, for `bmputil probe option-bytes`'s JSON output.
+#[derive(Debug, Clone, Serialize)]
+struct OptionBytesReport
+{
+    probe: String,
+    /// The option bytes' address, in hex.
+    address: String,
+    /// The option bytes, in hex.
+    bytes: String,
+    /// The STM32 family whose layout they're in, if known.
+    family: Option<String>,
+    /// The read protection level, if it's the same whichever family of that layout the MCU is.
+    rdp_level: Option<u8>,
+    /// The read protection level the RDP byte means on each family of that layout.
+    rdp_levels: BTreeMap<&'static str, u8>,
+    /// The option bytes we know the meaning of, by name.
+    fields: BTreeMap<&'static str, u32>,
+}
+
+/// Prints the read protection (RDP) level and option bytes of a probe's MCU, as read through the STM32
+/// built-in bootloader, so it's known whether the flash can be read back before trying to.
+fn option_bytes_command(matches: &ArgMatches, config: &Config) -> Result<(), Error>
+{
+    let matcher = BmpMatcher::from_cli_args(matches, config)?;
+    let mut dev = select_single_probe(&matcher, config, "option-bytes")?;
+    let probe = dev.summary();
+    let (address, bytes) = dev.read_option_bytes()
+        .map_err(|e| e.with_ctx("reading the option bytes"))?;
+    let option_bytes = OptionBytes::new(address, bytes);
+
+    if output::json_output() {
+        output::print_json(&OptionBytesReport {
+            probe,
+            address: format!("0x{:08x}", option_bytes.address),
+            bytes: option_bytes.bytes.iter().map(|byte| format!("{:02x}", byte)).collect(),
+            family: option_bytes.family(),
+            rdp_level: option_bytes.rdp_level().map(|level| level.number()),
+            rdp_levels: option_bytes.rdp_levels()
+                .into_iter()
+                .map(|(family, level)| (family, level.number()))
+                .collect(),
+            fields: option_bytes.fields().into_iter().collect(),
+        });
+        return Ok(());
+    }
+
+    let address = format!("0x{:08x}", option_bytes.address);
+    match option_bytes.family() {
+        Some(family) => println!("{}", tr!("option-bytes-at-layout", address = address, family = family)),
+        None => println!("{}", tr!("option-bytes-at", address = address)),
+    }
+    let hex = option_bytes.bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join(" ");
+    println!("  {}", hex);
+    for (name, value) in option_bytes.fields() {
+        println!("  {:<6} 0x{:02x}", name, value);
+    }
+    let levels = option_bytes.rdp_levels();
+    match option_bytes.rdp_level() {
+        Some(level) => println!("{}", tr!("option-bytes-rdp", level = level.to_string())),
+        None if levels.is_empty() => status!("{}", tr!("option-bytes-unknown-layout")),
+        None => {
+            let levels = levels
+                .iter()
+                .map(|(family, level)| tr!("option-bytes-rdp-on-family", level = level.number(), family = *family))
+                .collect::<Vec<_>>()
+                .join(&tr!("option-bytes-rdp-separator"));
+            println!(
+                "{}",
+                tr!(
+                    "option-bytes-rdp-depends",
+                    rdp = format!("0x{:02x}", option_bytes.rdp().unwrap_or_default()),
+                    levels = levels,
+                ),
+            );
         },
-    }?;
+    }
 
-    drop(dev); // Force libusb to free the device.
-    thread::sleep(Duration::from_millis(250));
+    Ok(())
+}
 
-    let dev = bmp::wait_for_probe_reboot(&port, Duration::from_secs(5), "flash")
-        .map_err(|e| {
-            error!("Black Magic Probe did not re-enumerate after flashing! Invalid firmware?");
-            e
-        })?;
+/// Saves the firmware currently on `dev` (of `firmware_type`) to `path`, leaving `dev` in DFU mode.
+fn backup_firmware(dev: &mut BmpDevice, firmware_type: FirmwareType, path: &str) -> Result<(), Error>
+{
+    status!("Backing up current {} to {}...", firmware_type, path);
 
+    let firmware = dev.read_firmware(firmware_type)
+        .map_err(|e| e.with_ctx("reading back current firmware"))?;
+    write_firmware_file(path, &firmware)?;
 
-    let desc = dev.device().device_descriptor().unwrap();
+    status!("Saved {} bytes.", firmware.len());
 
-    let product_string = dev
-        .handle()
-        .read_product_string_ascii(
-            &desc
-        )
-        .map_err(|e| {
-            error!("Error reading firmware version after flash! Invalid firmware?");
-            e
-        })?;
+    Ok(())
+}
 
-    let version_string = product_string
-        .chars()
-        .skip("Black Magic Probe ".len())
-        .collect::<String>();
+/// Writes `firmware` out to the file `path`, or to stdout if it's `-`.
+fn write_firmware_file(path: &str, firmware: &[u8]) -> Result<(), Error>
+{
+    let res = if path == "-" {
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(firmware).and_then(|()| stdout.flush())
+    } else {
+        std::fs::write(path, firmware)
+    };
+
+    res.map_err(|source| ErrorKind::FirmwareFileWrite(path.to_string()).error_from(source))
+}
+
+/// Reads the flash out of a probe into a file, as-is.
+///
+/// Unlike `backup`, this keeps any trailing erased flash, so the file is an image of the whole region.
+fn read_command(matches: &ArgMatches, config: &Config) -> Result<(), Error>
+{
+    let path = matches.get_one::<String>("output_file")
+        .expect("No output file was specified!"); // Should be impossible, thanks to clap.
+    let firmware_type = if matches.get_flag("bootloader") {
+        FirmwareType::Bootloader
+    } else {
+        FirmwareType::Application
+    };
+
+    let matcher = BmpMatcher::from_cli_args(matches, config)?;
+    let mut dev = select_single_probe(&matcher, config, "read")?;
+
+    // Keep stdout clean for the image itself, if that's where it's going.
+    eprintln!("Reading {} region of flash...", firmware_type);
+    let image = dev.read_region(firmware_type)
+        .map_err(|e| e.with_ctx("reading flash"))?;
+    write_firmware_file(path, &image)?;
+    eprintln!("Read {} bytes from 0x{:08x}.", image.len(), dev.load_address(firmware_type));
+
+    // Reading the flash leaves the probe in its bootloader, so send it back to its firmware.
+    dev.detach_and_destroy()
+        .map_err(|e| e.with_ctx("returning device to runtime mode"))?;
+
+    Ok(())
+}
+
+fn backup_command(matches: &ArgMatches, config: &Config) -> Result<(), Error>
+{
+    let path = matches.get_one::<String>("backup_file")
+        .expect("No backup file was specified!"); // Should be impossible, thanks to clap.
+    let firmware_type = if matches.get_flag("bootloader") {
+        FirmwareType::Bootloader
+    } else {
+        FirmwareType::Application
+    };
+
+    let matcher = BmpMatcher::from_cli_args(matches, config)?;
+    let mut dev = select_single_probe(&matcher, config, "back up")?;
+
+    backup_firmware(&mut dev, firmware_type, path)?;
+
+    // Reading the firmware back leaves the probe in its bootloader, so send it back to its firmware.
+    dev.detach_and_destroy()
+        .map_err(|e| e.with_ctx("returning device to runtime mode"))?;
+
+    Ok(())
+}
+
+/// The target families a firmware supports, for `bmputil firmware targets`'s JSON output.
+#[derive(Debug, Clone, Serialize)]
+struct TargetsReport
+{
+    /// The probe, or firmware file, the firmware came from.
+    firmware: String,
+    targets: Vec<&'static str>,
+}
+
+/// Lists the target families the firmware on a probe (or in a firmware file) has drivers for. For a probe,
+/// that's the release build for its hardware of the version its product string says it's running, so
+/// it needn't be rebooted to read its firmware back.
+fn targets_command(matches: &ArgMatches, config: &Config) -> Result<(), Error>
+{
+    let (source, firmware) = match matches.get_one::<String>("firmware_binary") {
+        Some(filename) => {
+            let firmware = load_firmware(filename, firmware_format(matches), true)
+                .map_err(|e| e.with_ctx("reading firmware file"))?;
+            (filename.clone(), firmware.data)
+        },
+        None => {
+            let matcher = BmpMatcher::from_cli_args(matches, config)?;
+            let dev = select_single_probe(&matcher, config, "targets")?;
+
+            let hardware = dev.hardware().ok_or_else(|| ErrorKind::Unsupported(S!(
+                "can't tell what hardware this probe is, so can't tell which firmware build it has; \
+                give the firmware file to look in instead"
+            )).error())?;
+            let version = dev.identity()?.version.ok_or_else(|| ErrorKind::Unsupported(S!(
+                "this probe doesn't say which firmware version it's running; give the firmware file to look in instead"
+            )).error())?;
+
+            let index = ReleaseIndex::from_cli_args(matches, config);
+            let release = index.by_tag(&version).map_err(|e| match e.kind {
+                ErrorKind::UnknownRelease(_) => ErrorKind::Unsupported(format!(
+                    "this probe's firmware ({}) isn't an official release, so there's no build of it to look in; \
+                    give the firmware file it was flashed with instead",
+                    version,
+                )).error(),
+                _ => e,
+            })?;
+            let asset = release.firmware_asset(hardware)
+                .ok_or_else(|| ErrorKind::NoReleaseForHardware(release.tag_name.clone(), hardware.to_string()).error())?;
+            let firmware = release_firmware(&dev, hardware, &index, &release, asset, false, &Reporter::default())?;
+
+            (format!("{} (release {})", dev.summary(), release.tag_name), firmware.data)
+        },
+    };
+
+    // Firmware without any drivers we know of is a mistake (or something that isn't firmware at all),
+    // whether the list is wanted as JSON or not.
+    let targets = targets::supported_targets(&firmware);
+    if targets.is_empty() {
+        return Err(ErrorKind::InvalidFirmware(Some(S!("no target drivers bmputil knows of were found in it"))).error());
+    }
+    if output::json_output() {
+        output::print_json(&TargetsReport { firmware: source, targets });
+        return Ok(());
+    }
 
-    println!("Black Magic Probe successfully rebooted into firmware version {}", version_string);
+    status!("Target families {} has drivers for:", source);
+    for target in targets {
+        println!("{}", target);
+    }
 
     Ok(())
 }
 
-fn info_command(matches: &ArgMatches) -> Result<(), Error>
+/// Reads the whole of the firmware file `filename`, which may be `-` for stdin, or an HTTPS URL.
+fn read_firmware_file(filename: &str) -> Result<Vec<u8>, Error>
+{
+    let mut firmware_data = Vec::new();
+
+    if fetch::is_url(filename) {
+        firmware_data = fetch::fetch_firmware(filename)?;
+    } else if filename == "-" {
+        // Buffer it all up front, so it can be validated before we go anywhere near the device.
+        std::io::stdin()
+            .lock()
+            .read_to_end(&mut firmware_data)
+            .map_err(|source| ErrorKind::FirmwareFileIo(Some(S!("<stdin>"))).error_from(source))?;
+    } else {
+        let firmware_file = std::fs::File::open(filename)
+            .map_err(|source| ErrorKind::FirmwareFileIo(Some(filename.to_string())).error_from(source))?;
+
+        std::io::BufReader::new(firmware_file)
+            .read_to_end(&mut firmware_data)
+            .map_err(|source| ErrorKind::FirmwareFileIo(Some(filename.to_string())).error_from(source))?;
+    }
+
+    Ok(firmware_data)
+}
+
+/// A firmware image loaded from a file.
+struct FirmwareImage
+{
+    /// The firmware itself, ready to be flashed.
+    data: Vec<u8>,
+
+    /// The address the file says the firmware is to be written at, for formats that say.
+    address: Option<u32>,
+
+    /// The DFU suffix the file had, if any.
+    suffix: Option<DfuSuffix>,
+}
+
+impl FirmwareImage
+{
+    /// Checks the address the file says the firmware goes at (if it says) is where firmware of
+    /// `firmware_type` goes on `dev`.
+    fn check_address(&self, dev: &BmpDevice, firmware_type: FirmwareType) -> Result<(), Error>
+    {
+        let load_address = dev.load_address(firmware_type);
+        match self.address {
+            Some(address) if address != load_address => Err(ErrorKind::InvalidFirmware(Some(format!(
+                "file says to write the firmware at 0x{:08x}, but {} firmware goes at 0x{:08x} on this probe",
+                address,
+                firmware_type,
+                load_address,
+            ))).error()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks the image's DFU suffix (if it has one) says it's for `dev`, only warning if `force` is set.
+    fn check_suffix_ids(&self, dev: &BmpDevice, force: bool) -> Result<(), Error>
+    {
+        let suffix = match self.suffix {
+            Some(suffix) => suffix,
+            None => return Ok(()),
+        };
+
+        // The suffix could name the probe's runtime or DFU mode IDs, and it could be in either mode right now.
+        let desc = dev.device().device_descriptor()
+            .expect(libusb_cannot_fail!("libusb_get_device_descriptor()"));
+        let platform = dev.platform();
+        let custom_ids = dev.custom_ids();
+        let ids = [
+            Some((Vid(desc.vendor_id()), Pid(desc.product_id()))),
+            Some(platform.runtime_ids()),
+            Some(platform.dfu_ids()),
+            custom_ids.runtime_ids(),
+            custom_ids.dfu_ids(),
+        ];
+        if ids.iter().flatten().any(|&(vid, pid)| suffix.matches_ids(vid, pid)) {
+            return Ok(());
+        }
+
+        let e = ErrorKind::InvalidFirmware(Some(format!(
+            "DFU suffix says it is for USB device {:04x}:{:04x}, which is not this probe",
+            suffix.id_vendor,
+            suffix.id_product,
+        ))).error();
+        if !force {
+            return Err(e);
+        }
+        warn!("{}; continuing anyway as --force was given", e);
+
+        Ok(())
+    }
+
+    /// Checks the hardware variant the image was built for (if it says) is the hardware `dev` is,
+    /// only warning if `force` is set.
+    ///
+    /// Firmware for one variant generally won't boot on another, and can leave the probe needing
+    /// recovery with an external debugger.
+    fn check_hardware(&self, dev: &BmpDevice, force: bool) -> Result<(), Error>
+    {
+        let image_hardware = ProbeIdentity::from_firmware_image(&self.data).and_then(|identity| identity.hardware());
+        let (image_hardware, probe_hardware) = match (image_hardware, dev.hardware()) {
+            (Some(image_hardware), Some(probe_hardware)) => (image_hardware, probe_hardware),
+            // If either can't be told, there's nothing to go on.
+            _ => return Ok(()),
+        };
+        if image_hardware == probe_hardware {
+            return Ok(());
+        }
+
+        let e = ErrorKind::HardwareMismatch(image_hardware.to_string(), probe_hardware.to_string()).error();
+        if !force {
+            return Err(e);
+        }
+        warn!("{}; continuing anyway as --force was given", e);
+
+        Ok(())
+    }
+}
+
+/// The firmware file format given with `--format`, if it was given and isn't "auto".
+fn firmware_format(matches: &ArgMatches) -> Option<FirmwareFormat>
+{
+    matches.get_one::<String>("format")
+        .and_then(|name| FirmwareFormat::from_cli_name(name))
+}
+
+/// Reads the firmware file `filename` (as [`read_firmware_file()`] does) and extracts the firmware from it.
+///
+/// The file's format is detected from its contents unless `format` says what it is.
+/// If the file has a DFU suffix, its CRC is checked (only warning on mismatch if `force` is set) and it's
+/// stripped off.
+fn load_firmware(filename: &str, format: Option<FirmwareFormat>, force: bool) -> Result<FirmwareImage, Error>
+{
+    parse_firmware(read_firmware_file(filename)?, format, force)
+}
+
+/// Like [`load_firmware()`], but for the contents of a firmware file that's already been read.
+fn parse_firmware(mut firmware_data: Vec<u8>, format: Option<FirmwareFormat>, force: bool) -> Result<FirmwareImage, Error>
+{
+    let suffix = DfuSuffix::parse(&firmware_data);
+    if let Some(suffix) = suffix {
+        debug!("Firmware file has a DFU suffix: {:?}", suffix);
+        if !suffix.crc_matches(&firmware_data) {
+            let e = ErrorKind::InvalidFirmware(Some(S!("DFU suffix CRC does not match (corrupted file?)"))).error();
+            if !force {
+                return Err(e);
+            }
+            warn!("{}; continuing anyway as --force was given", e);
+        }
+        let length = suffix.strip(&firmware_data).len();
+        firmware_data.truncate(length);
+    }
+
+    // FirmwareFormat::detect_from_firmware() needs at least 4 bytes, and
+    // FirmwareType::detect_from_firmware() needs at least 8 bytes,
+    // but also if we don't even have 8 bytes there's _no way_ this is valid firmware.
+    if firmware_data.len() < 8 {
+        return Err(
+            ErrorKind::InvalidFirmware(Some(S!("less than 8 bytes long"))).error()
+        );
+    }
+
+    // Extract the actual firmware data from the file, based on the format we're using.
+    let format = match format {
+        Some(format) => format,
+        None => {
+            let format = FirmwareFormat::detect_from_firmware(&firmware_data);
+            debug!("Firmware file was detected as {}", format);
+            format
+        },
+    };
+    let (address, firmware_data) = match format {
+        FirmwareFormat::Binary => (None, firmware_data),
+        FirmwareFormat::Elf => {
+            let (address, image) = segments::assemble(elf::extract_segments(&firmware_data)?)?;
+            (Some(address), image)
+        },
+        FirmwareFormat::IntelHex => {
+            let (address, image) = segments::assemble(ihex::parse(&firmware_data)?)?;
+            (Some(address), image)
+        },
+        FirmwareFormat::DfuSe => {
+            let (address, image) = dfuse::flash_image(dfuse::parse(&firmware_data)?)?;
+            (Some(address), image)
+        },
+    };
+
+    if firmware_data.len() < 8 {
+        return Err(
+            ErrorKind::InvalidFirmware(Some(S!("less than 8 bytes of firmware"))).error()
+        );
+    }
+
+    Ok(FirmwareImage {
+        data: firmware_data,
+        address,
+        suffix,
+    })
+}
+
+fn verify_command(matches: &ArgMatches, config: &Config) -> Result<(), Error>
+{
+    let firmware = match matches.get_one::<String>("firmware_binary") {
+        Some(filename) => Some(
+            load_firmware(filename, firmware_format(matches), false)
+                .map_err(|e| e.with_ctx("reading firmware file to verify against"))?
+        ),
+        None => None,
+    };
+
+    let matcher = BmpMatcher::from_cli_args(matches, config)?;
+    let mut dev = select_single_probe(&matcher, config, "verify")?;
+    status!("{}", tr!("status-found", probe = dev.to_string()));
+
+    status!("Reading back installed firmware...");
+    let res = match (firmware, matches.get_one::<String>("sha256")) {
+        (Some(firmware), _) => {
+            let firmware_type = FirmwareType::detect_from_firmware(dev.platform(), dev.hardware(), &firmware.data)
+                .map_err(|e| e.with_ctx("detecting firmware type"))?;
+            dev.verify(&firmware.data, firmware_type)
+        },
+        (None, Some(sha256)) => {
+            let installed = dev.read_firmware(FirmwareType::Application)?;
+            fetch::verify_sha256(&installed, sha256, "installed firmware")
+        },
+        (None, None) => unreachable!("Clap ensures either a firmware file or a hash is given"),
+    };
+
+    // Reading the firmware back leaves the probe in its bootloader, so send it back to its firmware
+    // whether or not it matched.
+    dev.detach_and_destroy()
+        .map_err(|e| e.with_ctx("returning device to runtime mode"))?;
+
+    res?;
+    status!("Installed firmware matches.");
+
+    Ok(())
+}
+
+fn restore_command(matches: &ArgMatches, config: &Config) -> Result<(), Error>
+{
+    let filename = matches.get_one::<String>("backup_file")
+        .expect("No backup file was specified!"); // Should be impossible, thanks to clap.
+    let force = matches.get_flag("force");
+    // Backups are always raw binaries.
+    let firmware = load_firmware(filename, Some(FirmwareFormat::Binary), force)
+        .map_err(|e| e.with_ctx("reading firmware file to restore"))?;
+    let image = firmware.data.as_slice();
+
+    let matcher = BmpMatcher::from_cli_args(matches, config)?;
+    let dev = select_single_probe(&matcher, config, "restore")?;
+
+    // Make sure the image is actually for this probe, as far as we can tell.
+    firmware.check_suffix_ids(&dev, force)?;
+    firmware.check_hardware(&dev, force)?;
+
+    let firmware_type = FirmwareType::detect_from_firmware(dev.platform(), dev.hardware(), image)
+        .map_err(|e| e.with_ctx("detecting firmware type"))?;
+    firmware.check_address(&dev, firmware_type)?;
+    // A backup of the bootloader is as able to brick the probe as any other bootloader image.
+    if firmware_type == FirmwareType::Bootloader {
+        confirm_bootloader_update(&dev, image, matches)?;
+    }
+
+    status!("{}", tr!("status-found", probe = dev.to_string()));
+    status!("Restoring {} from {} ({} bytes)...", firmware_type, filename, image.len());
+
+    write_firmware(dev, image, firmware_type, true, &Reporter::default())
+}
+
+/// Updates bmputil itself to its latest release, if there's a newer one.
+fn self_update_command(matches: &ArgMatches, config: &Config) -> Result<(), Error>
+{
+    let index = ReleaseIndex::from_cli_args(matches, config).for_bmputil();
+    let release = index.latest(Channel::Stable)?;
+    let current = self_update::current_version();
+    status!("Latest bmputil release: {}", release.tag_name);
+
+    if let (Some(current), Some(latest)) = (&current, release.version()) {
+        if *current >= latest && !matches.get_flag("force") {
+            status!("This is bmputil {}; nothing to do (use --force to reinstall).", current);
+            return Ok(());
+        }
+    }
+
+    let exe = self_update::install(&index, &release)?;
+    status!("Updated {} to bmputil {}.", exe.display(), release.tag_name);
+
+    Ok(())
+}
+
+/// Extra bash completion, for completing `--serial` with the serial numbers of the connected probes.
+const BASH_SERIAL_COMPLETION: &str = r#"
+_bmputil_with_serials() {
+    local prev="${COMP_WORDS[COMP_CWORD-1]}"
+    if [[ "$prev" == "--serial" || "$prev" == "-s" ]]; then
+        COMPREPLY=( $(compgen -W "$(bmputil completions --probe-serials 2>/dev/null)" -- "${COMP_WORDS[COMP_CWORD]}") )
+        return 0
+    fi
+    _bmputil "$@"
+}
+complete -F _bmputil_with_serials -o bashdefault -o default bmputil
+"#;
+
+/// Extra fish completion, for completing `--serial` with the serial numbers of the connected probes.
+const FISH_SERIAL_COMPLETION: &str = r#"
+complete -c bmputil -s s -l serial -f -r -a "(bmputil completions --probe-serials 2>/dev/null)"
+"#;
+
+/// Prints a shell completion script for bmputil, or the serial numbers of the connected probes, for
+/// those scripts to complete `--serial` with.
+fn completions_command(matches: &ArgMatches) -> Result<(), Error>
+{
+    if matches.get_flag("probe-serials") {
+        // This runs as the user presses tab, so just quietly list whatever can be found.
+        let results = BmpMatcher::new().find_matching_probes();
+        for dev in &results.found {
+            if let Ok(serial) = dev.serial_number() {
+                println!("{}", &*serial);
+            }
+        }
+        return Ok(());
+    }
+
+    let shell = *matches.get_one::<Shell>("shell")
+        .expect("No shell was specified!"); // Should be impossible, thanks to clap.
+    clap_complete::generate(shell, &mut cli(), crate_name!(), &mut std::io::stdout());
+    match shell {
+        Shell::Bash => print!("{}", BASH_SERIAL_COMPLETION),
+        Shell::Fish => print!("{}", FISH_SERIAL_COMPLETION),
+        _ => (),
+    };
+
+    Ok(())
+}
+
+/// Writes a roff manual page for bmputil, and one for each of its (non-hidden) subcommands, into the
+/// given directory.
+fn generate_manpages_command(matches: &ArgMatches) -> Result<(), Error>
+{
+    let dir = matches.get_one::<PathBuf>("dir")
+        .expect("No directory was specified!"); // Should be impossible, thanks to clap.
+
+    std::fs::create_dir_all(dir)
+        .and_then(|()| clap_mangen::generate_to(cli(), dir))
+        .map_err(|e| ErrorKind::External(ErrorSource::StdIo(e)).error().with_ctx("writing manual pages"))?;
+    status!("Wrote manual pages to {}", dir.display());
+
+    Ok(())
+}
+
+/// Deletes all the firmware releases that have been downloaded and cached.
+fn cache_clean_command() -> Result<(), Error>
+{
+    let freed = cache::clean()?;
+    status!("Deleted {} of downloaded firmware.", HumanBytes(freed));
+
+    Ok(())
+}
+
+/// A firmware release, for `bmputil firmware releases`'s JSON output.
+#[derive(Debug, Clone, Serialize)]
+struct ReleaseReport
+{
+    tag: String,
+    /// When it was published, as YYYY-MM-DD.
+    date: Option<String>,
+    prerelease: bool,
+    /// Its firmware files, for the probe's hardware if that's known.
+    firmware: Vec<String>,
+}
+
+/// Lists the published firmware releases, with the files for a probe's hardware (as given, or of the
+/// one connected probe), or otherwise all of their firmware files.
+fn releases_command(matches: &ArgMatches, config: &Config) -> Result<(), Error>
+{
+    let hardware = match matches.get_one::<ProbeHardware>("hardware") {
+        Some(&hardware) => Some(hardware),
+        None => {
+            // This is just a nicety, so don't complain if there isn't exactly one probe.
+            let matcher = BmpMatcher::from_cli_args(matches, config)?;
+            let results = matcher.find_matching_probes();
+            match results.found.as_slice() {
+                [dev] => dev.hardware(),
+                _ => None,
+            }
+        },
+    };
+    if let Some(hardware) = hardware {
+        status!("Showing firmware for {} hardware", hardware);
+    }
+
+    let releases = ReleaseIndex::from_cli_args(matches, config).releases()?;
+    // The firmware files of each release, for the hardware if we know it.
+    let firmware = |release: &Release| -> Vec<String> {
+        match hardware {
+            Some(hardware) => release.firmware_asset(hardware).into_iter().map(|asset| asset.name.clone()).collect(),
+            None => release.assets
+                .iter()
+                .filter(|asset| asset.name.starts_with("blackmagic-"))
+                .map(|asset| asset.name.clone())
+                .collect(),
+        }
+    };
+
+    if output::json_output() {
+        let reports = releases
+            .iter()
+            .map(|release| ReleaseReport {
+                tag: release.tag_name.clone(),
+                date: release.date().map(str::to_string),
+                prerelease: release.prerelease,
+                firmware: firmware(release),
+            })
+            .collect::<Vec<_>>();
+        output::print_json(&reports);
+        return Ok(());
+    }
+
+    for release in &releases {
+        status!(
+            "{}  {}{}",
+            release.tag_name,
+            release.date().unwrap_or("(unknown date)"),
+            if release.prerelease { "  (pre-release)" } else { "" },
+        );
+
+        let files = firmware(release);
+        match hardware {
+            Some(hardware) if files.is_empty() => status!("    (no build for {} hardware)", hardware),
+            _ => {
+                for file in files {
+                    status!("    {}", file);
+                }
+            },
+        };
+    }
+
+    Ok(())
+}
+
+/// Prints the release notes of `release`, if it has any.
+fn show_release_notes(release: &Release)
+{
+    let notes = match release.body.as_deref().map(str::trim) {
+        Some(notes) if !notes.is_empty() => notes,
+        _ => return,
+    };
+
+    status!("\nRelease notes for {}:\n", release.tag_name);
+    if let Err(e) = markdown::print(notes) {
+        warn!("Failed to show the release notes: {}", e);
+    }
+    status!();
+}
+
+/// Which firmware builds to update to, as given with `--channel`, `BMPUTIL_CHANNEL` or in the configuration
+/// file. The environment variable isn't clap's `env()`, as clap would count it as `--channel` being given,
+/// which overrides the project's pin file.
+fn update_channel(matches: &ArgMatches, config: &Config) -> Channel
+{
+    let env_channel = || {
+        let name = std::env::var("BMPUTIL_CHANNEL").ok().filter(|name| !name.is_empty())?;
+        let channel = Channel::from_cli_name(&name);
+        if channel.is_none() {
+            warn!("Ignoring BMPUTIL_CHANNEL={}, which isn't one of {}", name, Channel::CLI_NAMES.join(", "));
+        }
+        channel
+    };
+
+    matches.get_one::<String>("channel")
+        .and_then(|name| Channel::from_cli_name(name))
+        .or_else(env_channel)
+        .or(config.channel)
+        .unwrap_or_default()
+}
+
+/// How a probe's firmware compares to what it should be running, for `bmputil firmware check`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum CheckStatus
+{
+    UpToDate,
+    UpdateAvailable,
+    AsPinned,
+    DiffersFromPin,
+    /// The probe doesn't say what version it's running (or the latest release doesn't).
+    Unknown,
+}
+
+/// The result of `bmputil firmware check` for one probe, for JSON output.
+#[derive(Debug, Clone, Serialize)]
+struct ProbeCheck
+{
+    probe: String,
+    serial: Option<String>,
+    installed: Option<String>,
+    /// The version the probe should be running: the pinned version, or the latest.
+    wanted: Option<String>,
+    status: CheckStatus,
+}
+
+/// The result of `bmputil firmware check`, for JSON output.
+#[derive(Debug, Clone, Serialize)]
+struct CheckReport
+{
+    latest: Option<String>,
+    pin_file: Option<String>,
+    probes: Vec<ProbeCheck>,
+    outdated: usize,
+}
+
+/// Compares the firmware of each matching probe against the latest release (or the version pinned by
+/// the project's pin file), without changing anything, exiting with [`ExitStatus::UpdateAvailable`] if any
+/// of them could be updated.
+fn check_command(matches: &ArgMatches, config: &Config) -> Result<(), Error>
+{
+    let matcher = BmpMatcher::from_cli_args(matches, config)?;
+    let mut results = matcher.find_matching_probes();
+    let devices = results.pop_all()?;
+
+    // Probes pinned to a version by the project's pin file are checked against that rather than the latest.
+    let pin = FirmwarePin::find()?;
+    if let Some(pin) = &pin {
+        status!("{}", tr!("status-using-pin", path = pin.path().display().to_string()));
+    }
+    let pinned = |dev: &BmpDevice| pin.as_ref().and_then(|pin| pin.version_for(dev.hardware()));
+
+    let latest = if devices.iter().any(|dev| pinned(dev).is_none()) {
+        let channel = update_channel(matches, config);
+        let latest = ReleaseIndex::from_cli_args(matches, config).latest(channel)?;
+        status!("{}", tr!("status-latest-release", channel = channel.to_string(), release = latest.tag_name.as_str()));
+        Some(latest)
+    } else {
+        None
+    };
+    let latest_version = latest.as_ref().and_then(|latest| latest.version());
+
+    // Line the probes up after the longest status, whatever language it's in.
+    let labels = [
+        tr!("status-check-up-to-date"),
+        tr!("status-check-as-pinned"),
+        tr!("status-check-update-available"),
+        tr!("status-check-differs-from-pin"),
+        tr!("status-check-unknown"),
+    ];
+    let width = labels.iter().map(|label| label.chars().count()).max().unwrap_or_default() + 2;
+    let label = |index: usize| format!("{:<width$}", labels[index], width = width);
+
+    let mut probes = Vec::with_capacity(devices.len());
+    for dev in &devices {
+        let installed = dev.identity().ok().and_then(|identity| identity.firmware_version());
+        let (status, wanted) = match (&installed, pinned(dev)) {
+            (None, pinned) => (CheckStatus::Unknown, pinned.or(latest_version.as_ref())),
+            (Some(installed), Some(pinned)) if installed == pinned => (CheckStatus::AsPinned, Some(pinned)),
+            (Some(_), Some(pinned)) => (CheckStatus::DiffersFromPin, Some(pinned)),
+            (Some(installed), None) => match &latest_version {
+                Some(latest_version) if installed >= latest_version => (CheckStatus::UpToDate, Some(latest_version)),
+                Some(latest_version) => (CheckStatus::UpdateAvailable, Some(latest_version)),
+                None => (CheckStatus::Unknown, None),
+            },
+        };
+
+        let summary = dev.summary();
+        let installed = installed.map(|installed| installed.to_string());
+        let wanted = wanted.map(|wanted| wanted.to_string());
+        let installed_text = installed.as_deref().unwrap_or_default();
+        let wanted_text = wanted.as_deref().unwrap_or_default();
+        match status {
+            CheckStatus::UpdateAvailable => output::annotate(
+                Annotation::Warning,
+                format!("{} is running {}, but {} is available", summary, installed_text, wanted_text),
+            ),
+            CheckStatus::DiffersFromPin => output::annotate(
+                Annotation::Warning,
+                format!("{} is running {}, but {} is pinned", summary, installed_text, wanted_text),
+            ),
+            _ => (),
+        };
+        match status {
+            CheckStatus::UpToDate => status!(
+                "  {}{} ({})",
+                output::paint(label(0), style::GOOD),
+                summary,
+                installed_text,
+            ),
+            CheckStatus::AsPinned => status!(
+                "  {}{} ({})",
+                output::paint(label(1), style::GOOD),
+                summary,
+                installed_text,
+            ),
+            CheckStatus::UpdateAvailable => status!(
+                "  {}{} ({} -> {})",
+                output::paint(label(2), style::NOTICE),
+                summary,
+                output::paint(installed_text, style::BAD),
+                output::paint(wanted_text, style::GOOD),
+            ),
+            CheckStatus::DiffersFromPin => status!(
+                "  {}{} ({}, {})",
+                output::paint(label(3), style::NOTICE),
+                summary,
+                output::paint(installed_text, style::BAD),
+                output::paint(tr!("status-check-pinned", version = wanted_text), style::GOOD),
+            ),
+            CheckStatus::Unknown => status!(
+                "  {}{} {}",
+                output::paint(label(4), style::FAINT),
+                summary,
+                output::paint(tr!("status-check-unknown-version"), style::FAINT),
+            ),
+        };
+
+        probes.push(ProbeCheck {
+            probe: summary,
+            serial: dev.serial_number().ok().map(|serial| serial.to_string()),
+            installed,
+            wanted,
+            status,
+        });
+    }
+
+    let outdated = probes
+        .iter()
+        .filter(|probe| matches!(probe.status, CheckStatus::UpdateAvailable | CheckStatus::DiffersFromPin))
+        .count();
+    if output::json_output() {
+        output::print_json(&CheckReport {
+            latest: latest.map(|latest| latest.tag_name),
+            pin_file: pin.map(|pin| pin.path().display().to_string()),
+            probes,
+            outdated,
+        });
+    }
+
+    if outdated > 0 {
+        status!("{}", tr!("status-check-outdated", outdated = outdated, total = devices.len()));
+        ExitStatus::UpdateAvailable.exit();
+    }
+
+    Ok(())
+}
+
+/// Downloads the latest official firmware release (or the one asked for, or pinned by the project's
+/// pin file) for a probe's hardware and flashes it, unless the probe is already running it (or, for
+/// the latest, something newer).
+fn update_command(matches: &ArgMatches, config: &Config) -> Result<(), Error>
+{
+    let force = matches.get_flag("force");
+
+    let matcher = BmpMatcher::from_cli_args(matches, config)?;
+    let dev = select_single_probe(&matcher, config, "update")?;
+    status!("{}", tr!("status-found", probe = dev.to_string()));
+
+    let hardware = dev.hardware().ok_or_else(|| ErrorKind::Unsupported(S!(
+        "can't tell what hardware this probe is, so can't pick a firmware build for it; \
+        flash one yourself with bmputil firmware flash"
+    )).error())?;
+
+    let installed = dev.identity().ok().and_then(|identity| identity.firmware_version());
+    let index = ReleaseIndex::from_cli_args(matches, config);
+
+    // Unless told otherwise, install the version pinned by the project's pin file, if there is one.
+    let pinned = if matches.contains_id("version") || matches.contains_id("channel") {
+        None
+    } else {
+        FirmwarePin::find()?.and_then(|pin| {
+            let version = pin.version_for(Some(hardware))?.to_string();
+            status!("{}", tr!("status-using-pin", path = pin.path().display().to_string()));
+            Some(version)
+        })
+    };
+
+    // A specific version is installed whatever it is, even if it's older, unless it's already there.
+    let release = if let Some(tag) = matches.get_one::<String>("version").or(pinned.as_ref()) {
+        let release = index.by_tag(tag)?;
+        if let (Some(installed), Some(wanted)) = (&installed, release.version()) {
+            if *installed == wanted && !force {
+                status!("{}", tr!("status-already-running", version = installed.to_string()));
+                return Ok(());
+            }
+        }
+        release
+    } else {
+        let channel = update_channel(matches, config);
+        let release = index.latest(channel)?;
+        let latest_message = if release.prerelease {
+            tr!("status-latest-prerelease", channel = channel.to_string(), release = release.tag_name.as_str())
+        } else {
+            tr!("status-latest-release", channel = channel.to_string(), release = release.tag_name.as_str())
+        };
+        status!("{}", latest_message);
+
+        if let (Some(installed), Some(latest)) = (&installed, release.version()) {
+            if *installed >= latest && !force {
+                status!("{}", tr!("status-already-running", version = installed.to_string()));
+                return Ok(());
+            }
+        }
+        release
+    };
+
+    let asset = release.firmware_asset(hardware)
+        .ok_or_else(|| ErrorKind::NoReleaseForHardware(release.tag_name.clone(), hardware.to_string()).error())?;
+
+    // Let the user see what's changed, and back out if they don't like the look of it.
+    if !matches.get_flag("no-release-notes") {
+        show_release_notes(&release);
+    }
+    if !force && prompt::can_prompt() {
+        let confirmed = prompt::confirm(&tr!("status-confirm-update", release = release.tag_name.as_str()))
+            .map_err(|e| ErrorKind::External(ErrorSource::StdIo(e)).error())?;
+        if !confirmed {
+            return Err(ErrorKind::NotConfirmed(S!("firmware update")).error());
+        }
+    }
+
+    let reporter = Reporter::default();
+    let firmware = release_firmware(&dev, hardware, &index, &release, asset, force, &reporter)?;
+
+    let from = installed.map_or_else(|| tr!("status-unknown-firmware"), |installed| installed.to_string());
+    status!("{}", tr!(
+        "status-updating",
+        from = output::paint(from, style::BAD),
+        to = output::paint(&release.tag_name, style::GOOD),
+        length = firmware.data.len(),
+    ));
+    let summary = dev.summary();
+    write_firmware(dev, &firmware.data, FirmwareType::Application, !matches.get_flag("no-verify"), &reporter)?;
+    output::annotate(Annotation::Notice, format!("Updated {} to {}", summary, release.tag_name));
+
+    Ok(())
+}
+
+/// Gets the firmware `asset` of `release` for `dev` (which is `hardware`): from the cache if it's been
+/// downloaded before, or otherwise downloading it, and checks it against the release's checksums and
+/// signatures, and against the probe, as for any other firmware file.
+fn release_firmware(
+    dev: &BmpDevice,
+    hardware: ProbeHardware,
+    index: &ReleaseIndex,
+    release: &Release,
+    asset: &Asset,
+    force: bool,
+    reporter: &Reporter,
+) -> Result<FirmwareImage, Error>
+{
+    let variant = release::asset_name(hardware);
+    let firmware_data = match cache::load(&release.tag_name, variant, &asset.name) {
+        Some(data) => {
+            reporter.println(format!("Using previously downloaded {}", asset.name));
+            data
+        },
+        None if index.is_offline() => {
+            return Err(ErrorKind::NotCached(format!("{} from release {}", asset.name, release.tag_name)).error());
+        },
+        None => {
+            reporter.println(format!("Downloading {}...", asset.name));
+            let data = fetch::fetch_firmware(&asset.browser_download_url)
+                .map_err(|e| e.with_ctx("downloading firmware release"))?;
+            cache::store(&release.tag_name, variant, &asset.name, &data);
+            data
+        },
+    };
+    index.verify_checksum(release, variant, asset, &firmware_data)?;
+    index.verify_signature(release, variant, asset, &firmware_data)?;
+    let firmware = parse_firmware(firmware_data, None, force)?;
+
+    // Same checks as for any other firmware file, in case the release has something unexpected in it.
+    firmware.check_suffix_ids(dev, force)?;
+    firmware.check_hardware(dev, force)?;
+    let firmware_type = FirmwareType::detect_from_firmware(dev.platform(), dev.hardware(), &firmware.data)
+        .map_err(|e| e.with_ctx("detecting firmware type"))?;
+    if firmware_type != FirmwareType::Application {
+        return Err(ErrorKind::InvalidFirmware(Some(S!("release firmware appears to be a bootloader"))).error());
+    }
+    firmware.check_address(dev, firmware_type)?;
+
+    Ok(firmware)
+}
+
+/// Rewrites the bootloader (and optionally the application firmware) of a probe in the STM32's
+/// built-in DFU bootloader, for probes whose own bootloader has been damaged.
+fn recover_command(matches: &ArgMatches, config: &Config) -> Result<(), Error>
+{
+    let force = matches.get_flag("force");
+    let bootloader_file = matches.get_one::<String>("bootloader_file")
+        .expect("No bootloader file was specified!"); // Should be impossible, thanks to clap.
+    let bootloader = load_firmware(bootloader_file, firmware_format(matches), force)
+        .map_err(|e| e.with_ctx("reading bootloader file to recover with"))?;
+    let application = matches.get_one::<String>("firmware_binary")
+        .map(|filename| load_firmware(filename, firmware_format(matches), force))
+        .transpose()
+        .map_err(|e| e.with_ctx("reading firmware file to recover with"))?;
+
+    let matcher = BmpMatcher::from_cli_args(matches, config)?;
+    let mut dev = select_single_probe(&matcher, config, "recover")?;
+    status!("{}", tr!("status-found", probe = dev.to_string()));
+
+    bootloader.check_address(&dev, FirmwareType::Bootloader)?;
+    confirm_bootloader_update(&dev, &bootloader.data, matches)?;
+
+    recover_probe(&mut dev, &bootloader, application.as_ref())
+}
+
+/// Writes `bootloader`, and `application` if given, to `dev` through the STM32's built-in DFU bootloader,
+/// once the user has confirmed doing so.
+fn recover_probe(dev: &mut BmpDevice, bootloader: &FirmwareImage, application: Option<&FirmwareImage>) -> Result<(), Error>
+{
+    // The built-in bootloader can't tell us what hardware this is, but the bootloader image may.
+    let hardware = dev.hardware()
+        .or_else(|| ProbeIdentity::from_firmware_image(&bootloader.data).and_then(|identity| identity.hardware()));
+    let application_address = BmpPlatform::BlackMagicDebug.load_address(FirmwareType::Application, hardware);
+    if let Some(application) = application {
+        if let Some(address) = application.address.filter(|&address| address != application_address) {
+            return Err(ErrorKind::InvalidFirmware(Some(format!(
+                "file says to write the firmware at 0x{:08x}, but application firmware goes at 0x{:08x}",
+                address,
+                application_address,
+            ))).error());
+        }
+        if let Some(hardware) = hardware {
+            let available = hardware.flash_size() - (application_address - FLASH_BASE);
+            if application.data.len() > available as usize {
+                return Err(ErrorKind::InvalidFirmware(Some(format!(
+                    "firmware is {} bytes but {} hardware only has {} bytes of flash available for it",
+                    application.data.len(),
+                    hardware,
+                    available,
+                ))).error());
+            }
+        }
+    }
+
+    let total = bootloader.data.len() + application.map_or(0, |application| application.data.len());
+    let progress_bar = Reporter::default().progress_bar(total as u64);
+    let res = dev.recover(
+        &bootloader.data,
+        application.map(|application| (application_address, application.data.as_slice())),
+        |delta| progress_bar.inc(delta as u64),
+    );
+    progress_bar.finish();
+    res?;
+
+    if application.is_some() {
+        status!("Recovered. The probe should now start its new bootloader and firmware.");
+    } else {
+        status!("Recovered. The probe should now start its new bootloader; flash its firmware next.");
+    }
+
+    Ok(())
+}
+
+/// Removes the read protection (RDP) of a probe's MCU through the STM32's built-in DFU bootloader, which
+/// mass erases it, and then writes a bootloader (and firmware) back, as for `recover`. This is how to
+/// bring a locked (e.g. second-hand) probe back into use.
+fn unlock_command(matches: &ArgMatches, config: &Config) -> Result<(), Error>
+{
+    let force = matches.get_flag("force");
+    // Read the images first, so we don't erase the probe only to find we have nothing to write back.
+    let bootloader_file = matches.get_one::<String>("bootloader_file")
+        .expect("No bootloader file was specified!"); // Should be impossible, thanks to clap.
+    let bootloader = load_firmware(bootloader_file, firmware_format(matches), force)
+        .map_err(|e| e.with_ctx("reading bootloader file to write after unlocking"))?;
+    let application = matches.get_one::<String>("firmware_binary")
+        .map(|filename| load_firmware(filename, firmware_format(matches), force))
+        .transpose()
+        .map_err(|e| e.with_ctx("reading firmware file to write after unlocking"))?;
+
+    let matcher = BmpMatcher::from_cli_args(matches, config)?;
+    let mut dev = select_single_probe(&matcher, config, "unlock")?;
+    status!("{}", tr!("status-found", probe = dev.to_string()));
+
+    // The bootloader may refuse to read the option bytes while the flash is read-protected, which is
+    // as good as saying it is. Where what the RDP byte means depends on which STM32 the probe is built
+    // on, we can't rule out that it's read-protected, so go ahead.
+    let option_bytes = match dev.read_option_bytes() {
+        Ok((address, bytes)) => Some(OptionBytes::new(address, bytes)),
+        Err(e @ Error { kind: ErrorKind::DfuStatus(..), .. }) => {
+            debug!("Couldn't read the option bytes: {}", e);
+            status!("{}", tr!("unlock-option-bytes-refused"));
+            None
+        },
+        Err(e) => return Err(e.with_ctx("reading the option bytes")),
+    };
+    if let Some(option_bytes) = option_bytes {
+        match option_bytes.rdp_level() {
+            Some(RdpLevel::Level0) => {
+                status!("{}", tr!("unlock-not-protected"));
+                return Ok(());
+            },
+            Some(RdpLevel::Level2) => {
+                return Err(ErrorKind::Unsupported(tr!("unlock-permanently-locked")).error());
+            },
+            Some(level) => status!("{}", tr!("option-bytes-rdp", level = level.to_string())),
+            None if option_bytes.rdp_levels().is_empty() => warn!("{}", tr!("unlock-unknown-layout")),
+            None => status!(
+                "{}",
+                tr!("unlock-rdp-depends", rdp = format!("0x{:02x}", option_bytes.rdp().unwrap_or_default())),
+            ),
+        }
+    }
+
+    bootloader.check_address(&dev, FirmwareType::Bootloader)?;
+    check_bootloader_image(&dev, &bootloader.data)?;
+    if !force {
+        if !prompt::can_prompt() {
+            return Err(ErrorKind::NotConfirmed(S!("unlocking")).error());
+        }
+        let question = tr!("unlock-confirm", probe = dev.summary());
+        let confirmed = prompt::confirm(&question)
+            .map_err(|e| ErrorKind::External(ErrorSource::StdIo(e)).error())?;
+        if !confirmed {
+            return Err(ErrorKind::NotConfirmed(S!("unlocking")).error());
+        }
+    }
+
+    let port = dev.port();
+    let custom_ids = dev.custom_ids();
+    let timeouts = dev.timeouts();
+    status!("{}", tr!("unlock-removing"));
+    dev.read_unprotect()
+        .map_err(|e| e.with_ctx("removing read protection"))?;
+    drop(dev);
+    if usb::device_fd().is_some() {
+        status!("{}", tr!("unlock-device-fd"));
+        return Ok(());
+    }
+
+    // Mass erasing all of the flash takes much longer than the probe would normally take to come back.
+    thread::sleep(timeouts.detach_wait());
+    let mut dev = bmp::wait_for_probe_reboot(&port, custom_ids, timeouts.mass_erase(), "unlock")
+        .map_err(|e| e.with_ctx("waiting for the probe to come back after removing read protection"))?;
+    dev.set_timeouts(timeouts);
+    status!("{}", tr!("unlock-removed"));
+
+    recover_probe(&mut dev, &bootloader, application.as_ref())
+}
+
+fn flash(matches: &ArgMatches, config: &Config) -> Result<(), Error>
+{
+    let filename = matches.get_one::<String>("firmware_binary").map(|s| s.as_str())
+        .expect("No firmware file was specified!"); // Should be impossible, thanks to clap.
+    let firmware = load_firmware(filename, firmware_format(matches), matches.get_flag("force"))
+        .map_err(|e| e.with_ctx("reading firmware file to flash"))?;
+
+    // Try to find the Black Magic Probe device based on the filter arguments.
+    let matcher = BmpMatcher::from_cli_args(matches, config)?;
+
+    // If we're only updating outdated probes and the only matches are up to date, there's nothing to do.
+    if let Some(version) = matches.get_one::<FirmwareVersion>("only-older-than") {
+        let results = matcher.find_matching_probes();
+        if results.found.is_empty() && results.errors.is_empty() && results.up_to_date > 0 {
+            status!("No Black Magic Probe with firmware older than {} found; nothing to do.", version);
+            return Ok(());
+        }
+    }
+
+    if matches.get_flag("all") {
+        return flash_all(&matcher, matches, &firmware);
+    }
+
+    let dev = select_single_probe(&matcher, config, "flash")?;
+    flash_probe(dev, &firmware, matches, &Reporter::default())
+}
+
+/// The result of `bmputil firmware flash --all`, for JSON output.
+#[derive(Debug, Clone, Serialize)]
+struct FlashReport
+{
+    probes: Vec<ProbeOutcome>,
+    failed: usize,
+    total: usize,
+}
+
+/// How an operation on one of several probes went, for JSON output.
+#[derive(Debug, Clone, Serialize)]
+struct ProbeOutcome
+{
+    probe: String,
+    #[serde(flatten)]
+    outcome: Outcome,
+}
+
+/// Flashes every probe `matcher` matches in turn, for `flash --all`, summarising how each went at the end.
+fn flash_all(matcher: &BmpMatcher, matches: &ArgMatches, firmware: &FirmwareImage) -> Result<(), Error>
+{
+    let mut results = matcher.find_matching_probes();
+    let devices = results.pop_all()?;
+    let total = devices.len();
+    let jobs = matches.get_one::<u64>("jobs").map_or(1, |&jobs| jobs as usize);
+
+    let outcomes = if jobs > 1 && total > 1 {
+        // The probes being flashed at once can't each ask, so ask about them all up front.
+        if matches.get_flag("bootloader") {
+            confirm_bootloader_change(matches, "update")?;
+        }
+        flash_in_parallel(devices, jobs, firmware, matches)
+    } else {
+        let mut outcomes = Vec::with_capacity(total);
+        for (index, dev) in devices.into_iter().enumerate() {
+            let name = dev.summary();
+            status!("\n[{}/{}] {}", index + 1, total, name);
+
+            let res = flash_probe(dev, firmware, matches, &Reporter::default());
+            if let Err(e) = &res {
+                error!("Failed to flash {}: {}", name, e);
+                output::annotate(Annotation::Error, format!("Failed to flash {}: {}", name, e));
+            }
+            outcomes.push((name, res));
+        }
+        outcomes
+    };
+
+    status!("\n{}", tr!("status-summary"));
+    let (ok_label, failed_label) = (tr!("status-summary-ok"), tr!("status-summary-failed"));
+    let width = ok_label.chars().count().max(failed_label.chars().count()) + 2;
+    for (name, res) in &outcomes {
+        match res {
+            Ok(()) => status!("  {:<width$}{}", ok_label, name, width = width),
+            Err(e) => status!("  {:<width$}{}: {}", failed_label, name, e, width = width),
+        };
+    }
+
+    let failed = outcomes.iter().filter(|(_name, res)| res.is_err()).count();
+    if output::json_output() {
+        output::print_json(&FlashReport {
+            probes: outcomes
+                .iter()
+                .map(|(name, res)| ProbeOutcome {
+                    probe: name.clone(),
+                    outcome: Outcome {
+                        ok: res.is_ok(),
+                        error: res.as_ref().err().map(|e| e.to_string()),
+                    },
+                })
+                .collect(),
+            failed,
+            total,
+        });
+    }
+
+    if failed == 0 {
+        Ok(())
+    } else {
+        Err(ErrorKind::SomeProbesFailed(failed, total).error())
+    }
+}
+
+/// Flashes `devices`, up to `jobs` at a time, returning how each went (in the same order as `devices`).
+fn flash_in_parallel(
+    devices: Vec<BmpDevice>,
+    jobs: usize,
+    firmware: &FirmwareImage,
+    matches: &ArgMatches,
+) -> Vec<(String, Result<(), Error>)>
+{
+    let total = devices.len();
+    let multi = MultiProgress::new();
+
+    // Label each device's output with its serial number, which is short but unique.
+    let queue: VecDeque<_> = devices
+        .into_iter()
+        .enumerate()
+        .map(|(index, dev)| {
+            let label = dev.serial_number()
+                .map(|serial| serial.to_string())
+                .unwrap_or_else(|_| format!("probe {}", index));
+            (index, label, dev.summary(), dev)
+        })
+        .collect();
+    let queue = Mutex::new(queue);
+    let outcomes = Mutex::new(Vec::with_capacity(total));
+
+    status!("{}", tr!("status-flashing-probes", total = total, jobs = jobs));
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(total) {
+            scope.spawn(|| loop {
+                let next = queue.lock().unwrap().pop_front();
+                let (index, label, name, dev) = match next {
+                    Some(next) => next,
+                    None => break,
+                };
+
+                let reporter = Reporter::for_job(&multi, label);
+                let res = flash_probe(dev, firmware, matches, &reporter);
+                if let Err(e) = &res {
+                    error!("{}", reporter.labelled(format!("Failed to flash: {}", e)));
+                    output::annotate(Annotation::Error, format!("Failed to flash {}: {}", name, e));
+                }
+                outcomes.lock().unwrap().push((index, name, res));
+            });
+        }
+    });
+
+    let mut outcomes = outcomes.into_inner().unwrap();
+    outcomes.sort_by_key(|(index, _name, _res)| *index);
+    outcomes
+        .into_iter()
+        .map(|(_index, name, res)| (name, res))
+        .collect()
+}
+
+/// Flashes `firmware` onto `dev`, as the `flash` subcommand's arguments say to.
+fn flash_probe(mut dev: BmpDevice, firmware: &FirmwareImage, matches: &ArgMatches, reporter: &Reporter) -> Result<(), Error>
+{
+    let firmware_data = firmware.data.as_slice();
+    firmware.check_suffix_ids(&dev, matches.get_flag("force"))?;
+    firmware.check_hardware(&dev, matches.get_flag("force"))?;
+
+    // Grab the platform, which we need for firmware type detection.
+    let platform = dev.platform();
+
+    // Detect what kind of firmware this is, using the platform to determine the link address.
+    let firmware_type = FirmwareType::detect_from_firmware(platform, dev.hardware(), firmware_data)
+        .map_err(|e| e.with_ctx("detecting firmware type"))?;
+
+    debug!("Firmware file was detected as {}", firmware_type);
+
+    // But allow the user to override that type, if they *really* know what they are doing.
+    let firmware_type = if let Some(location) = matches
+        .get_one::<String>("override-firmware-type").map(|s| s.as_str()) {
+        if let Some("really") = matches.get_one::<String>("allow-dangerous-options").map(|s| s.as_str()) {
+            warn!("Overriding firmware-type detection and flashing to user-specified location ({}) instead!", location);
+        } else {
+            // We're ignoring errors for setting the color because the most important thing is
+            // getting the message itself out.
+            // If the messages themselves don't write, though, then we might as well just panic.
+            let mut stderr = StandardStream::stderr(output::color_choice(true));
+            let _res = stderr.set_color(ColorSpec::new().set_fg(Some(Color::Red)));
+            write!(&mut stderr, "WARNING: ").expect("failed to write to stderr");
+            let _res = stderr.reset();
+            writeln!(
+                &mut stderr,
+                "--override-firmware-type is used to override the firmware type detection and flash \
+                a firmware binary to a location other than the one that it seems to be designed for.\n\
+                This is a potentially destructive operation and can result in an unbootable device! \
+                (can require a second, external JTAG debugger and manual wiring to fix!)\n\
+                \nDo not use this option unless you are a firmware developer and really know what you are doing!\n\
+                \nIf you are sure this is really what you want to do, run again with --allow-dangerous-options=really"
+            ).expect("failed to write to stderr");
+            ExitStatus::Usage.exit();
+        };
+        if location == "bootloader" {
+            FirmwareType::Bootloader
+        } else if location == "application" {
+            FirmwareType::Application
+        } else {
+            unreachable!("Clap ensures invalid option cannot be passed to --override-firmware-type");
+        }
+    } else {
+        firmware_type
+    };
+
+    // Bootloader updates are only ever done when explicitly asked for, as a bad one bricks the probe.
+    let firmware_type = if matches.get_flag("bootloader") {
+        // Probes flashed alongside others were confirmed for before they started.
+        if reporter.shares_terminal() {
+            check_bootloader_image(&dev, firmware_data)?;
+        } else {
+            confirm_bootloader_update(&dev, firmware_data, matches)?;
+        }
+        FirmwareType::Bootloader
+    } else if firmware_type == FirmwareType::Bootloader && !matches.contains_id("override-firmware-type") {
+        return Err(ErrorKind::InvalidFirmware(Some(S!(
+            "this appears to be a bootloader image; use --bootloader if you really mean to update the bootloader"
+        ))).error());
+    } else {
+        firmware_type
+    };
+
+    // Formats that say where the firmware goes had better agree with us (unless overridden).
+    if !matches.contains_id("override-firmware-type") {
+        firmware.check_address(&dev, firmware_type)?;
+    }
+
+    // If we can't get the string descriptors, try to go ahead with flashing anyway.
+    // It's unlikely that other control requests will succeed, but the OS might be messing with
+    // the string descriptor stuff.
+    reporter.println(tr!("status-found", probe = dev.to_string()));
+
+    if matches.get_flag("dry-run") {
+        return dry_run_report(&dev, firmware_data, firmware_type, matches.get_flag("full-erase"), reporter);
+    }
+
+    if dev.operating_mode() == DfuOperatingMode::FirmwareUpgrade {
+        reporter.println("Black Magic Probe is already in DFU (bootloader) mode; flashing it directly.");
+    }
+
+    if let Some(path) = matches.get_one::<String>("backup") {
+        backup_firmware(&mut dev, firmware_type, path)?;
+    }
+
+    if matches.get_flag("full-erase") {
+        reporter.println(format!("Erasing all of the {} flash region...", firmware_type));
+        dev.erase_region(firmware_type)
+            .map_err(|e| e.with_ctx("erasing flash"))?;
+    }
+
+    write_firmware(dev, firmware_data, firmware_type, !matches.get_flag("no-verify"), reporter)
+}
+
+/// Prints what flashing `firmware` onto `dev` would do, for `flash --dry-run`.
+fn dry_run_report(
+    dev: &BmpDevice,
+    firmware: &[u8],
+    firmware_type: FirmwareType,
+    full_erase: bool,
+    reporter: &Reporter,
+) -> Result<(), Error>
+{
+    /// A rough figure for how fast probes take firmware over DFU, erasing included, for estimates.
+    const TYPICAL_DFU_RATE: f64 = 12.0 * 1024.0;
+    /// A rough figure for how long a probe takes to switch modes and re-enumerate.
+    const TYPICAL_REBOOT_SECS: f64 = 2.0;
+
+    let length = u32::try_from(firmware.len())
+        .expect("firmware filesize exceeded 32 bits! Firmware binary must be invalid");
+    dev.check_fits(length, firmware_type)?;
+
+    let load_address = dev.load_address(firmware_type);
+    let hardware = dev.hardware();
+    let image_hardware = ProbeIdentity::from_firmware_image(firmware).and_then(|identity| identity.hardware());
+
+    reporter.println("Dry run: nothing will be written. Flashing would:");
+    let mut reboots = 1.0;
+    if dev.operating_mode() == DfuOperatingMode::Runtime {
+        reporter.println("  - switch the probe into DFU (bootloader) mode");
+        reboots += 1.0;
+    }
+    let erase_length = if full_erase {
+        dev.region_length(firmware_type).unwrap_or(length)
+    } else {
+        length
+    };
+    match hardware {
+        Some(hardware) => reporter.println(format!(
+            "  - erase {} of flash from 0x{:08x}",
+            HumanBytes(hardware.erase_length(load_address, erase_length) as u64),
+            load_address,
+        )),
+        None => reporter.println(format!(
+            "  - erase flash from 0x{:08x} (unknown hardware, so unknown how much)",
+            load_address,
+        )),
+    };
+    reporter.println(format!(
+        "  - write {} of {} firmware to 0x{:08x}-0x{:08x}",
+        HumanBytes(length as u64),
+        firmware_type,
+        load_address,
+        load_address + length,
+    ));
+    reporter.println("  - reboot the probe into the new firmware");
+    reporter.println(format!(
+        "This would take about {:.0} seconds.",
+        length as f64 / TYPICAL_DFU_RATE + reboots * TYPICAL_REBOOT_SECS,
+    ));
+
+    match (image_hardware, hardware) {
+        (Some(image_hardware), Some(hardware)) if image_hardware != hardware => {
+            warn!("{}", ErrorKind::HardwareMismatch(image_hardware.to_string(), hardware.to_string()));
+        },
+        (Some(image_hardware), _) => reporter.println(format!("The firmware is built for {} hardware.", image_hardware)),
+        (None, _) => reporter.println("Could not tell what hardware the firmware is built for."),
+    };
+
+    Ok(())
+}
+
+/// Checks a bootloader update of `dev` with `firmware` is possible and sane: that `dev` is in the STM32's
+/// built-in bootloader, and `firmware` looks like a bootloader that fits.
+fn check_bootloader_image(dev: &BmpDevice, firmware: &[u8]) -> Result<(), Error>
+{
+    // Neither the Black Magic Debug bootloader nor dragonBoot can overwrite themselves.
+    if dev.platform() != BmpPlatform::STM32DeviceDFU {
+        return Err(ErrorKind::Unsupported(S!(
+            "the bootloader can only be updated through the STM32's built-in DFU bootloader; \
+            hold the probe's BOOT0 button (or jumper) while plugging it in, then try again"
+        )).error());
+    }
+
+    let bootloader_size = dev.hardware().map_or(0x2000, |hardware| hardware.bootloader_size());
+    let reset_vector = FirmwareType::reset_vector(firmware)?;
+    if reset_vector >= FLASH_BASE + bootloader_size {
+        return Err(ErrorKind::InvalidFirmware(Some(format!(
+            "image's reset vector (0x{:08x}) is outside the bootloader region (0x{:08x}-0x{:08x}); \
+            is this really a bootloader image?",
+            reset_vector,
+            FLASH_BASE,
+            FLASH_BASE + bootloader_size,
+        ))).error());
+    }
+    if firmware.len() > bootloader_size as usize {
+        return Err(ErrorKind::InvalidFirmware(Some(format!(
+            "image is {} bytes, but the bootloader region is only {} bytes",
+            firmware.len(),
+            bootloader_size,
+        ))).error());
+    }
+
+    Ok(())
+}
+
+/// Checks `length` bytes from `address` are all within `dev`'s flash, or at least don't start before it
+/// or run past the end of the address space, if we don't know how big its flash is.
+fn check_flash_range(dev: &BmpDevice, address: u32, length: u32) -> Result<(), Error>
+{
+    let flash_end = dev.hardware().map_or(u32::MAX as u64 + 1, |hardware| (FLASH_BASE + hardware.flash_size()) as u64);
+    if address < FLASH_BASE || address as u64 + length as u64 > flash_end {
+        return Err(ErrorKind::OutsideFlash(address, length).error());
+    }
+
+    Ok(())
+}
+
+/// Whether `length` bytes from `address` overlap `dev`'s bootloader region.
+fn overlaps_bootloader(dev: &BmpDevice, address: u32, length: u32) -> bool
+{
+    let bootloader_size = dev.hardware().map_or(0x2000, |hardware| hardware.bootloader_size());
+    address < FLASH_BASE + bootloader_size && address.saturating_add(length) > FLASH_BASE
+}
+
+/// Checks a bootloader update of `dev` with `firmware` is possible and sane, and gets the user to
+/// confirm it (twice), unless `--force` was given.
+fn confirm_bootloader_update(dev: &BmpDevice, firmware: &[u8], matches: &ArgMatches) -> Result<(), Error>
+{
+    check_bootloader_image(dev, firmware)?;
+    confirm_bootloader_change(matches, "update")
+}
+
+/// Gets the user to confirm (twice) they mean to `action` the probe's bootloader, i.e. "update", "erase"
+/// or "overwrite" it, unless `--force` was given.
+fn confirm_bootloader_change(matches: &ArgMatches, action: &str) -> Result<(), Error>
+{
+    let operation = format!("bootloader {}", action);
+
+    // A dry run isn't going to do anything that needs confirming.
+    if matches.try_get_one::<bool>("dry-run").ok().flatten() == Some(&true) {
+        return Ok(());
+    }
+
+    if matches.get_flag("force") {
+        warn!("{}", tr!("bootloader-not-confirming", action = action));
+        return Ok(());
+    }
+    if !prompt::can_prompt() {
+        return Err(ErrorKind::NotConfirmed(operation).error());
+    }
+
+    // We're ignoring errors for setting the color because the most important thing is
+    // getting the message itself out.
+    // If the messages themselves don't write, though, then we might as well just panic.
+    let mut stderr = StandardStream::stderr(output::color_choice(true));
+    let _res = stderr.set_color(ColorSpec::new().set_fg(Some(Color::Red)));
+    write!(&mut stderr, "{} ", tr!("bootloader-warning-label")).expect("failed to write to stderr");
+    let _res = stderr.reset();
+    writeln!(&mut stderr, "{}", tr!("bootloader-warning", action = action)).expect("failed to write to stderr");
+
+    let confirmed = prompt::confirm(&tr!("bootloader-confirm", action = action))
+        .and_then(|yes| if yes {
+            prompt::confirm_typed(&tr!("bootloader-confirm-again"), &tr!("bootloader-confirm-typed", action = action))
+        } else {
+            Ok(false)
+        })
+        .map_err(|e| ErrorKind::External(ErrorSource::StdIo(e)).error())?;
+
+    if confirmed {
+        Ok(())
+    } else {
+        Err(ErrorKind::NotConfirmed(operation).error())
+    }
+}
+
+/// Works out where to carry on flashing from, if the last attempt to flash the same firmware onto the
+/// probe with serial number `serial` was interrupted and the user wants to pick up where it left off,
+/// or otherwise 0. Operations that can't ask (see [`Reporter::can_prompt()`]) start over.
+fn resume_offset(serial: &str, current: &InterruptedFlash, length: usize, reporter: &Reporter) -> usize
+{
+    let last = match state::interrupted_flash(serial) {
+        Some(last) if last.firmware_sha256 == current.firmware_sha256 && last.firmware_type == current.firmware_type => last,
+        _ => return 0,
+    };
+    if last.written == 0 || last.written >= length {
+        return 0;
+    }
+
+    if !reporter.can_prompt() {
+        reporter.println(format!(
+            "Flashing this {} was interrupted last time, after {} of {} bytes; starting over.",
+            current.firmware_type,
+            last.written,
+            length,
+        ));
+        return 0;
+    }
+
+    let question = format!(
+        "Flashing this {} was interrupted last time, after {} of {} bytes. Carry on from there?",
+        current.firmware_type,
+        last.written,
+        length,
+    );
+    match prompt::confirm(&question) {
+        Ok(true) => last.written,
+        Ok(false) => 0,
+        Err(e) => {
+            warn!("Failed to ask about resuming: {}", e);
+            0
+        },
+    }
+}
+
+/// Writes `firmware_data` onto `dev`, optionally verifies it, and waits for the probe to reboot into it.
+fn write_firmware(
+    mut dev: BmpDevice,
+    firmware_data: &[u8],
+    firmware_type: FirmwareType,
+    verify: bool,
+    reporter: &Reporter,
+) -> Result<(), Error>
+{
+    // How often to record how far the flash has got, so it can carry on from about there if bmputil
+    // is killed or the probe unplugged.
+    const RECORD_INTERVAL: usize = 4 * 1024;
+
+    // Grab the port and USB IDs, which we need to find the probe after rebooting.
+    let port = dev.port();
+    let custom_ids = dev.custom_ids();
+    let timeouts = dev.timeouts();
+
+    let file_size = u32::try_from(firmware_data.len())
+        .expect("firmware filesize exceeded 32 bits! Firmware binary must be invalid");
+
+    // Record the flash as we start it, and how far it's got as it goes, so the next run can carry on
+    // from there if it fails. That needs the serial number to tell which probe it was.
+    let serial = dev.serial_number().map(|serial| serial.to_string()).ok();
+    let interrupted = InterruptedFlash {
+        firmware_sha256: fetch::sha256_hex(firmware_data),
+        firmware_type: firmware_type.to_string(),
+        written: 0,
+    };
+    let resume_from = serial
+        .as_deref()
+        .map_or(0, |serial| resume_offset(serial, &interrupted, firmware_data.len(), reporter));
+    let record = |written: usize| {
+        if let Some(serial) = &serial {
+            state::set_interrupted_flash(serial, &InterruptedFlash { written, ..interrupted.clone() });
+        }
+    };
+    record(resume_from);
+    let written = Cell::new(0);
+
+    // If the progress bar is hidden, we just say when we're done.
+    let progress_bar = reporter.progress_bar(file_size as u64);
+
+    let in_dfu_mode = match dev.download(firmware_data, firmware_type, resume_from, |flash_pos_delta| {
+        // Don't actually print flashing until the erasing has finished.
+        if progress_bar.position() == 0 {
+            let message = if firmware_type == FirmwareType::Application {
+                "Flashing..."
+            } else {
+                "Flashing bootloader..."
+            };
+            if progress_bar.is_hidden() {
+                reporter.println(message);
+            } else {
+                progress_bar.println(reporter.labelled(message));
+            }
+        }
+        progress_bar.inc(flash_pos_delta as u64);
+
+        // What we're given only ever adds up to what the probe has acknowledged, even if it's retried.
+        let last_recorded = written.get() / RECORD_INTERVAL;
+        written.set(written.get() + flash_pos_delta);
+        if written.get() / RECORD_INTERVAL > last_recorded {
+            record(written.get());
+        }
+    }) {
+        Ok(in_dfu_mode) => {
+            progress_bar.finish();
+            Ok(in_dfu_mode)
+        },
+        Err(e) => {
+            progress_bar.finish();
+            if written.get() == firmware_data.len() {
+                warn!("Possibly spurious error from OS at the very end of flashing: {}", e);
+                Ok(false)
+            } else {
+                // We've been told of everything written (and skipped, in resuming), so know how far
+                // into the image the probe got.
+                record(written.get());
+                Err(e)
+            }
+        },
+    }?;
+    if let Some(serial) = &serial {
+        state::clear_interrupted_flash(serial);
+    }
+
+    if progress_bar.is_hidden() {
+        let elapsed = progress_bar.elapsed();
+        reporter.println(format!(
+            "Wrote {} in {:.1}s ({}/s)",
+            HumanBytes(progress_bar.position()),
+            elapsed.as_secs_f64(),
+            HumanBytes((progress_bar.position() as f64 / elapsed.as_secs_f64().max(0.001)) as u64),
+        ));
+    }
+
+    // Manifestation-tolerant bootloaders are still there to talk to, so there's no need to wait for
+    // the probe to reboot and find it again before verifying.
+    let mut dev = if in_dfu_mode {
+        dev
+    } else if usb::device_fd().is_some() {
+        // The rebooted probe is a new device as far as whoever gave us its file descriptor is concerned.
+        reporter.println("Flashing finished; the probe is rebooting, which bmputil can't follow with --device-fd.");
+        return Ok(());
+    } else {
+        drop(dev); // Force libusb to free the device.
+        thread::sleep(timeouts.detach_wait());
+
+        bmp::wait_for_probe_reboot(&port, custom_ids, timeouts.reenumerate, "flash")
+            .inspect_err(|_| error!("Black Magic Probe did not re-enumerate after flashing! Invalid firmware?"))?
+    };
+
+    if verify {
+        reporter.println("Verifying...");
+        dev.verify(firmware_data, firmware_type)?;
+        reporter.println("Flash contents match the firmware image.");
+    }
+
+    // Verifying leaves the probe in its bootloader, as do manifestation-tolerant bootloaders, so send
+    // it back to the new firmware.
+    if verify || in_dfu_mode {
+        dev.detach_and_destroy()?;
+        if usb::device_fd().is_some() {
+            reporter.println("The probe is rebooting into its new firmware, which bmputil can't follow with --device-fd.");
+            return Ok(());
+        }
+        thread::sleep(timeouts.detach_wait());
+        dev = bmp::wait_for_probe_reboot(&port, custom_ids, timeouts.reenumerate, "flash")?;
+    }
+
+
+    let desc = dev.device().device_descriptor().unwrap();
+
+    let product_string = dev
+        .handle()
+        .read_product_string_ascii(
+            &desc
+        )
+        .map_err(|e| {
+            error!("Error reading firmware version after flash! Invalid firmware?");
+            e
+        })?;
+
+    let version_string = product_string
+        .chars()
+        .skip("Black Magic Probe ".len())
+        .collect::<String>();
+
+    reporter.println(format!("Black Magic Probe successfully rebooted into firmware version {}", version_string));
+
+    Ok(())
+}
+
+fn info_command(matches: &ArgMatches, config: &Config) -> Result<(), Error>
+{
+    let matcher = BmpMatcher::from_cli_args(matches, config)?;
+
+    let mut results = matcher.find_matching_probes();
+
+    let devices = results.pop_all()?;
+
+    // Reading the target voltage needs the GDB server, which probes only have in runtime mode. It means
+    // opening its serial port, which would get in the way of a GDB session using it, so it's only read
+    // when asked for, and shown where it can be.
+    let read_voltages = matches.get_flag("voltage");
+    let voltages = devices
+        .iter()
+        .map(|dev| {
+            if !read_voltages || dev.operating_mode() != DfuOperatingMode::Runtime {
+                return None;
+            }
+            read_target_voltage(dev, None)
+                .map_err(|e| debug!("Couldn't read the target voltage of {}: {}", dev.port(), e))
+                .ok()
+                .flatten()
+        })
+        .collect::<Vec<_>>();
+
+    if output::json_output() {
+        let mut descriptions = describe_probes(&devices, config);
+        for (description, voltage) in descriptions.iter_mut().zip(&voltages) {
+            description.target_voltage = *voltage;
+        }
+        output::print_json(&descriptions);
+        return Ok(());
+    }
+
+    let multiple = devices.len() > 1;
+    for (index, dev) in devices.iter().enumerate() {
+
+        status!("{}", tr!("status-found", probe = dev.to_string()));
+        if let Some(voltage) = voltages[index] {
+            status!("  {}", tr!("voltage-target", voltage = format!("{:.2}", voltage)));
+        }
+
+        // If we have multiple connected probes, then additionally display their index
+        // and print a trailing newline.
+        if multiple {
+            status!("  Index:  {}\n", index);
+        }
+    }
+
+    Ok(())
+}
+
+/// Describes each of `devices`, with their aliases from the configuration file.
+fn describe_probes(devices: &[BmpDevice], config: &Config) -> Vec<ProbeDescription>
+{
+    // Don't let one misbehaving probe stop us from describing the rest.
+    devices
+        .iter()
+        .map(|dev| {
+            let mut description = dev.describe();
+            description.alias = description.serial
+                .as_deref()
+                .and_then(|serial| config.alias_for_serial(serial))
+                .map(String::from);
+            description
+        })
+        .collect()
+}
+
+fn list_command(matches: &ArgMatches, config: &Config) -> Result<(), Error>
+{
+    let matcher = BmpMatcher::from_cli_args(matches, config)?;
+
+    let mut results = matcher.find_matching_probes();
+
+    let devices = results.pop_all()?;
+    let descriptions = describe_probes(&devices, config);
+
+    if matches.get_flag("json") || output::json_output() {
+        output::print_json(&descriptions);
+        return Ok(());
+    }
+
+    let unknown = || output::paint("unknown", style::FAINT);
+    for (index, description) in descriptions.into_iter().enumerate() {
+        if index != 0 {
+            status!();
+        }
+        status!("{}", output::paint(format!("Black Magic Probe {}:", index), style::HEADING));
+        if let Some(alias) = description.alias {
+            status!("  Alias:      {}", alias);
+        }
+        status!("  Serial:     {}", description.serial.unwrap_or_else(unknown));
+        status!("  Version:    {}", description.version.unwrap_or_else(unknown));
+        status!("  Hardware:   {}", description.hardware.or(description.variant).unwrap_or_else(unknown));
+        status!("  Mode:       {}", description.mode);
+        match (description.bootloader_version, description.mode) {
+            (Some(version), DfuOperatingMode::Runtime) => status!("  Bootloader: {} (when last in DFU mode)", version),
+            (Some(version), DfuOperatingMode::FirmwareUpgrade) => status!("  Bootloader: {}", version),
+            (None, _) => (),
+        }
+        status!("  Port:       {}", description.port);
+        status!("  USB ID:     {}:{}", description.vid, description.pid);
+        for interface in description.dfu_interfaces {
+            status!(
+                "  DFU:        interface {} alt {}: {}",
+                interface.number,
+                interface.alt_setting,
+                interface.name.as_deref().unwrap_or("(unnamed)"),
+            );
+        }
+        for port in description.serial_ports {
+            status!("  COM port:   {}", port);
+        }
+        #[cfg(windows)]
+        match description.driverless_dfu {
+            Some(true) => status!("  Windows:    no driver needed (Microsoft OS 2.0 descriptors)"),
+            Some(false) => status!("  Windows:    needs WinUSB installing for DFU"),
+            None => (),
+        }
+    }
+
+    Ok(())
+}
+
+/// The `--gdb-port` option, for the subcommands that talk to a probe's GDB server.
+fn gdb_port_arg() -> Arg
+{
+    Arg::new("gdb_port")
+        .long("gdb-port")
+        .value_name("PORT")
+        .action(ArgAction::Set)
+        .help("The serial port of the probe's GDB server, where bmputil can't find it itself")
+}
+
+/// The `--format` option, shared by the subcommands that read firmware files.
+fn format_arg() -> Arg
+{
+    let mut formats = vec!["auto"];
+    formats.extend(FirmwareFormat::CLI_NAMES);
+
+    Arg::new("format")
+        .long("format")
+        .value_name("FORMAT")
+        .action(ArgAction::Set)
+        .value_parser(formats)
+        .default_value("auto")
+        .help("The firmware file's format; by default this is detected from the file's contents")
+}
+
+/// The `--transfer-size` option, shared by the subcommands that flash or read probes.
+fn transfer_size_arg() -> Arg
+{
+    Arg::new("transfer-size")
+        .long("transfer-size")
+        .env("BMPUTIL_TRANSFER_SIZE")
+        .value_name("BYTES")
+        .action(ArgAction::Set)
+        .value_parser(clap::value_parser!(u16).range(1..))
+        .hide_short_help(true)
+        .help("The DFU transfer size to use, for unreliable USB hubs (at most the probe's own maximum)")
+}
+
+/// Clap v3 style (approximate)
+/// See https://stackoverflow.com/a/75343828
+fn style() -> clap::builder::Styles {
+    Styles::styled()
+        .usage(
+            anstyle::Style::new()
+                .fg_color(Some(anstyle::Color::Ansi(anstyle::AnsiColor::Yellow)))
+                .bold(),
+        )
+        .header(
+            anstyle::Style::new()
+                .bold()
+                .fg_color(Some(anstyle::Color::Ansi(anstyle::AnsiColor::Yellow))),
+        )
+        .literal(
+            anstyle::Style::new().fg_color(Some(anstyle::Color::Ansi(anstyle::AnsiColor::Green))),
+        )
+}
+
+/// The `probe` commands, for finding out about the connected probes.
+fn probe_commands() -> Vec<Command>
+{
+    vec![
+        Command::new("info")
+            .display_order(0)
+            .about("Print information about connected Black Magic Probe devices")
+            .arg(Arg::new("voltage")
+                .long("voltage")
+                .action(ArgAction::SetTrue)
+                .help("Also read the target voltage, through the GDB server (don't use while GDB is connected)")
+            ),
+        Command::new("list")
+            .display_order(0)
+            .about("List all connected Black Magic Probe devices, including those in DFU mode, with their details")
+            .arg(Arg::new("json")
+                .long("json")
+                .action(ArgAction::SetTrue)
+                .help("Print the list as JSON, for use by scripts and other tools")
+            ),
+        Command::new("serial")
+            .display_order(0)
+            .about("Read a probe's serial number, or program a new one where its bootloader supports it")
+            .arg_required_else_help(true)
+            .subcommand_required(true)
+            .subcommand(Command::new("get")
+                .about("Print the probe's serial number")
+            )
+            .subcommand(Command::new("set")
+                .about("Program a new serial number into the probe, for bootloaders that keep one in flash")
+                .arg(Arg::new("serial")
+                    .value_parser(bmp::parse_serial)
+                    .action(ArgAction::Set)
+                    .required(true)
+                    .help("The new serial number")
+                )
+                .arg(Arg::new("force")
+                    .long("force")
+                    .action(ArgAction::SetTrue)
+                    .help("Don't ask for confirmation")
+                )
+            ),
+        Command::new("power")
+            .display_order(0)
+            .about("Switch the power the probe supplies to its target on or off, or say whether it's on")
+            .arg_required_else_help(true)
+            .subcommand_required(true)
+            .arg(gdb_port_arg().global(true))
+            .subcommand(Command::new("on")
+                .about("Switch target power on")
+            )
+            .subcommand(Command::new("off")
+                .about("Switch target power off")
+            )
+            .subcommand(Command::new("status")
+                .about("Print whether target power is on or off")
+            ),
+        Command::new("dfu")
+            .display_order(0)
+            .about("Switch a probe into its bootloader (DFU mode), e.g. for other tools, or back to its firmware")
+            .arg_required_else_help(true)
+            .subcommand_required(true)
+            .subcommand(Command::new("enter")
+                .about("Switch the probe into its bootloader, and leave it there")
+            )
+            .subcommand(Command::new("leave")
+                .about("Switch the probe from its bootloader back to its firmware")
+            ),
+        Command::new("option-bytes")
+            .display_order(0)
+            .about("Print the probe MCU's read protection (RDP) level and option bytes, through the STM32 built-in bootloader"),
+        Command::new("voltage")
+            .display_order(0)
+            .about("Print the target voltage the probe measures, in volts")
+            .arg(gdb_port_arg()),
+    ]
+}
+
+/// The `firmware` commands, for reading, writing and updating probes' firmware.
+fn firmware_commands() -> Vec<Command>
+{
+    vec![
+        Command::new("flash")
+            .display_order(1)
+            .about("Flash new firmware onto a Black Magic Probe device")
+            .arg(Arg::new("firmware_binary")
+                .action(ArgAction::Set)
+                .required(true)
+                .help("The firmware file to flash, an https:// URL to download it from, or - to read it from stdin")
+            )
+            .arg(format_arg())
+            .arg(Arg::new("only-older-than")
+                .long("only-older-than")
+                .required(false)
+                .value_name("VERSION")
+                .value_parser(FirmwareVersion::from_str)
+                .action(ArgAction::Set)
+                .help("Only flash a device whose firmware is older than the given version (e.g. v1.10.0)")
+            )
+            .arg(Arg::new("bootloader")
+                .long("bootloader")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("override-firmware-type")
+                .help("Update the probe's bootloader rather than its application firmware (dangerous!)")
+            )
+            .arg(Arg::new("force")
+                .long("force")
+                .action(ArgAction::SetTrue)
+                .help("Don't ask for confirmation of dangerous operations, such as bootloader updates, \
+                    and only warn if the firmware file's DFU suffix or hardware variant doesn't match")
+            )
+            .arg(Arg::new("all")
+                .long("all")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("backup")
+                .help("Flash every matching Black Magic Probe, one after another")
+            )
+            .arg(Arg::new("jobs")
+                .long("jobs")
+                .short('j')
+                .value_name("N")
+                .action(ArgAction::Set)
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .requires("all")
+                .conflicts_with("bootloader")
+                .help("With --all, flash up to N probes at once")
+            )
+            .arg(Arg::new("dry-run")
+                .long("dry-run")
+                .action(ArgAction::SetTrue)
+                .help("Check everything and say what would be done, without actually writing anything")
+            )
+            .arg(Arg::new("backup")
+                .long("backup")
+                .value_name("FILE")
+                .action(ArgAction::Set)
+                .help("Save the firmware currently on the probe to FILE before flashing")
+            )
+            .arg(Arg::new("full-erase")
+                .long("full-erase")
+                .action(ArgAction::SetTrue)
+                .help("Erase the whole firmware region of flash before writing, not just what the new firmware needs")
+            )
+            .arg(transfer_size_arg())
+            .arg(Arg::new("no-verify")
+                .long("no-verify")
+                .action(ArgAction::SetTrue)
+                .help("Don't read the firmware back from the probe to check it was flashed correctly")
+            )
+            .arg(Arg::new("override-firmware-type")
+                .long("override-firmware-type")
+                .required(false)
+                .action(ArgAction::Set)
+                .value_parser(["bootloader", "application"])
+                .hide_short_help(true)
+                .help("flash the specified firmware space regardless of autodetected firmware type")
+            )
+            .arg(Arg::new("force-override-flash")
+                .long("force-override-flash")
+                .required(false)
+                .action(ArgAction::Set)
+                .value_parser(["really"])
+                .hide(true)
+                .help("forcibly override firmware-type autodetection and flash anyway (may result in an unbootable device!)")
+            ),
+        Command::new("update")
+            .display_order(1)
+            .about("Download the latest official firmware for a Black Magic Probe device and flash it")
+            .arg(Arg::new("channel")
+                .long("channel")
+                .value_name("CHANNEL")
+                .action(ArgAction::Set)
+                .value_parser(Channel::CLI_NAMES)
+                .help("Which firmware builds to update to (default: stable) [env: BMPUTIL_CHANNEL]")
+            )
+            .arg(Arg::new("version")
+                .long("version")
+                .value_name("TAG")
+                .action(ArgAction::Set)
+                .help("Install this release (e.g. v1.10.2) rather than the latest on any channel, even if it's older")
+            )
+            .arg(Arg::new("force")
+                .long("force")
+                .action(ArgAction::SetTrue)
+                .help("Flash the firmware without asking, even if the probe already has it or it appears to be for different hardware")
+            )
+            .arg(Arg::new("no-verify")
+                .long("no-verify")
+                .action(ArgAction::SetTrue)
+                .help("Don't read the firmware back to check it after flashing")
+            )
+            .arg(transfer_size_arg())
+            .arg(Arg::new("offline")
+                .long("offline")
+                .action(ArgAction::SetTrue)
+                .help("Don't use the network; only use releases and firmware downloaded before")
+            )
+            .arg(Arg::new("no-release-notes")
+                .long("no-release-notes")
+                .action(ArgAction::SetTrue)
+                .help("Don't show the release notes of the firmware being updated to")
+            ),
+        Command::new("check")
+            .display_order(1)
+            .about("Check whether Black Magic Probe devices are running the latest firmware, without changing anything")
+            .after_help(format!(
+                "Exits with status {} if any probe's firmware can be updated (see bmputil --help for the others).",
+                ExitStatus::UpdateAvailable as i32,
+            ))
+            .arg(Arg::new("channel")
+                .long("channel")
+                .value_name("CHANNEL")
+                .action(ArgAction::Set)
+                .value_parser(Channel::CLI_NAMES)
+                .help("Which firmware builds to compare against (default: stable) [env: BMPUTIL_CHANNEL]")
+            )
+            .arg(Arg::new("offline")
+                .long("offline")
+                .action(ArgAction::SetTrue)
+                .help("Don't use the network; compare against the releases as last looked up")
+            ),
+        Command::new("backup")
+            .display_order(2)
+            .about("Save the firmware currently on a Black Magic Probe device to a file")
+            .arg(Arg::new("backup_file")
+                .action(ArgAction::Set)
+                .required(true)
+            )
+            .arg(Arg::new("bootloader")
+                .long("bootloader")
+                .action(ArgAction::SetTrue)
+                .hide_short_help(true)
+                .help("Save the bootloader rather than the application firmware")
+            ),
+        Command::new("read")
+            .display_order(2)
+            .about("Read the whole firmware region of flash out of a Black Magic Probe device into a file")
+            .arg(Arg::new("output_file")
+                .action(ArgAction::Set)
+                .required(true)
+                .help("The file to write the image to, or - for stdout")
+            )
+            .arg(Arg::new("bootloader")
+                .long("bootloader")
+                .action(ArgAction::SetTrue)
+                .help("Read the bootloader region rather than the application firmware region")
+            ),
+        Command::new("verify")
+            .display_order(4)
+            .about("Check the firmware on a Black Magic Probe device matches a firmware file, without writing anything")
+            .arg(Arg::new("firmware_binary")
+                .action(ArgAction::Set)
+                .required_unless_present("sha256")
+                .conflicts_with("sha256")
+                .help("The firmware file to compare against, an https:// URL to download it from, or - for stdin")
+            )
+            .arg(format_arg().conflicts_with("sha256"))
+            .arg(Arg::new("sha256")
+                .long("sha256")
+                .value_name("HASH")
+                .action(ArgAction::Set)
+                .help("Compare against the SHA-256 hash of a firmware .bin file instead")
+            ),
+        Command::new("restore")
+            .display_order(3)
+            .about("Flash a Black Magic Probe device with firmware previously saved with the backup command")
+            .arg(Arg::new("backup_file")
+                .action(ArgAction::Set)
+                .required(true)
+            )
+            .arg(Arg::new("force")
+                .long("force")
+                .action(ArgAction::SetTrue)
+                .help("Restore the image even if it appears to be for a different probe, and restore bootloaders without asking")
+            )
+            .arg(transfer_size_arg()),
+        Command::new("recover")
+            .display_order(5)
+            .about("Rewrite a probe's bootloader (and firmware) through the STM32's built-in DFU bootloader, if its own is damaged")
+            .arg(Arg::new("bootloader_file")
+                .action(ArgAction::Set)
+                .required(true)
+                .help("The bootloader file to write")
+            )
+            .arg(Arg::new("firmware_binary")
+                .action(ArgAction::Set)
+                .required(false)
+                .help("The application firmware file to write after the bootloader, if any")
+            )
+            .arg(format_arg())
+            .arg(Arg::new("force")
+                .long("force")
+                .action(ArgAction::SetTrue)
+                .help("Don't ask for confirmation, and write images even if they appear to be for a different probe")
+            ),
+        Command::new("targets")
+            .display_order(1)
+            .about("List the target families a probe's firmware (or a firmware file) has drivers for")
+            .arg(Arg::new("firmware_binary")
+                .action(ArgAction::Set)
+                .required(false)
+                .help("The firmware file to look in, rather than the release build the probe says it's running")
+            )
+            .arg(format_arg()),
+        Command::new("unlock")
+            .display_order(5)
+            .about("Remove a locked probe's read protection through the STM32's built-in DFU bootloader (erasing it), then rewrite its bootloader (and firmware)")
+            .arg(Arg::new("bootloader_file")
+                .action(ArgAction::Set)
+                .required(true)
+                .help("The bootloader file to write once the probe is unlocked")
+            )
+            .arg(Arg::new("firmware_binary")
+                .action(ArgAction::Set)
+                .required(false)
+                .help("The application firmware file to write after the bootloader, if any")
+            )
+            .arg(format_arg())
+            .arg(Arg::new("force")
+                .long("force")
+                .action(ArgAction::SetTrue)
+                .help("Don't ask for confirmation, and write images even if they appear to be for a different probe")
+            ),
+        Command::new("releases")
+            .display_order(1)
+            .about("List the official firmware releases, with the files for a probe's hardware")
+            .arg(Arg::new("hardware")
+                .long("hardware")
+                .value_name("VARIANT")
+                .action(ArgAction::Set)
+                .value_parser(bmp::parse_hardware)
+                .help("Show the files for this hardware (e.g. native, stlink), rather than the connected probe's")
+            )
+            .arg(Arg::new("offline")
+                .long("offline")
+                .action(ArgAction::SetTrue)
+                .help("Don't use the network; show the releases as last listed")
+            ),
+    ]
+}
+
+/// Parses the name of a group to give access to probes to, which ends up in the rules we write, so
+/// has to be a plain group name as `groupadd` would take.
+fn parse_group(value: &str) -> Result<String, String>
 {
-    let matcher = BmpMatcher::from_cli_args(matches);
-
-    let mut results = matcher.find_matching_probes();
-
-    let devices = results.pop_all()?;
-
-    let multiple = devices.len() > 1;
-    for (index, dev) in devices.iter().enumerate() {
-
-        println!("Found: {}", dev);
-
-        // If we have multiple connected probes, then additionally display their index
-        // and print a trailing newline.
-        if multiple {
-            println!("  Index:  {}\n", index);
-        }
+    let mut chars = value.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_lowercase() || c == '_') &&
+        chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-');
+    if !valid {
+        return Err(format!(
+            "{:?} is not a valid group name (use lowercase letters, digits, _ and -, starting with a letter or _)",
+            value
+        ));
     }
-
-    Ok(())
-}
-
-/// Clap v3 style (approximate)
-/// See https://stackoverflow.com/a/75343828
-fn style() -> clap::builder::Styles {
-    Styles::styled()
-        .usage(
-            anstyle::Style::new()
-                .fg_color(Some(anstyle::Color::Ansi(anstyle::AnsiColor::Yellow)))
-                .bold(),
-        )
-        .header(
-            anstyle::Style::new()
-                .bold()
-                .fg_color(Some(anstyle::Color::Ansi(anstyle::AnsiColor::Yellow))),
-        )
-        .literal(
-            anstyle::Style::new().fg_color(Some(anstyle::Color::Ansi(anstyle::AnsiColor::Green))),
-        )
+    Ok(value.to_string())
 }
 
-fn main()
+/// Builds the command line interface definition.
+fn cli() -> Command
 {
-    env_logger::Builder::new()
-        .filter_level(log::LevelFilter::Warn)
-        .parse_default_env()
-        .init();
-
     let mut parser = Command::new(crate_name!());
     if cfg!(windows) {
         parser = parser
@@ -303,10 +2786,29 @@ fn main()
                 .global(true)
                 .hide(true)
                 .help("Internal argument used when re-executing this command to acquire admin for installing drivers")
+            )
+            .arg(Arg::new("elevated-from")
+                .long("elevated-from")
+                .required(false)
+                .value_parser(u32::from_str)
+                .action(ArgAction::Set)
+                .global(true)
+                .hide(true)
+                .help("Internal argument used when re-executing this command as an administrator after access was denied")
             );
     }
     parser = parser
         .about(crate_description!())
+        .after_help(format!(
+            "Defaults for many options (e.g. the probe to use, or the release channel) can be set in {} \
+            (or the file BMPUTIL_CONFIG names), or with the BMPUTIL_* environment variables shown with each \
+            option. BMPUTIL_CACHE_DIR sets where downloaded firmware is kept.\n\n\
+            Exit status: 0 on success, 2 if no probe was found, 3 if permission was denied, 4 if verification \
+            failed, 5 for invalid firmware, 6 if a download failed, 7 if a download couldn't be trusted, \
+            8 for USB errors, 9 if not confirmed, 10 if only some probes failed, 64 for invalid usage, \
+            and 1 otherwise.",
+            Config::path().map_or_else(|| S!("the configuration file"), |path| path.display().to_string()),
+        ))
         .version(crate_version!())
         .styles(style())
         .disable_colored_help(false)
@@ -318,7 +2820,15 @@ fn main()
             .required(false)
             .action(ArgAction::Set)
             .global(true)
-            .help("Use the device with the given serial number")
+            .help("Use the device with the given serial number (case-insensitive) [env: BMPUTIL_SERIAL]")
+        )
+        .arg(Arg::new("probe")
+            .long("probe")
+            .required(false)
+            .action(ArgAction::Set)
+            .global(true)
+            .conflicts_with("serial_number")
+            .help("Use the device with the given alias, as defined in the configuration file [env: BMPUTIL_PROBE]")
         )
         .arg(Arg::new("index")
             .long("index")
@@ -332,9 +2842,199 @@ fn main()
             .short('p')
             .long("port")
             .required(false)
+            .value_parser(bmp::parse_port_path)
+            .action(ArgAction::Set)
+            .global(true)
+            .help("Use the device on the given USB port, as <bus>-<port>[.<port>...] (see bmputil probe list)")
+        )
+        .arg(Arg::new("retries")
+            .long("retries")
+            .env("BMPUTIL_RETRIES")
+            .value_name("N")
+            .required(false)
+            .value_parser(clap::value_parser!(u32).range(1..))
+            .action(ArgAction::Set)
+            .global(true)
+            .hide_short_help(true)
+            .help("Try USB requests that fail for transient reasons up to N times in total (default: 3)")
+        )
+        .arg(Arg::new("alt")
+            .long("alt")
+            .value_name("INDEX|NAME")
+            .required(false)
+            .value_parser(bmp::parse_alt_setting)
+            .action(ArgAction::Set)
+            .global(true)
+            .hide_short_help(true)
+            .help("Use the DFU alt-setting with the given index or name (e.g. Bootloader), instead of the internal flash")
+        )
+        .arg(Arg::new("verbose")
+            .short('v')
+            .long("verbose")
+            .action(ArgAction::Count)
+            .global(true)
+            .help("Log more about what's happening (-v for progress, -vv for details, -vvv for everything)")
+        )
+        .arg(Arg::new("trace-dfu")
+            .long("trace-dfu")
+            .action(ArgAction::SetTrue)
+            .global(true)
+            .hide_short_help(true)
+            .help("Log every DFU request and status poll the probe answers, for debugging bootloaders")
+        )
+        .arg(Arg::new("debug-usb")
+            .long("debug-usb")
+            .action(ArgAction::SetTrue)
+            .global(true)
+            .hide_short_help(true)
+            .help("Log libusb's own debug messages, for diagnosing problems finding probes or with drivers")
+        )
+        .arg(Arg::new("timeout")
+            .long("timeout")
+            .env("BMPUTIL_TIMEOUT")
+            .value_name("MS")
+            .required(false)
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .action(ArgAction::Set)
+            .global(true)
+            .hide_short_help(true)
+            .help("Give probes MS milliseconds to answer each USB request, or their GDB server to reply (default: 2000)")
+        )
+        .arg(Arg::new("detach-timeout")
+            .long("detach-timeout")
+            .env("BMPUTIL_DETACH_TIMEOUT")
+            .value_name("MS")
+            .required(false)
+            .value_parser(clap::value_parser!(u64).range(1..=u16::MAX as u64))
+            .action(ArgAction::Set)
+            .global(true)
+            .hide_short_help(true)
+            .help("Give probes MS milliseconds to detach when switching to DFU mode (default: the probe's own timeout)")
+        )
+        .arg(Arg::new("reenumerate-timeout")
+            .long("reenumerate-timeout")
+            .env("BMPUTIL_REENUMERATE_TIMEOUT")
+            .value_name("MS")
+            .required(false)
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .action(ArgAction::Set)
+            .global(true)
+            .hide_short_help(true)
+            .help("Wait up to MS milliseconds for probes to re-enumerate after switching modes, or 6 times that after a mass erase (default: 5000)")
+        )
+        .arg(Arg::new("output-format")
+            .long("output-format")
+            .env("BMPUTIL_OUTPUT_FORMAT")
+            .value_name("FORMAT")
+            .required(false)
+            .action(ArgAction::Set)
+            .value_parser(output::OUTPUT_FORMATS)
+            .default_value("text")
+            .global(true)
+            .hide_short_help(true)
+            .help("Print the result as JSON for scripts, with status messages going to stderr instead")
+        )
+        .arg(Arg::new("color")
+            .long("color")
+            .env("BMPUTIL_COLOR")
+            .value_name("WHEN")
+            .required(false)
+            .action(ArgAction::Set)
+            .value_parser(output::COLOR_CHOICES)
+            .default_value("auto")
+            .global(true)
+            .hide_short_help(true)
+            .help("When to use colour: auto uses it on terminals unless NO_COLOR is set")
+        )
+        .arg(Arg::new("progress")
+            .long("progress")
+            .env("BMPUTIL_PROGRESS")
+            .value_name("STYLE")
+            .required(false)
+            .action(ArgAction::Set)
+            .value_parser(output::PROGRESS_STYLES)
+            .default_value("auto")
+            .global(true)
+            .hide_short_help(true)
+            .help("How to show progress: auto uses a bar on terminals, and plain lines every few seconds otherwise")
+        )
+        .arg(Arg::new("mirror")
+            .long("mirror")
+            .env("BMPUTIL_MIRROR")
+            .value_name("URL")
+            .required(false)
+            .action(ArgAction::Set)
+            .global(true)
+            .hide_short_help(true)
+            .help("Look up firmware releases from this mirror of GitHub's releases API, rather than GitHub")
+        )
+        .arg(Arg::new("proxy")
+            .long("proxy")
+            .env("BMPUTIL_PROXY")
+            .value_name("URL")
+            .required(false)
+            .action(ArgAction::Set)
+            .global(true)
+            .hide_short_help(true)
+            .help("Download firmware through this proxy, rather than the one set by HTTPS_PROXY")
+        )
+        .arg(Arg::new("vid")
+            .long("vid")
+            .required(false)
+            .value_parser(bmp::parse_usb_id)
+            .action(ArgAction::Set)
+            .global(true)
+            .hide_short_help(true)
+            // A vendor ID alone would match nothing new, so would be silently ignored.
+            .requires("pid")
+            .help("Also consider devices with this (hex) USB vendor ID and --pid in runtime mode to be Black Magic Probes")
+        )
+        .arg(Arg::new("pid")
+            .long("pid")
+            .required(false)
+            .value_parser(bmp::parse_usb_id)
+            .action(ArgAction::Set)
+            .global(true)
+            .hide_short_help(true)
+            .help("Also consider devices with this (hex) USB product ID in runtime mode to be Black Magic Probes")
+        )
+        .arg(Arg::new("dfu-vid")
+            .long("dfu-vid")
+            .required(false)
+            .value_parser(bmp::parse_usb_id)
+            .action(ArgAction::Set)
+            .global(true)
+            .hide_short_help(true)
+            .requires("dfu-pid")
+            .help("Also consider devices with this (hex) USB vendor ID and --dfu-pid in DFU mode to be Black Magic Probes")
+        )
+        .arg(Arg::new("dfu-pid")
+            .long("dfu-pid")
+            .required(false)
+            .value_parser(bmp::parse_usb_id)
             .action(ArgAction::Set)
             .global(true)
-            .help("Use the device on the given USB port")
+            .hide_short_help(true)
+            .help("Also consider devices with this (hex) USB product ID in DFU mode to be Black Magic Probes")
+        )
+        .arg(Arg::new("wait")
+            .long("wait")
+            .global(true)
+            .action(ArgAction::SetTrue)
+            .help("If no matching device is connected, wait for one to be plugged in")
+        )
+        .arg(Arg::new("non-interactive")
+            .long("non-interactive")
+            .env("BMPUTIL_NON_INTERACTIVE")
+            .global(true)
+            .action(ArgAction::SetTrue)
+            // So the environment variable can be set to e.g. 1 or yes, too.
+            .value_parser(clap::builder::BoolishValueParser::new())
+            .help("Never prompt, e.g. to choose between multiple devices or to confirm an update; fail instead")
+            .long_help(
+                "Never prompt, e.g. to choose between multiple devices or to confirm an update; fail instead. \
+                This is also the default when the CI environment variable is set, as CI systems do."
+            )
         )
         .arg(Arg::new("allow-dangerous-options")
             .long("allow-dangerous-options")
@@ -344,33 +3044,110 @@ fn main()
             .hide(true)
             .help("Allow usage of advanced, dangerous options that can result in unbootable devices (use with heavy caution!)")
         )
-        .subcommand(Command::new("info")
+        .subcommand(Command::new("probe")
             .display_order(0)
-            .about("Print information about connected Black Magic Probe devices")
+            .about("Find out about the connected Black Magic Probes")
+            .arg_required_else_help(true)
+            .subcommand_required(true)
+            .subcommands(probe_commands())
         )
-        .subcommand(Command::new("flash")
+        .subcommand(Command::new("firmware")
             .display_order(1)
-            .about("Flash new firmware onto a Black Magic Probe device")
-            .arg(Arg::new("firmware_binary")
+            .about("Flash, update, back up and verify Black Magic Probe firmware")
+            .arg_required_else_help(true)
+            .subcommand_required(true)
+            .subcommands(firmware_commands())
+        )
+        .subcommand(Command::new("tui")
+            .display_order(2)
+            .about("Show a live dashboard of the connected Black Magic Probe devices, to update or back them up from")
+            .arg(Arg::new("channel")
+                .long("channel")
+                .value_name("CHANNEL")
                 .action(ArgAction::Set)
-                .required(true)
+                .value_parser(Channel::CLI_NAMES)
+                .help("Which firmware builds to compare against and update to (default: stable) [env: BMPUTIL_CHANNEL]")
             )
-            .arg(Arg::new("override-firmware-type")
-                .long("override-firmware-type")
-                .required(false)
-                .action(ArgAction::Set)
-                .value_parser(["bootloader", "application"])
-                .hide_short_help(true)
-                .help("flash the specified firmware space regardless of autodetected firmware type")
+            .arg(Arg::new("offline")
+                .long("offline")
+                .action(ArgAction::SetTrue)
+                .help("Don't use the network; use the releases as last looked up, and firmware downloaded before")
             )
-            .arg(Arg::new("force-override-flash")
-                .long("force-override-flash")
-                .required(false)
+        )
+        .subcommand(Command::new("completions")
+            .display_order(9)
+            .about("Print a tab completion script for a shell")
+            .after_help(
+                "For example, for bash: bmputil completions bash > /usr/share/bash-completion/completions/bmputil\n\
+                The bash and fish scripts also complete --serial with the serial numbers of the connected probes."
+            )
+            .arg(Arg::new("shell")
+                .value_parser(clap::value_parser!(Shell))
                 .action(ArgAction::Set)
-                .value_parser(["really"])
+                .required_unless_present("probe-serials")
+                .help("The shell to print the completion script for")
+            )
+            .arg(Arg::new("probe-serials")
+                .long("probe-serials")
+                .action(ArgAction::SetTrue)
                 .hide(true)
-                .help("forcibly override firmware-type autodetection and flash anyway (may result in an unbootable device!)")
+                .help("Print the serial numbers of the connected probes, for completion scripts")
+            )
+        )
+        .subcommand(Command::new("generate-manpages")
+            .hide(true)
+            .about("Write manual pages for bmputil and each of its subcommands, for packaging")
+            .arg(Arg::new("dir")
+                .value_parser(clap::value_parser!(PathBuf))
+                .action(ArgAction::Set)
+                .required(true)
+                .help("The directory to write the pages to (created if needed)")
+            )
+        )
+        .subcommand(Command::new("self-update")
+            .display_order(9)
+            .about("Update bmputil itself to its latest release, if installed as a standalone executable")
+            .arg(Arg::new("force")
+                .long("force")
+                .action(ArgAction::SetTrue)
+                .help("Reinstall bmputil even if this is already the latest release")
+            )
+        )
+        .subcommand(Command::new("setup")
+            .display_order(3)
+            .about("Walk through making sure bmputil can use your probe, choosing a release channel, and updating it")
+        )
+        .subcommand(Command::new("doctor")
+            .display_order(9)
+            .about("Write a report on the system, drivers and connected probes, to attach to bug reports")
+            .arg(Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("FILE")
+                .action(ArgAction::Set)
+                .help("Where to write the report, or - for stdout (default: bmputil-doctor-<time>.txt)")
+            )
+            .arg(Arg::new("no-redact")
+                .long("no-redact")
+                .action(ArgAction::SetTrue)
+                .help("Leave serial numbers, user names and credentials in the report")
             )
+        )
+        .subcommand(Command::new("cache")
+            .display_order(9)
+            .about("Manage the firmware releases bmputil has downloaded")
+            .arg_required_else_help(true)
+            .subcommand_required(true)
+            .subcommand(Command::new("clean")
+                .about("Delete all downloaded firmware releases and release information")
+            )
+        )
+        // The commands from before they were grouped, so that scripts using them keep working.
+        .subcommands(
+            probe_commands()
+                .into_iter()
+                .chain(firmware_commands())
+                .map(|command| command.hide(true))
         );
 
     let mut debug_subcmd = Command::new("debug")
@@ -380,6 +3157,51 @@ fn main()
         .subcommand_required(true)
         .subcommand(Command::new("detach")
             .about("Request device to switch from runtime mode to DFU mode or vice versa")
+        )
+        .subcommand(Command::new("erase")
+            .about("Erase the flash pages (or sectors) covering a range of addresses, with a DfuSe bootloader")
+            .arg(Arg::new("address")
+                .value_parser(bmp::parse_address)
+                .action(ArgAction::Set)
+                .required_unless_present("mass")
+                .help("The address to start erasing at")
+            )
+            .arg(Arg::new("length")
+                .value_parser(bmp::parse_address)
+                .action(ArgAction::Set)
+                .required_unless_present("mass")
+                .help("How many bytes to erase")
+            )
+            .arg(Arg::new("mass")
+                .long("mass")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["address", "length"])
+                .help("Erase all of the flash, bootloader included (STM32 built-in bootloader only)")
+            )
+            .arg(Arg::new("force")
+                .long("force")
+                .action(ArgAction::SetTrue)
+                .help("Don't ask for confirmation before touching the bootloader")
+            )
+        )
+        .subcommand(Command::new("write")
+            .about("Write a raw binary file to the flash at any address, with a DfuSe bootloader")
+            .arg(Arg::new("address")
+                .value_parser(bmp::parse_address)
+                .action(ArgAction::Set)
+                .required(true)
+                .help("The address to write the file at")
+            )
+            .arg(Arg::new("file")
+                .action(ArgAction::Set)
+                .required(true)
+                .help("The file to write, an https:// URL to download it from, or - to read it from stdin")
+            )
+            .arg(Arg::new("force")
+                .long("force")
+                .action(ArgAction::SetTrue)
+                .help("Don't ask for confirmation before touching the bootloader")
+            )
         );
 
     if cfg!(windows) {
@@ -394,15 +3216,185 @@ fn main()
                     .help("install the driver even if one is already installed")
                 )
             );
+
+        parser = parser
+            .subcommand(Command::new("winusb-install")
+                .hide(true)
+                .about("Internal command run as admin to install WinUSB for the given devices")
+                .arg(Arg::new("parent-pid")
+                    .long("parent-pid")
+                    .value_parser(u32::from_str)
+                    .action(ArgAction::Set)
+                )
+                .arg(Arg::new("hardware-ids")
+                    .required(true)
+                    .num_args(1..)
+                    .action(ArgAction::Append)
+                )
+            );
+    }
+
+    if cfg!(target_os = "linux") {
+        parser = parser
+            .arg(Arg::new("auto-attach")
+                .long("auto-attach")
+                .env("BMPUTIL_AUTO_ATTACH")
+                .global(true)
+                .hide_short_help(true)
+                .action(ArgAction::SetTrue)
+                // As with --non-interactive, so the environment variable can be e.g. 1 or yes.
+                .value_parser(clap::builder::BoolishValueParser::new())
+                .help("In WSL, have usbipd on Windows attach any probes it can see, if none are attached")
+            )
+            .subcommand(Command::new("install-udev-rules")
+                .display_order(4)
+                .about("Install udev rules so probes can be used without root, and check they work")
+                .arg(Arg::new("mode")
+                    .long("mode")
+                    .value_parser(["uaccess", "plugdev"])
+                    .default_value("uaccess")
+                    .action(ArgAction::Set)
+                    .help("Give access to whoever is logged in at the machine (uaccess), or to a group (plugdev)")
+                )
+                .arg(Arg::new("group")
+                    .long("group")
+                    .value_name("GROUP")
+                    .value_parser(parse_group)
+                    .action(ArgAction::Set)
+                    .help("The group to give access to (implies --mode plugdev) [default: plugdev]")
+                )
+                .arg(Arg::new("print")
+                    .long("print")
+                    .action(ArgAction::SetTrue)
+                    .help("Print the rules instead of installing them")
+                )
+            );
+    }
+
+    if cfg!(any(target_os = "linux", target_os = "android")) {
+        parser = parser
+            .arg(Arg::new("device-fd")
+                .long("device-fd")
+                .value_name("FD")
+                .global(true)
+                .hide_short_help(true)
+                .value_parser(clap::value_parser!(i32).range(0..))
+                .action(ArgAction::Set)
+                .help("Use the already-open USB device with this file descriptor (e.g. from Android's termux-usb) rather than looking for probes")
+            );
+    }
+
+    if cfg!(target_os = "freebsd") {
+        parser = parser
+            .subcommand(Command::new("install-devd-rules")
+                .display_order(4)
+                .about("Install devd rules so probes can be used without root, and check they work")
+                .arg(Arg::new("group")
+                    .long("group")
+                    .value_name("GROUP")
+                    .value_parser(parse_group)
+                    .action(ArgAction::Set)
+                    .help("The group to give access to [default: usb]")
+                )
+                .arg(Arg::new("print")
+                    .long("print")
+                    .action(ArgAction::SetTrue)
+                    .help("Print the rules instead of installing them")
+                )
+            );
     }
 
-    parser = parser.subcommand(debug_subcmd);
+    parser.subcommand(debug_subcmd)
+}
+
+/// Sets up logging to stderr, at the level `-v` asks for unless `RUST_LOG` says otherwise.
+fn init_logging(matches: &ArgMatches)
+{
+    let verbosity = matches.get_count("verbose");
+    let default_directives = match verbosity {
+        0 => "warn",
+        1 => "warn,bmputil=info",
+        2 => "warn,bmputil=debug",
+        _ => "debug,bmputil=trace",
+    };
+    let mut filter = match std::env::var(EnvFilter::DEFAULT_ENV) {
+        Ok(directives) if !directives.is_empty() => EnvFilter::new(directives),
+        _ => EnvFilter::new(default_directives),
+    };
+    if matches.get_flag("trace-dfu") {
+        let directive = format!("{}=trace", dfu::TRACE_TARGET)
+            .parse()
+            .expect("DFU trace target should be a valid filter directive");
+        filter = filter.add_directive(directive);
+    }
+    if matches.get_flag("debug-usb") {
+        usb::set_debug_log(true);
+        let directive = format!("{}=debug", usb::LIBUSB_LOG_TARGET)
+            .parse()
+            .expect("libusb log target should be a valid filter directive");
+        filter = filter.add_directive(directive);
+    }
 
+    // From -vv, also say how long each span (e.g. a download, or waiting for a reboot) took.
+    let span_events = if verbosity >= 2 { FmtSpan::CLOSE } else { FmtSpan::NONE };
+
+    // bmputil doctor keeps a detailed log of everything it did for its report, whatever's shown on stderr.
+    let capture = (matches.subcommand_name() == Some("doctor")).then(|| {
+        output::start_log_capture();
+        usb::set_debug_log(true);
+        tracing_subscriber::fmt::layer()
+            .with_writer(output::log_capture_writer)
+            .with_ansi(false)
+            .with_filter(EnvFilter::new("debug,bmputil=trace"))
+    });
+
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer()
+            .with_span_events(span_events)
+            .with_writer(output::log_writer)
+            .with_ansi(output::stderr_color())
+            .with_filter(filter)
+        )
+        .with(capture)
+        .init();
+}
 
-    let matches = parser.get_matches();
+fn main()
+{
+    // clap exits with 2 for usage errors, which we use for not finding a probe.
+    let matches = cli().try_get_matches().unwrap_or_else(|e| {
+        if !e.use_stderr() {
+            // --help and --version aren't errors.
+            e.exit();
+        }
+        let _ = e.print();
+        ExitStatus::Usage.exit();
+    });
+    output::set_json_output(matches.get_one::<String>("output-format").is_some_and(|format| format == "json"));
+    output::set_color_choice(matches.get_one::<String>("color").map_or("auto", String::as_str));
+    output::set_progress_style(matches.get_one::<String>("progress").map_or("auto", String::as_str));
+    prompt::set_non_interactive(matches.get_flag("non-interactive") || prompt::running_in_ci());
+
+    init_logging(&matches);
+
+    // This has to happen before libusb is first used.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if let Some(&fd) = matches.try_get_one::<i32>("device-fd").ok().flatten() {
+        if let Err(e) = usb::set_device_fd(fd) {
+            let e = Error::from(e).with_ctx("setting libusb up to use --device-fd");
+            status!("{} {}", output::paint(tr!("error-label"), style::BAD.bold()), e);
+            e.exit_status().exit();
+        }
+    }
 
     let (subcommand, subcommand_matches) = matches.subcommand()
         .expect("No subcommand given!"); // Should be impossible, thanks to clap.
+    // e.g. `bmputil firmware flash` is the same as the older `bmputil flash`.
+    let (subcommand, subcommand_matches) = match subcommand {
+        "probe" | "firmware" => subcommand_matches.subcommand()
+            .expect("No subcommand given!"), // Also impossible, as these need one.
+        _ => (subcommand, subcommand_matches),
+    };
 
     // Minor HACK: these Windows specific subcommands and operations need to be checked and handled
     // before the others.
@@ -428,9 +3420,24 @@ fn main()
                 },
                 _ => (),
             },
+            // This is us, elevated to install WinUSB; see winusb::offer_install().
+            "winusb-install" => match winusb::install_command(subcommand_matches) {
+                Ok(()) => std::process::exit(0),
+                Err(e) => {
+                    status!("{} {}", output::paint(tr!("error-label"), style::BAD.bold()), e);
+                    e.exit_status().exit();
+                },
+            },
             _ => (),
         }
 
+        // This is us, run again as an administrator by offer_elevated_retry(), so we need the console back.
+        if let Some(&parent_pid) = matches.get_one::<u32>("elevated-from") {
+            if let Err(e) = windows::restore_cstdio(parent_pid) {
+                warn!("Couldn't attach to the console of bmputil process {}: {}", parent_pid, e);
+            }
+        }
+
         // Otherwise, potentially install drivers, but still do whatever else the user wanted.
         windows::ensure_access(
             matches
@@ -441,34 +3448,114 @@ fn main()
         );
     }
 
-    let res = match subcommand {
-        "info" => info_command(subcommand_matches),
-        "flash" => flash(subcommand_matches),
-        "debug" => match subcommand_matches.subcommand().unwrap() {
-            ("detach", detach_matches) => detach_command(detach_matches),
-            other => unreachable!("Unhandled subcommand {:?}", other),
-        },
+    let res = Config::load()
+        .and_then(|config| {
+            match matches.get_one::<String>("proxy").or(config.proxy.as_ref()) {
+                Some(proxy) => fetch::set_proxy(proxy).map(|()| config),
+                None => Ok(config),
+            }
+        })
+        .and_then(|config| {
+            // Newcomers are most likely to start by looking for or updating their probe, and anyone
+            // with a configuration file has set bmputil up already.
+            let configured = Config::path().is_some_and(|path| path.exists());
+            if !configured && matches!(subcommand, "info" | "list" | "flash" | "update" | "check" | "tui") {
+                setup::offer_on_first_run(subcommand_matches, config)
+            } else {
+                Ok(config)
+            }
+        })
+        .and_then(|config| match subcommand {
+            "info" => info_command(subcommand_matches, &config),
+            "list" => list_command(subcommand_matches, &config),
+            "serial" => match subcommand_matches.subcommand().unwrap() {
+                ("get", get_matches) => serial_get_command(get_matches, &config),
+                ("set", set_matches) => serial_set_command(set_matches, &config),
+                other => unreachable!("Unhandled subcommand {:?}", other),
+            },
+            "dfu" => match subcommand_matches.subcommand().unwrap() {
+                ("enter", enter_matches) => dfu_command(enter_matches, &config, DfuOperatingMode::FirmwareUpgrade),
+                ("leave", leave_matches) => dfu_command(leave_matches, &config, DfuOperatingMode::Runtime),
+                other => unreachable!("Unhandled subcommand {:?}", other),
+            },
+            "option-bytes" => option_bytes_command(subcommand_matches, &config),
+            "voltage" => voltage_command(subcommand_matches, &config),
+            "power" => match subcommand_matches.subcommand().unwrap() {
+                ("on", on_matches) => power_command(on_matches, &config, Some(true)),
+                ("off", off_matches) => power_command(off_matches, &config, Some(false)),
+                ("status", status_matches) => power_command(status_matches, &config, None),
+                other => unreachable!("Unhandled subcommand {:?}", other),
+            },
+            "flash" => flash(subcommand_matches, &config),
+            "backup" => backup_command(subcommand_matches, &config),
+            "read" => read_command(subcommand_matches, &config),
+            "restore" => restore_command(subcommand_matches, &config),
+            "verify" => verify_command(subcommand_matches, &config),
+            "recover" => recover_command(subcommand_matches, &config),
+            "targets" => targets_command(subcommand_matches, &config),
+            "unlock" => unlock_command(subcommand_matches, &config),
+            "update" => update_command(subcommand_matches, &config),
+            "check" => check_command(subcommand_matches, &config),
+            "releases" => releases_command(subcommand_matches, &config),
+            "self-update" => self_update_command(subcommand_matches, &config),
+            "tui" => tui::run(subcommand_matches, &config),
+            "doctor" => doctor::run(subcommand_matches, &config),
+            "setup" => setup::run(subcommand_matches, &config),
+            #[cfg(target_os = "linux")]
+            "install-udev-rules" => udev::install_command(subcommand_matches, &config),
+            #[cfg(target_os = "freebsd")]
+            "install-devd-rules" => devd::install_command(subcommand_matches, &config),
+            "completions" => completions_command(subcommand_matches),
+            "generate-manpages" => generate_manpages_command(subcommand_matches),
+            "cache" => match subcommand_matches.subcommand().unwrap() {
+                ("clean", _) => cache_clean_command(),
+                other => unreachable!("Unhandled subcommand {:?}", other),
+            },
+            "debug" => match subcommand_matches.subcommand().unwrap() {
+                ("detach", detach_matches) => detach_command(detach_matches, &config),
+                ("erase", erase_matches) => erase_command(erase_matches, &config),
+                ("write", write_matches) => write_command(write_matches, &config),
+                other => unreachable!("Unhandled subcommand {:?}", other),
+            },
+
 
+            &_ => unimplemented!(),
+        });
 
-        &_ => unimplemented!(),
+    // Operations that write to a probe can be denied access on Windows where an administrator wouldn't be.
+    #[cfg(windows)]
+    let res = match res {
+        Err(e) if matches!(subcommand, "flash" | "update" | "recover" | "restore" | "unlock") => {
+            windows::offer_elevated_retry(e, matches.contains_id("elevated-from"))
+        },
+        res => res,
     };
 
 
+    // Commands with nothing more to say in JSON than whether they worked still need to say that.
+    if output::json_output() && !output::document_printed() {
+        output::print_json(&Outcome {
+            ok: res.is_ok(),
+            error: res.as_ref().err().map(|e| e.to_string()),
+        });
+    }
+
     // Unfortunately, we have to do the printing ourselves, as we need to print a note
     // in the event that backtraces are supported but not enabled.
     if let Err(e) = res {
-        println!("Error: {}", e);
+        status!("{} {}", output::paint(tr!("error-label"), style::BAD.bold()), e);
+        output::annotate(Annotation::Error, &e);
         #[cfg(feature = "backtrace")]
         {
             if e.backtrace.status() == BacktraceStatus::Disabled {
-                println!("note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace.");
+                status!("{}", tr!("error-backtrace-hint"));
             }
         }
 
         if cfg!(not(feature = "backtrace")) {
-            println!("note: recompile with nightly toolchain and run with `RUST_BACKTRACE=1` environment variable to display a backtrace.");
+            status!("{}", tr!("error-backtrace-nightly-hint"));
         }
 
-        std::process::exit(1);
+        e.exit_status().exit();
     }
 }
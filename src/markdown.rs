@@ -0,0 +1,171 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+//! Module for showing Markdown text, such as release notes, readably in a terminal.
+//!
+//! This only handles what release notes actually use: headings, lists, code blocks, emphasis and
+//! links. Anything else is shown as it was written, which is still readable enough.
+
+use std::io::{self, Write};
+
+use termcolor::{ColorSpec, StandardStream, WriteColor};
+
+use crate::output;
+
+/// Prints `markdown` where status messages go (see [`status!`](crate::status)), so stderr when stdout is
+/// for JSON output, with headings and code blocks highlighted if it's a terminal.
+pub fn print(markdown: &str) -> io::Result<()>
+{
+    // Whatever has the terminal shows what's going on itself.
+    if output::terminal_taken() {
+        return Ok(());
+    }
+    let mut out = if output::json_output() {
+        StandardStream::stderr(output::color_choice(true))
+    } else {
+        StandardStream::stdout(output::color_choice(false))
+    };
+
+    let mut in_code_block = false;
+    let mut in_comment = false;
+    let mut last_blank = true;
+    for line in markdown.lines() {
+        let trimmed = line.trim();
+
+        // GitHub's release templates leave HTML comments around, which aren't shown on the web either.
+        if in_comment || trimmed.starts_with("<!--") {
+            in_comment = !trimmed.ends_with("-->");
+            continue;
+        }
+
+        if trimmed.starts_with("```") {
+            in_code_block = !in_code_block;
+            continue;
+        }
+        if in_code_block {
+            out.set_color(ColorSpec::new().set_dimmed(true))?;
+            writeln!(out, "    {}", line)?;
+            out.reset()?;
+            last_blank = false;
+            continue;
+        }
+
+        // Don't let runs of blank lines spread things out.
+        if trimmed.is_empty() {
+            if !last_blank {
+                writeln!(out)?;
+            }
+            last_blank = true;
+            continue;
+        }
+        last_blank = false;
+
+        let heading_level = trimmed.chars().take_while(|&c| c == '#').count();
+        if heading_level > 0 && trimmed[heading_level..].starts_with(' ') {
+            out.set_color(ColorSpec::new().set_bold(true).set_underline(heading_level <= 2))?;
+            write!(out, "{}", inline(trimmed[heading_level..].trim()))?;
+            out.reset()?;
+            writeln!(out)?;
+            continue;
+        }
+
+        let indent = &line[..line.len() - line.trim_start().len()];
+        let item = ["- ", "* ", "+ "]
+            .iter()
+            .find_map(|bullet| trimmed.strip_prefix(bullet));
+        match item {
+            Some(item) => writeln!(out, "{}  • {}", indent, inline(item))?,
+            None => writeln!(out, "{}", inline(line))?,
+        };
+    }
+
+    out.flush()
+}
+
+/// Renders the inline Markdown in `text`: drops emphasis and code markers, and shows links as the link
+/// text followed by where it goes.
+fn inline(text: &str) -> String
+{
+    let mut rendered = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('[') {
+        // Images are shown as their alt text.
+        let is_image = rest[..start].ends_with('!');
+        let before = if is_image { &rest[..start - 1] } else { &rest[..start] };
+
+        let link = rest[start + 1..]
+            .split_once("](")
+            .and_then(|(label, after)| after.split_once(')').map(|(url, after)| (label, url, after)))
+            .filter(|(label, _url, _after)| !label.contains(']'));
+        match link {
+            Some((label, url, after)) => {
+                rendered.push_str(before);
+                if is_image || label == url {
+                    rendered.push_str(label);
+                } else {
+                    rendered.push_str(&format!("{} ({})", label, url));
+                }
+                rest = after;
+            },
+            None => {
+                rendered.push_str(&rest[..=start]);
+                rest = &rest[start + 1..];
+            },
+        }
+    }
+    rendered.push_str(rest);
+
+    strip_underscore_emphasis(&rendered.replace("**", ""))
+        .replace('`', "")
+}
+
+/// Drops the `__` markers of strong emphasis from `text`. Unlike `**`, these only count at the edges of
+/// words, so names like `snake__case` keep theirs.
+fn strip_underscore_emphasis(text: &str) -> String
+{
+    let mut stripped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("__") {
+        let before = rest[..start].chars().next_back().or_else(|| stripped.chars().next_back());
+        let after = rest[start + 2..].chars().next();
+
+        // An opening marker starts a word, and a closing one ends it.
+        let opens = !before.is_some_and(|c| c.is_alphanumeric()) && after.is_some_and(|c| !c.is_whitespace());
+        let closes = before.is_some_and(|c| !c.is_whitespace()) && !after.is_some_and(|c| c.is_alphanumeric());
+
+        stripped.push_str(&rest[..start]);
+        if !opens && !closes {
+            stripped.push_str("__");
+        }
+        rest = &rest[start + 2..];
+    }
+    stripped.push_str(rest);
+
+    stripped
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn strips_underscore_emphasis()
+    {
+        assert_eq!(strip_underscore_emphasis("__bold__"), "bold");
+        assert_eq!(strip_underscore_emphasis("a __b__ c"), "a b c");
+        assert_eq!(strip_underscore_emphasis("snake__case"), "snake__case");
+        assert_eq!(strip_underscore_emphasis("__init__.py"), "init.py");
+    }
+
+    #[test]
+    fn renders_inline_markdown()
+    {
+        assert_eq!(inline("**bold** and `code`"), "bold and code");
+        assert_eq!(inline("a __b__ c, not snake__case"), "a b c, not snake__case");
+        assert_eq!(inline("see [the docs](https://example.com)"), "see the docs (https://example.com)");
+        assert_eq!(inline("<[https://example.com](https://example.com)>"), "<https://example.com>");
+        assert_eq!(inline("![a probe](probe.png)"), "a probe");
+        assert_eq!(inline("[not a link] (really)"), "[not a link] (really)");
+    }
+}
@@ -0,0 +1,435 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+//! Module for reading the platform capabilities a device advertises in its BOS (Binary device Object
+//! Store) descriptor: Microsoft OS 2.0 descriptors, and WebUSB.
+//!
+//! Newer Black Magic Probe firmware (and bootloaders) use Microsoft OS 2.0 descriptors to give their
+//! DFU interface the `WINUSB` compatible ID, which has Windows (8.1 and later) bind WinUSB to it by
+//! itself, with no driver to install. On Windows, [`advertises_winusb()`] reads them to find out
+//! whether a probe does that for a given interface.
+//!
+//! See Microsoft's [OS 2.0 descriptors specification](https://learn.microsoft.com/windows-hardware/drivers/usbcon/microsoft-os-2-0-descriptors-specification)
+//! and the [WebUSB specification](https://wicg.github.io/webusb/#webusb-platform-capability-descriptor).
+
+use std::fmt::{self, Display, Formatter};
+use std::time::Duration;
+
+use rusb::{Direction, Recipient, RequestType};
+use tracing::{debug, trace};
+
+type UsbHandle = rusb::DeviceHandle<rusb::Context>;
+
+/// The BOS descriptor's descriptor type.
+const BOS_TYPE: u8 = 0x0f;
+/// The descriptor type of the device capability descriptors in the BOS descriptor.
+const DEVICE_CAPABILITY_TYPE: u8 = 0x10;
+/// The device capability type of platform capability descriptors.
+const PLATFORM_CAPABILITY: u8 = 0x05;
+
+/// The platform capability UUID for Microsoft OS 2.0 descriptors, {D8DD60DF-4589-4CC7-9CD2-659D9E648A9F},
+/// as it appears on the wire.
+const MS_OS_20_UUID: [u8; 16] = [
+    0xdf, 0x60, 0xdd, 0xd8, 0x89, 0x45, 0xc7, 0x4c, 0x9c, 0xd2, 0x65, 0x9d, 0x9e, 0x64, 0x8a, 0x9f,
+];
+/// The platform capability UUID for WebUSB, {3408B638-09A9-47A0-8BFD-A0768815B665}, as it appears on
+/// the wire.
+const WEBUSB_UUID: [u8; 16] = [
+    0x38, 0xb6, 0x08, 0x34, 0xa9, 0x09, 0xa0, 0x47, 0x8b, 0xfd, 0xa0, 0x76, 0x88, 0x15, 0xb6, 0x65,
+];
+
+/// The wIndex of the vendor request that reads the MS OS 2.0 descriptor set.
+const MS_OS_20_DESCRIPTOR_INDEX: u16 = 7;
+/// The wIndex of the WebUSB vendor request that reads a URL descriptor.
+const WEBUSB_GET_URL: u16 = 2;
+
+// The wDescriptorType of each kind of descriptor within an MS OS 2.0 descriptor set.
+const MS_OS_20_SET_HEADER: u16 = 0x00;
+const MS_OS_20_SUBSET_HEADER_FUNCTION: u16 = 0x02;
+const MS_OS_20_FEATURE_COMPATIBLE_ID: u16 = 0x03;
+
+/// The platform capabilities bmputil knows about that a device advertises.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PlatformCapabilities
+{
+    /// The vendor request code to read the device's MS OS 2.0 descriptor set with, and its length.
+    pub ms_os_20: Option<(u8, u16)>,
+
+    /// The vendor request code for WebUSB requests, and the string index of the landing page URL.
+    pub webusb: Option<(u8, u8)>,
+}
+
+/// What a device's MS OS 2.0 descriptors have Windows bind WinUSB to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WinUsbBinding
+{
+    /// The whole device, which isn't composite.
+    Device,
+    /// The functions of a composite device starting at these interfaces.
+    Interfaces(Vec<u8>),
+}
+
+impl WinUsbBinding
+{
+    /// Whether this covers interface `interface` of a device, which is composite if `composite`.
+    #[cfg(windows)]
+    pub fn covers(&self, interface: u8, composite: bool) -> bool
+    {
+        match self {
+            Self::Device => !composite,
+            Self::Interfaces(interfaces) => interfaces.contains(&interface),
+        }
+    }
+}
+
+impl Display for WinUsbBinding
+{
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result
+    {
+        match self {
+            Self::Device => write!(f, "the whole device"),
+            Self::Interfaces(interfaces) => {
+                let interfaces = interfaces
+                    .iter()
+                    .map(u8::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(f, "interface {}", interfaces)
+            },
+        }
+    }
+}
+
+/// Parses a BOS descriptor (including its device capability descriptors).
+fn parse_bos(bytes: &[u8]) -> PlatformCapabilities
+{
+    let mut capabilities = PlatformCapabilities::default();
+
+    // Skip the BOS descriptor's own 5-byte header.
+    let mut rest = bytes.get(5..).unwrap_or_default();
+    while let [length, descriptor_type, ..] = *rest {
+        let length = length as usize;
+        if length < 3 || length > rest.len() {
+            debug!("BOS descriptor has a device capability of invalid length {}", length);
+            break;
+        }
+        let (capability, remaining) = rest.split_at(length);
+        rest = remaining;
+
+        // Platform capabilities are bLength, bDescriptorType, bDevCapabilityType, bReserved, the
+        // platform's UUID, and then whatever that platform defines.
+        if descriptor_type != DEVICE_CAPABILITY_TYPE || capability[2] != PLATFORM_CAPABILITY || length < 20 {
+            continue;
+        }
+        let (uuid, data) = capability[4..].split_at(16);
+        if uuid == MS_OS_20_UUID {
+            // dwWindowsVersion, wMSOSDescriptorSetTotalLength, bMS_VendorCode, bAltEnumCode.
+            if let [_, _, _, _, length_low, length_high, vendor_code, ..] = *data {
+                capabilities.ms_os_20 = Some((vendor_code, u16::from_le_bytes([length_low, length_high])));
+            }
+        } else if uuid == WEBUSB_UUID {
+            // bcdVersion, bVendorCode, iLandingPage.
+            if let [_, _, vendor_code, landing_page, ..] = *data {
+                capabilities.webusb = Some((vendor_code, landing_page));
+            }
+        }
+    }
+
+    capabilities
+}
+
+/// Parses an MS OS 2.0 descriptor set, returning what it has Windows bind WinUSB to, if anything.
+fn parse_descriptor_set(bytes: &[u8]) -> Option<WinUsbBinding>
+{
+    let mut device = false;
+    let mut interfaces = Vec::new();
+    // The interface the function subset we're in (if any) starts at.
+    let mut function = None;
+
+    // Every descriptor in the set starts with a wLength and a wDescriptorType.
+    let mut rest = bytes;
+    while let [length_low, length_high, type_low, type_high, ..] = *rest {
+        let length = u16::from_le_bytes([length_low, length_high]) as usize;
+        let descriptor_type = u16::from_le_bytes([type_low, type_high]);
+        if length < 4 || length > rest.len() {
+            debug!("MS OS 2.0 descriptor set has a descriptor of invalid length {}", length);
+            break;
+        }
+        let (descriptor, remaining) = rest.split_at(length);
+        // The set and subset headers' lengths are of just the header, rather than what they contain.
+        rest = remaining;
+        trace!("MS OS 2.0 descriptor type {}: {:02x?}", descriptor_type, descriptor);
+
+        match descriptor_type {
+            MS_OS_20_SET_HEADER => (),
+            MS_OS_20_SUBSET_HEADER_FUNCTION => function = descriptor.get(4).copied(),
+            MS_OS_20_FEATURE_COMPATIBLE_ID => {
+                let winusb = descriptor
+                    .get(4..12)
+                    .is_some_and(|compatible_id| compatible_id.starts_with(b"WINUSB"));
+                if !winusb {
+                    continue;
+                }
+                match function {
+                    Some(interface) => interfaces.push(interface),
+                    None => device = true,
+                }
+            },
+            _ => (),
+        }
+    }
+
+    if device {
+        Some(WinUsbBinding::Device)
+    } else if !interfaces.is_empty() {
+        Some(WinUsbBinding::Interfaces(interfaces))
+    } else {
+        None
+    }
+}
+
+/// Reads the platform capabilities `handle`'s device advertises in its BOS descriptor. Devices
+/// that only support USB 2.0 (rather than 2.01) don't have one.
+pub fn platform_capabilities(handle: &UsbHandle, timeout: Duration) -> rusb::Result<PlatformCapabilities>
+{
+    let desc = handle.device().device_descriptor()?;
+    let usb_version = desc.usb_version();
+    if (usb_version.major(), usb_version.minor(), usb_version.sub_minor()) < (2, 0, 1) {
+        return Ok(PlatformCapabilities::default());
+    }
+
+    let request_type = rusb::request_type(Direction::In, RequestType::Standard, Recipient::Device);
+    let get_descriptor = |length: usize| {
+        let mut buf = vec![0; length];
+        let read = handle.read_control(
+            request_type,
+            rusb::constants::LIBUSB_REQUEST_GET_DESCRIPTOR,
+            (BOS_TYPE as u16) << 8,
+            0,
+            &mut buf,
+            timeout,
+        )?;
+        buf.truncate(read);
+        Ok(buf)
+    };
+
+    // Read just the header first, to find out how long the whole thing is.
+    let header = get_descriptor(5)?;
+    let [_, BOS_TYPE, total_low, total_high, _] = *header.as_slice() else {
+        debug!("Device returned an invalid BOS descriptor header: {:02x?}", header);
+        return Ok(PlatformCapabilities::default());
+    };
+    let bos = get_descriptor(u16::from_le_bytes([total_low, total_high]) as usize)?;
+
+    Ok(parse_bos(&bos))
+}
+
+/// Reads `handle`'s device's MS OS 2.0 descriptors (if it advertises any in `capabilities`), and
+/// returns what they have Windows bind WinUSB to.
+pub fn winusb_binding(
+    handle: &UsbHandle,
+    capabilities: &PlatformCapabilities,
+    timeout: Duration,
+) -> rusb::Result<Option<WinUsbBinding>>
+{
+    let Some((vendor_code, length)) = capabilities.ms_os_20 else {
+        return Ok(None);
+    };
+
+    let request_type = rusb::request_type(Direction::In, RequestType::Vendor, Recipient::Device);
+    let mut buf = vec![0; length as usize];
+    let read = handle.read_control(request_type, vendor_code, 0, MS_OS_20_DESCRIPTOR_INDEX, &mut buf, timeout)?;
+    buf.truncate(read);
+
+    Ok(parse_descriptor_set(&buf))
+}
+
+/// Whether `handle`'s device's MS OS 2.0 descriptors have Windows bind WinUSB to its interface
+/// `interface` by itself, so it needs no driver installing.
+#[cfg(windows)]
+pub fn advertises_winusb(handle: &UsbHandle, interface: u8, timeout: Duration) -> rusb::Result<bool>
+{
+    let capabilities = platform_capabilities(handle, timeout)?;
+    let Some(binding) = winusb_binding(handle, &capabilities, timeout)? else {
+        return Ok(false);
+    };
+
+    let device = handle.device();
+    let configuration = device
+        .active_config_descriptor()
+        .or_else(|_| device.config_descriptor(0))?;
+
+    Ok(binding.covers(interface, configuration.num_interfaces() > 1))
+}
+
+/// Reads the URL of the landing page `handle`'s device gives WebUSB (if it advertises one in
+/// `capabilities`), which browsers offer to open when it's plugged in.
+pub fn landing_page(
+    handle: &UsbHandle,
+    capabilities: &PlatformCapabilities,
+    timeout: Duration,
+) -> rusb::Result<Option<String>>
+{
+    let Some((vendor_code, index)) = capabilities.webusb else {
+        return Ok(None);
+    };
+    if index == 0 {
+        return Ok(None);
+    }
+
+    let request_type = rusb::request_type(Direction::In, RequestType::Vendor, Recipient::Device);
+    let mut buf = [0; 255];
+    let read = handle.read_control(request_type, vendor_code, index as u16, WEBUSB_GET_URL, &mut buf, timeout)?;
+
+    // URL descriptors are bLength, bDescriptorType, bScheme, and then the rest of the URL in UTF-8.
+    let [length, _, scheme, ref url @ ..] = buf[..read] else {
+        return Ok(None);
+    };
+    let url = url.get(..(length as usize).saturating_sub(3)).unwrap_or(url);
+    let scheme = match scheme {
+        0 => "http://",
+        1 => "https://",
+        _ => "",
+    };
+
+    Ok(Some(format!("{}{}", scheme, String::from_utf8_lossy(url))))
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// A BOS descriptor laid out as the Black Magic Probe firmware's is: a USB 2.0 extension, then
+    /// WebUSB (vendor code 0x22, landing page string 1), then MS OS 2.0 descriptors (vendor code
+    /// 0x21, a 0x00b2-byte descriptor set, for Windows 8.1 and later).
+    const BMP_BOS: [u8; 64] = [
+        0x05, 0x0f, 0x40, 0x00, 0x03,
+        0x07, 0x10, 0x02, 0x02, 0x00, 0x00, 0x00,
+        0x18, 0x10, 0x05, 0x00,
+        0x38, 0xb6, 0x08, 0x34, 0xa9, 0x09, 0xa0, 0x47, 0x8b, 0xfd, 0xa0, 0x76, 0x88, 0x15, 0xb6, 0x65,
+        0x00, 0x01, 0x22, 0x01,
+        0x1c, 0x10, 0x05, 0x00,
+        0xdf, 0x60, 0xdd, 0xd8, 0x89, 0x45, 0xc7, 0x4c, 0x9c, 0xd2, 0x65, 0x9d, 0x9e, 0x64, 0x8a, 0x9f,
+        0x00, 0x00, 0x03, 0x06, 0xb2, 0x00, 0x21, 0x00,
+    ];
+
+    /// An MS OS 2.0 descriptor of type `descriptor_type`, its header followed by `rest`.
+    fn descriptor(descriptor_type: u16, rest: &[u8]) -> Vec<u8>
+    {
+        let mut descriptor = Vec::new();
+        descriptor.extend_from_slice(&(4 + rest.len() as u16).to_le_bytes());
+        descriptor.extend_from_slice(&descriptor_type.to_le_bytes());
+        descriptor.extend_from_slice(rest);
+        descriptor
+    }
+
+    /// A compatible ID descriptor giving `compatible_id`.
+    fn compatible_id(compatible_id: &[u8; 8]) -> Vec<u8>
+    {
+        let mut rest = compatible_id.to_vec();
+        rest.extend_from_slice(&[0; 8]);
+        descriptor(MS_OS_20_FEATURE_COMPATIBLE_ID, &rest)
+    }
+
+    /// A registry property descriptor giving the DeviceInterfaceGUIDs, which should be skipped over.
+    fn interface_guids() -> Vec<u8>
+    {
+        let utf16 = |string: &str| {
+            string
+                .encode_utf16()
+                .chain([0, 0])
+                .flat_map(u16::to_le_bytes)
+                .collect::<Vec<_>>()
+        };
+        let name = utf16("DeviceInterfaceGUIDs");
+        let data = utf16("{76be5ca1-e305-45b2-9b5d-1c4ecc6e7a2a}");
+
+        let mut rest = vec![0x07, 0x00];
+        rest.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        rest.extend_from_slice(&name);
+        rest.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        rest.extend_from_slice(&data);
+        descriptor(0x04, &rest)
+    }
+
+    /// A descriptor set made up of `descriptors`, with its header.
+    fn descriptor_set(descriptors: &[Vec<u8>]) -> Vec<u8>
+    {
+        let total = 10 + descriptors.iter().map(Vec::len).sum::<usize>() as u16;
+        let mut set = vec![0x0a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x06];
+        set.extend_from_slice(&total.to_le_bytes());
+        for descriptor in descriptors {
+            set.extend_from_slice(descriptor);
+        }
+        set
+    }
+
+    #[test]
+    fn bos_with_webusb_and_ms_os_20()
+    {
+        assert_eq!(
+            parse_bos(&BMP_BOS),
+            PlatformCapabilities {
+                ms_os_20: Some((0x21, 0x00b2)),
+                webusb: Some((0x22, 1)),
+            },
+        );
+    }
+
+    #[test]
+    fn bos_without_platform_capabilities()
+    {
+        // Just the USB 2.0 extension, as on devices with link power management but nothing else.
+        assert_eq!(parse_bos(&BMP_BOS[..12]), PlatformCapabilities::default());
+        assert_eq!(parse_bos(&[]), PlatformCapabilities::default());
+    }
+
+    #[test]
+    fn bos_with_bad_lengths()
+    {
+        // A capability claiming to run past the end of the descriptor ends the parsing there.
+        let mut truncated = BMP_BOS[..30].to_vec();
+        truncated[2] = 30;
+        assert_eq!(parse_bos(&truncated), PlatformCapabilities::default());
+
+        // As does one with a zero length, rather than looping forever.
+        let mut zero = BMP_BOS.to_vec();
+        zero[5] = 0;
+        assert_eq!(parse_bos(&zero), PlatformCapabilities::default());
+    }
+
+    #[test]
+    fn descriptor_set_for_composite_device()
+    {
+        // As for the Black Magic Probe's firmware, whose DFU interface (4) is one function of many.
+        let set = descriptor_set(&[
+            descriptor(0x01, &[0x00, 0x00, 0x00, 0x00]),
+            descriptor(MS_OS_20_SUBSET_HEADER_FUNCTION, &[0x04, 0x00, 0x00, 0x00]),
+            compatible_id(b"WINUSB\0\0"),
+            interface_guids(),
+        ]);
+        assert_eq!(parse_descriptor_set(&set), Some(WinUsbBinding::Interfaces(vec![4])));
+    }
+
+    #[test]
+    fn descriptor_set_for_whole_device()
+    {
+        // As for the bootloaders, which have nothing but the DFU interface.
+        let set = descriptor_set(&[compatible_id(b"WINUSB\0\0"), interface_guids()]);
+        assert_eq!(parse_descriptor_set(&set), Some(WinUsbBinding::Device));
+    }
+
+    #[test]
+    fn descriptor_set_without_winusb()
+    {
+        assert_eq!(parse_descriptor_set(&descriptor_set(&[compatible_id(b"XUSB20\0\0")])), None);
+        assert_eq!(parse_descriptor_set(&descriptor_set(&[interface_guids()])), None);
+        assert_eq!(parse_descriptor_set(&[]), None);
+
+        // A descriptor running past the end of the set ends the parsing there.
+        let mut set = descriptor_set(&[compatible_id(b"WINUSB\0\0")]);
+        set.truncate(set.len() - 1);
+        assert_eq!(parse_descriptor_set(&set), None);
+    }
+}
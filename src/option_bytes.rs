@@ -0,0 +1,290 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+//! Module for making sense of the option bytes of the STM32s probes are built on, which the STM32 built-in
+//! bootloader reads out for `bmputil probe option-bytes`. Chiefly, they say the flash's read protection
+//! (RDP) level: at level 1, nothing can read the flash back (so it can't be backed up), and taking it back
+//! to level 0 mass erases it; at level 2, the MCU is locked for good.
+//!
+//! Where the option bytes are, and how they're laid out, depends on the family, so the address the
+//! bootloader gives them at tells us how to read them:
+//!
+//! * `0x1ffff800`, the STM32F0, F1 and F3: RDP, USER, Data0, Data1 and WRP0-3, each followed by its complement.
+//! * `0x1fffc000`, the STM32F2 and F4: USER and RDP, then nWRP at `0x1fffc008`.
+//!
+//! The families sharing a layout don't all read the RDP byte the same way, though: the STM32F1 has no
+//! level 2, and takes 0xa5 as level 0, where the others take 0xaa. Nothing the bootloader tells us says
+//! which family it is, so some RDP values only tell us the level up to which family the MCU is.
+
+use std::fmt::{self, Display, Formatter};
+
+use crate::tr;
+
+/// The read protection (RDP) level of an STM32's flash.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RdpLevel
+{
+    /// Not protected.
+    Level0,
+    /// The flash can't be read by debuggers or the bootloader, and removing the protection erases it.
+    Level1,
+    /// The MCU is locked permanently: debugging, and the built-in bootloader, are disabled.
+    Level2,
+}
+
+impl RdpLevel
+{
+    /// The level's number, e.g. for JSON output.
+    pub fn number(&self) -> u8
+    {
+        match self {
+            Self::Level0 => 0,
+            Self::Level1 => 1,
+            Self::Level2 => 2,
+        }
+    }
+}
+
+impl Display for RdpLevel
+{
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result
+    {
+        match self {
+            Self::Level0 => write!(f, "{}", tr!("rdp-level-0")),
+            Self::Level1 => write!(f, "{}", tr!("rdp-level-1")),
+            Self::Level2 => write!(f, "{}", tr!("rdp-level-2")),
+        }
+    }
+}
+
+/// The layouts of option bytes we know, by STM32 family.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Layout
+{
+    /// The STM32F0, F1 and F3, whose option bytes are each followed by their complement.
+    F0F1F3,
+    /// The STM32F2 and F4.
+    F2F4,
+}
+
+impl Layout
+{
+    fn at(address: u32) -> Option<Self>
+    {
+        match address {
+            0x1fff_f800 => Some(Self::F0F1F3),
+            0x1fff_c000 => Some(Self::F2F4),
+            _ => None,
+        }
+    }
+
+    /// What RDP byte `rdp` means on each of the families with this layout.
+    fn rdp_levels(&self, rdp: u8) -> Vec<(&'static str, RdpLevel)>
+    {
+        let level = match rdp {
+            0xaa => RdpLevel::Level0,
+            0xcc => RdpLevel::Level2,
+            _ => RdpLevel::Level1,
+        };
+        let f1_level = match rdp {
+            0xa5 => RdpLevel::Level0,
+            _ => RdpLevel::Level1,
+        };
+
+        match self {
+            Self::F0F1F3 => vec![("STM32F0/F3", level), ("STM32F1", f1_level)],
+            Self::F2F4 => vec![("STM32F2/F4", level)],
+        }
+    }
+}
+
+impl Display for Layout
+{
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result
+    {
+        match self {
+            Self::F0F1F3 => write!(f, "STM32F0/F1/F3"),
+            Self::F2F4 => write!(f, "STM32F2/F4"),
+        }
+    }
+}
+
+/// An STM32's option bytes, as read from `address`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptionBytes
+{
+    pub address: u32,
+    pub bytes: Vec<u8>,
+}
+
+impl OptionBytes
+{
+    pub fn new(address: u32, bytes: Vec<u8>) -> Self
+    {
+        Self { address, bytes }
+    }
+
+    /// Which family's layout the option bytes are in, if we know it.
+    pub fn family(&self) -> Option<String>
+    {
+        Layout::at(self.address).map(|layout| layout.to_string())
+    }
+
+    /// The RDP byte, where we know the layout.
+    pub fn rdp(&self) -> Option<u8>
+    {
+        match Layout::at(self.address)? {
+            Layout::F0F1F3 => self.bytes.first().copied(),
+            Layout::F2F4 => self.bytes.get(1).copied(),
+        }
+    }
+
+    /// What the RDP byte means on each of the families the option bytes could be from, where we know
+    /// the layout.
+    pub fn rdp_levels(&self) -> Vec<(&'static str, RdpLevel)>
+    {
+        match (Layout::at(self.address), self.rdp()) {
+            (Some(layout), Some(rdp)) => layout.rdp_levels(rdp),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The flash's read protection level, where we know the layout, and the RDP byte means the same on
+    /// every family with it.
+    pub fn rdp_level(&self) -> Option<RdpLevel>
+    {
+        let levels = self.rdp_levels();
+        let (_family, level) = *levels.first()?;
+        levels
+            .iter()
+            .all(|&(_family, other)| other == level)
+            .then_some(level)
+    }
+
+    /// The option bytes we know the meaning of, by name, where we know the layout.
+    pub fn fields(&self) -> Vec<(&'static str, u32)>
+    {
+        let byte = |offset: usize| self.bytes.get(offset).map(|&byte| byte as u32);
+
+        let fields = match Layout::at(self.address) {
+            Some(Layout::F0F1F3) => vec![
+                ("RDP", byte(0)),
+                ("USER", byte(2)),
+                ("Data0", byte(4)),
+                ("Data1", byte(6)),
+                ("WRP0", byte(8)),
+                ("WRP1", byte(10)),
+                ("WRP2", byte(12)),
+                ("WRP3", byte(14)),
+            ],
+            Some(Layout::F2F4) => vec![
+                ("USER", byte(0)),
+                ("RDP", byte(1)),
+                ("nWRP", byte(8).zip(byte(9)).map(|(low, high)| (high << 8 | low) & 0x0fff)),
+            ],
+            None => Vec::new(),
+        };
+
+        fields
+            .into_iter()
+            .filter_map(|(name, value)| Some((name, value?)))
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// The option bytes of an STM32F0/F1/F3 with RDP byte `rdp`, as they'd be read back.
+    fn f0f1f3(rdp: u8) -> OptionBytes
+    {
+        let mut bytes = Vec::new();
+        for byte in [rdp, 0xff, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff] {
+            bytes.extend_from_slice(&[byte, !byte]);
+        }
+        OptionBytes::new(0x1fff_f800, bytes)
+    }
+
+    /// The option bytes of an STM32F2/F4 with RDP byte `rdp`, as they'd be read back.
+    fn f2f4(rdp: u8) -> OptionBytes
+    {
+        OptionBytes::new(0x1fff_c000, vec![0xec, rdp, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x0f, 0xff, 0xff])
+    }
+
+    #[test]
+    fn f0f1f3_rdp_levels_depend_on_the_family()
+    {
+        // 0xaa is level 0 on the F0 and F3, but level 1 on the F1.
+        assert_eq!(f0f1f3(0xaa).rdp_level(), None);
+        assert_eq!(
+            f0f1f3(0xaa).rdp_levels(),
+            vec![("STM32F0/F3", RdpLevel::Level0), ("STM32F1", RdpLevel::Level1)],
+        );
+        // 0xa5 is the F1's level 0, but level 1 on the others.
+        assert_eq!(f0f1f3(0xa5).rdp_level(), None);
+        assert_eq!(
+            f0f1f3(0xa5).rdp_levels(),
+            vec![("STM32F0/F3", RdpLevel::Level1), ("STM32F1", RdpLevel::Level0)],
+        );
+        // 0xcc is level 2 on the F0 and F3, but the F1 has no level 2.
+        assert_eq!(f0f1f3(0xcc).rdp_level(), None);
+        assert_eq!(
+            f0f1f3(0xcc).rdp_levels(),
+            vec![("STM32F0/F3", RdpLevel::Level2), ("STM32F1", RdpLevel::Level1)],
+        );
+    }
+
+    #[test]
+    fn f0f1f3_other_rdp_values_are_level1()
+    {
+        assert_eq!(f0f1f3(0x00).rdp_level(), Some(RdpLevel::Level1));
+        assert_eq!(f0f1f3(0xff).rdp_level(), Some(RdpLevel::Level1));
+    }
+
+    #[test]
+    fn f2f4_rdp_levels()
+    {
+        assert_eq!(f2f4(0xaa).rdp_level(), Some(RdpLevel::Level0));
+        assert_eq!(f2f4(0x55).rdp_level(), Some(RdpLevel::Level1));
+        assert_eq!(f2f4(0xcc).rdp_level(), Some(RdpLevel::Level2));
+    }
+
+    #[test]
+    fn unknown_layout()
+    {
+        let option_bytes = OptionBytes::new(0x1fff_7800, vec![0xaa; 16]);
+        assert_eq!(option_bytes.family(), None);
+        assert_eq!(option_bytes.rdp(), None);
+        assert_eq!(option_bytes.rdp_level(), None);
+        assert!(option_bytes.fields().is_empty());
+    }
+
+    #[test]
+    fn truncated_option_bytes()
+    {
+        let option_bytes = OptionBytes::new(0x1fff_c000, vec![0xec]);
+        assert_eq!(option_bytes.rdp_level(), None);
+        assert_eq!(option_bytes.fields(), vec![("USER", 0xec)]);
+    }
+
+    #[test]
+    fn fields()
+    {
+        assert_eq!(
+            f0f1f3(0xa5).fields(),
+            vec![
+                ("RDP", 0xa5),
+                ("USER", 0xff),
+                ("Data0", 0x00),
+                ("Data1", 0x00),
+                ("WRP0", 0xff),
+                ("WRP1", 0xff),
+                ("WRP2", 0xff),
+                ("WRP3", 0xff),
+            ],
+        );
+        assert_eq!(f2f4(0xaa).fields(), vec![("USER", 0xec), ("RDP", 0xaa), ("nWRP", 0x0fff)]);
+    }
+}
@@ -0,0 +1,464 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+//! Module for reporting the progress of operations on probes, which may be running one at a time
+//! or several at once, and their results.
+//!
+//! With `--output-format json`, stdout is kept for a single JSON document giving the result of the
+//! command, and the status messages meant for humans go to stderr instead.
+//!
+//! Status messages are coloured when going to a terminal, unless `NO_COLOR` is set or `--color` says
+//! otherwise.
+
+use std::env;
+use std::fmt::Display;
+use std::io::{self, IsTerminal, Write};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anstyle::{AnsiColor, Style};
+use indicatif::{HumanBytes, HumanDuration, MultiProgress, ProgressBar, ProgressStyle};
+use serde::Serialize;
+use termcolor::ColorChoice;
+
+use crate::prompt;
+
+/// The names of the output formats, as given to `--output-format`.
+pub const OUTPUT_FORMATS: [&str; 2] = ["text", "json"];
+
+/// The choices for when to use colour, as given to `--color`.
+pub const COLOR_CHOICES: [&str; 3] = ["auto", "always", "never"];
+
+/// Which of [`COLOR_CHOICES`] was made, as its index.
+static COLOR_CHOICE: AtomicU8 = AtomicU8::new(0);
+
+/// The ways progress can be shown, as given to `--progress`.
+pub const PROGRESS_STYLES: [&str; 4] = ["auto", "bar", "plain", "none"];
+
+/// Which of [`PROGRESS_STYLES`] was chosen, as its index.
+static PROGRESS_STYLE: AtomicU8 = AtomicU8::new(0);
+
+/// How often plain progress updates are printed.
+const PLAIN_PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Styles for parts of status messages, for use with [`paint()`].
+pub mod style
+{
+    use super::{AnsiColor, Style};
+
+    /// Things having gone well, or the version being moved to.
+    pub const GOOD: Style = AnsiColor::Green.on_default();
+    /// Things needing attention, such as an update being available.
+    pub const NOTICE: Style = AnsiColor::Yellow.on_default();
+    /// Things having gone wrong, or the version being moved from.
+    pub const BAD: Style = AnsiColor::Red.on_default();
+    /// Headings and labels.
+    pub const HEADING: Style = Style::new().bold();
+    /// Things of less interest, such as unknown values.
+    pub const FAINT: Style = Style::new().dimmed();
+}
+
+/// Whether the result of the command is to be printed as JSON.
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+/// Whether the command has printed its JSON document yet.
+static DOCUMENT_PRINTED: AtomicBool = AtomicBool::new(false);
+
+/// Whether something else (the dashboard) has the terminal, so status messages and logs must keep off it.
+static TERMINAL_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Sets whether the result of the command is to be printed as JSON, from `--output-format`.
+pub fn set_json_output(json: bool)
+{
+    JSON_OUTPUT.store(json, Ordering::Relaxed);
+}
+
+/// Whether the result of the command is to be printed as JSON, leaving stdout for that alone.
+pub fn json_output() -> bool
+{
+    JSON_OUTPUT.load(Ordering::Relaxed)
+}
+
+/// Sets whether something else has taken over the terminal, stopping status messages and logs being
+/// printed while it has.
+pub fn set_terminal_taken(taken: bool)
+{
+    TERMINAL_TAKEN.store(taken, Ordering::Relaxed);
+}
+
+/// Whether something else has taken over the terminal (see [`set_terminal_taken()`]).
+pub fn terminal_taken() -> bool
+{
+    TERMINAL_TAKEN.load(Ordering::Relaxed)
+}
+
+/// Where log messages go: stderr, unless something else has taken over the terminal.
+pub fn log_writer() -> Box<dyn Write>
+{
+    if terminal_taken() {
+        Box::new(io::sink())
+    } else {
+        Box::new(io::stderr())
+    }
+}
+
+/// Everything logged since [`start_log_capture()`], kept for `bmputil doctor`'s report.
+static LOG_CAPTURE: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+
+/// Starts keeping everything that's written to [`log_capture_writer()`].
+pub fn start_log_capture()
+{
+    *LOG_CAPTURE.lock().unwrap() = Some(Vec::new());
+}
+
+/// Returns everything that's been logged since [`start_log_capture()`], and starts over.
+pub fn take_captured_log() -> String
+{
+    let mut capture = LOG_CAPTURE.lock().unwrap();
+    let log = capture.as_mut().map(std::mem::take).unwrap_or_default();
+    String::from_utf8_lossy(&log).into_owned()
+}
+
+/// Where log messages go to be captured, which is nowhere unless [`start_log_capture()`] was called.
+pub fn log_capture_writer() -> LogCapture
+{
+    LogCapture
+}
+
+/// A [`Write`] that appends to the captured log.
+pub struct LogCapture;
+
+impl Write for LogCapture
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+    {
+        if let Some(log) = LOG_CAPTURE.lock().unwrap().as_mut() {
+            log.extend_from_slice(buf);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    {
+        Ok(())
+    }
+}
+
+/// Sets when to use colour, from `--color` (one of [`COLOR_CHOICES`]).
+pub fn set_color_choice(choice: &str)
+{
+    let index = COLOR_CHOICES
+        .iter()
+        .position(|&name| name == choice)
+        .unwrap_or(0);
+    COLOR_CHOICE.store(index as u8, Ordering::Relaxed);
+}
+
+/// Sets how progress is shown, from `--progress` (one of [`PROGRESS_STYLES`]).
+pub fn set_progress_style(style: &str)
+{
+    let index = PROGRESS_STYLES
+        .iter()
+        .position(|&name| name == style)
+        .unwrap_or(0);
+    PROGRESS_STYLE.store(index as u8, Ordering::Relaxed);
+}
+
+/// How progress is to be shown: as a progress bar, as a line every so often, or not at all.
+fn progress_style() -> &'static str
+{
+    match PROGRESS_STYLES[PROGRESS_STYLE.load(Ordering::Relaxed) as usize] {
+        // Progress bars only work on terminals that can move the cursor around; anywhere else, such
+        // as a CI log, they make a mess.
+        "auto" if io::stderr().is_terminal() && env::var_os("TERM").is_none_or(|term| term != "dumb") => "bar",
+        "auto" => "plain",
+        style => style,
+    }
+}
+
+/// Whether to colour output going to a stream, given whether that stream is a terminal.
+fn use_color(is_terminal: bool) -> bool
+{
+    match COLOR_CHOICES[COLOR_CHOICE.load(Ordering::Relaxed) as usize] {
+        "always" => true,
+        "never" => false,
+        // See https://no-color.org; terminals that can't do colour say so with TERM=dumb.
+        _ => is_terminal &&
+            env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()) &&
+            env::var_os("TERM").is_none_or(|term| term != "dumb"),
+    }
+}
+
+/// Whether to colour output going to stdout.
+pub fn stdout_color() -> bool
+{
+    use_color(io::stdout().is_terminal())
+}
+
+/// Whether to colour output going to stderr, such as warnings.
+pub fn stderr_color() -> bool
+{
+    use_color(io::stderr().is_terminal())
+}
+
+/// The [`ColorChoice`] for writing to stdout (or stderr, with `stderr` set) with termcolor.
+pub fn color_choice(stderr: bool) -> ColorChoice
+{
+    let color = if stderr { stderr_color() } else { stdout_color() };
+    if color { ColorChoice::Always } else { ColorChoice::Never }
+}
+
+/// Returns `text` in the given style (see [`style`]), if status messages are being coloured.
+pub fn paint<T: Display>(text: T, style: Style) -> String
+{
+    let color = if json_output() { stderr_color() } else { stdout_color() };
+    if color {
+        format!("{}{}{}", style.render(), text, style.render_reset())
+    } else {
+        text.to_string()
+    }
+}
+
+/// The kinds of annotation GitHub Actions shows in a workflow run's summary.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Annotation
+{
+    Error,
+    Warning,
+    Notice,
+}
+
+/// Whether we're running under GitHub Actions, which picks annotations out of what we print.
+pub fn github_actions() -> bool
+{
+    env::var_os("GITHUB_ACTIONS").is_some_and(|value| value == "true")
+}
+
+/// Prints `message` as an annotation for GitHub Actions to show in the workflow summary, if we're
+/// running under it.
+pub fn annotate<M: Display>(kind: Annotation, message: M)
+{
+    if !github_actions() {
+        return;
+    }
+
+    let command = match kind {
+        Annotation::Error => "error",
+        Annotation::Warning => "warning",
+        Annotation::Notice => "notice",
+    };
+    // Workflow commands are a line each, so the message has to be escaped to fit on one.
+    let message = message
+        .to_string()
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A");
+    if json_output() {
+        eprintln!("::{}::{}", command, message);
+    } else {
+        println!("::{}::{}", command, message);
+    }
+}
+
+/// Prints `document` to stdout as the JSON result of the command.
+pub fn print_json<T: Serialize>(document: &T)
+{
+    let json = serde_json::to_string_pretty(document)
+        .expect("Serializing command results cannot fail");
+    println!("{}", json);
+    DOCUMENT_PRINTED.store(true, Ordering::Relaxed);
+}
+
+/// Whether the command has printed its JSON document already.
+pub fn document_printed() -> bool
+{
+    DOCUMENT_PRINTED.load(Ordering::Relaxed)
+}
+
+/// The JSON document for commands that have nothing more to say than whether they worked.
+#[derive(Debug, Clone, Serialize)]
+pub struct Outcome
+{
+    pub ok: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Prints a status message for humans: to stdout, unless that's being kept for JSON output.
+#[macro_export]
+macro_rules! status
+{
+    ($($arg:tt)*) => {
+        if $crate::output::terminal_taken() {
+            // Whatever has the terminal shows what's going on itself.
+        } else if $crate::output::json_output() {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Where an operation on a probe reports its status messages and progress to.
+#[derive(Debug, Clone, Default)]
+pub struct Reporter
+{
+    /// Set when several operations are running at once, so their progress bars don't fight.
+    multi: Option<MultiProgress>,
+
+    /// The probe this operation is on, when several are running at once.
+    label: Option<String>,
+
+    /// Set when the dashboard shows the operation's status, instead of it being printed.
+    job: Option<Arc<Mutex<JobStatus>>>,
+}
+
+/// How an operation the dashboard is showing is getting on.
+#[derive(Debug, Clone, Default)]
+pub struct JobStatus
+{
+    /// The last status message.
+    pub message: String,
+
+    /// The progress of the current transfer, if there is one.
+    pub progress: Option<ProgressBar>,
+}
+
+impl Reporter
+{
+    /// A reporter for one of several operations running at once, labelling its output with `label`.
+    pub fn for_job(multi: &MultiProgress, label: String) -> Self
+    {
+        Self {
+            multi: Some(multi.clone()),
+            label: Some(label),
+            job: None,
+        }
+    }
+
+    /// A reporter for an operation the dashboard is showing, which keeps `job` up to date.
+    pub fn for_dashboard(job: Arc<Mutex<JobStatus>>) -> Self
+    {
+        Self {
+            multi: None,
+            label: None,
+            job: Some(job),
+        }
+    }
+
+    /// Whether this operation shares the terminal with others running at once, or with the dashboard,
+    /// so mustn't ask the user anything: its questions and their answers would get mixed up.
+    pub fn shares_terminal(&self) -> bool
+    {
+        self.multi.is_some() || self.job.is_some()
+    }
+
+    /// Whether this operation can ask the user questions (see [`prompt::can_prompt()`]).
+    pub fn can_prompt(&self) -> bool
+    {
+        !self.shares_terminal() && prompt::can_prompt()
+    }
+
+    /// Returns `message`, labelled with which probe it's about if need be.
+    pub fn labelled<M: Display>(&self, message: M) -> String
+    {
+        match &self.label {
+            Some(label) => format!("[{}] {}", label, message),
+            None => message.to_string(),
+        }
+    }
+
+    /// Prints a status message.
+    pub fn println<M: Display>(&self, message: M)
+    {
+        let message = self.labelled(message);
+        if let Some(job) = &self.job {
+            job.lock().unwrap().message = message;
+            return;
+        }
+        match &self.multi {
+            // If stdout isn't a terminal, MultiProgress won't print anything, so fall back to printing ourselves.
+            Some(multi) if !multi.is_hidden() => {
+                let _ = multi.println(message);
+            },
+            _ => status!("{}", message),
+        }
+    }
+
+    /// Creates a progress bar for transferring `length` bytes.
+    ///
+    /// Unless progress is to be shown as a bar (see `--progress`), this is hidden, and with plain
+    /// progress, a line saying how far it's got is printed every so often instead. It's also hidden
+    /// when the dashboard is showing the progress itself.
+    pub fn progress_bar(&self, length: u64) -> ProgressBar
+    {
+        let style = progress_style();
+        if self.job.is_some() || style != "bar" {
+            let progress_bar = ProgressBar::hidden();
+            progress_bar.set_length(length);
+            if let Some(job) = &self.job {
+                job.lock().unwrap().progress = Some(progress_bar.clone());
+            } else if style == "plain" {
+                self.print_progress_periodically(&progress_bar);
+            }
+            return progress_bar;
+        }
+
+        // Default template: `{wide_bar} {pos}/{len}`.
+        let template = match self.label {
+            Some(_) => "{prefix} {spinner} {percent:>3}% |{bar:30}| {bytes}/{total_bytes} [{binary_bytes_per_sec}, ETA {eta}]",
+            None => "{spinner} {percent:>3}% |{bar:50}| {bytes}/{total_bytes} [{binary_bytes_per_sec} {elapsed}, ETA {eta}]",
+        };
+        let progress_bar = ProgressBar::new(length)
+            .with_style(ProgressStyle::default_bar().template(template).unwrap());
+        if let Some(label) = &self.label {
+            progress_bar.set_prefix(format!("[{}]", label));
+        }
+        let progress_bar = match &self.multi {
+            Some(multi) => multi.add(progress_bar),
+            None => progress_bar,
+        };
+
+        // Keep the spinner and elapsed time moving while the device erases flash, so it doesn't look stuck.
+        progress_bar.enable_steady_tick(Duration::from_millis(100));
+
+        progress_bar
+    }
+
+    /// Prints a line saying how far `progress_bar` has got every [`PLAIN_PROGRESS_INTERVAL`], until
+    /// it's finished (or dropped).
+    fn print_progress_periodically(&self, progress_bar: &ProgressBar)
+    {
+        let reporter = self.clone();
+        let progress_bar = progress_bar.downgrade();
+        thread::spawn(move || {
+            let mut last_printed = Instant::now();
+            loop {
+                thread::sleep(Duration::from_millis(100));
+                let Some(progress_bar) = progress_bar.upgrade() else {
+                    break;
+                };
+                if progress_bar.is_finished() {
+                    break;
+                }
+                // Don't say anything while the device is still erasing.
+                if last_printed.elapsed() < PLAIN_PROGRESS_INTERVAL || progress_bar.position() == 0 {
+                    continue;
+                }
+
+                let length = progress_bar.length().unwrap_or(0).max(1);
+                reporter.println(format!(
+                    "{:>3}% ({}/{}, {}/s, ETA {})",
+                    progress_bar.position() * 100 / length,
+                    HumanBytes(progress_bar.position()),
+                    HumanBytes(length),
+                    HumanBytes(progress_bar.per_sec() as u64),
+                    HumanDuration(progress_bar.eta()),
+                ));
+                last_printed = Instant::now();
+            }
+        });
+    }
+}
@@ -0,0 +1,122 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+//! Module for project firmware pin files, which say what firmware a project's probes should run.
+//!
+//! A pin file is a `bmp-firmware.toml` in the working directory or any directory above it, e.g.:
+//!
+//! ```toml
+//! # The firmware release all probes should run...
+//! version = "v1.10.2"
+//!
+//! # ...except for these kinds of hardware.
+//! [hardware]
+//! stlink = "v1.9.2"
+//! ```
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tracing::debug;
+use serde::Deserialize;
+
+use crate::bmp::{self, ProbeHardware};
+use crate::error::{Error, ErrorKind};
+use crate::version::FirmwareVersion;
+
+/// The name of pin files.
+pub const FILE_NAME: &str = "bmp-firmware.toml";
+
+/// A pin file, as written.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PinFile
+{
+    version: Option<String>,
+    hardware: BTreeMap<String, String>,
+}
+
+/// The firmware versions a project's probes are pinned to.
+#[derive(Debug, Clone)]
+pub struct FirmwarePin
+{
+    /// Where the pins came from.
+    path: PathBuf,
+
+    /// The version all probes are pinned to, unless their hardware has its own pin.
+    version: Option<FirmwareVersion>,
+
+    /// The versions probes of particular kinds of hardware are pinned to.
+    hardware: Vec<(ProbeHardware, FirmwareVersion)>,
+}
+
+impl FirmwarePin
+{
+    /// Finds and loads the pin file in the working directory or the nearest directory above it that
+    /// has one, if there is one.
+    pub fn find() -> Result<Option<Self>, Error>
+    {
+        let cwd = match env::current_dir() {
+            Ok(cwd) => cwd,
+            Err(e) => {
+                debug!("Can't tell the working directory, so not looking for a pin file: {}", e);
+                return Ok(None);
+            },
+        };
+
+        cwd.ancestors()
+            .map(|dir| dir.join(FILE_NAME))
+            .find(|path| path.is_file())
+            .map(|path| Self::load(&path))
+            .transpose()
+    }
+
+    /// Loads the pin file at `path`.
+    fn load(path: &Path) -> Result<Self, Error>
+    {
+        debug!("Loading firmware pins from {}", path.display());
+        let error = |why| ErrorKind::PinFile(path.display().to_string(), why);
+
+        let contents = fs::read_to_string(path).map_err(|e| error(None).error_from(e))?;
+        let file: PinFile = toml::from_str(&contents).map_err(|e| error(None).error_from(e))?;
+
+        let parse_version = |version: &str| {
+            version.parse::<FirmwareVersion>()
+                .map_err(|why| error(Some(why)).error())
+        };
+        let version = file.version.as_deref().map(parse_version).transpose()?;
+        let hardware = file.hardware
+            .iter()
+            .map(|(hardware, version)| {
+                let hardware = bmp::parse_hardware(hardware).map_err(|why| error(Some(why)).error())?;
+                Ok((hardware, parse_version(version)?))
+            })
+            .collect::<Result<_, Error>>()?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            version,
+            hardware,
+        })
+    }
+
+    /// Where the pins came from.
+    pub fn path(&self) -> &Path
+    {
+        &self.path
+    }
+
+    /// The version probes of `hardware` are pinned to, if they are.
+    pub fn version_for(&self, hardware: Option<ProbeHardware>) -> Option<&FirmwareVersion>
+    {
+        hardware
+            .and_then(|hardware| {
+                self.hardware
+                    .iter()
+                    .find(|(pinned, _version)| *pinned == hardware)
+                    .map(|(_hardware, version)| version)
+            })
+            .or(self.version.as_ref())
+    }
+}
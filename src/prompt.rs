@@ -0,0 +1,97 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+//! Helpers for asking the user questions on the terminal.
+
+use std::env;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{output, tr};
+
+/// Whether prompting has been turned off, with `--non-interactive` or by running under CI.
+static NON_INTERACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Turns off all prompts, menus and confirmation questions for the rest of the run.
+pub fn set_non_interactive(non_interactive: bool)
+{
+    NON_INTERACTIVE.store(non_interactive, Ordering::Relaxed);
+}
+
+/// Guesses whether we're running under a CI system, from the `CI` environment variable they all set.
+///
+/// CI jobs sometimes get a pseudo-terminal for stdin, and a prompt there would hang the job until it
+/// times out, so this is worth treating like `--non-interactive`.
+pub fn running_in_ci() -> bool
+{
+    env::var("CI").is_ok_and(|ci| !ci.is_empty() && ci != "0" && !ci.eq_ignore_ascii_case("false"))
+}
+
+/// Returns whether we're able to ask the user anything at all (i.e. prompting hasn't been turned off,
+/// stdin is a terminal, and stdout isn't being kept for JSON output).
+pub fn can_prompt() -> bool
+{
+    !NON_INTERACTIVE.load(Ordering::Relaxed) && io::stdin().is_terminal() && !output::json_output()
+}
+
+/// Reads a single trimmed line from stdin, returning `None` on EOF.
+fn read_answer() -> io::Result<Option<String>>
+{
+    let mut line = String::new();
+    if io::stdin().lock().read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some(line.trim().to_string()))
+}
+
+/// Presents `options` to the user as a numbered menu and asks them to choose one.
+///
+/// Returns the index of the chosen option, or `None` if the user declined to choose
+/// (by entering an empty line or closing stdin).
+pub fn pick<S: AsRef<str>>(question: &str, options: &[S]) -> io::Result<Option<usize>>
+{
+    for (index, option) in options.iter().enumerate() {
+        println!("  [{}] {}", index, option.as_ref());
+    }
+
+    loop {
+        print!("{} ", tr!("prompt-pick", question = question, last = options.len().saturating_sub(1)));
+        io::stdout().flush()?;
+
+        let answer = match read_answer()? {
+            Some(answer) if !answer.is_empty() => answer,
+            _ => return Ok(None),
+        };
+
+        match answer.parse::<usize>() {
+            Ok(choice) if choice < options.len() => return Ok(Some(choice)),
+            _ => println!("{}", tr!("prompt-pick-invalid", answer = format!("{:?}", answer))),
+        }
+    }
+}
+
+/// Asks the user a yes/no question, defaulting to no.
+pub fn confirm(question: &str) -> io::Result<bool>
+{
+    print!("{} ", tr!("prompt-confirm", question = question));
+    io::stdout().flush()?;
+
+    // English answers are always understood, in case the user doesn't expect a translated prompt.
+    let answer = read_answer()?.unwrap_or_default().to_lowercase();
+    let yes_answers = tr!("prompt-yes-answers");
+    let yes = ["y", "yes"]
+        .into_iter()
+        .chain(yes_answers.split(',').map(str::trim))
+        .any(|yes| answer == yes.to_lowercase());
+
+    Ok(yes)
+}
+
+/// Asks the user to confirm something by typing out `expected` exactly.
+pub fn confirm_typed(question: &str, expected: &str) -> io::Result<bool>
+{
+    print!("{} ", tr!("prompt-confirm-typed", question = question, expected = expected));
+    io::stdout().flush()?;
+
+    Ok(read_answer()?.as_deref() == Some(expected))
+}
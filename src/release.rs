@@ -0,0 +1,409 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+//! Module for finding official Black Magic Debug firmware releases, as published on GitHub.
+
+use std::fmt::{self, Display, Formatter};
+
+use clap::ArgMatches;
+use tracing::{debug, info, warn};
+use serde::Deserialize;
+
+use crate::S;
+use crate::bmp::ProbeHardware;
+use crate::cache;
+use crate::config::Config;
+use crate::error::{Error, ErrorKind};
+use crate::fetch;
+use crate::signature;
+use crate::version::FirmwareVersion;
+
+/// The GitHub API endpoint listing the Black Magic Debug firmware releases.
+const RELEASES_API: &str = "https://api.github.com/repos/blackmagic-debug/blackmagic/releases";
+
+/// The GitHub API endpoint listing bmputil's own releases.
+const BMPUTIL_RELEASES_API: &str = "https://api.github.com/repos/blackmagic-debug/bmputil/releases";
+
+/// A published firmware release.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Release
+{
+    /// The release's tag, e.g. `v1.10.2`.
+    pub tag_name: String,
+
+    /// When the release was published, as an RFC 3339 timestamp.
+    pub published_at: Option<String>,
+
+    pub prerelease: bool,
+    pub draft: bool,
+
+    /// The release notes, in Markdown.
+    pub body: Option<String>,
+
+    /// The files published with the release.
+    pub assets: Vec<Asset>,
+}
+
+/// Which firmware builds to update to.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel
+{
+    /// Stable releases only.
+    #[default]
+    Stable,
+    /// Release candidates and other pre-releases, as well as stable releases.
+    Prerelease,
+    /// Development builds, from the nightly build feed set in the configuration file.
+    Nightly,
+}
+
+impl Channel
+{
+    /// The names of the channels, as given to `--channel`.
+    pub const CLI_NAMES: [&'static str; 3] = ["stable", "prerelease", "nightly"];
+
+    /// Returns the channel with the given `--channel` name, one of [`Channel::CLI_NAMES`].
+    pub fn from_cli_name(name: &str) -> Option<Self>
+    {
+        match name {
+            "stable" => Some(Channel::Stable),
+            "prerelease" => Some(Channel::Prerelease),
+            "nightly" => Some(Channel::Nightly),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Channel
+{
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result
+    {
+        match self {
+            Channel::Stable => write!(f, "stable"),
+            Channel::Prerelease => write!(f, "pre-release"),
+            Channel::Nightly => write!(f, "nightly"),
+        }
+    }
+}
+
+/// A file published with a release.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Asset
+{
+    pub name: String,
+    pub browser_download_url: String,
+}
+
+impl Release
+{
+    /// The firmware version this release is of, if its tag is a valid version.
+    pub fn version(&self) -> Option<FirmwareVersion>
+    {
+        self.tag_name.parse().ok()
+    }
+
+    /// The date the release was published, e.g. `2024-05-01`, if GitHub says.
+    pub fn date(&self) -> Option<&str>
+    {
+        self.published_at.as_deref().and_then(|timestamp| timestamp.split('T').next())
+    }
+
+    /// Finds the checksum manifest among this release's files, if it was published with one.
+    pub fn checksums_asset(&self) -> Option<&Asset>
+    {
+        self.assets
+            .iter()
+            .find(|asset| ["SHA256SUMS", "SHA256SUMS.txt"].iter().any(|name| asset.name.eq_ignore_ascii_case(name)))
+    }
+
+    /// Finds the minisign signature of `asset` among this release's files, if it was published with one.
+    pub fn signature_asset(&self, asset: &Asset) -> Option<&Asset>
+    {
+        let name = format!("{}.minisig", asset.name);
+        self.assets.iter().find(|candidate| candidate.name == name)
+    }
+
+    /// Finds the firmware for `hardware` among this release's files, preferring the plain build over
+    /// any with extra features (e.g. `blackmagic-native-rtt-v1.10.2.elf`), then ELF files (which say
+    /// where they go) over raw binaries.
+    pub fn firmware_asset(&self, hardware: ProbeHardware) -> Option<&Asset>
+    {
+        self.assets
+            .iter()
+            .filter_map(|asset| {
+                // Releases have used both `-` and `_` as separators, so don't mind which.
+                let name = asset.name.to_ascii_lowercase().replace('_', "-");
+                let rest = asset_names(hardware)
+                    .iter()
+                    .find_map(|variant| name.strip_prefix(&format!("blackmagic-{}", variant)))?;
+                // Make sure e.g. `stlink` doesn't also match `stlinkv3`, which is different hardware.
+                if !rest.starts_with(['-', '.']) || rest.contains("bootloader") {
+                    return None;
+                }
+
+                let (qualifier, extension_rank) = if let Some(qualifier) = rest.strip_suffix(".elf") {
+                    (qualifier, 0)
+                } else if let Some(qualifier) = rest.strip_suffix(".bin") {
+                    (qualifier, 1)
+                } else {
+                    return None;
+                };
+                // The plain build has nothing but the version (if that) between the variant and extension.
+                let qualifier = qualifier.trim_start_matches('-');
+                let qualifier = qualifier.strip_prefix('v').unwrap_or(qualifier);
+                let is_plain = qualifier.is_empty() || qualifier.starts_with(|c: char| c.is_ascii_digit());
+
+                Some(((!is_plain, extension_rank), asset))
+            })
+            .min_by_key(|(rank, _asset)| *rank)
+            .map(|(_rank, asset)| asset)
+    }
+}
+
+/// The names the release files for `hardware` have been given, the current one first.
+fn asset_names(hardware: ProbeHardware) -> &'static [&'static str]
+{
+    match hardware {
+        ProbeHardware::Native => &["native"],
+        ProbeHardware::Stlink => &["stlink", "st-link"],
+        ProbeHardware::Swlink => &["swlink"],
+        ProbeHardware::BlackpillF401CC => &["blackpill-f401cc"],
+        ProbeHardware::BlackpillF401CE => &["blackpill-f401ce"],
+        ProbeHardware::BlackpillF411CE => &["blackpill-f411ce"],
+        ProbeHardware::F4Discovery => &["f4discovery", "f4-discovery"],
+        ProbeHardware::CtxLink => &["ctxlink"],
+    }
+}
+
+/// The name the release files for `hardware` are given.
+pub fn asset_name(hardware: ProbeHardware) -> &'static str
+{
+    asset_names(hardware)[0]
+}
+
+/// Where firmware releases are looked up: normally GitHub (or a mirror of it), but with `--offline`,
+/// only what has been cached from earlier lookups.
+#[derive(Debug, Clone)]
+pub struct ReleaseIndex
+{
+    /// The GitHub API endpoint listing the releases.
+    api: String,
+    /// Prefixed to the names release information is cached under, so different projects' (and
+    /// mirrors') don't mix.
+    cache_prefix: String,
+    offline: bool,
+    require_signed: bool,
+    nightly_feed: Option<String>,
+    signing_keys: Vec<String>,
+}
+
+impl ReleaseIndex
+{
+    pub(crate) fn from_cli_args(matches: &ArgMatches, config: &Config) -> Self
+    {
+        let mirror = matches.try_get_one::<String>("mirror").ok().flatten().or(config.mirror.as_ref());
+        let api = mirror.map_or_else(|| S!(RELEASES_API), |mirror| mirror.trim_end_matches('/').to_string());
+        // What one mirror says the releases are needn't be what GitHub (or another mirror) does.
+        let cache_prefix = if api == RELEASES_API {
+            String::new()
+        } else {
+            format!("mirror-{}-", &fetch::sha256_hex(api.as_bytes())[..16])
+        };
+        Self {
+            api,
+            cache_prefix,
+            offline: matches.try_get_one::<bool>("offline").ok().flatten().copied().unwrap_or(false),
+            require_signed: config.require_signed,
+            nightly_feed: config.nightly_feed.clone(),
+            signing_keys: config.signing_keys.clone(),
+        }
+    }
+
+    /// Looks up bmputil's own releases instead of firmware releases, for updating bmputil itself.
+    pub fn for_bmputil(self) -> Self
+    {
+        Self {
+            api: S!(BMPUTIL_RELEASES_API),
+            cache_prefix: S!("bmputil-"),
+            ..self
+        }
+    }
+
+    /// Whether only cached releases and firmware can be used.
+    pub fn is_offline(&self) -> bool
+    {
+        self.offline
+    }
+
+    /// Fetches and parses the release (or list of releases) at `url`, caching it as `name`, or just
+    /// uses the cached copy if offline.
+    fn fetch_json<T>(&self, url: &str, name: &str, what: &str) -> Result<T, Error>
+    where
+        T: for<'de> Deserialize<'de>,
+    {
+        if self.offline {
+            let data = self.load_index(name).ok_or_else(|| ErrorKind::NotCached(what.to_string()).error())?;
+            return parse_json(url, &data);
+        }
+
+        let data = fetch::fetch(url)?;
+        let parsed = parse_json(url, &data)?;
+        cache::store_index(&format!("{}{}", self.cache_prefix, name), &data);
+        Ok(parsed)
+    }
+
+    /// Returns the cached copy of the release information `name`, if there is one.
+    fn load_index(&self, name: &str) -> Option<Vec<u8>>
+    {
+        cache::load_index(&format!("{}{}", self.cache_prefix, name))
+    }
+
+    /// Fetches the release tagged `tag`. The leading `v` of version tags can be left off.
+    pub fn by_tag(&self, tag: &str) -> Result<Release, Error>
+    {
+        let tag = if tag.starts_with(|c: char| c.is_ascii_digit()) {
+            format!("v{}", tag)
+        } else {
+            tag.to_string()
+        };
+
+        debug!("Looking up firmware release {}", tag);
+        let url = format!("{}/tags/{}", self.api, tag);
+        let name = format!("tag-{}", tag);
+        if self.offline {
+            // It may have been looked up on its own before, or be in a cached list of releases.
+            if let Some(data) = self.load_index(&name) {
+                return parse_json(&url, &data);
+            }
+            return self.releases()?
+                .into_iter()
+                .find(|release| release.tag_name == tag)
+                .ok_or_else(|| ErrorKind::NotCached(format!("firmware release {}", tag)).error());
+        }
+
+        match fetch::fetch_optional(&url)? {
+            Some(data) => {
+                let release = parse_json(&url, &data)?;
+                cache::store_index(&format!("{}{}", self.cache_prefix, name), &data);
+                Ok(release)
+            },
+            None => Err(ErrorKind::UnknownRelease(tag).error()),
+        }
+    }
+
+    /// Checks `data`, the release file `asset` of `release` for hardware `variant`, against the checksum
+    /// manifest published with the release, if there is one. If it doesn't match, it's also removed from
+    /// the cache, so it's downloaded again next time. Returns whether there was a checksum to check.
+    pub fn verify_checksum(&self, release: &Release, variant: &str, asset: &Asset, data: &[u8]) -> Result<bool, Error>
+    {
+        let manifest_asset = match release.checksums_asset() {
+            Some(manifest_asset) => manifest_asset,
+            None => {
+                warn!("Release {} has no checksum manifest; not verifying {}", release.tag_name, asset.name);
+                return Ok(false);
+            },
+        };
+
+        let manifest = self.fetch_asset(release, variant, manifest_asset)?;
+        let manifest = String::from_utf8_lossy(&manifest);
+        let expected = match fetch::checksum_for(&manifest, &asset.name) {
+            Some(expected) => expected,
+            None => {
+                warn!("{} of release {} doesn't list {}; not verifying it", manifest_asset.name, release.tag_name, asset.name);
+                return Ok(false);
+            },
+        };
+
+        fetch::verify_sha256(data, expected, &asset.name)
+            .inspect_err(|_| cache::remove(&release.tag_name, variant, &asset.name))?;
+        info!("{} matches the checksum in {}", asset.name, manifest_asset.name);
+        Ok(true)
+    }
+
+    /// Checks `data`, the release file `asset` of `release` for hardware `variant`, against the signature
+    /// published with it, if there is one. Unsigned files, and ones signed by keys we don't know, are only
+    /// refused if signatures are required. Returns whether the signature was checked.
+    pub fn verify_signature(&self, release: &Release, variant: &str, asset: &Asset, data: &[u8]) -> Result<bool, Error>
+    {
+        let signature_asset = match release.signature_asset(asset) {
+            Some(signature_asset) => signature_asset,
+            None if self.require_signed => return Err(ErrorKind::Unsigned(asset.name.clone()).error()),
+            None => {
+                debug!("{} of release {} isn't signed", asset.name, release.tag_name);
+                return Ok(false);
+            },
+        };
+
+        let signature = self.fetch_asset(release, variant, signature_asset)?;
+        signature::verify(data, &signature, &self.signing_keys, self.require_signed, &asset.name)
+            .inspect_err(|_| cache::remove(&release.tag_name, variant, &asset.name))
+    }
+
+    /// Fetches the small supplementary release file `asset` (e.g. a checksum manifest) of `release`, for
+    /// hardware `variant`, from the cache if it's there.
+    fn fetch_asset(&self, release: &Release, variant: &str, asset: &Asset) -> Result<Vec<u8>, Error>
+    {
+        if let Some(data) = cache::load(&release.tag_name, variant, &asset.name) {
+            return Ok(data);
+        }
+        if self.offline {
+            return Err(ErrorKind::NotCached(format!("{} from release {}", asset.name, release.tag_name)).error());
+        }
+
+        let data = fetch::fetch(&asset.browser_download_url)?;
+        cache::store(&release.tag_name, variant, &asset.name, &data);
+        Ok(data)
+    }
+
+    /// Fetches the published firmware releases (not including drafts), newest first.
+    pub fn releases(&self) -> Result<Vec<Release>, Error>
+    {
+        let releases: Vec<Release> = self.fetch_json(&self.api, "releases", "the list of releases")?;
+        Ok(releases.into_iter().filter(|release| !release.draft).collect())
+    }
+
+    /// Fetches the latest firmware release on `channel`. Nightly builds come from the configured nightly
+    /// build feed, which gives the latest build in the same form as the GitHub API does a release.
+    pub fn latest(&self, channel: Channel) -> Result<Release, Error>
+    {
+        debug!("Looking up the latest {} release from {}", channel, self.api);
+        match channel {
+            // Offline, the cached list of releases may be more recent than the cached latest release.
+            Channel::Stable if self.offline && self.load_index("releases").is_some() => {
+                self.newest(self.releases()?.into_iter().filter(|release| !release.prerelease))
+            },
+            Channel::Stable => self.fetch_json(
+                &format!("{}/latest", self.api),
+                "latest",
+                "the latest release",
+            ),
+            // GitHub's idea of the latest release skips pre-releases, so find it ourselves.
+            Channel::Prerelease => self.newest(self.releases()?.into_iter()),
+            Channel::Nightly => match &self.nightly_feed {
+                Some(feed) => self.fetch_json(feed, "nightly", "the latest nightly build"),
+                None => Err(ErrorKind::Unsupported(S!(
+                    "no nightly build feed is configured; set nightly_feed in the configuration file"
+                )).error()),
+            },
+        }
+    }
+
+    /// The newest of `releases` by version.
+    fn newest(&self, releases: impl Iterator<Item = Release>) -> Result<Release, Error>
+    {
+        releases
+            .filter_map(|release| release.version().map(|version| (version, release)))
+            .max_by(|(a, _), (b, _)| a.cmp(b))
+            .map(|(_version, release)| release)
+            .ok_or_else(|| ErrorKind::ReleaseIndex(self.api.clone()).error())
+    }
+}
+
+/// Parses the release (or list of releases) `data`, fetched from `url`.
+fn parse_json<T>(url: &str, data: &[u8]) -> Result<T, Error>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    serde_json::from_slice(data)
+        .map_err(|e| ErrorKind::ReleaseIndex(url.to_string()).error_from(e))
+}
@@ -0,0 +1,76 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+//! Module for retrying USB operations that fail for transient reasons.
+//!
+//! Flaky hubs, long cables and busy host controllers can make a single control transfer fail now
+//! and then, which shouldn't be enough to fail a whole flash.
+
+use std::thread;
+use std::time::Duration;
+
+use tracing::warn;
+
+use crate::error::Error;
+
+/// How many times to try USB operations that fail for transient reasons.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RetryPolicy
+{
+    /// The total number of attempts made, including the first.
+    attempts: u32,
+}
+
+impl RetryPolicy
+{
+    /// The delay before the first retry, doubling for each one after that.
+    const INITIAL_DELAY: Duration = Duration::from_millis(100);
+    /// The most we'll wait between attempts.
+    const MAX_DELAY: Duration = Duration::from_secs(2);
+
+    /// A policy of making `attempts` attempts in total (at least one).
+    pub fn new(attempts: u32) -> Self
+    {
+        Self {
+            attempts: attempts.max(1),
+        }
+    }
+
+    /// Runs `operation`, retrying it with exponential backoff for as long as it fails with a transient
+    /// USB error, up to the number of attempts allowed. Other errors are returned immediately.
+    ///
+    /// `what` describes the operation, for the warnings about retrying it.
+    pub fn run<T, F>(&self, what: &str, mut operation: F) -> Result<T, Error>
+    where
+        F: FnMut() -> Result<T, Error>,
+    {
+        let mut delay = Self::INITIAL_DELAY;
+        let mut attempt = 1;
+        loop {
+            match operation() {
+                Err(e) if e.is_transient_usb() && attempt < self.attempts => {
+                    warn!(
+                        "{} failed ({}); retrying in {} ms (attempt {} of {})",
+                        what,
+                        e.kind,
+                        delay.as_millis(),
+                        attempt + 1,
+                        self.attempts,
+                    );
+                    thread::sleep(delay);
+                    delay = (delay * 2).min(Self::MAX_DELAY);
+                    attempt += 1;
+                },
+                other => return other,
+            }
+        }
+    }
+}
+
+/// Defaults to 3 attempts.
+impl Default for RetryPolicy
+{
+    fn default() -> Self
+    {
+        Self::new(3)
+    }
+}
@@ -0,0 +1,59 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+//! Module for assembling firmware that comes in pieces at different addresses (as in Intel HEX,
+//! DfuSe, and ELF files) into one flat image.
+
+use crate::S;
+use crate::error::{Error, ErrorKind};
+
+/// The largest gap between segments we'll fill in. Anything bigger is more likely to be a segment
+/// meant for some other memory entirely (e.g. RAM or option bytes) than padding.
+const MAX_GAP: u32 = 64 * 1024;
+
+/// A piece of firmware and the address it goes at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment
+{
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+/// Assembles `segments` into one contiguous image, filling any gaps between them with erased flash
+/// (`0xff`). Returns the address the image starts at, and the image.
+///
+/// Overlapping segments, and gaps of more than 64 KiB, are errors.
+pub fn assemble(mut segments: Vec<Segment>) -> Result<(u32, Vec<u8>), Error>
+{
+    segments.retain(|segment| !segment.data.is_empty());
+    segments.sort_by_key(|segment| segment.address);
+
+    let start = segments
+        .first()
+        .ok_or_else(|| ErrorKind::InvalidFirmware(Some(S!("no data"))).error())?
+        .address;
+
+    let mut image: Vec<u8> = Vec::new();
+    for segment in segments {
+        let end = start as u64 + image.len() as u64;
+        if (segment.address as u64) < end {
+            return Err(ErrorKind::InvalidFirmware(Some(format!(
+                "data at 0x{:08x} overlaps the data before it",
+                segment.address,
+            ))).error());
+        }
+
+        let gap = (segment.address as u64 - end) as u32;
+        if gap > MAX_GAP {
+            return Err(ErrorKind::InvalidFirmware(Some(format!(
+                "data at 0x{:08x} is {} bytes past the data before it; is it meant for another memory?",
+                segment.address,
+                gap,
+            ))).error());
+        }
+
+        image.resize(image.len() + gap as usize, 0xff);
+        image.extend_from_slice(&segment.data);
+    }
+
+    Ok((start, image))
+}
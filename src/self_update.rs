@@ -0,0 +1,129 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+//! Module for updating bmputil itself, for those who installed a standalone binary of it.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use tracing::{debug, warn};
+
+use crate::error::{Error, ErrorKind, ErrorSource};
+use crate::fetch;
+use crate::status;
+use crate::release::{Asset, Release, ReleaseIndex};
+use crate::version::FirmwareVersion;
+
+/// What bmputil's own release files are cached under, in place of a probe's hardware variant.
+const VARIANT: &str = "bmputil";
+
+/// The version of bmputil that's running.
+pub fn current_version() -> Option<FirmwareVersion>
+{
+    env!("CARGO_PKG_VERSION").parse().ok()
+}
+
+/// The names this platform's operating system and architecture might go by in release file names.
+fn platform_names() -> (&'static [&'static str], &'static [&'static str])
+{
+    let os: &'static [&'static str] = match env::consts::OS {
+        "linux" => &["linux"],
+        "windows" => &["windows", "win"],
+        "macos" => &["macos", "darwin", "apple"],
+        "freebsd" => &["freebsd"],
+        _ => &[],
+    };
+    let arch: &'static [&'static str] = match env::consts::ARCH {
+        "x86_64" => &["x86_64", "x86-64", "amd64", "x64"],
+        "aarch64" => &["aarch64", "arm64"],
+        "x86" => &["i686", "i386", "x86"],
+        "arm" => &["armv7", "armhf", "arm"],
+        _ => &[],
+    };
+    (os, arch)
+}
+
+/// Finds the standalone bmputil executable for this platform among `release`'s files.
+pub fn executable_asset(release: &Release) -> Option<&Asset>
+{
+    let (os_names, arch_names) = platform_names();
+    release.assets
+        .iter()
+        .find(|asset| {
+            let name = asset.name.to_ascii_lowercase();
+            // Only bare executables will do; we've no way to unpack archives or installers.
+            let is_executable = if cfg!(windows) {
+                name.ends_with(".exe")
+            } else {
+                ![".exe", ".zip", ".gz", ".xz", ".tgz", ".msi", ".dmg", ".pkg", ".minisig", ".sha256", ".txt"]
+                    .iter()
+                    .any(|extension| name.ends_with(extension))
+            };
+            name.starts_with("bmputil") &&
+                is_executable &&
+                os_names.iter().any(|os| name.contains(os)) &&
+                arch_names.iter().any(|arch| name.contains(arch))
+        })
+}
+
+/// Downloads and verifies bmputil's executable from `release`, and replaces the running one with it.
+/// Returns where it was installed.
+pub fn install(index: &ReleaseIndex, release: &Release) -> Result<PathBuf, Error>
+{
+    let platform = format!("{}-{}", env::consts::OS, env::consts::ARCH);
+    let asset = executable_asset(release)
+        .ok_or_else(|| ErrorKind::NoReleaseForPlatform(release.tag_name.clone(), platform).error())?;
+
+    status!("Downloading {}...", asset.name);
+    let data = fetch::fetch_executable(&asset.browser_download_url)?;
+    // Unlike firmware, which the probe's bootloader checks is for it, nothing else stands between a bad
+    // download and running it, so it has to have been checked against something.
+    let checksummed = index.verify_checksum(release, VARIANT, asset, &data)?;
+    let signed = index.verify_signature(release, VARIANT, asset, &data)?;
+    if !checksummed && !signed {
+        return Err(ErrorKind::Unverified(asset.name.clone()).error());
+    }
+
+    let exe = env::current_exe()
+        .and_then(|exe| exe.canonicalize())
+        .map_err(|e| ErrorKind::External(ErrorSource::StdIo(e)).error().with_ctx("finding the bmputil executable"))?;
+    replace_executable(&exe, &data)
+        .map_err(|e| ErrorKind::External(ErrorSource::StdIo(e)).error().with_ctx("replacing the bmputil executable"))?;
+
+    Ok(exe)
+}
+
+/// Replaces the executable at `exe` with `data`, such that if anything goes wrong, the old one is left.
+fn replace_executable(exe: &Path, data: &[u8]) -> io::Result<()>
+{
+    // Write the new executable alongside the old one, so renaming it into place is atomic.
+    let mut temp_name = exe.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".new");
+    let temp_path = exe.with_file_name(temp_name);
+    debug!("Writing new executable to {}", temp_path.display());
+
+    let res = fs::write(&temp_path, data)
+        .and_then(|()| fs::metadata(exe))
+        .and_then(|metadata| fs::set_permissions(&temp_path, metadata.permissions()))
+        .and_then(|()| {
+            // Windows won't let a running executable be replaced, but it will let it be renamed.
+            if cfg!(windows) {
+                let old_path = exe.with_extension("old");
+                let _ = fs::remove_file(&old_path);
+                fs::rename(exe, &old_path)?;
+                // If the new one can't take its place, put the old one back rather than leave neither.
+                return fs::rename(&temp_path, exe).inspect_err(|_| {
+                    if let Err(e) = fs::rename(&old_path, exe) {
+                        warn!("Couldn't restore the old executable from {}: {}", old_path.display(), e);
+                    }
+                });
+            }
+            fs::rename(&temp_path, exe)
+        });
+
+    if res.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    res
+}
@@ -0,0 +1,233 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+//! Module for `bmputil setup`, which walks newcomers through getting from a plugged-in probe to an
+//! updated one: making sure bmputil can use the probe, choosing which firmware releases to follow,
+//! and updating the probe's firmware.
+//!
+//! The first time bmputil is run on a terminal, it offers to do this before anything else.
+
+use clap::ArgMatches;
+use tracing::debug;
+
+use crate::bmp::{BmpDevice, BmpMatcher, FirmwareType};
+use crate::config::Config;
+use crate::error::{Error, ErrorKind, ErrorSource, ExitStatus};
+use crate::output::{self, style, Reporter};
+use crate::release::{Channel, ReleaseIndex};
+#[cfg(target_os = "linux")]
+use crate::udev;
+#[cfg(target_os = "freebsd")]
+use crate::devd;
+use crate::{prompt, state, status, tr};
+
+fn io_error(e: std::io::Error) -> Error
+{
+    ErrorKind::External(ErrorSource::StdIo(e)).error()
+}
+
+fn heading(text: String)
+{
+    status!("\n{}", output::paint(text, style::HEADING));
+}
+
+/// Offers to run setup, the first time bmputil's run where it can ask. Returns the configuration to
+/// carry on with, which setup may have changed.
+pub fn offer_on_first_run(matches: &ArgMatches, config: Config) -> Result<Config, Error>
+{
+    if state::setup_offered() || !prompt::can_prompt() {
+        return Ok(config);
+    }
+    // Only ever ask once, whatever the answer.
+    state::set_setup_offered();
+
+    if !prompt::confirm(&tr!("setup-offer")).map_err(io_error)? {
+        status!("{}", tr!("setup-declined"));
+        return Ok(config);
+    }
+
+    run(matches, &config)?;
+    status!("\n{}", tr!("setup-continuing"));
+
+    Config::load()
+}
+
+/// Walks the user through setting bmputil up.
+pub fn run(matches: &ArgMatches, config: &Config) -> Result<(), Error>
+{
+    if !prompt::can_prompt() {
+        return Err(ErrorKind::Unsupported(tr!("setup-needs-terminal")).error());
+    }
+    state::set_setup_offered();
+
+    status!("{}", tr!("setup-welcome"));
+
+    heading(tr!("setup-access-heading"));
+    let probes = find_probes(config)?;
+
+    heading(tr!("setup-channel-heading"));
+    let channel = choose_channel(config)?;
+
+    heading(tr!("setup-update-heading"));
+    offer_updates(matches, config, channel, probes)?;
+
+    status!("\n{}", tr!("setup-done"));
+
+    Ok(())
+}
+
+/// Makes sure bmputil can see and use the user's probes, helping them sort out permissions or drivers
+/// if not.
+fn find_probes(config: &Config) -> Result<Vec<BmpDevice>, Error>
+{
+    let mut wait = false;
+    loop {
+        let results = BmpMatcher::new()
+            .custom_ids(config.usb_ids)
+            .wait(wait)
+            .find_matching_probes();
+
+        if !results.found.is_empty() {
+            for dev in &results.found {
+                status!("{}", tr!("status-found", probe = dev.summary()));
+            }
+            return Ok(results.found);
+        }
+
+        debug!("Setup found no usable probes; errors: {:?}", results.errors);
+        if results.errors.iter().any(|e| e.exit_status() == ExitStatus::PermissionDenied) {
+            explain_permissions();
+            #[cfg(target_os = "linux")]
+            if prompt::confirm(&tr!("setup-install-udev-rules")).map_err(io_error)? {
+                if let Err(e) = udev::install(&udev::Access::default(), config.usb_ids) {
+                    status!("{}", tr!("setup-udev-failed", error = e.to_string()));
+                }
+                continue;
+            }
+            #[cfg(target_os = "freebsd")]
+            if prompt::confirm(&tr!("setup-install-devd-rules")).map_err(io_error)? {
+                if let Err(e) = devd::install(devd::DEFAULT_GROUP, config.usb_ids) {
+                    status!("{}", tr!("setup-devd-failed", error = e.to_string()));
+                }
+                continue;
+            }
+            if !prompt::confirm(&tr!("setup-try-again")).map_err(io_error)? {
+                return Ok(Vec::new());
+            }
+            continue;
+        }
+
+        status!("{}", tr!("setup-no-probe"));
+        if !prompt::confirm(&tr!("setup-wait-for-probe")).map_err(io_error)? {
+            return Ok(Vec::new());
+        }
+        wait = true;
+    }
+}
+
+/// Says how to give bmputil permission to use probes, on this platform.
+fn explain_permissions()
+{
+    if cfg!(target_os = "linux") {
+        status!("{}", tr!("setup-permissions-linux"));
+    } else if cfg!(target_os = "freebsd") {
+        status!("{}", tr!("setup-permissions-freebsd"));
+    } else if cfg!(target_os = "openbsd") {
+        status!("{}", tr!("setup-permissions-openbsd"));
+    } else if cfg!(windows) {
+        status!("{}", tr!("setup-permissions-windows"));
+    } else {
+        status!("{}", tr!("setup-permissions-other"));
+    }
+}
+
+/// Asks which release channel to follow, saving the choice in the configuration file.
+fn choose_channel(config: &Config) -> Result<Channel, Error>
+{
+    let current = config.channel.unwrap_or_default();
+
+    // The nightly channel is no use without a feed to get the builds from.
+    let mut channels = vec![
+        ("stable", Channel::Stable, tr!("setup-channel-stable")),
+        ("prerelease", Channel::Prerelease, tr!("setup-channel-prerelease")),
+    ];
+    if config.nightly_feed.is_some() {
+        channels.push(("nightly", Channel::Nightly, tr!("setup-channel-nightly")));
+    }
+
+    let options = channels
+        .iter()
+        .map(|(_name, channel, description)| {
+            if *channel == current {
+                tr!("setup-channel-current", option = description.as_str())
+            } else {
+                description.clone()
+            }
+        })
+        .collect::<Vec<_>>();
+    let choice = prompt::pick(&tr!("setup-channel-question"), &options).map_err(io_error)?;
+
+    let Some((name, channel, _description)) = choice.map(|choice| &channels[choice]) else {
+        status!("{}", tr!("setup-channel-unchanged", channel = current.to_string()));
+        return Ok(current);
+    };
+    if config.channel != Some(*channel) {
+        let path = Config::save_setting("channel", &format!("\"{}\"", name))?;
+        status!("{}", tr!("setup-channel-saved", channel = channel.to_string(), path = path.display().to_string()));
+    }
+
+    Ok(*channel)
+}
+
+/// Offers to update each of `probes` to the latest firmware on `channel`.
+fn offer_updates(matches: &ArgMatches, config: &Config, channel: Channel, probes: Vec<BmpDevice>) -> Result<(), Error>
+{
+    if probes.is_empty() {
+        status!("{}", tr!("setup-update-no-probes"));
+        return Ok(());
+    }
+
+    let index = ReleaseIndex::from_cli_args(matches, config);
+    // Not being able to get online shouldn't undo the rest of setup.
+    let latest = match index.latest(channel) {
+        Ok(latest) => latest,
+        Err(e) => {
+            status!("{}", tr!("setup-update-lookup-failed", error = e.to_string()));
+            return Ok(());
+        },
+    };
+
+    for dev in probes {
+        let summary = dev.summary();
+        let installed = dev.identity().ok().and_then(|identity| identity.firmware_version());
+        if let (Some(installed), Some(latest)) = (&installed, latest.version()) {
+            if *installed >= latest {
+                status!("{}", tr!("setup-update-up-to-date", probe = summary.as_str()));
+                continue;
+            }
+        }
+
+        let Some(hardware) = dev.hardware() else {
+            status!("{}", tr!("setup-update-unknown-hardware", probe = summary.as_str()));
+            continue;
+        };
+        let Some(asset) = latest.firmware_asset(hardware) else {
+            status!("{}", tr!(
+                "setup-update-no-build",
+                probe = summary.as_str(),
+                release = latest.tag_name.as_str(),
+            ));
+            continue;
+        };
+
+        let question = tr!("setup-update-question", probe = summary.as_str(), release = latest.tag_name.as_str());
+        if !prompt::confirm(&question).map_err(io_error)? {
+            continue;
+        }
+
+        let reporter = Reporter::default();
+        let firmware = crate::release_firmware(&dev, hardware, &index, &latest, asset, false, &reporter)?;
+        crate::write_firmware(dev, &firmware.data, FirmwareType::Application, true, &reporter)?;
+    }
+
+    Ok(())
+}
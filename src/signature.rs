@@ -0,0 +1,68 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+//! Module for checking the minisign signatures published alongside firmware releases.
+//!
+//! bmputil doesn't pin any keys of its own until the project publishes one to sign releases with, so
+//! signatures are only checked against the keys given with `signing_keys` in the configuration file,
+//! e.g. for in-house builds.
+
+use tracing::{debug, info, warn};
+use minisign_verify::{PublicKey, Signature};
+
+use crate::error::{Error, ErrorKind};
+
+/// Checks `signature`, the contents of a `.minisig` file, is a valid signature for `data` by one of the
+/// `keys` from the configuration file (in base64, as minisign prints them), naming the file `what` in
+/// errors.
+///
+/// A signature by a key we don't know can't be checked, which is only an error if signatures are
+/// `required`; one by a key we do know that doesn't match `data` always is. Returns whether the
+/// signature was checked.
+pub fn verify(data: &[u8], signature: &[u8], keys: &[String], required: bool, what: &str) -> Result<bool, Error>
+{
+    // Without any keys, there's nothing to check against, and nothing worth warning about every time.
+    if keys.is_empty() && !required {
+        debug!("No signing keys are configured, so {}'s signature can't be checked", what);
+        return Ok(false);
+    }
+
+    let signature = std::str::from_utf8(signature)
+        .ok()
+        .and_then(|signature| Signature::decode(signature).ok())
+        .ok_or_else(|| ErrorKind::BadSignature(what.to_string()).error())?;
+
+    let keys = keys
+        .iter()
+        .map(String::as_str)
+        .map(|key| {
+            PublicKey::from_base64(key)
+                .map(|public_key| (key, public_key))
+                .map_err(|e| ErrorKind::InvalidSigningKey(key.to_string()).error_from(e))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    for (key, public_key) in keys {
+        match public_key.verify(data, &signature, false) {
+            Ok(()) => {
+                info!("{} is signed by {}", what, key);
+                return Ok(true);
+            },
+            Err(minisign_verify::Error::UnexpectedKeyId) => debug!("{} is not signed by {}", what, key),
+            Err(e) => {
+                debug!("{} does not match its signature by {}: {}", what, key, e);
+                return Err(ErrorKind::BadSignature(what.to_string()).error());
+            },
+        }
+    }
+
+    if required {
+        return Err(ErrorKind::BadSignature(what.to_string()).error());
+    }
+    warn!(
+        "{} is signed by a key bmputil doesn't know, so its signature can't be checked \
+        (add the key to signing_keys in the configuration file, or set require_signed to refuse it)",
+        what,
+    );
+
+    Ok(false)
+}
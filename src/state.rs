@@ -0,0 +1,216 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+//! Module for the small amount of state bmputil persists between invocations.
+//!
+//! This lives in the platform's state directory (or local data directory, where there is no such
+//! thing), e.g. `~/.local/state/bmputil` on Linux.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use tracing::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+/// The directory bmputil's state is kept in, if the platform has one.
+fn state_dir() -> Option<PathBuf>
+{
+    dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .map(|dir| dir.join("bmputil"))
+}
+
+/// Returns the serial number of the probe last selected, if one was remembered.
+pub fn last_probe() -> Option<String>
+{
+    let path = state_dir()?.join("last-probe");
+    let serial = fs::read_to_string(&path).ok()?;
+    let serial = serial.trim();
+
+    if serial.is_empty() {
+        None
+    } else {
+        debug!("Last used probe was {} (from {})", serial, path.display());
+        Some(serial.to_string())
+    }
+}
+
+/// Remembers `serial` as the serial number of the probe last selected.
+///
+/// Failing to do so is not worth failing the whole operation over, so this only warns.
+pub fn set_last_probe(serial: &str)
+{
+    let dir = match state_dir() {
+        Some(dir) => dir,
+        None => return,
+    };
+
+    let res = fs::create_dir_all(&dir)
+        .and_then(|()| fs::write(dir.join("last-probe"), serial));
+
+    if let Err(e) = res {
+        warn!("Failed to remember the last used probe in {}: {}", dir.display(), e);
+    }
+}
+
+/// A flash that didn't finish, recorded so it can be resumed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InterruptedFlash
+{
+    /// The SHA-256 hash (in hex) of the firmware image being flashed.
+    pub firmware_sha256: String,
+
+    /// What the firmware image was, i.e. application or bootloader.
+    pub firmware_type: String,
+
+    /// How many bytes of the image the probe acknowledged writing.
+    pub written: usize,
+}
+
+/// Held while updating the record of interrupted flashes, as probes can be flashed in parallel.
+static INTERRUPTED_FLASHES: Mutex<()> = Mutex::new(());
+
+/// Reads the flashes recorded as interrupted, by the serial number of the probe being flashed.
+pub fn interrupted_flashes() -> BTreeMap<String, InterruptedFlash>
+{
+    let Some(path) = state_dir().map(|dir| dir.join("interrupted-flashes.toml")) else {
+        return BTreeMap::new();
+    };
+    let Ok(contents) = fs::read_to_string(&path) else {
+        return BTreeMap::new();
+    };
+
+    toml::from_str(&contents).unwrap_or_else(|e| {
+        warn!("Ignoring unreadable record of interrupted flashes in {}: {}", path.display(), e);
+        BTreeMap::new()
+    })
+}
+
+/// Returns the flash of the probe with serial number `serial` that was last interrupted, if one was recorded.
+pub fn interrupted_flash(serial: &str) -> Option<InterruptedFlash>
+{
+    interrupted_flashes().remove(serial)
+}
+
+/// Writes `flashes` back as the record of interrupted flashes.
+///
+/// Failing to do so is not worth failing the whole operation over, so this only warns.
+fn set_interrupted_flashes(flashes: &BTreeMap<String, InterruptedFlash>)
+{
+    let dir = match state_dir() {
+        Some(dir) => dir,
+        None => return,
+    };
+    let path = dir.join("interrupted-flashes.toml");
+
+    let res = if flashes.is_empty() {
+        // It not being there in the first place is fine.
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    } else {
+        let contents = toml::to_string(flashes)
+            .expect("Unreachable: InterruptedFlash is always serializable");
+        fs::create_dir_all(&dir).and_then(|()| fs::write(&path, contents))
+    };
+
+    if let Err(e) = res {
+        warn!("Failed to record flashing progress in {}: {}", dir.display(), e);
+    }
+}
+
+/// Records how far a flash of the probe with serial number `serial` has got, so it can be resumed if
+/// it's interrupted.
+pub fn set_interrupted_flash(serial: &str, interrupted: &InterruptedFlash)
+{
+    let _lock = INTERRUPTED_FLASHES.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut flashes = interrupted_flashes();
+    flashes.insert(serial.to_string(), interrupted.clone());
+    set_interrupted_flashes(&flashes);
+}
+
+/// Forgets about any interrupted flash of the probe with serial number `serial`, e.g. once a flash of
+/// it has completed.
+pub fn clear_interrupted_flash(serial: &str)
+{
+    let _lock = INTERRUPTED_FLASHES.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut flashes = interrupted_flashes();
+    if flashes.remove(serial).is_some() {
+        set_interrupted_flashes(&flashes);
+    }
+}
+
+/// Returns whether bmputil has offered to walk the user through setting it up before (or can't
+/// remember whether it has, in which case it's best not to keep asking).
+pub fn setup_offered() -> bool
+{
+    state_dir().is_none_or(|dir| dir.join("setup-offered").exists())
+}
+
+/// Remembers that bmputil has offered to walk the user through setting it up.
+pub fn set_setup_offered()
+{
+    let dir = match state_dir() {
+        Some(dir) => dir,
+        None => return,
+    };
+
+    let res = fs::create_dir_all(&dir)
+        .and_then(|()| fs::write(dir.join("setup-offered"), ""));
+
+    if let Err(e) = res {
+        warn!("Failed to remember that setup was offered in {}: {}", dir.display(), e);
+    }
+}
+
+/// Reads the bootloader versions remembered for probes, by their serial number when running their firmware.
+fn bootloader_versions() -> BTreeMap<String, String>
+{
+    let Some(path) = state_dir().map(|dir| dir.join("bootloader-versions.toml")) else {
+        return BTreeMap::new();
+    };
+    let Ok(contents) = fs::read_to_string(&path) else {
+        return BTreeMap::new();
+    };
+
+    toml::from_str(&contents).unwrap_or_else(|e| {
+        warn!("Ignoring unreadable bootloader versions in {}: {}", path.display(), e);
+        BTreeMap::new()
+    })
+}
+
+/// Returns the version of the bootloader the probe with serial number `serial` (as it is when running its
+/// firmware) had when it was last switched to or from DFU mode, if it has been.
+pub fn bootloader_version(serial: &str) -> Option<String>
+{
+    bootloader_versions().remove(serial)
+}
+
+/// Remembers that the probe with serial number `serial` (as it is when running its firmware) has bootloader
+/// version `version`, so it can be reported while the probe is running its firmware.
+///
+/// Failing to do so is not worth failing the whole operation over, so this only warns.
+pub fn set_bootloader_version(serial: &str, version: &str)
+{
+    let dir = match state_dir() {
+        Some(dir) => dir,
+        None => return,
+    };
+
+    let mut versions = bootloader_versions();
+    if versions.get(serial).map(String::as_str) == Some(version) {
+        return;
+    }
+    versions.insert(serial.to_string(), version.to_string());
+
+    let contents = toml::to_string(&versions)
+        .expect("Unreachable: a map of strings is always serializable");
+    let res = fs::create_dir_all(&dir)
+        .and_then(|()| fs::write(dir.join("bootloader-versions.toml"), contents));
+
+    if let Err(e) = res {
+        warn!("Failed to remember the probe's bootloader version in {}: {}", dir.display(), e);
+    }
+}
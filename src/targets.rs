@@ -0,0 +1,153 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+//! Module for working out which target families a Black Magic Debug firmware image supports, for
+//! `bmputil firmware targets`.
+//!
+//! Builds of the firmware differ in which target drivers they include (e.g. to fit the smaller probes'
+//! flash), and the firmware doesn't advertise which it has: not in its product string, nor through
+//! its GDB server until a target is found. What it does have is each driver's names for the parts it
+//! drives, e.g. `RP2040` or `STM32F1 medium density`, so we look for those in the image: the file
+//! given, or for a probe, the release build its product string says it's running.
+//!
+//! Names only count as whole words, so e.g. `GD32F1` isn't taken for the GD32VF103's driver, and
+//! several families' parts are driven by one driver (the STM32F1's also drives its GD32, CH32, AT32
+//! and MM32 clones), so images with that driver list all of them.
+
+/// The target families we know of, with the names their drivers give the parts.
+const FAMILIES: &[(&str, &[&str])] = &[
+    ("Raspberry Pi RP2040", &["RP2040"]),
+    ("Raspberry Pi RP2350", &["RP2350"]),
+    (
+        "STM32F0/F1/F3",
+        &["STM32F0", "STM32F03", "STM32F04", "STM32F05", "STM32F07", "STM32F09", "STM32F1", "STM32F3", "STM32F37x"],
+    ),
+    (
+        "STM32F2/F4/F7",
+        &[
+            "STM32F2", "STM32F4", "STM32F40x", "STM32F401", "STM32F411", "STM32F412", "STM32F413", "STM32F42x",
+            "STM32F446", "STM32F7", "STM32F72x", "STM32F74x", "STM32F76x",
+        ],
+    ),
+    ("STM32G0/C0", &["STM32G0", "STM32C0"]),
+    ("STM32G4", &["STM32G4"]),
+    ("STM32H5", &["STM32H5"]),
+    ("STM32H7", &["STM32H7", "STM32H72x", "STM32H74x", "STM32H7Bx"]),
+    ("STM32L0/L1", &["STM32L0", "STM32L1"]),
+    ("STM32L4/L5/U5", &["STM32L4", "STM32L5", "STM32U5"]),
+    ("STM32WB/WL", &["STM32WB", "STM32WB55", "STM32WL", "STM32WLxx"]),
+    ("STM32MP15", &["STM32MP15"]),
+    ("GigaDevice GD32", &["GD32F1", "GD32F3", "GD32F4", "GD32E230", "GD32E5"]),
+    ("WCH CH32", &["CH32F1"]),
+    ("Artery AT32", &["AT32F403A", "AT32F407", "AT32F415", "AT32F43x"]),
+    ("MindMotion MM32", &["MM32L0x", "MM32F3x", "MM32SPIN"]),
+    ("HDSC HC32", &["HC32L110"]),
+    ("Puya PY32", &["PY32F0"]),
+    ("Nordic nRF51/nRF52", &["nRF51", "nRF52"]),
+    ("Nordic nRF91", &["nRF91"]),
+    (
+        "NXP LPC",
+        &["LPC8xx", "LPC11xx", "LPC15xx", "LPC17xx", "LPC40xx", "LPC43xx", "LPC546xx", "LPC55xx"],
+    ),
+    ("NXP Kinetis", &["Kinetis"]),
+    ("NXP i.MX RT", &["i.MXRT", "i.MXRT10xx"]),
+    ("NXP S32K3", &["S32K3xx"]),
+    (
+        "Microchip (Atmel) SAM",
+        &["Atmel SAM", "SAM3N", "SAM3S", "SAM3U", "SAM3X", "SAM4L", "SAM4S", "SAMx5x"],
+    ),
+    ("Silicon Labs EFM32/EFR32", &["EFM32", "EFR32"]),
+    ("TI MSP432", &["MSP432"]),
+    ("TI MSPM0", &["MSPM0"]),
+    ("TI Tiva/Stellaris", &["Stellaris/Tiva", "LM3S", "TM4C"]),
+    ("Renesas RA/RZ", &["Renesas"]),
+    ("Ambiq Apollo3", &["Apollo3"]),
+    ("Espressif ESP32-C", &["ESP32-C3"]),
+    ("Xilinx Zynq", &["Zynq"]),
+];
+
+/// The shortest run of printable characters worth treating as a string.
+const MIN_STRING_LENGTH: usize = 4;
+
+/// Whether `name` is in `string` as a whole word (or words), rather than part of a longer name.
+fn contains_name(string: &str, name: &str) -> bool
+{
+    string.match_indices(name).any(|(start, _)| {
+        let before = string[..start].chars().next_back();
+        let after = string[start + name.len()..].chars().next();
+        !before.is_some_and(|c| c.is_ascii_alphanumeric()) && !after.is_some_and(|c| c.is_ascii_alphanumeric())
+    })
+}
+
+/// Finds the target families whose drivers are built into `image`, in the order we list them.
+pub fn supported_targets(image: &[u8]) -> Vec<&'static str>
+{
+    let strings = image
+        .split(|byte| !byte.is_ascii_graphic() && *byte != b' ')
+        .filter(|string| string.len() >= MIN_STRING_LENGTH)
+        .filter_map(|string| std::str::from_utf8(string).ok())
+        .collect::<Vec<_>>();
+
+    FAMILIES
+        .iter()
+        .filter(|(_family, names)| {
+            strings
+                .iter()
+                .any(|string| names.iter().any(|name| contains_name(string, name)))
+        })
+        .map(|(family, _names)| *family)
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// An image with `strings` in it, as the compiler would lay them out.
+    fn image(strings: &[&str]) -> Vec<u8>
+    {
+        let mut image = vec![0x00, 0x20, 0x00, 0x20, 0x01, 0x01, 0x00, 0x08];
+        for string in strings {
+            image.extend_from_slice(string.as_bytes());
+            image.push(0);
+        }
+        image.extend_from_slice(&[0xff; 8]);
+        image
+    }
+
+    #[test]
+    fn finds_drivers_by_their_part_names()
+    {
+        let firmware = image(&["RP2040", "STM32F1 medium density", "STM32F40x", "Atmel SAM%c%02d%c%d%c (rev %c)"]);
+        assert_eq!(
+            supported_targets(&firmware),
+            vec!["Raspberry Pi RP2040", "STM32F0/F1/F3", "STM32F2/F4/F7", "Microchip (Atmel) SAM"],
+        );
+    }
+
+    #[test]
+    fn names_only_count_as_whole_words()
+    {
+        // The GD32VF103 is a RISC-V part with a driver of its own, and neither is the STM32F103 the
+        // STM32F1 driver's name, nor the RP2040 of an RP20400.
+        assert!(supported_targets(&image(&["GD32VF103", "STM32F103", "RP20400"])).is_empty());
+        // Nor do strings that merely contain a name, like SAML (or SAMx5x) in other words.
+        assert!(supported_targets(&image(&["SAMLTool", "Use SAMLR21", "xSAMx5x"])).is_empty());
+        // But punctuation does separate words.
+        assert_eq!(supported_targets(&image(&["(GD32F1)"])), vec!["GigaDevice GD32"]);
+    }
+
+    #[test]
+    fn stm32f1_clones()
+    {
+        // The STM32F1's driver drives its clones too, so its image names them all.
+        let firmware = image(&["STM32F1 medium density", "GD32F1", "CH32F1", "AT32F403A/407", "MM32L0x"]);
+        assert_eq!(
+            supported_targets(&firmware),
+            vec!["STM32F0/F1/F3", "GigaDevice GD32", "WCH CH32", "Artery AT32", "MindMotion MM32"],
+        );
+        // But the driver's name alone doesn't mean the clones are there.
+        assert_eq!(supported_targets(&image(&["STM32F1 medium density"])), vec!["STM32F0/F1/F3"]);
+    }
+}
@@ -0,0 +1,445 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+//! Module for `bmputil tui`: a dashboard of the connected probes, kept up to date as they're plugged
+//! in and unplugged, from which their firmware can be updated or backed up.
+//!
+//! One operation runs at a time, and looking for probes pauses while it does, so as not to get in the
+//! way of a probe that's switching between its firmware and bootloader.
+
+use std::collections::HashMap;
+use std::io::{self, IsTerminal};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use clap::ArgMatches;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Cell, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::bmp::{BmpMatcher, FirmwareType};
+use crate::config::Config;
+use crate::error::{Error, ErrorKind, ErrorSource};
+use crate::output::{self, JobStatus, Reporter};
+use crate::release::{Release, ReleaseIndex};
+use crate::version::FirmwareVersion;
+use crate::{prompt, S};
+
+/// How often to look for probes having been plugged in or unplugged.
+const SCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long to wait for a key press before redrawing anyway, e.g. to move a progress bar along.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Shows the dashboard until the user quits.
+pub fn run(matches: &ArgMatches, config: &Config) -> Result<(), Error>
+{
+    if output::json_output() || !io::stdout().is_terminal() {
+        return Err(ErrorKind::Unsupported(S!("the dashboard needs a terminal to run in")).error());
+    }
+
+    // The dashboard shows every probe, whatever the configured default probe is.
+    let matcher = BmpMatcher::from_cli_args(matches, config)?
+        .serial(None)
+        .index(None)
+        .port(None)
+        .wait(false);
+
+    // Looking up the latest release can take a moment, so show the probes while it happens.
+    let index = ReleaseIndex::from_cli_args(matches, config);
+    let channel = crate::update_channel(matches, config);
+    let (sender, latest) = mpsc::channel();
+    thread::spawn(move || {
+        let res = index.latest(channel).map(|release| (index, release));
+        let _ = sender.send(res.map_err(|e| e.to_string()));
+    });
+
+    // Nothing else may write to the terminal while the dashboard has it, nor ask questions on it.
+    output::set_terminal_taken(true);
+    prompt::set_non_interactive(true);
+    let res = ratatui::try_init()
+        .map_err(terminal_error)
+        .and_then(|mut terminal| Dashboard::new(matcher, latest).run(&mut terminal));
+    ratatui::restore();
+    output::set_terminal_taken(false);
+
+    res
+}
+
+fn terminal_error(e: io::Error) -> Error
+{
+    ErrorKind::External(ErrorSource::StdIo(e)).error().with_ctx("drawing the dashboard")
+}
+
+/// A probe, as shown on the dashboard.
+struct ProbeRow
+{
+    port: String,
+    summary: String,
+    serial: Option<String>,
+    version: Option<FirmwareVersion>,
+}
+
+/// The operations that can be started on a probe from the dashboard.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum JobKind
+{
+    Update,
+    Backup,
+}
+
+/// An operation running on the probe on `port`, returning what it did.
+struct Job
+{
+    port: String,
+    status: Arc<Mutex<JobStatus>>,
+    handle: JoinHandle<Result<String, Error>>,
+}
+
+/// What's known of the latest release.
+enum Latest
+{
+    Pending(Receiver<Result<(ReleaseIndex, Release), String>>),
+    Found(ReleaseIndex, Release),
+    Failed(String),
+}
+
+struct Dashboard
+{
+    matcher: BmpMatcher,
+    probes: Vec<ProbeRow>,
+    table: TableState,
+    last_scan: Option<Instant>,
+    latest: Latest,
+    job: Option<Job>,
+
+    /// How the last operation on each probe went, by port.
+    outcomes: HashMap<String, Result<String, String>>,
+
+    /// A note for the user, shown instead of the key help until the next key press.
+    notice: Option<String>,
+
+    color: bool,
+}
+
+impl Dashboard
+{
+    fn new(matcher: BmpMatcher, latest: Receiver<Result<(ReleaseIndex, Release), String>>) -> Self
+    {
+        Self {
+            matcher,
+            probes: Vec::new(),
+            table: TableState::default(),
+            last_scan: None,
+            latest: Latest::Pending(latest),
+            job: None,
+            outcomes: HashMap::new(),
+            notice: None,
+            color: output::stdout_color(),
+        }
+    }
+
+    fn run(mut self, terminal: &mut DefaultTerminal) -> Result<(), Error>
+    {
+        loop {
+            self.check_latest();
+            self.check_job();
+            let scan_due = self.last_scan.is_none_or(|last| last.elapsed() >= SCAN_INTERVAL);
+            if self.job.is_none() && scan_due {
+                self.scan();
+            }
+
+            terminal.draw(|frame| self.draw(frame)).map_err(terminal_error)?;
+
+            if !event::poll(REDRAW_INTERVAL).map_err(terminal_error)? {
+                continue;
+            }
+            let key = match event::read().map_err(terminal_error)? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => key,
+                _ => continue,
+            };
+            self.notice = None;
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc if self.quit() => return Ok(()),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) && self.quit() => return Ok(()),
+                KeyCode::Up | KeyCode::Char('k') => self.table.select_previous(),
+                KeyCode::Down | KeyCode::Char('j') => self.table.select_next(),
+                KeyCode::Char('u') => self.start(JobKind::Update),
+                KeyCode::Char('b') => self.start(JobKind::Backup),
+                KeyCode::Char('r') => self.last_scan = None,
+                _ => (),
+            };
+        }
+    }
+
+    /// Returns whether it's alright to quit, which it isn't in the middle of writing to a probe.
+    fn quit(&mut self) -> bool
+    {
+        if self.job.is_some() {
+            self.notice = Some(S!("Wait for the current operation to finish before quitting."));
+            return false;
+        }
+
+        true
+    }
+
+    /// Picks up the latest release, if it's been looked up since we last checked.
+    fn check_latest(&mut self)
+    {
+        let res = match &self.latest {
+            Latest::Pending(receiver) => match receiver.try_recv() {
+                Ok(res) => res,
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => Err(S!("lookup stopped unexpectedly")),
+            },
+            _ => return,
+        };
+        self.latest = match res {
+            Ok((index, release)) => Latest::Found(index, release),
+            Err(e) => Latest::Failed(e),
+        };
+    }
+
+    /// Picks up how the running operation went, if it's finished.
+    fn check_job(&mut self)
+    {
+        if !self.job.as_ref().is_some_and(|job| job.handle.is_finished()) {
+            return;
+        }
+        let job = self.job.take().unwrap();
+        let outcome = match job.handle.join() {
+            Ok(res) => res.map_err(|e| e.to_string()),
+            Err(_) => Err(S!("the operation panicked")),
+        };
+        self.outcomes.insert(job.port, outcome);
+
+        // The probe has likely come back on a different device, so look again straight away.
+        self.last_scan = None;
+    }
+
+    /// Looks for probes, keeping the same one selected if it's still there.
+    fn scan(&mut self)
+    {
+        let selected = self.selected().map(|probe| probe.port.clone());
+
+        let mut results = self.matcher.find_matching_probes();
+        self.probes = results.found
+            .drain(..)
+            .map(|dev| ProbeRow {
+                port: dev.port(),
+                summary: dev.summary(),
+                serial: dev.serial_number().ok().map(|serial| serial.to_string()),
+                version: dev.identity().ok().and_then(|identity| identity.firmware_version()),
+            })
+            .collect();
+        self.last_scan = Some(Instant::now());
+
+        let position = selected.and_then(|port| self.probes.iter().position(|probe| probe.port == port));
+        let position = match position {
+            Some(position) => Some(position),
+            None if self.probes.is_empty() => None,
+            None => Some(self.table.selected().unwrap_or(0).min(self.probes.len() - 1)),
+        };
+        self.table.select(position);
+    }
+
+    fn selected(&self) -> Option<&ProbeRow>
+    {
+        self.table.selected().and_then(|index| self.probes.get(index))
+    }
+
+    /// Starts an operation on the selected probe, unless one is already running.
+    fn start(&mut self, kind: JobKind)
+    {
+        if self.job.is_some() {
+            self.notice = Some(S!("Another operation is still running."));
+            return;
+        }
+        let (port, installed) = match self.selected() {
+            Some(probe) => (probe.port.clone(), probe.version.clone()),
+            None => return,
+        };
+        let matcher = self.matcher.clone().port(port.as_str());
+        let status = Arc::new(Mutex::new(JobStatus::default()));
+        let reporter = Reporter::for_dashboard(status.clone());
+
+        let handle = match kind {
+            JobKind::Update => {
+                let (index, release) = match &self.latest {
+                    Latest::Found(index, release) => (index.clone(), release.clone()),
+                    _ => {
+                        self.notice = Some(S!("The latest release hasn't been found, so there's nothing to update to."));
+                        return;
+                    },
+                };
+                if let (Some(installed), Some(latest)) = (&installed, release.version()) {
+                    if *installed >= latest {
+                        self.notice = Some(format!("This probe is already running {}.", installed));
+                        return;
+                    }
+                }
+                reporter.println(format!("Updating to {}...", release.tag_name));
+                thread::spawn(move || update(matcher, &index, &release, &reporter))
+            },
+            JobKind::Backup => {
+                reporter.println("Backing up...");
+                thread::spawn(move || backup(matcher, &reporter))
+            },
+        };
+
+        self.outcomes.remove(&port);
+        self.job = Some(Job { port, status, handle });
+    }
+
+    fn style(&self, color: Color) -> Style
+    {
+        if self.color { Style::new().fg(color) } else { Style::new() }
+    }
+
+    fn draw(&mut self, frame: &mut Frame)
+    {
+        let [header, body, footer] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(3),
+            Constraint::Length(1),
+        ]).areas(frame.area());
+
+        let latest = match &self.latest {
+            Latest::Pending(_) => Span::raw("looking up the latest release..."),
+            Latest::Found(_index, release) => Span::raw(format!("latest release {}", release.tag_name)),
+            Latest::Failed(e) => Span::styled(format!("couldn't look up the latest release: {}", e), self.style(Color::Red)),
+        };
+        let title = Line::from(vec![
+            Span::styled("Black Magic Probes", Style::new().add_modifier(Modifier::BOLD)),
+            Span::raw(" - "),
+            latest,
+        ]);
+        frame.render_widget(Paragraph::new(title), header);
+
+        let block = Block::bordered();
+        if self.probes.is_empty() {
+            let empty = Paragraph::new("No Black Magic Probes found. Plug one in and it'll show up here.")
+                .block(block);
+            frame.render_widget(empty, body);
+        } else {
+            let rows: Vec<Row> = self.probes
+                .iter()
+                .map(|probe| Row::new(vec![
+                    Cell::from(probe.summary.clone()),
+                    Cell::from(probe.serial.clone().unwrap_or_default()),
+                    Cell::from(probe.version.as_ref().map_or_else(|| S!("unknown"), |version| version.to_string())),
+                    self.status_cell(probe),
+                ]))
+                .collect();
+            let widths = [
+                Constraint::Fill(3),
+                Constraint::Length(26),
+                Constraint::Length(14),
+                Constraint::Fill(2),
+            ];
+            let table = Table::new(rows, widths)
+                .header(Row::new(["Probe", "Serial", "Firmware", "Status"]).style(Style::new().add_modifier(Modifier::BOLD)))
+                .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+                .block(block);
+            frame.render_stateful_widget(table, body, &mut self.table);
+        }
+
+        let help = match &self.notice {
+            Some(notice) => Line::styled(notice.clone(), self.style(Color::Yellow)),
+            None => Line::raw("up/down: select   u: update   b: back up   r: rescan   q: quit"),
+        };
+        frame.render_widget(Paragraph::new(help), footer);
+    }
+
+    /// What's going on with `probe`: how the operation running on it is getting on, how the last one
+    /// went, or otherwise how its firmware compares with the latest release.
+    fn status_cell(&self, probe: &ProbeRow) -> Cell<'static>
+    {
+        if let Some(job) = self.job.as_ref().filter(|job| job.port == probe.port) {
+            let status = job.status.lock().unwrap();
+            let text = match &status.progress {
+                Some(progress) if !progress.is_finished() => {
+                    let length = progress.length().unwrap_or(0).max(1);
+                    let filled = (progress.position().min(length) * 20 / length) as usize;
+                    format!(
+                        "{} {}{} {:>3}%",
+                        status.message,
+                        "█".repeat(filled),
+                        "░".repeat(20 - filled),
+                        progress.position() * 100 / length,
+                    )
+                },
+                _ => status.message.clone(),
+            };
+            return Cell::from(text).style(self.style(Color::Cyan));
+        }
+
+        match self.outcomes.get(&probe.port) {
+            Some(Ok(done)) => return Cell::from(done.clone()).style(self.style(Color::Green)),
+            Some(Err(e)) => return Cell::from(format!("failed: {}", e)).style(self.style(Color::Red)),
+            None => (),
+        };
+
+        let latest = match &self.latest {
+            Latest::Found(_index, release) => release.version(),
+            _ => None,
+        };
+        match (&probe.version, latest) {
+            (Some(installed), Some(latest)) if *installed >= latest => {
+                Cell::from("up to date").style(self.style(Color::Green))
+            },
+            (Some(_installed), Some(latest)) => {
+                Cell::from(format!("update available ({})", latest)).style(self.style(Color::Yellow))
+            },
+            _ => Cell::from(""),
+        }
+    }
+}
+
+/// Updates the probe `matcher` finds to `release`, as `bmputil firmware update` would.
+fn update(matcher: BmpMatcher, index: &ReleaseIndex, release: &Release, reporter: &Reporter) -> Result<String, Error>
+{
+    let dev = matcher.find_matching_probes().pop_single("update")?;
+    let hardware = dev.hardware().ok_or_else(|| ErrorKind::Unsupported(S!(
+        "can't tell what hardware this probe is, so can't pick a firmware build for it"
+    )).error())?;
+    let asset = release.firmware_asset(hardware)
+        .ok_or_else(|| ErrorKind::NoReleaseForHardware(release.tag_name.clone(), hardware.to_string()).error())?;
+
+    let firmware = crate::release_firmware(&dev, hardware, index, release, asset, false, reporter)?;
+    crate::write_firmware(dev, &firmware.data, FirmwareType::Application, true, reporter)?;
+
+    Ok(format!("updated to {}", release.tag_name))
+}
+
+/// Backs up the firmware of the probe `matcher` finds into the current directory, as `bmputil firmware backup`
+/// would.
+fn backup(matcher: BmpMatcher, reporter: &Reporter) -> Result<String, Error>
+{
+    let mut dev = matcher.find_matching_probes().pop_single("back up")?;
+
+    // Name the file so that backing up several probes, or the same one again later, doesn't overwrite anything.
+    // Serial numbers can be any printable ASCII (see `bmp::parse_serial()`), so only keep what's safe in a
+    // file name, wherever it's written.
+    let serial = dev.serial_number().map_or_else(|_| S!("unknown"), |serial| {
+        serial
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+            .collect::<String>()
+    });
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let path = format!("bmp-backup-{}-{}.bin", serial, timestamp);
+
+    reporter.println(format!("Backing up to {}...", path));
+    crate::backup_firmware(&mut dev, FirmwareType::Application, &path)?;
+    dev.detach_and_destroy()
+        .map_err(|e| e.with_ctx("returning device to runtime mode"))?;
+
+    Ok(format!("backed up to {}", path))
+}
@@ -0,0 +1,194 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+//! Module for `bmputil install-udev-rules`, which gives users access to their probes on Linux. Without
+//! udev rules, only root can open a probe, which makes `Access denied` by far the most common way for
+//! bmputil to fail there.
+//!
+//! The rules cover every USB ID bmputil knows a probe by, in both runtime and DFU mode, along with
+//! any custom IDs in use, and the serial ports of probes in runtime mode. Access is given either to
+//! whoever is logged in at the machine (udev's `uaccess` tag), or to a group (`plugdev`, by default).
+//! Installing the rules needs root, so unless we're already root, that's done through `sudo`.
+//!
+//! FreeBSD's equivalent is [`crate::devd`].
+
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use clap::ArgMatches;
+use tracing::debug;
+
+use crate::access::{as_root, ensure_group, in_group, known_ids, run, verify, write_as_root};
+use crate::bmp::CustomUsbIds;
+use crate::config::Config;
+use crate::error::Error;
+use crate::usb::DfuOperatingMode;
+use crate::{status, tr, S};
+
+/// Where udev rules local to this machine go.
+pub const RULES_DIR: &str = "/etc/udev/rules.d";
+
+/// Who the udev rules give access to probes to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Access
+{
+    /// Whoever is logged in at the machine, through systemd-logind.
+    #[default]
+    Uaccess,
+    /// Members of the given group.
+    Group(String),
+}
+
+impl Access
+{
+    pub(crate) fn from_cli_args(matches: &ArgMatches) -> Self
+    {
+        // Asking for a group only makes sense with plugdev-style access, so it implies it.
+        if let Some(group) = matches.get_one::<String>("group") {
+            return Self::Group(group.clone());
+        }
+        match matches.get_one::<String>("mode").map(String::as_str) {
+            Some("plugdev") => Self::Group(S!("plugdev")),
+            _ => Self::Uaccess,
+        }
+    }
+
+    /// The name of the rules file for this kind of access.
+    ///
+    /// The `uaccess` tag has to be set before systemd's `73-seat-late.rules` acts on it, so those
+    /// rules have to sort before that.
+    fn file_name(&self) -> &'static str
+    {
+        match self {
+            Self::Uaccess => "70-bmputil-uaccess.rules",
+            Self::Group(_) => "99-bmputil-plugdev.rules",
+        }
+    }
+
+    /// What to add to each rule to give this access.
+    fn grant(&self) -> String
+    {
+        match self {
+            Self::Uaccess => S!(r#"TAG+="uaccess""#),
+            Self::Group(group) => format!(r#"MODE="0660", GROUP="{}""#, group),
+        }
+    }
+}
+
+/// Writes the udev rules giving `access` to probes, recognising `custom_ids` as well as the usual IDs.
+pub fn rules(access: &Access, custom_ids: CustomUsbIds) -> String
+{
+    let mut rules = format!(
+        "# udev rules giving access to Black Magic Probes, written by bmputil {} (bmputil install-udev-rules).\n",
+        env!("CARGO_PKG_VERSION"),
+    );
+    match access {
+        Access::Uaccess => rules.push_str("# Access is given to whoever is logged in at this machine.\n"),
+        Access::Group(group) => {
+            let _ = writeln!(rules, "# Access is given to members of the {} group.", group);
+        },
+    }
+
+    let grant = access.grant();
+    for ((vid, pid), description, mode) in known_ids(custom_ids) {
+        let _ = writeln!(rules, "\n# {}", description);
+        let _ = writeln!(
+            rules,
+            r#"SUBSYSTEM=="usb", ATTR{{idVendor}}=="{:04x}", ATTR{{idProduct}}=="{:04x}", {}"#,
+            vid.0,
+            pid.0,
+            grant,
+        );
+        // The GDB server and target UART.
+        if mode == DfuOperatingMode::Runtime {
+            let _ = writeln!(
+                rules,
+                r#"SUBSYSTEM=="tty", ATTRS{{idVendor}}=="{:04x}", ATTRS{{idProduct}}=="{:04x}", {}"#,
+                vid.0,
+                pid.0,
+                grant,
+            );
+        }
+    }
+
+    rules
+}
+
+/// Has udev pick up the rules, and apply them to anything already plugged in.
+fn reload() -> Result<(), Error>
+{
+    status!("{}", tr!("udev-reloading"));
+
+    let mut reload = as_root("udevadm");
+    reload.args(["control", "--reload-rules"]);
+    run(&mut reload)?;
+
+    let mut trigger = as_root("udevadm");
+    trigger.args(["trigger", "--subsystem-match=usb", "--subsystem-match=tty"]);
+    run(&mut trigger)?;
+
+    // Settling only waits for the events to be handled, so not being able to isn't fatal.
+    if let Err(e) = run(Command::new("udevadm").arg("settle")) {
+        debug!("Couldn't wait for udev to settle: {}", e);
+    }
+
+    Ok(())
+}
+
+/// Installs udev rules giving `access` to probes (including any with `custom_ids`), reloads udev, and
+/// checks the rules work.
+pub fn install(access: &Access, custom_ids: CustomUsbIds) -> Result<(), Error>
+{
+    if let Access::Group(group) = access {
+        ensure_group(group)?;
+    }
+
+    let path = Path::new(RULES_DIR).join(access.file_name());
+    status!("{}", tr!("udev-installing", path = path.display().to_string()));
+    // Systems without any local rules yet may not have the directory.
+    let mut mkdir = as_root("mkdir");
+    mkdir.args(["-p", RULES_DIR]);
+    run(&mut mkdir)?;
+    write_as_root(&path, &rules(access, custom_ids))?;
+
+    // Switching from one kind of access to the other shouldn't leave the old rules behind.
+    let others = [Access::Uaccess, Access::Group(S!("plugdev"))]
+        .iter()
+        .map(|other| Path::new(RULES_DIR).join(other.file_name()))
+        .filter(|other| *other != path && other.exists())
+        .collect::<Vec<PathBuf>>();
+    for other in others {
+        status!("{}", tr!("udev-removing", path = other.display().to_string()));
+        let mut rm = as_root("rm");
+        rm.arg("-f").arg(&other);
+        run(&mut rm)?;
+    }
+
+    reload()?;
+
+    let explanation = match access {
+        Access::Uaccess => tr!("udev-verify-failed-uaccess"),
+        Access::Group(group) => {
+            if !in_group(group) {
+                status!("{}", tr!("udev-not-in-group", group = group.as_str()));
+                return Ok(());
+            }
+            tr!("udev-verify-failed-group", group = group.as_str())
+        },
+    };
+    verify(custom_ids, &explanation)
+}
+
+/// Runs `bmputil install-udev-rules`.
+pub fn install_command(matches: &ArgMatches, config: &Config) -> Result<(), Error>
+{
+    let access = Access::from_cli_args(matches);
+    let custom_ids = CustomUsbIds::from_cli_args(matches).or(config.usb_ids);
+
+    if matches.get_flag("print") {
+        print!("{}", rules(&access, custom_ids));
+        return Ok(());
+    }
+
+    install(&access, custom_ids)
+}
@@ -2,7 +2,89 @@
 // SPDX-FileCopyrightText: 2022-2023 1BitSquared <info@1bitsquared.com>
 // SPDX-FileContributor: Written by Mikaela Szekely <mikaela.szekely@qyriad.me>
 
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+
+use rusb::{LogCallbackMode, LogLevel, UsbContext};
+use serde::Serialize;
 use thiserror::Error;
+use tracing::{debug, error, info, warn};
+
+/// The log target libusb's own log is passed on under, with `--debug-usb`.
+pub const LIBUSB_LOG_TARGET: &str = "bmputil::libusb";
+
+/// Whether libusb's own log is wanted, from `--debug-usb`.
+static DEBUG_LOG: AtomicBool = AtomicBool::new(false);
+
+/// Turns libusb's own debug log on for the libusb contexts created after this.
+pub fn set_debug_log(enabled: bool)
+{
+    DEBUG_LOG.store(enabled, Ordering::Relaxed);
+}
+
+/// The file descriptor of the already-open USB device given with `--device-fd`, or -1 if there isn't one.
+static DEVICE_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// Has bmputil use the already-open USB device with file descriptor `fd` rather than enumerating USB
+/// devices itself, which Android doesn't let apps (or Termux) do. Android hands out such descriptors
+/// through its USB host API, e.g. with `termux-usb`.
+///
+/// This must be called before any libusb context is created.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn set_device_fd(fd: i32) -> rusb::Result<()>
+{
+    rusb::disable_device_discovery()?;
+    DEVICE_FD.store(fd, Ordering::Relaxed);
+    Ok(())
+}
+
+/// The file descriptor of the USB device we were given to use with `--device-fd`, if any.
+pub fn device_fd() -> Option<i32>
+{
+    let fd = DEVICE_FD.load(Ordering::Relaxed);
+    (fd >= 0).then_some(fd)
+}
+
+/// Opens the already-open USB device with file descriptor `fd` with libusb, which doesn't take
+/// ownership of it.
+pub fn open_device_fd(context: &rusb::Context, fd: i32) -> rusb::Result<rusb::DeviceHandle<rusb::Context>>
+{
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        // SAFETY: fd comes from whoever ran us, who's responsible for it being a USB device's, and
+        // for keeping it open for as long as we run.
+        unsafe { context.open_device_with_fd(fd) }
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    {
+        let _ = (context, fd);
+        Err(rusb::Error::NotSupported)
+    }
+}
+
+/// Creates a libusb context, passing libusb's debug log on to ours if that's been turned on.
+pub fn new_context() -> rusb::Result<rusb::Context>
+{
+    let mut context = rusb::Context::new()?;
+    if DEBUG_LOG.load(Ordering::Relaxed) {
+        context.set_log_level(LogLevel::Debug);
+        context.set_log_callback(Box::new(log_libusb_message), LogCallbackMode::Context);
+    }
+
+    Ok(context)
+}
+
+fn log_libusb_message(level: LogLevel, message: String)
+{
+    // libusb ends its messages with a newline, as it'd normally print them itself.
+    let message = message.trim_end();
+    match level {
+        LogLevel::Error => error!(target: LIBUSB_LOG_TARGET, "{}", message),
+        LogLevel::Warning => warn!(target: LIBUSB_LOG_TARGET, "{}", message),
+        LogLevel::Info => info!(target: LIBUSB_LOG_TARGET, "{}", message),
+        LogLevel::Debug | LogLevel::None => debug!(target: LIBUSB_LOG_TARGET, "{}", message),
+    };
+}
 
 /// Simple newtype struct for some clarity in function arguments and whatnot.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -81,13 +163,26 @@ pub enum DfuRequest
 /// into this mode at the host's request.
 /// \[[USB DFU Device Class Spec § 4.1](https://usb.org/sites/default/files/DFU_1.1.pdf#page=11)
 /// and [§ 4.2](https://usb.org/sites/default/files/DFU_1.1.pdf#page=14)\].
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum DfuOperatingMode
 {
     Runtime,
+    #[serde(rename = "dfu")]
     FirmwareUpgrade,
 }
 
+impl Display for DfuOperatingMode
+{
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result
+    {
+        match self {
+            Self::Runtime => write!(f, "runtime"),
+            Self::FirmwareUpgrade => write!(f, "DFU"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct GenericDescriptorRef<'a>
 {
@@ -0,0 +1,213 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+//! Module for parsing and comparing Black Magic Debug firmware version strings.
+
+use std::cmp::Ordering;
+use std::str::FromStr;
+use std::fmt::{self, Display, Formatter};
+
+/// A Black Magic Debug firmware version.
+///
+/// Firmware versions come from `git describe`, so as well as plain release tags (`v1.10.0`) and
+/// pre-release tags (`v2.0.0-rc.1`), development builds carry the number of commits since the tag
+/// they were built from and the commit hash (`v1.9.2-123-gabcdef1`), and may be marked `-dirty`.
+/// Unlike semver, a development build is considered *newer* than the tag it is based on.
+#[derive(Debug, Clone)]
+pub struct FirmwareVersion
+{
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+
+    /// The pre-release label and number, e.g. `("rc", 1)` for `-rc.1` or `-rc1`.
+    pub pre_release: Option<(String, u32)>,
+
+    /// The number of commits on top of the tag, for development builds.
+    pub commits: u32,
+
+    /// The original version string.
+    raw: String,
+}
+
+impl FromStr for FirmwareVersion
+{
+    type Err = String;
+
+    fn from_str(version: &str) -> Result<Self, Self::Err>
+    {
+        let invalid = || format!("{:?} is not a valid firmware version (expected e.g. v1.10.0)", version);
+
+        let trimmed = version.trim();
+        let trimmed = trimmed.strip_prefix('v').unwrap_or(trimmed);
+        let trimmed = trimmed.strip_suffix("-dirty").unwrap_or(trimmed);
+
+        let mut parts = trimmed.split('-');
+
+        // The numeric part, of which the patch number is optional.
+        let numbers = parts
+            .next()
+            .ok_or_else(invalid)?
+            .split('.')
+            .map(|n| n.parse::<u32>().map_err(|_| invalid()))
+            .collect::<Result<Vec<u32>, String>>()?;
+        let (major, minor, patch) = match *numbers.as_slice() {
+            [major, minor] => (major, minor, 0),
+            [major, minor, patch] => (major, minor, patch),
+            _ => return Err(invalid()),
+        };
+
+        let rest: Vec<&str> = parts.collect();
+
+        // The git-describe suffix is always the last two parts: the commit count and `g<hash>`.
+        let (pre_release, describe) = match rest.as_slice() {
+            [pre @ .., commits, hash] if hash.starts_with('g') && commits.parse::<u32>().is_ok() => {
+                (pre, commits.parse::<u32>().ok())
+            },
+            pre => (pre, None),
+        };
+
+        let pre_release = match pre_release {
+            [] => None,
+            [pre] => {
+                let label = pre.trim_end_matches(|c: char| c.is_ascii_digit()).trim_end_matches('.');
+                let number = pre[label.len()..].trim_start_matches('.');
+                let number = if number.is_empty() { 0 } else { number.parse::<u32>().map_err(|_| invalid())? };
+                if label.is_empty() {
+                    return Err(invalid());
+                }
+                Some((label.to_string(), number))
+            },
+            _ => return Err(invalid()),
+        };
+
+        Ok(Self {
+            major,
+            minor,
+            patch,
+            pre_release,
+            commits: describe.unwrap_or(0),
+            raw: version.trim().to_string(),
+        })
+    }
+}
+
+impl Ord for FirmwareVersion
+{
+    fn cmp(&self, other: &Self) -> Ordering
+    {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            // A release sorts after any of its pre-releases.
+            .then_with(|| match (&self.pre_release, &other.pre_release) {
+                (None, None) => Ordering::Equal,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(_), None) => Ordering::Less,
+                (Some(ours), Some(theirs)) => ours.cmp(theirs),
+            })
+            .then_with(|| self.commits.cmp(&other.commits))
+    }
+}
+
+impl PartialEq for FirmwareVersion
+{
+    fn eq(&self, other: &Self) -> bool
+    {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for FirmwareVersion
+{ }
+
+impl PartialOrd for FirmwareVersion
+{
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering>
+    {
+        Some(self.cmp(other))
+    }
+}
+
+impl Display for FirmwareVersion
+{
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result
+    {
+        write!(f, "{}", self.raw)
+    }
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn version(version: &str) -> FirmwareVersion
+    {
+        version.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_versions()
+    {
+        // The version, its numbers, its pre-release, and its commits since the tag.
+        let cases = [
+            ("v1.10.0", (1, 10, 0), None, 0),
+            ("1.10", (1, 10, 0), None, 0),
+            ("v2.0.0-rc.1", (2, 0, 0), Some(("rc", 1)), 0),
+            ("v2.0.0-rc1", (2, 0, 0), Some(("rc", 1)), 0),
+            ("v2.0.0-rc", (2, 0, 0), Some(("rc", 0)), 0),
+            ("v1.9.2-123-gabcdef1", (1, 9, 2), None, 123),
+            ("v2.0.0-rc.2-5-g0123abc-dirty", (2, 0, 0), Some(("rc", 2)), 5),
+            (" v1.10.2-dirty\n", (1, 10, 2), None, 0),
+        ];
+        for (string, (major, minor, patch), pre_release, commits) in cases {
+            let parsed = version(string);
+            assert_eq!((parsed.major, parsed.minor, parsed.patch), (major, minor, patch), "{:?}", string);
+            assert_eq!(
+                parsed.pre_release,
+                pre_release.map(|(label, number): (&str, u32)| (label.to_string(), number)),
+                "{:?}",
+                string,
+            );
+            assert_eq!(parsed.commits, commits, "{:?}", string);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_versions()
+    {
+        for string in ["", "v", "v1", "v1.2.3.4", "vx.y.z", "v1.10.0-", "v1.10.0-.1", "v1.10.0-a-b"] {
+            assert!(string.parse::<FirmwareVersion>().is_err(), "{:?} was accepted", string);
+        }
+    }
+
+    #[test]
+    fn orders_versions()
+    {
+        // Each is older than the next.
+        let ordered = [
+            "v1.9.2",
+            "v1.9.2-123-gabcdef1",
+            "v1.10.0-rc.1",
+            "v1.10.0-rc.1-4-g0123abc",
+            "v1.10.0-rc.2",
+            "v1.10.0",
+            "v1.10.0-1-g0123abc",
+            "v1.10.1",
+            "v2.0.0",
+        ];
+        for pair in ordered.windows(2) {
+            assert!(version(pair[0]) < version(pair[1]), "{} should be older than {}", pair[0], pair[1]);
+        }
+
+        // Spellings of the same version.
+        let same = [
+            ("v2.0.0-rc1", "v2.0.0-rc.1"),
+            ("v1.10.0-dirty", "v1.10.0"),
+            ("1.10", "v1.10.0"),
+        ];
+        for (ours, theirs) in same {
+            assert_eq!(version(ours), version(theirs), "{} should be the same as {}", ours, theirs);
+        }
+    }
+}
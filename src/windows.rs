@@ -31,14 +31,13 @@ use std::mem;
 use std::env;
 use std::iter;
 use std::thread;
-use std::str::FromStr;
 use std::time::Duration;
 use std::io::{Error as IoError, Result as IoResult};
 use std::ffi::{OsStr, OsString, CString};
 use std::os::windows::ffi::OsStrExt;
 
 use libc::{intptr_t, c_int, c_uint, c_long, c_char, FILE};
-use log::{trace, debug, info, warn, error};
+use tracing::{trace, debug, info, warn, error};
 use bstr::ByteSlice;
 use lazy_static::lazy_static;
 use winreg::enums::*;
@@ -49,6 +48,18 @@ use winapi::um::wincon::{FreeConsole, AttachConsole};
 use winapi::um::winbase::{STD_INPUT_HANDLE, STD_OUTPUT_HANDLE, STD_ERROR_HANDLE};
 use winapi::um::consoleapi::AllocConsole;
 use deelevate::{Token, PrivilegeLevel};
+use winapi::shared::devguid::GUID_DEVCLASS_PORTS;
+use winapi::shared::minwindef::HKEY;
+use winapi::um::cfgmgr32::{CM_Get_Device_IDW, CM_Get_Parent, CR_SUCCESS, DEVINST, MAX_DEVICE_ID_LEN};
+use winapi::um::handleapi::INVALID_HANDLE_VALUE;
+use winapi::um::setupapi::{SetupDiDestroyDeviceInfoList, SetupDiEnumDeviceInfo, SetupDiGetClassDevsW};
+use winapi::um::setupapi::{SetupDiOpenDevRegKey, SP_DEVINFO_DATA, DICS_FLAG_GLOBAL, DIGCF_PRESENT, DIREG_DEV};
+use winapi::um::setupapi::{SetupDiGetDeviceRegistryPropertyW, DIGCF_ALLCLASSES, SPDRP_SERVICE};
+use winapi::shared::winerror::ERROR_INVALID_DATA;
+use winapi::um::winnt::KEY_READ;
+
+use crate::error::{Error, ExitStatus};
+use crate::{prompt, status, tr};
 
 /// From fnctl.h
 /// ```c
@@ -223,6 +234,40 @@ fn os_str_to_null_terminated_vec(s: &OsStr) -> Vec<u16>
 }
 
 
+/// Quotes `arg` for a command line, as the C runtime (and `CommandLineToArgvW()`) will split it up
+/// again, so that e.g. paths with spaces in survive being passed to [run_elevated].
+fn quote_arg(arg: &OsStr) -> Vec<u16>
+{
+    const QUOTE: u16 = b'"' as u16;
+    const BACKSLASH: u16 = b'\\' as u16;
+
+    let arg: Vec<u16> = arg.encode_wide().collect();
+    let needs_quotes = arg.is_empty() || arg.iter().any(|&c| c == b' ' as u16 || c == b'\t' as u16 || c == QUOTE);
+    if !needs_quotes {
+        return arg;
+    }
+
+    // Backslashes only need escaping (by doubling them) before a quote, including the closing quote.
+    let mut quoted = vec![QUOTE];
+    let mut backslashes = 0;
+    for c in arg {
+        if c == BACKSLASH {
+            backslashes += 1;
+        } else {
+            if c == QUOTE {
+                quoted.extend(iter::repeat(BACKSLASH).take(backslashes + 1));
+            }
+            backslashes = 0;
+        }
+        quoted.push(c);
+    }
+    quoted.extend(iter::repeat(BACKSLASH).take(backslashes));
+    quoted.push(QUOTE);
+
+    quoted
+}
+
+
 /// Install drivers for each libwdi [wdi::DeviceInfo] in `devices`. Must be called from admin.
 fn admin_install_drivers(devices: &mut [wdi::DeviceInfo])
 {
@@ -264,6 +309,124 @@ fn admin_install_drivers(devices: &mut [wdi::DeviceInfo])
 }
 
 
+/// Whether this process needs to re-execute itself as an administrator to install drivers.
+pub fn needs_elevation() -> bool
+{
+    let token = Token::with_current_process()
+        .expect("Unable to determine the current process's privilege level");
+    let level = token.privilege_level()
+        .expect("Unable to determine the current process's privilege level");
+
+    matches!(level, PrivilegeLevel::NotPrivileged | PrivilegeLevel::HighIntegrityAdmin)
+}
+
+
+/// Runs this executable again as an administrator with `args`, which shows the user a UAC prompt,
+/// and waits for it to finish. Returns its exit code.
+///
+/// The elevated process doesn't share our console, so should be passed our process ID to
+/// [restore_cstdio] with if it's going to print anything.
+pub fn run_elevated(args: &[OsString]) -> IoResult<u32>
+{
+    use winapi::um::winbase;
+    use winapi::um::winuser;
+    use winapi::um::shellapi;
+    use winapi::um::shellapi::SHELLEXECUTEINFOW;
+    use winapi::um::shellapi::ShellExecuteExW;
+    use winapi::um::synchapi;
+
+    let verb: Vec<u16> = OsStr::new("runas").encode_wide().chain(iter::once(0)).collect();
+
+    let file = os_str_to_null_terminated_vec(env::current_exe()?.as_os_str());
+    let parameters: Vec<u16> = args
+        .iter()
+        .map(|arg| quote_arg(arg))
+        .collect::<Vec<_>>()
+        .join(&(b' ' as u16))
+        .into_iter()
+        .chain(iter::once(0))
+        .collect();
+
+    let cwd = os_str_to_null_terminated_vec(env::current_dir()?.as_os_str());
+
+    let mut info = SHELLEXECUTEINFOW {
+        cbSize: mem::size_of::<SHELLEXECUTEINFOW>() as u32,
+        fMask: shellapi::SEE_MASK_NOCLOSEPROCESS,
+        hwnd: ptr::null_mut(),
+        lpVerb: verb.as_ptr(),
+        lpFile: file.as_ptr(),
+        lpParameters: parameters.as_ptr(),
+        lpDirectory: cwd.as_ptr(),
+        nShow: winuser::SW_HIDE,
+        hInstApp: ptr::null_mut(),
+        lpIDList: ptr::null_mut(),
+        lpClass: ptr::null_mut(),
+        hkeyClass: ptr::null_mut(),
+        hMonitor: ptr::null_mut(),
+        dwHotKey: 0,
+        hProcess: ptr::null_mut(),
+    };
+
+    // This fails if the user says no to the UAC prompt, too.
+    winapi_bool!(unsafe { ShellExecuteExW(&mut info) })?;
+
+    let hproc = info.hProcess;
+    let ret = unsafe { synchapi::WaitForSingleObject(hproc, winbase::INFINITE) };
+    if ret == winbase::WAIT_FAILED {
+        return Err(IoError::last_os_error());
+    }
+    std::thread::sleep(std::time::Duration::from_secs(5));
+
+    let mut exit_code = 0;
+    winapi_bool!(unsafe { winapi::um::processthreadsapi::GetExitCodeProcess(hproc, &mut exit_code) })?;
+    unsafe {
+        winapi::um::handleapi::CloseHandle(hproc);
+    }
+
+    Ok(exit_code)
+}
+
+
+/// Offers to run the command that failed with `error` again as an administrator, if it failed because
+/// access was denied, which shows the user a UAC prompt. The elevated bmputil carries on with the
+/// command on our console, and we exit with its exit code once it's done.
+///
+/// `elevated` says whether we're already the elevated bmputil, in which case there's nothing more
+/// elevation can do.
+pub fn offer_elevated_retry(error: Error, elevated: bool) -> Result<(), Error>
+{
+    if elevated || error.exit_status() != ExitStatus::PermissionDenied || !prompt::can_prompt() || !needs_elevation() {
+        return Err(error);
+    }
+
+    // The offer says access was denied; the error itself is left for main() to print, if we don't get
+    // past it.
+    debug!("Offering to run as an administrator after: {}", error);
+    match prompt::confirm(&tr!("elevate-offer")) {
+        Ok(true) => (),
+        Ok(false) => return Err(error),
+        Err(e) => {
+            debug!("Couldn't ask whether to elevate: {}", e);
+            return Err(error);
+        },
+    }
+
+    // Skip argv[0], as run_elevated() uses the full path to the executable.
+    let mut args: Vec<OsString> = env::args_os().skip(1).collect();
+    args.push(OsString::from(format!("--elevated-from={}", std::process::id())));
+
+    status!("{}", tr!("elevate-running"));
+    match run_elevated(&args) {
+        // The elevated bmputil has said how it went, so all that's left is to pass on its exit code.
+        Ok(exit_code) => std::process::exit(exit_code as i32),
+        Err(e) => {
+            warn!("Couldn't run bmputil as an administrator: {}", e);
+            Err(error)
+        },
+    }
+}
+
+
 lazy_static! {
     pub static ref APP_MODE_WDI_INFO: wdi::DeviceInfo = wdi::DeviceInfo {
         vid: 0x1d50,
@@ -382,6 +545,172 @@ pub fn hwid_bound_to_driver(hardware_id: &str, enumerator: &str) -> IoResult<Vec
 }
 
 
+/// Returns the device instance ID of the devnode `devinst`, e.g. `USB\VID_1D50&PID_6018\<serial>`.
+fn device_instance_id(devinst: DEVINST) -> Option<String>
+{
+    let mut buffer = vec![0u16; MAX_DEVICE_ID_LEN as usize + 1];
+    let ret = unsafe { CM_Get_Device_IDW(devinst, buffer.as_mut_ptr(), buffer.len() as u32, 0) };
+    if ret != CR_SUCCESS {
+        return None;
+    }
+
+    let len = buffer.iter().position(|&c| c == 0).unwrap_or(buffer.len());
+    Some(String::from_utf16_lossy(&buffer[..len]))
+}
+
+
+/// Whether the parent of the devnode `devinst` is the USB device with serial number `serial`, which
+/// is the last part of its instance ID.
+fn parent_has_serial(devinst: DEVINST, serial: &str) -> bool
+{
+    let mut parent: DEVINST = 0;
+    if unsafe { CM_Get_Parent(&mut parent, devinst, 0) } != CR_SUCCESS {
+        return false;
+    }
+
+    device_instance_id(parent)
+        .and_then(|parent_id| parent_id.rsplit('\\').next().map(|s| s.eq_ignore_ascii_case(serial)))
+        .unwrap_or(false)
+}
+
+
+/// Finds the COM ports Windows has given the serial interfaces of the USB device with IDs `vid`:`pid`
+/// and serial number `serial`. Returns each one's interface number, and its port name (e.g. `COM5`).
+///
+/// Serial ports are devnodes of the Ports device setup class, and for composite devices like the BMP,
+/// each is the child of the device's own devnode. Their instance IDs look like
+/// `USB\VID_1D50&PID_6018&MI_00\7&2a1b3c4d&0&0000` (with `MI_00` saying which interface it is), and
+/// their parents' like `USB\VID_1D50&PID_6018\<serial>`. Each one's port name is in its hardware
+/// registry key.
+pub fn com_ports(vid: u16, pid: u16, serial: &str) -> IoResult<Vec<(u8, String)>>
+{
+    let devices = unsafe { SetupDiGetClassDevsW(&GUID_DEVCLASS_PORTS, ptr::null(), ptr::null_mut(), DIGCF_PRESENT) };
+    if devices == INVALID_HANDLE_VALUE {
+        return Err(IoError::last_os_error());
+    }
+
+    let interface_prefix = format!(r"USB\VID_{:04X}&PID_{:04X}&MI_", vid, pid);
+    let mut ports = Vec::new();
+    for index in 0.. {
+        let mut info: SP_DEVINFO_DATA = unsafe { mem::zeroed() };
+        info.cbSize = mem::size_of::<SP_DEVINFO_DATA>() as u32;
+        if unsafe { SetupDiEnumDeviceInfo(devices, index, &mut info) } == 0 {
+            // ERROR_NO_MORE_ITEMS, most likely.
+            break;
+        }
+
+        let Some(instance_id) = device_instance_id(info.DevInst) else {
+            continue;
+        };
+        let instance_id = instance_id.to_uppercase();
+        let Some(interface) = instance_id
+            .strip_prefix(&interface_prefix)
+            .and_then(|rest| rest.get(..2))
+            .and_then(|number| u8::from_str_radix(number, 16).ok())
+        else {
+            continue;
+        };
+
+        if !parent_has_serial(info.DevInst, serial) {
+            continue;
+        }
+
+        let key = unsafe { SetupDiOpenDevRegKey(devices, &mut info, DICS_FLAG_GLOBAL, 0, DIREG_DEV, KEY_READ) };
+        if key == INVALID_HANDLE_VALUE as HKEY {
+            debug!("Failed to open the registry key of {}: {}", instance_id, IoError::last_os_error());
+            continue;
+        }
+        // Wrapping the key lets winreg close it for us.
+        match RegKey::predef(key).get_value::<String, _>("PortName") {
+            Ok(name) => ports.push((interface, name)),
+            Err(e) => debug!("Failed to read the port name of {}: {}", instance_id, e),
+        };
+    }
+
+    unsafe {
+        SetupDiDestroyDeviceInfoList(devices);
+    }
+
+    ports.sort();
+    Ok(ports)
+}
+
+
+/// Finds which driver Windows has bound to interface `interface` of the USB device with IDs `vid`:`pid`
+/// and serial number `serial` (or to the device itself, if `interface` is `None`, for devices that
+/// aren't composite). Returns the name of the driver's service, e.g. `WinUSB` or `usbser`, or `None` if
+/// there's no driver bound to it at all.
+///
+/// The device's own devnode has an instance ID like `USB\VID_1D50&PID_6017\<serial>`, and those of its
+/// interfaces look like `USB\VID_1D50&PID_6018&MI_04\7&2a1b3c4d&0&0004`, as children of the device's.
+pub fn bound_driver(vid: u16, pid: u16, serial: &str, interface: Option<u8>) -> IoResult<Option<String>>
+{
+    let enumerator = os_str_to_null_terminated_vec(OsStr::new("USB"));
+    let devices = unsafe {
+        SetupDiGetClassDevsW(ptr::null(), enumerator.as_ptr(), ptr::null_mut(), DIGCF_PRESENT | DIGCF_ALLCLASSES)
+    };
+    if devices == INVALID_HANDLE_VALUE {
+        return Err(IoError::last_os_error());
+    }
+
+    let wanted = match interface {
+        Some(interface) => format!(r"USB\VID_{:04X}&PID_{:04X}&MI_{:02X}\", vid, pid, interface),
+        None => format!(r"USB\VID_{:04X}&PID_{:04X}\{}", vid, pid, serial.to_uppercase()),
+    };
+    let mut res = Err(IoError::new(std::io::ErrorKind::NotFound, format!("no device {}", wanted)));
+    for index in 0.. {
+        let mut info: SP_DEVINFO_DATA = unsafe { mem::zeroed() };
+        info.cbSize = mem::size_of::<SP_DEVINFO_DATA>() as u32;
+        if unsafe { SetupDiEnumDeviceInfo(devices, index, &mut info) } == 0 {
+            break;
+        }
+
+        let Some(instance_id) = device_instance_id(info.DevInst) else {
+            continue;
+        };
+        let instance_id = instance_id.to_uppercase();
+        let found = match interface {
+            Some(_) => instance_id.starts_with(&wanted) && parent_has_serial(info.DevInst, serial),
+            None => instance_id == wanted,
+        };
+        if !found {
+            continue;
+        }
+
+        let mut buffer = [0u16; 256];
+        let ret = unsafe {
+            SetupDiGetDeviceRegistryPropertyW(
+                devices,
+                &mut info,
+                SPDRP_SERVICE,
+                ptr::null_mut(),
+                buffer.as_mut_ptr() as *mut u8,
+                mem::size_of_val(&buffer) as u32,
+                ptr::null_mut(),
+            )
+        };
+        res = if ret != 0 {
+            let len = buffer.iter().position(|&c| c == 0).unwrap_or(buffer.len());
+            Ok(Some(String::from_utf16_lossy(&buffer[..len])))
+        } else {
+            // Devnodes without a driver don't have a service at all.
+            let e = IoError::last_os_error();
+            match e.raw_os_error() {
+                Some(code) if code == ERROR_INVALID_DATA as i32 => Ok(None),
+                _ => Err(e),
+            }
+        };
+        break;
+    }
+
+    unsafe {
+        SetupDiDestroyDeviceInfoList(devices);
+    }
+
+    res
+}
+
+
 /// This function ensures that all connected Black Magic Probe devices have the necessary drivers installed, via libwdi.
 /// If `explicitly_requested` is true, then this will print if there is nothing to do.
 /// If `force` is true, then this will install even if there is an existing driver.
@@ -450,19 +779,7 @@ pub fn ensure_access(parent_pid: Option<u32>, explicitly_requested: bool, force:
 
     // If we're here, that means we're installing drivers.
     // So we need admin.
-    let token = Token::with_current_process()
-        .expect("Unable to determine the current process's privilege level");
-    let level = token.privilege_level()
-        .expect("Unable to determine the current process's privilege level");
-
-    let need_to_elevate = match level {
-        PrivilegeLevel::NotPrivileged | PrivilegeLevel::HighIntegrityAdmin => true,
-        _ => {
-            false
-        },
-    };
-
-    if !need_to_elevate {
+    if !needs_elevation() {
 
         if let Some(pid) = parent_pid {
             match restore_cstdio(pid) {
@@ -485,80 +802,17 @@ pub fn ensure_access(parent_pid: Option<u32>, explicitly_requested: bool, force:
     }
 
     // If we need to elevate, then we have to re-execute this process.
-
-    // FIXME: this elevated-execution code should be cleaned up.
-
-    let mut args: Vec<OsString> = Vec::with_capacity(env::args_os().len() + 1);
-    args.extend(env::args_os().map(|s| s.to_owned()));
-    args.push(OsString::from_str("--windows-wdi-install-mode").unwrap());
-
-    use winapi::um::winbase;
-    use winapi::um::winuser;
-    use winapi::um::shellapi;
-    use winapi::um::shellapi::SHELLEXECUTEINFOW;
-    use winapi::um::shellapi::ShellExecuteExW;
-    use winapi::um::synchapi;
-
-    let verb: Vec<u16> = OsStr::new("runas").encode_wide().chain(iter::once(0)).collect();
-
-    let mut args: Vec<OsString> = env::args_os().map(|s| s.to_owned()).collect();
-    // Remove argv[0], as we're going to replace it with the full path to the process.
-    let _ = args.remove(0);
+    // Skip argv[0], as run_elevated() uses the full path to the executable.
+    let mut args: Vec<OsString> = env::args_os().skip(1).collect();
     args.push(OsStr::new(&format!("--windows-wdi-install-mode={}", std::process::id())).to_owned());
-    let file = os_str_to_null_terminated_vec(env::current_exe().unwrap().as_os_str());
-    let parameters: OsString = args
-        .join(OsStr::new(" "));
-    let parameters = os_str_to_null_terminated_vec(&parameters);
-
-    let cwd = os_str_to_null_terminated_vec(
-        env::current_dir()
-            .expect("Unable to get current working directory")
-            .as_os_str()
-    );
 
-    let mut info = SHELLEXECUTEINFOW {
-        cbSize: mem::size_of::<SHELLEXECUTEINFOW>() as u32,
-        fMask: shellapi::SEE_MASK_NOCLOSEPROCESS,
-        hwnd: ptr::null_mut(),
-        lpVerb: verb.as_ptr(),
-        lpFile: file.as_ptr(),
-        lpParameters: parameters.as_ptr(),
-        lpDirectory: cwd.as_ptr(),
-        nShow: winuser::SW_HIDE,
-        hInstApp: ptr::null_mut(),
-        lpIDList: ptr::null_mut(),
-        lpClass: ptr::null_mut(),
-        hkeyClass: ptr::null_mut(),
-        hMonitor: ptr::null_mut(),
-        dwHotKey: 0,
-        hProcess: ptr::null_mut(),
-    };
-
-    let res = unsafe { ShellExecuteExW(&mut info) };
-    if res == winapi::shared::minwindef::FALSE {
-        Err::<(), _>(IoError::last_os_error())
-            .expect("Error calling ShellExecuteExW()");
-    }
-
-    let hproc = info.hProcess;
-    let ret = unsafe { synchapi::WaitForSingleObject(hproc, winbase::INFINITE) };
-    if ret == winbase::WAIT_FAILED {
-        Err::<(), _>(IoError::last_os_error())
-            .expect("Error calling WaitForSingleObject()");
-    }
-    std::thread::sleep(std::time::Duration::from_secs(5));
-
-    let mut exit_code = 0;
-    if unsafe { winapi::um::processthreadsapi::GetExitCodeProcess(hproc, &mut exit_code) } != 0 {
-        if exit_code != 0 {
-            error!("Elevated process exited with {}; driver installation probably failed", exit_code);
-            std::process::exit(exit_code as i32);
-        } else {
-            info!("Exiting parent process. Elevated process exited successfully.");
-        }
+    let exit_code = run_elevated(&args)
+        .expect("Error running elevated process to install drivers");
+    if exit_code != 0 {
+        error!("Elevated process exited with {}; driver installation probably failed", exit_code);
+        std::process::exit(exit_code as i32);
     } else {
-        Err::<(), _>(IoError::last_os_error())
-            .expect("Error calling GetExitCodeProcess()");
+        info!("Exiting parent process. Elevated process exited successfully.");
     }
 
     println!(
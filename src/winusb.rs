@@ -0,0 +1,319 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+//! Module for installing WinUSB on Windows for probes whose DFU interface has no usable driver, so
+//! nobody has to go and find Zadig.
+//!
+//! Where [`ensure_access()`](crate::windows::ensure_access) installs drivers up front for the standard
+//! Black Magic Probe USB IDs, this works from the devices actually plugged in: when a probe's DFU
+//! interface can't be opened, [`offer_install()`] asks whether to bind WinUSB to it. That covers probes
+//! using other USB IDs, and interfaces something else has since been bound to. Installing a driver
+//! needs an administrator, so unless we already are one, the installation itself is done by an
+//! elevated copy of bmputil running the hidden `winusb-install` command with the hardware IDs to
+//! install for.
+
+use std::ffi::{CString, OsString};
+use std::fmt::{self, Display, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use clap::ArgMatches;
+use rusb::UsbContext;
+use tracing::{debug, warn};
+
+use crate::bmp::{CustomUsbIds, UsbTimeouts};
+use crate::error::{Error, ErrorKind, ErrorSource};
+use crate::usb::{self, InterfaceClass, InterfaceSubClass, Pid, Vid};
+use crate::{msos, windows};
+use crate::{prompt, status, tr};
+
+type UsbDevice = rusb::Device<rusb::Context>;
+
+/// Whether we've already asked to install WinUSB in this run, so we don't keep asking.
+static OFFERED: AtomicBool = AtomicBool::new(false);
+
+/// A device node Windows needs WinUSB bound to for us to use a probe's DFU interface: the interface
+/// itself for composite devices, or the whole device otherwise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DriverTarget
+{
+    vid: u16,
+    pid: u16,
+    /// bcdDevice, which Windows includes in the device's most specific hardware ID.
+    revision: u16,
+    /// The DFU interface's number, if the device is composite.
+    interface: Option<u8>,
+    /// bInterfaceProtocol of the DFU interface, which is 1 for runtime mode and 2 for DFU mode.
+    protocol: u8,
+}
+
+impl DriverTarget
+{
+    /// Works out the driver target for `device`'s DFU interface, if it has one.
+    fn for_device(device: &UsbDevice) -> Option<Self>
+    {
+        let desc = device.device_descriptor().ok()?;
+        let configuration = device
+            .active_config_descriptor()
+            .or_else(|_| device.config_descriptor(0))
+            .ok()?;
+
+        let dfu_interface = configuration
+            .interfaces()
+            .flat_map(|interface| interface.descriptors())
+            .find(|desc| {
+                desc.class_code() == InterfaceClass::APPLICATION_SPECIFIC.0 &&
+                    desc.sub_class_code() == InterfaceSubClass::DFU.0
+            })?;
+
+        // rusb decodes bcdDevice, so encode it again.
+        let version = desc.device_version();
+        let revision = (version.major() as u16 / 10) << 12 |
+            (version.major() as u16 % 10) << 8 |
+            (version.minor() as u16) << 4 |
+            version.sub_minor() as u16;
+
+        Some(Self {
+            vid: desc.vendor_id(),
+            pid: desc.product_id(),
+            revision,
+            interface: (configuration.num_interfaces() > 1).then(|| dfu_interface.interface_number()),
+            protocol: dfu_interface.protocol_code(),
+        })
+    }
+
+    /// The hardware ID Windows' driver database knows this device node by, without the `USB\`
+    /// enumerator, as for [`hwid_bound_to_driver()`](crate::windows::hwid_bound_to_driver).
+    pub fn hardware_id(&self) -> String
+    {
+        match self.interface {
+            Some(interface) => format!("VID_{:04X}&PID_{:04X}&MI_{:02X}", self.vid, self.pid, interface),
+            None => format!("VID_{:04X}&PID_{:04X}", self.vid, self.pid),
+        }
+    }
+
+    /// The libwdi description of this device node, to install WinUSB for.
+    fn wdi_info(&self) -> wdi::DeviceInfo
+    {
+        let (desc, hardware_id) = match self.interface {
+            Some(interface) => (
+                format!("Black Magic DFU (Interface {})", interface),
+                format!(r"USB\VID_{:04X}&PID_{:04X}&REV_{:04X}&MI_{:02X}", self.vid, self.pid, self.revision, interface),
+            ),
+            None => (
+                String::from("Black Magic Probe DFU"),
+                format!(r"USB\VID_{:04X}&PID_{:04X}&REV_{:04X}", self.vid, self.pid, self.revision),
+            ),
+        };
+        let compatible_id = format!(r"USB\Class_FE&SubClass_01&Prot_{:02X}", self.protocol);
+
+        let c_bytes = |s: String| CString::new(s).unwrap().into_bytes_with_nul();
+        wdi::DeviceInfo {
+            vid: self.vid,
+            pid: self.pid,
+            is_composite: self.interface.is_some(),
+            mi: self.interface.unwrap_or(0),
+            desc: c_bytes(desc),
+            driver: None,
+            device_id: None,
+            hardware_id: Some(c_bytes(hardware_id)),
+            compatible_id: Some(c_bytes(compatible_id)),
+            upper_filter: None,
+            driver_version: 0,
+        }
+    }
+}
+
+impl Display for DriverTarget
+{
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result
+    {
+        write!(f, "{:04x}:{:04x}", self.vid, self.pid)?;
+        if let Some(interface) = self.interface {
+            write!(f, " interface {}", interface)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether we can't use `target`'s DFU interface on `device` for want of a driver.
+///
+/// Windows gives devices (and the interfaces of composite devices) with no driver, or one libusb
+/// can't use, to libusb as unsupported, so we try opening the device and claiming its interface.
+fn lacks_usable_driver(device: &UsbDevice, target: &DriverTarget) -> bool
+{
+    let handle = match device.open() {
+        Ok(handle) => handle,
+        Err(rusb::Error::NotSupported | rusb::Error::Access | rusb::Error::NotFound) => return true,
+        Err(e) => {
+            debug!("Couldn't open {} to check its driver: {}", target, e);
+            return false;
+        },
+    };
+
+    match handle.claim_interface(target.interface.unwrap_or(0)) {
+        Ok(()) => false,
+        Err(rusb::Error::NotSupported | rusb::Error::Access | rusb::Error::NotFound) => true,
+        Err(e) => {
+            // Busy, say, means something's using it, which another driver won't help with.
+            debug!("Couldn't claim the DFU interface of {} to check its driver: {}", target, e);
+            false
+        },
+    }
+}
+
+/// Whether `device`'s Microsoft OS 2.0 descriptors ask Windows to bind WinUSB to `target` by itself.
+///
+/// Devices with no usable driver at all can't be opened to ask, but the other interfaces of composite
+/// devices still answer for them.
+fn advertises_winusb(device: &UsbDevice, target: &DriverTarget) -> bool
+{
+    let Ok(handle) = device.open() else {
+        return false;
+    };
+
+    msos::advertises_winusb(&handle, target.interface.unwrap_or(0), UsbTimeouts::DEFAULT_REQUEST)
+        .map_err(|e| debug!("Couldn't read the Microsoft OS 2.0 descriptors of {}: {}", target, e))
+        .unwrap_or(false)
+}
+
+/// Finds the connected devices `wanted` picks out (by VID and PID), with the driver targets for their
+/// DFU interfaces.
+fn find_targets<F>(wanted: F) -> Result<Vec<(UsbDevice, DriverTarget)>, Error>
+where
+    F: Fn(Vid, Pid) -> bool,
+{
+    let context = usb::new_context()?;
+    let devices = context.devices()?;
+
+    let targets = devices
+        .iter()
+        .filter(|device| {
+            device
+                .device_descriptor()
+                .is_ok_and(|desc| wanted(Vid(desc.vendor_id()), Pid(desc.product_id())))
+        })
+        .filter_map(|device| {
+            let target = DriverTarget::for_device(&device)?;
+            Some((device, target))
+        })
+        .collect();
+
+    Ok(targets)
+}
+
+/// Offers to install WinUSB for any connected probes (recognised with `custom_ids`) whose DFU
+/// interface has no usable driver, asking at most once per run. Returns whether it was installed.
+pub fn offer_install(custom_ids: CustomUsbIds) -> Result<bool, Error>
+{
+    if OFFERED.swap(true, Ordering::Relaxed) {
+        return Ok(false);
+    }
+
+    let lacking = find_targets(|vid, pid| custom_ids.identify(vid, pid).is_some())?
+        .into_iter()
+        .filter(|(device, target)| lacks_usable_driver(device, target))
+        .collect::<Vec<_>>();
+    // Windows only reads a device's Microsoft OS 2.0 descriptors the first time it sees it, so probes
+    // whose firmware has since started asking for WinUSB can still be without it.
+    if lacking.iter().any(|(device, target)| advertises_winusb(device, target)) {
+        status!("{}", tr!("winusb-remembered"));
+    }
+    let mut targets = lacking
+        .into_iter()
+        .map(|(_device, target)| target)
+        .collect::<Vec<_>>();
+    // Several probes of the same kind share a device node as far as drivers are concerned.
+    targets.sort_by_key(|target| target.hardware_id());
+    targets.dedup_by_key(|target| target.hardware_id());
+    if targets.is_empty() {
+        return Ok(false);
+    }
+
+    let devices = targets
+        .iter()
+        .map(|target| target.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    if !prompt::can_prompt() {
+        status!("{}", tr!("winusb-needed", devices = devices));
+        return Ok(false);
+    }
+    let question = tr!("winusb-offer", devices = devices);
+    if !prompt::confirm(&question).map_err(|e| ErrorKind::External(ErrorSource::StdIo(e)).error())? {
+        return Ok(false);
+    }
+
+    install(&targets)?;
+    status!("{}", tr!("winusb-installed"));
+
+    Ok(true)
+}
+
+/// Installs WinUSB for `targets`, elevating if need be.
+fn install(targets: &[DriverTarget]) -> Result<(), Error>
+{
+    if !windows::needs_elevation() {
+        return install_as_admin(targets.iter().map(|target| target.hardware_id()).collect());
+    }
+
+    status!("{}", tr!("winusb-elevating"));
+    let mut args = vec![
+        OsString::from("winusb-install"),
+        OsString::from(format!("--parent-pid={}", std::process::id())),
+    ];
+    args.extend(targets.iter().map(|target| OsString::from(target.hardware_id())));
+
+    let exit_code = windows::run_elevated(&args)
+        .map_err(|e| ErrorKind::DriverInstall(tr!("winusb-not-elevated")).error_from(e))?;
+    if exit_code != 0 {
+        return Err(ErrorKind::DriverInstall(tr!("winusb-elevated-failed", status = exit_code)).error());
+    }
+
+    Ok(())
+}
+
+/// Installs WinUSB for the connected devices with the given hardware IDs. Must be called from admin.
+fn install_as_admin(hardware_ids: Vec<String>) -> Result<(), Error>
+{
+    let targets = find_targets(|_vid, _pid| true)?
+        .into_iter()
+        .map(|(_device, target)| target)
+        .filter(|target| hardware_ids.iter().any(|id| id.eq_ignore_ascii_case(&target.hardware_id())))
+        .collect::<Vec<_>>();
+    if targets.is_empty() {
+        return Err(ErrorKind::DeviceNotFound.error().with_ctx("installing WinUSB"));
+    }
+
+    for target in targets {
+        let hardware_id = target.hardware_id();
+        status!("{}", tr!("winusb-installing", device = target.to_string()));
+
+        let mut info = target.wdi_info();
+        wdi::prepare_driver(&mut info, "usb_driver", "usb_device.inf", &mut Default::default())
+            .map_err(|e| ErrorKind::DriverInstall(format!("{:?}", e)).error().with_ctx("preparing the driver"))?;
+        wdi::install_driver(&mut info, "usb_driver", "usb_device.inf", &mut Default::default())
+            .map_err(|e| ErrorKind::DriverInstall(format!("{:?}", e)).error())?;
+
+        debug!("Installed WinUSB for {}", hardware_id);
+    }
+
+    Ok(())
+}
+
+/// Runs the hidden `winusb-install` command, which an unelevated bmputil elevates itself with to
+/// install WinUSB.
+pub fn install_command(matches: &ArgMatches) -> Result<(), Error>
+{
+    if let Some(&parent_pid) = matches.get_one::<u32>("parent-pid") {
+        if let Err(e) = windows::restore_cstdio(parent_pid) {
+            warn!("Couldn't attach to the console of bmputil process {}: {}", parent_pid, e);
+        }
+    }
+
+    let hardware_ids = matches
+        .get_many::<String>("hardware-ids")
+        .expect("clap should require at least one hardware ID")
+        .cloned()
+        .collect();
+
+    install_as_admin(hardware_ids)
+}
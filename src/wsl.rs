@@ -0,0 +1,337 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+// SPDX-FileCopyrightText: 2026 1BitSquared <info@1bitsquared.com>
+//! Module for helping bmputil find probes when it's run inside WSL (the Windows Subsystem for Linux).
+//!
+//! WSL 2 only sees USB devices Windows has been told to hand over to it with
+//! [usbipd-win](https://github.com/dorssel/usbipd-win), so when no probe is found there, [`help_attach()`]
+//! asks usbipd which probes Windows can see, and either says exactly which `usbipd` commands would
+//! attach them, or (with `--auto-attach`) runs those commands itself. Probes are attached with
+//! usbipd's own `--auto-attach`, so they're attached again when they re-enumerate, e.g. on switching
+//! to DFU mode to be flashed.
+
+use std::env;
+use std::fs;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tracing::debug;
+
+use crate::bmp::CustomUsbIds;
+use crate::error::{Error, ErrorKind};
+use crate::usb::{Pid, Vid};
+use crate::{status, tr, S};
+
+/// usbipd-win's command line tool, run through WSL's interop with Windows.
+const USBIPD: &str = "usbipd.exe";
+
+/// Whether we've already tried to help in this run, so we don't keep repeating ourselves.
+static HELPED: AtomicBool = AtomicBool::new(false);
+
+/// Which version of WSL we're running in, if any.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Wsl
+{
+    /// WSL 1, which translates Linux system calls and has no USB support at all.
+    V1,
+    /// WSL 2, which is a virtual machine that can be given USB devices with usbipd.
+    V2,
+}
+
+/// Works out whether we're running in WSL, and which version.
+pub fn detect() -> Option<Wsl>
+{
+    let release = fs::read_to_string("/proc/sys/kernel/osrelease").unwrap_or_default().to_lowercase();
+    if !release.contains("microsoft") && env::var_os("WSL_DISTRO_NAME").is_none() {
+        return None;
+    }
+
+    // WSL 2's kernels are called e.g. 5.15.153.1-microsoft-standard-WSL2, where WSL 1 reports
+    // Windows' own build, e.g. 4.4.0-19041-Microsoft.
+    if release.contains("wsl2") || release.contains("microsoft-standard") {
+        Some(Wsl::V2)
+    } else {
+        Some(Wsl::V1)
+    }
+}
+
+/// A USB device Windows can see, as `usbipd list` describes it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct WindowsDevice
+{
+    busid: String,
+    vid: Vid,
+    pid: Pid,
+    description: String,
+    state: String,
+}
+
+impl WindowsDevice
+{
+    fn is_shared(&self) -> bool
+    {
+        self.state != "Not shared"
+    }
+
+    fn is_attached(&self) -> bool
+    {
+        self.state.starts_with("Attached")
+    }
+
+    /// The WSL distribution the device is attached to, where usbipd says: older versions do, e.g.
+    /// "Attached - Ubuntu", but newer ones only say "Attached".
+    fn attached_distribution(&self) -> Option<&str>
+    {
+        self.state.strip_prefix("Attached - ").map(str::trim)
+    }
+}
+
+/// Parses the "Connected" section of `usbipd list`'s output, which looks like:
+///
+/// ```text
+/// Connected:
+/// BUSID  VID:PID    DEVICE                                              STATE
+/// 2-3    1d50:6018  Black Magic Probe v2.0.0, USB Serial Device (COM5)  Not shared
+/// ```
+fn parse_list(output: &str) -> Vec<WindowsDevice>
+{
+    output
+        .lines()
+        .skip_while(|line| !line.trim_start().starts_with("Connected:"))
+        .skip(2)
+        .take_while(|line| !line.trim().is_empty())
+        .filter_map(|line| {
+            // Columns are separated by at least two spaces, which don't appear within them.
+            let columns = line
+                .split("  ")
+                .map(str::trim)
+                .filter(|column| !column.is_empty())
+                .collect::<Vec<_>>();
+            let [busid, ids, description @ .., state] = columns.as_slice() else {
+                return None;
+            };
+            let (vid, pid) = ids.split_once(':')?;
+
+            Some(WindowsDevice {
+                busid: S!(*busid),
+                vid: Vid(u16::from_str_radix(vid, 16).ok()?),
+                pid: Pid(u16::from_str_radix(pid, 16).ok()?),
+                description: description.join(" "),
+                state: S!(*state),
+            })
+        })
+        .collect()
+}
+
+/// Asks usbipd which probes (recognised with `custom_ids`) Windows can see. Returns `None` if usbipd
+/// can't be run.
+fn windows_probes(custom_ids: CustomUsbIds) -> Option<Vec<WindowsDevice>>
+{
+    let output = match Command::new(USBIPD).arg("list").stdin(Stdio::null()).output() {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            debug!("usbipd list failed ({}): {}", output.status, String::from_utf8_lossy(&output.stderr));
+            return None;
+        },
+        Err(e) => {
+            debug!("Couldn't run {}: {}", USBIPD, e);
+            return None;
+        },
+    };
+
+    let probes = parse_list(&String::from_utf8_lossy(&output.stdout))
+        .into_iter()
+        .filter(|device| custom_ids.identify(device.vid, device.pid).is_some())
+        .collect();
+
+    Some(probes)
+}
+
+/// Runs usbipd on the Windows side, erroring if it fails.
+fn run(command: &mut Command) -> Result<(), Error>
+{
+    let description = format!("{:?}", command);
+    debug!("Running {}", description);
+
+    let status = command
+        .stdin(Stdio::null())
+        .status()
+        .map_err(|e| ErrorKind::CommandFailed(description.clone()).error_from(e))?;
+    if !status.success() {
+        return Err(ErrorKind::CommandFailed(description).error());
+    }
+
+    Ok(())
+}
+
+/// Has Windows share `probe` with WSL, and starts usbipd attaching it (and attaching it again whenever
+/// it re-enumerates).
+fn attach(probe: &WindowsDevice) -> Result<(), Error>
+{
+    if !probe.is_shared() {
+        // Sharing a device needs an administrator, so this shows a UAC prompt.
+        status!("{}", tr!("wsl-binding", busid = probe.busid.as_str()));
+        let arguments = format!("'bind','--busid','{}'", probe.busid);
+        run(Command::new("powershell.exe").args([
+            "-NoProfile",
+            "-Command",
+            &format!("Start-Process usbipd -Verb RunAs -Wait -WindowStyle Hidden -ArgumentList {}", arguments),
+        ]))?;
+    }
+
+    // With --auto-attach, usbipd keeps running to attach the probe whenever it reappears, so it's
+    // left running after we exit.
+    status!("{}", tr!("wsl-attaching", busid = probe.busid.as_str()));
+    let mut command = Command::new(USBIPD);
+    command
+        .args(["attach", "--wsl", "--busid", &probe.busid, "--auto-attach"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    debug!("Running {:?}", command);
+    command
+        .spawn()
+        .map_err(|e| ErrorKind::CommandFailed(format!("{:?}", command)).error_from(e))?;
+
+    Ok(())
+}
+
+/// If we're running in WSL, helps get probes (recognised with `custom_ids`) that Windows can see
+/// attached to it, at most once per run: attaching them if `auto_attach` is set, and otherwise
+/// saying how to. Returns whether probes were (or already are) attached, so are worth looking for again.
+pub fn help_attach(custom_ids: CustomUsbIds, auto_attach: bool) -> bool
+{
+    let Some(wsl) = detect() else {
+        return false;
+    };
+    if HELPED.swap(true, Ordering::Relaxed) {
+        return false;
+    }
+
+    if wsl == Wsl::V1 {
+        status!("{}", tr!("wsl-v1"));
+        return false;
+    }
+
+    let Some(probes) = windows_probes(custom_ids) else {
+        status!("{}", tr!("wsl-no-usbipd"));
+        return false;
+    };
+    if probes.is_empty() {
+        status!("{}", tr!("wsl-no-probes"));
+        return false;
+    }
+    debug!("Probes Windows can see: {:?}", probes);
+
+    // Probes that are attached already, but that we can't see, are either attached to another WSL
+    // distribution, or still appearing here (e.g. usbipd attaching them again as they re-enumerate),
+    // so are worth waiting for unless usbipd says it's the former.
+    let (attached, probes): (Vec<_>, Vec<_>) = probes.into_iter().partition(|probe| probe.is_attached());
+    let distribution = env::var("WSL_DISTRO_NAME").ok();
+    let mut attached_any = false;
+    for probe in &attached {
+        match (probe.attached_distribution(), distribution.as_deref()) {
+            (Some(theirs), Some(ours)) if theirs != ours => status!(
+                "{}",
+                tr!(
+                    "wsl-attached-elsewhere",
+                    probe = probe.description.as_str(),
+                    busid = probe.busid.as_str(),
+                    distribution = theirs,
+                )
+            ),
+            _ => {
+                status!("{}", tr!("wsl-attached", probe = probe.description.as_str(), busid = probe.busid.as_str()));
+                attached_any = true;
+            },
+        }
+    }
+    if probes.is_empty() {
+        return attached_any;
+    }
+
+    if !auto_attach {
+        status!("{}", tr!("wsl-not-attached"));
+        for probe in &probes {
+            status!("  {} ({})", probe.description, probe.busid);
+            if !probe.is_shared() {
+                status!("    usbipd bind --busid {}", probe.busid);
+            }
+            status!("    usbipd attach --wsl --busid {} --auto-attach", probe.busid);
+        }
+        status!("{}", tr!("wsl-auto-attach-hint"));
+        return attached_any;
+    }
+
+    for probe in &probes {
+        match attach(probe) {
+            Ok(()) => attached_any = true,
+            Err(e) => status!("{}", tr!("wsl-attach-failed", busid = probe.busid.as_str(), error = e.to_string())),
+        }
+    }
+
+    attached_any
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn parse_list_connected()
+    {
+        let output = "\
+Connected:
+BUSID  VID:PID    DEVICE                                                        STATE
+1-2    046d:c52b  USB Input Device                                              Not shared
+2-3    1d50:6018  Black Magic Probe v2.0.0, USB Serial Device (COM5), USB ...  Shared
+2-4    1d50:6017  Black Magic Probe DFU                                         Attached
+
+Persisted:
+GUID                                  DEVICE
+0e6d3a5b-1c3e-4a1f-9a62-4e1e0b0f3c21  Black Magic Probe
+";
+        let devices = parse_list(output);
+        assert_eq!(devices.len(), 3);
+        assert_eq!(
+            devices[1],
+            WindowsDevice {
+                busid: S!("2-3"),
+                vid: Vid(0x1d50),
+                pid: Pid(0x6018),
+                description: S!("Black Magic Probe v2.0.0, USB Serial Device (COM5), USB ..."),
+                state: S!("Shared"),
+            },
+        );
+        assert!(!devices[0].is_shared());
+        assert!(devices[1].is_shared() && !devices[1].is_attached());
+        assert!(devices[2].is_attached());
+        assert_eq!(devices[2].attached_distribution(), None);
+    }
+
+    #[test]
+    fn parse_list_old_attached_state()
+    {
+        // Older versions of usbipd say which distribution a device is attached to.
+        let output = "\
+Connected:
+BUSID  VID:PID    DEVICE                        STATE
+2-3    1d50:6018  Black Magic Probe v1.10.2     Attached - Ubuntu-22.04
+";
+        let devices = parse_list(output);
+        assert_eq!(devices.len(), 1);
+        assert!(devices[0].is_shared());
+        assert!(devices[0].is_attached());
+        assert_eq!(devices[0].attached_distribution(), Some("Ubuntu-22.04"));
+    }
+
+    #[test]
+    fn parse_list_nothing_connected()
+    {
+        assert!(parse_list("").is_empty());
+        assert!(parse_list("Connected:\nBUSID  VID:PID    DEVICE    STATE\n\nPersisted:\n").is_empty());
+        // Lines that aren't devices are skipped, rather than misread.
+        assert!(parse_list("Connected:\nBUSID  VID:PID    DEVICE    STATE\n2-3  not-ids  Something  Shared\n").is_empty());
+    }
+}